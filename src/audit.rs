@@ -0,0 +1,184 @@
+//! Find dependencies which look like toolchain crates, but are not upgraded by any group.
+
+use crate::cache::CacheArgs;
+use crate::context::{Context, GlobalArgs};
+use crate::http::Network;
+use crate::manifest;
+use crate::upgrade;
+use crate::workspace;
+use anyhow::Result;
+use clap::Parser;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+/// Report Cairo-like dependencies which no toolchain dependency group manages.
+///
+/// Every `cairo-*` dependency in the root manifest and all workspace members is checked against
+/// the crates of all groups. Dependencies owned by none of them are listed on stdout, as
+/// `upgrade` leaves them untouched.
+#[derive(Parser)]
+pub struct Args {
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+/// A Cairo-like dependency that is not managed by any group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Unmanaged {
+    /// Path of the manifest, relative to the workspace root.
+    manifest: PathBuf,
+    table_path: String,
+    crate_name: String,
+}
+
+impl fmt::Display for Unmanaged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            self.manifest.display(),
+            self.table_path,
+            self.crate_name
+        )
+    }
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, &args)
+}
+
+fn run(ctx: &Context, args: &Args) -> Result<()> {
+    let unmanaged = ctx.phase("audit dependencies", || {
+        find_unmanaged(
+            &ctx.sh.current_dir(),
+            &ctx.lockfile(),
+            &args.cache,
+            ctx.network()?,
+        )
+    })?;
+    for unmanaged in &unmanaged {
+        ctx.output(&format!("{unmanaged}\n"));
+    }
+    if unmanaged.is_empty() {
+        ctx.note("all Cairo-like dependencies are managed by a toolchain dependency group");
+    } else {
+        ctx.warn(format_args!(
+            "found {} Cairo-like dependencies which are not upgraded by any group",
+            unmanaged.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Lists `cairo-*` dependencies of the workspace at `root` that no group owns.
+fn find_unmanaged(
+    root: &Path,
+    lockfile: &Path,
+    cache: &CacheArgs,
+    network: Network,
+) -> Result<Vec<Unmanaged>> {
+    let cargo_toml = manifest::read(&root.join("Cargo.toml"))?;
+    let mut manifests = vec![(PathBuf::from("Cargo.toml"), cargo_toml.clone())];
+    for member in workspace::members(root, &cargo_toml)? {
+        let manifest_path = member.join("Cargo.toml");
+        let manifest = manifest::read(&root.join(&manifest_path))?;
+        manifests.push((manifest_path, manifest));
+    }
+
+    let mut candidates = Vec::new();
+    for (manifest_path, manifest) in &manifests {
+        for (table_path, deps) in workspace::dependency_tables(manifest) {
+            for (key, dep) in deps.iter() {
+                let crate_name = manifest::crate_name(key, dep);
+                if crate_name.starts_with("cairo-") {
+                    candidates.push(Unmanaged {
+                        manifest: manifest_path.clone(),
+                        table_path: table_path.clone(),
+                        crate_name: crate_name.to_owned(),
+                    });
+                }
+            }
+        }
+    }
+
+    // The crates of the Cairo group are only known from its release script, so only fetch it if
+    // the workspace depends on any of them.
+    let with_cairo = candidates
+        .iter()
+        .any(|dep| dep.crate_name.starts_with("cairo-lang-"));
+    let cargo_lock = std::fs::read_to_string(lockfile)
+        .ok()
+        .and_then(|cargo_lock| cargo_lock.parse::<DocumentMut>().ok());
+    let managed =
+        upgrade::managed_crates(&cargo_toml, cargo_lock.as_ref(), cache, network, with_cairo)?;
+    candidates.retain(|dep| !managed.contains(&dep.crate_name));
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    #[test]
+    fn test_flags_unmanaged_dependencies() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.dependencies]\n\
+            cairo-language-server = \"2.9.0\"\ncairo-something = \"0.1\"\nanyhow = \"1\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "crates/foo/Cargo.toml",
+            "[dependencies]\nlint = { package = \"cairo-lint-core\", version = \"2.9.0\" }\n\n\
+            [dev-dependencies]\ncairo-test-helpers = { path = \"../helpers\" }\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["audit"]);
+        run(&ctx, &args).unwrap();
+
+        let (stdout, stderr) = ctx.captured();
+        assert_eq!(
+            stdout,
+            "Cargo.toml [workspace.dependencies] cairo-something\n\
+            crates/foo/Cargo.toml [dev-dependencies] cairo-test-helpers\n"
+        );
+        assert_eq!(
+            stderr,
+            ["warn: found 2 Cairo-like dependencies which are not upgraded by any group"]
+        );
+    }
+
+    #[test]
+    fn test_reads_crates_from_configured_branch() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\n\n[workspace.dependencies]\ncairo-lang-compiler = \"2.9.0\"\n\n\
+            [workspace.metadata.cairo-toolchain-xtasks.cairo]\nbranch = \"dev\"\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let err = run(&ctx, &Args::parse_from(["audit"])).unwrap_err();
+        assert!(
+            format!("{err:#}").contains(
+                "refusing to fetch https://raw.githubusercontent.com/starkware-libs/cairo/\
+                refs/heads/dev/scripts/release_crates.sh"
+            ),
+            "{err:#}"
+        );
+    }
+}
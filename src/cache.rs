@@ -0,0 +1,362 @@
+//! Inspect and clean on-disk caches of these xtasks.
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+
+/// Inspect and clean on-disk caches of these xtasks.
+#[derive(Parser)]
+pub struct Args {
+    #[command(flatten)]
+    pub cache: CacheArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List cached entries with their sizes and ages.
+    Show,
+
+    /// Remove cached entries.
+    Clean(CleanArgs),
+}
+
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct CleanArgs {
+    /// Only remove entries older than this age, e.g. `30m`, `12h` or `7d`.
+    #[arg(long, value_parser = parse_age)]
+    older_than: Option<Duration>,
+
+    /// Remove all cached entries.
+    #[arg(long, default_value_t = false)]
+    all: bool,
+}
+
+/// Arguments shared by all commands which make use of the on-disk cache.
+#[derive(clap::Args, Clone, Default)]
+pub struct CacheArgs {
+    /// Directory to store cached data in.
+    ///
+    /// Defaults to `$XDG_CACHE_HOME/cairo-toolchain-xtasks` or `~/.cache/cairo-toolchain-xtasks`.
+    #[arg(long, global = true)]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl CacheArgs {
+    pub fn cache(&self) -> Cache {
+        match &self.cache_dir {
+            Some(dir) => Cache::new(dir.clone()),
+            None => Cache::new(default_cache_dir()),
+        }
+    }
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let cache = args.cache.cache();
+
+    match args.command {
+        Command::Show => {
+            let entries = cache.entries()?;
+            if entries.is_empty() {
+                eprintln!("cache at {} is empty", cache.dir().display());
+            }
+            for entry in entries {
+                println!("{entry}");
+            }
+        }
+
+        Command::Clean(clean) => {
+            let removed = cache.clean(if clean.all { None } else { clean.older_than })?;
+            for entry in &removed {
+                eprintln!("removed {}", entry.key);
+            }
+            eprintln!("removed {} cache entries", removed.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// A directory of cached files, addressed by `/`-separated keys.
+///
+/// Every read treats a missing or unreadable entry as a cache miss, so the cache directory can be
+/// wiped at any time.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// Metadata of a single cached file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub size: u64,
+    pub age: Duration,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Reads the cached value for `key`, if present.
+    pub fn get(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.path(key)).ok()
+    }
+
+    /// Reads the cached value for `key`, unless it is older than `ttl`.
+    pub fn get_fresh(&self, key: &str, ttl: Duration) -> Option<String> {
+        let age = fs::metadata(self.path(key))
+            .ok()?
+            .modified()
+            .ok()?
+            .elapsed()
+            .ok()?;
+        if age > ttl {
+            return None;
+        }
+        self.get(key)
+    }
+
+    /// Stores `value` under `key`, replacing any previous value.
+    pub fn put(&self, key: &str, value: &str) -> Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, value).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Lists all cached entries, sorted by key.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        if self.dir.is_dir() {
+            self.collect_entries(&self.dir, &mut entries)?;
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    /// Removes entries older than `older_than`, or all entries if `None`.
+    ///
+    /// Returns the removed entries.
+    pub fn clean(&self, older_than: Option<Duration>) -> Result<Vec<Entry>> {
+        let mut removed = Vec::new();
+        for entry in self.entries()? {
+            if older_than.is_some_and(|older_than| entry.age <= older_than) {
+                continue;
+            }
+            let path = self.path(&entry.key);
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        key.split('/')
+            .filter(|segment| !segment.is_empty() && *segment != "." && *segment != "..")
+            .map(|segment| {
+                segment
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                            c
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .fold(self.dir.clone(), |path, segment| path.join(segment))
+    }
+
+    fn collect_entries(&self, dir: &Path, entries: &mut Vec<Entry>) -> Result<()> {
+        for dir_entry in
+            fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            if metadata.is_dir() {
+                self.collect_entries(&dir_entry.path(), entries)?;
+                continue;
+            }
+
+            let key = dir_entry
+                .path()
+                .strip_prefix(&self.dir)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+            entries.push(Entry {
+                key,
+                size: metadata.len(),
+                age,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{} B\t{} old",
+            self.key,
+            self.size,
+            format_age(self.age)
+        )
+    }
+}
+
+fn default_cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    base.join("cairo-toolchain-xtasks")
+}
+
+/// Parses a human-friendly age like `90s`, `30m`, `12h`, `7d` or `2w`.
+fn parse_age(s: &str) -> Result<Duration> {
+    let Some(unit_start) = s.find(|c: char| !c.is_ascii_digit()) else {
+        bail!("missing unit in `{s}`, expected one of: s, m, h, d, w");
+    };
+    let (amount, unit) = s.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("invalid age: `{s}`"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("unknown unit `{unit}` in `{s}`, expected one of: s, m, h, d, w"),
+    };
+    let Some(seconds) = amount.checked_mul(seconds) else {
+        bail!("age too large: `{s}`");
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use xshell::Shell;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn populate(cache: &Cache) {
+        cache
+            .put("release-crates/cairo/v2.9.0.sh", "fresh")
+            .unwrap();
+        cache.put("release-crates/cairo/v2.8.0.sh", "old").unwrap();
+        File::options()
+            .write(true)
+            .open(cache.dir().join("release-crates/cairo/v2.8.0.sh"))
+            .unwrap()
+            .set_modified(SystemTime::now() - 10 * DAY)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_show() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().join("cache"));
+
+        assert!(cache.entries().unwrap().is_empty());
+        assert_eq!(cache.get("release-crates/cairo/v2.9.0.sh"), None);
+
+        populate(&cache);
+        let entries = cache.entries().unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(),
+            [
+                "release-crates/cairo/v2.8.0.sh",
+                "release-crates/cairo/v2.9.0.sh"
+            ]
+        );
+        assert_eq!(entries[0].size, 3);
+        assert!(entries[0].age >= 10 * DAY);
+        assert_eq!(
+            entries[0].to_string(),
+            "release-crates/cairo/v2.8.0.sh\t3 B\t10d old"
+        );
+        assert_eq!(
+            cache.get("release-crates/cairo/v2.9.0.sh").as_deref(),
+            Some("fresh")
+        );
+    }
+
+    #[test]
+    fn test_clean() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        populate(&cache);
+
+        let removed = cache.clean(Some(7 * DAY)).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].key, "release-crates/cairo/v2.8.0.sh");
+        assert_eq!(cache.get("release-crates/cairo/v2.8.0.sh"), None);
+        assert_eq!(cache.entries().unwrap().len(), 1);
+
+        let removed = cache.clean(None).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wiped_cache_is_cold_start() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().join("does-not-exist"));
+        assert!(cache.entries().unwrap().is_empty());
+        assert!(cache.clean(None).unwrap().is_empty());
+        assert_eq!(cache.get_fresh("anything", DAY), None);
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_age("7d").unwrap(), 7 * DAY);
+        assert_eq!(parse_age("2w").unwrap(), 14 * DAY);
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("7y").is_err());
+        assert_eq!(
+            parse_age("18446744073709551615w").unwrap_err().to_string(),
+            "age too large: `18446744073709551615w`"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Check that the workspace still compiles with an upgrade, on a copy of it.
+//!
+//! The copy lives in a temporary directory, so that neither the files nor the target directory of
+//! the workspace are touched. Only the workspace root is copied, dependency and patch paths
+//! pointing outside of it are made absolute so that they still resolve from the copy.
+
+use crate::context::Context;
+use crate::{manifest, workspace};
+use anyhow::{Context as _, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+use xshell::cmd;
+
+/// Directories of the workspace root which are not copied.
+const SKIPPED: &[&str] = &[".git", "target"];
+
+/// Runs `cargo check --workspace` on a copy of the workspace, with `cargo_toml` as its root
+/// manifest and the `member_edits` written over the manifests of members.
+pub(crate) fn check(
+    ctx: &Context,
+    cargo_toml: &str,
+    member_edits: &[(String, String)],
+) -> Result<()> {
+    let sh = &ctx.sh;
+    let root = ctx.workspace_root();
+    let temp_dir = sh.create_temp_dir()?;
+    let copy = temp_dir.path().join("workspace");
+    copy_dir(&root, &copy, SKIPPED)?;
+    fs::write(copy.join("Cargo.toml"), cargo_toml)?;
+    for (path, contents) in member_edits {
+        fs::write(copy.join(path), contents)?;
+    }
+    let lockfile = ctx.lockfile();
+    if lockfile != root.join("Cargo.lock") && lockfile.is_file() {
+        fs::copy(&lockfile, copy.join("Cargo.lock"))
+            .with_context(|| format!("failed to copy {}", lockfile.display()))?;
+    }
+
+    let canonical_root = root.canonicalize()?;
+    let root_manifest = copy.join("Cargo.toml");
+    let mut members = vec![PathBuf::new()];
+    members.extend(workspace::members(&copy, &manifest::read(&root_manifest)?)?);
+    for member in members {
+        let manifest_path = copy.join(&member).join("Cargo.toml");
+        let mut manifest = fs::read_to_string(&manifest_path)?.parse::<DocumentMut>()?;
+        if anchor_outside_paths(&mut manifest, &root.join(&member), &canonical_root) {
+            fs::write(&manifest_path, manifest.to_string())?;
+        }
+    }
+
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network()?.cargo_args();
+    let target_dir = temp_dir.path().join("target");
+    ctx.phase("compat check", || {
+        let check = cmd!(sh, "{cargo} {toolchain...} check --workspace {offline...}")
+            .arg("--manifest-path")
+            .arg(&root_manifest)
+            .arg("--target-dir")
+            .arg(&target_dir);
+        ctx.run(check)
+            .context("the workspace does not compile with the upgrade")
+    })
+}
+
+/// Copies the directory `from` to `to`, except for the `skipped` entries of `from` itself.
+fn copy_dir(from: &Path, to: &Path, skipped: &[&str]) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from).with_context(|| format!("failed to read {}", from.display()))? {
+        let entry = entry?;
+        if skipped.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()), &[])?;
+        } else {
+            fs::copy(&path, to.join(entry.file_name()))
+                .with_context(|| format!("failed to copy {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the relative `path` of dependencies and patches absolute if they point outside of
+/// `root`, resolving them against `dir`, the original directory of the manifest. Returns whether
+/// any path changed.
+fn anchor_outside_paths(manifest: &mut DocumentMut, dir: &Path, root: &Path) -> bool {
+    let mut changed = false;
+    for (_, table) in workspace::dependency_tables_mut(manifest) {
+        changed |= anchor_table(table, dir, root);
+    }
+    if let Some(sources) = manifest.get_mut("patch").and_then(Item::as_table_like_mut) {
+        for (_, source) in sources.iter_mut() {
+            if let Some(table) = source.as_table_like_mut() {
+                changed |= anchor_table(table, dir, root);
+            }
+        }
+    }
+    changed
+}
+
+fn anchor_table(table: &mut dyn TableLike, dir: &Path, root: &Path) -> bool {
+    let mut changed = false;
+    for (_, dep) in table.iter_mut() {
+        let Some(path) = dep.get_mut("path").and_then(Item::as_value_mut) else {
+            continue;
+        };
+        let Some(target) = path
+            .as_str()
+            .and_then(|relative| dir.join(relative).canonicalize().ok())
+            .filter(|target| !target.starts_with(root))
+        else {
+            continue;
+        };
+        let Some(target) = target.to_str() else {
+            continue;
+        };
+        let decor = path.decor().clone();
+        *path = Value::from(target);
+        *path.decor_mut() = decor;
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::GlobalArgs;
+    use xshell::Shell;
+
+    #[test]
+    fn test_check() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        for (name, value) in [("helper", "VALUE"), ("helper-2", "RENAMED")] {
+            sh.write_file(
+                format!("{name}/Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n"),
+            )
+            .unwrap();
+            sh.write_file(
+                format!("{name}/src/lib.rs"),
+                format!("pub const {value}: u32 = 1;\n"),
+            )
+            .unwrap();
+        }
+        let cargo_toml = "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\nhelper = { path = \"../helper\" }\n";
+        sh.write_file("project/Cargo.toml", cargo_toml).unwrap();
+        sh.write_file(
+            "project/crates/foo/Cargo.toml",
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+            [dependencies]\nhelper.workspace = true\n",
+        )
+        .unwrap();
+        sh.write_file("project/crates/foo/src/lib.rs", "pub use helper::VALUE;\n")
+            .unwrap();
+        sh.change_dir("project");
+        sh.set_var("CARGO_NET_OFFLINE", "true");
+
+        let ctx = Context::with_shell(sh.clone(), &GlobalArgs::default()).unwrap();
+        check(&ctx, cargo_toml, &[]).unwrap();
+
+        let upgraded = cargo_toml.replace("../helper", "../helper-2");
+        let err = check(&ctx, &upgraded, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the workspace does not compile with the upgrade"
+        );
+
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), cargo_toml);
+        assert!(!sh.path_exists("target"));
+        assert!(!sh.path_exists("Cargo.lock"));
+    }
+}
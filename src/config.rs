@@ -0,0 +1,278 @@
+//! Read per-project configuration of xtasks from `Cargo.toml`.
+//!
+//! Configuration lives in a `[workspace.metadata.cairo-toolchain-xtasks]` table (or
+//! `[package.metadata.cairo-toolchain-xtasks]` in manifests without a `[workspace]`), with one
+//! sub-table per toolchain dependency group:
+//!
+//! ```toml
+//! [workspace.metadata.cairo-toolchain-xtasks]
+//! # Hosts which git sources in `[patch.crates-io]` may point to. Unrestricted if not set.
+//! allowed_git_hosts = ["github.com"]
+//! # Let xtasks access the network without `--confirm-network`.
+//! confirm_network = false
+//! # Directory repository-level files (like the vendored corelib) are relative to, `workspace` or
+//! # `repository`. Defaults to the workspace root, which may be nested inside the repository.
+//! files_root = "repository"
+//! # Where `sync-version` reads the Cairo version from when run without `--anchor`, also when
+//! # chained by `upgrade`.
+//! anchor = "scarb-toml"
+//!
+//! [workspace.metadata.cairo-toolchain-xtasks.cairols]
+//! # Branch used by `upgrade --branch` without a value and by the `dev` channel.
+//! branch = "release/2.9"
+//! # Channel used by `upgrade` when no source is given.
+//! channel = "dev"
+//! ```
+
+use crate::sync_version::Anchor;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use toml_edit::{DocumentMut, TableLike};
+
+const TABLE: &str = "cairo-toolchain-xtasks";
+
+/// Branch used for groups which do not configure one.
+pub(crate) const DEFAULT_BRANCH: &str = "main";
+
+/// Release channel of a toolchain dependency group.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Channel {
+    /// The latest non-prerelease version published on crates.io.
+    Stable,
+    /// The default branch of the group.
+    Dev,
+}
+
+/// Directory repository-level files are resolved against.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum FilesRoot {
+    /// The root of the Cargo workspace.
+    #[default]
+    Workspace,
+    /// The root of the git repository containing the workspace.
+    Repository,
+}
+
+/// Configuration of a single toolchain dependency group.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct GroupConfig {
+    pub branch: Option<String>,
+    pub channel: Option<Channel>,
+}
+
+impl GroupConfig {
+    /// The default branch of the group.
+    pub fn branch(&self) -> &str {
+        self.branch.as_deref().unwrap_or(DEFAULT_BRANCH)
+    }
+}
+
+/// Reads the hosts git patches may point to, or `None` if unrestricted.
+pub(crate) fn allowed_git_hosts(cargo_toml: &DocumentMut) -> Result<Option<Vec<String>>> {
+    let Some((root, config)) = table(cargo_toml) else {
+        return Ok(None);
+    };
+    let Some(item) = config.get("allowed_git_hosts") else {
+        return Ok(None);
+    };
+
+    let hosts = item.as_array().and_then(|hosts| {
+        hosts
+            .iter()
+            .map(|host| host.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+    });
+    match hosts {
+        Some(hosts) => Ok(Some(hosts)),
+        None => bail!("`{root}.metadata.{TABLE}.allowed_git_hosts` must be an array of strings"),
+    }
+}
+
+/// Reads whether network access requires `--confirm-network`, which it does unless the workspace
+/// opts out with `confirm_network = false`.
+pub(crate) fn confirm_network(cargo_toml: &DocumentMut) -> Result<bool> {
+    let Some((root, config)) = table(cargo_toml) else {
+        return Ok(true);
+    };
+    match config.get("confirm_network") {
+        None => Ok(true),
+        Some(item) => match item.as_bool() {
+            Some(confirm) => Ok(confirm),
+            None => bail!("`{root}.metadata.{TABLE}.confirm_network` must be a boolean"),
+        },
+    }
+}
+
+/// Reads the directory repository-level files are resolved against.
+pub(crate) fn files_root(cargo_toml: &DocumentMut) -> Result<FilesRoot> {
+    let Some((root, config)) = table(cargo_toml) else {
+        return Ok(FilesRoot::default());
+    };
+    let Some(item) = config.get("files_root") else {
+        return Ok(FilesRoot::default());
+    };
+    match item.as_str().map(|value| FilesRoot::from_str(value, false)) {
+        Some(Ok(files_root)) => Ok(files_root),
+        _ => bail!(
+            "`{root}.metadata.{TABLE}.files_root` must be `workspace` or `repository`, found `{}`",
+            item.to_string().trim()
+        ),
+    }
+}
+
+/// Reads where the crate version is anchored on, or `None` if not configured.
+pub(crate) fn anchor(cargo_toml: &DocumentMut) -> Result<Option<Anchor>> {
+    let Some((root, config)) = table(cargo_toml) else {
+        return Ok(None);
+    };
+    let Some(item) = config.get("anchor") else {
+        return Ok(None);
+    };
+    match item.as_str().map(|value| Anchor::from_str(value, false)) {
+        Some(Ok(anchor)) => Ok(Some(anchor)),
+        _ => bail!(
+            "`{root}.metadata.{TABLE}.anchor` must be `lockfile` or `scarb-toml`, found `{}`",
+            item.to_string().trim()
+        ),
+    }
+}
+
+/// Reads the configuration of dependency group `group`, which is empty if not configured.
+pub(crate) fn group(cargo_toml: &DocumentMut, group: &str) -> Result<GroupConfig> {
+    let Some((root, config)) = table(cargo_toml)
+        .and_then(|(root, config)| Some((root, config.get(group)?.as_table_like()?)))
+    else {
+        return Ok(GroupConfig::default());
+    };
+
+    let path = |key: &str| format!("{root}.metadata.{TABLE}.{group}.{key}");
+    let string = |config: &dyn TableLike, key: &str| match config.get(key) {
+        None => Ok(None),
+        Some(item) => match item.as_str() {
+            Some(value) => Ok(Some(value.to_owned())),
+            None => bail!("`{}` must be a string", path(key)),
+        },
+    };
+
+    let channel = match string(config, "channel")? {
+        None => None,
+        Some(channel) => match Channel::from_str(&channel, false) {
+            Ok(channel) => Some(channel),
+            Err(_) => bail!(
+                "`{}` must be `stable` or `dev`, found `{channel}`",
+                path("channel")
+            ),
+        },
+    };
+    Ok(GroupConfig {
+        branch: string(config, "branch")?,
+        channel,
+    })
+}
+
+/// Finds the configuration table, along with the name of the table it is nested in.
+fn table(cargo_toml: &DocumentMut) -> Option<(&'static str, &dyn TableLike)> {
+    ["workspace", "package"].iter().find_map(|&root| {
+        let config = cargo_toml
+            .get(root)?
+            .get("metadata")?
+            .get(TABLE)?
+            .as_table_like()?;
+        Some((root, config))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let cargo_toml = r#"
+[workspace.metadata.cairo-toolchain-xtasks.cairols]
+branch = "release/2.9"
+channel = "dev"
+
+[workspace.metadata.cairo-toolchain-xtasks.cairolint]
+channel = "nightly"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        assert_eq!(
+            group(&cargo_toml, "cairols").unwrap(),
+            GroupConfig {
+                branch: Some("release/2.9".to_owned()),
+                channel: Some(Channel::Dev),
+            }
+        );
+        assert_eq!(group(&cargo_toml, "cairo").unwrap().branch(), "main");
+        assert_eq!(files_root(&cargo_toml).unwrap(), FilesRoot::Workspace);
+        assert_eq!(
+            group(&cargo_toml, "cairolint").unwrap_err().to_string(),
+            "`workspace.metadata.cairo-toolchain-xtasks.cairolint.channel` must be `stable` or \
+             `dev`, found `nightly`"
+        );
+    }
+
+    #[test]
+    fn test_files_root() {
+        let parse = |s: &str| s.parse::<DocumentMut>().unwrap();
+        assert_eq!(
+            files_root(&parse(
+                "[package.metadata.cairo-toolchain-xtasks]\nfiles_root = \"repository\"\n"
+            ))
+            .unwrap(),
+            FilesRoot::Repository
+        );
+        assert_eq!(
+            files_root(&parse(
+                "[workspace.metadata.cairo-toolchain-xtasks]\nfiles_root = \"root\"\n"
+            ))
+            .unwrap_err()
+            .to_string(),
+            "`workspace.metadata.cairo-toolchain-xtasks.files_root` must be `workspace` or \
+             `repository`, found `\"root\"`"
+        );
+    }
+
+    #[test]
+    fn test_confirm_network() {
+        let parse = |s: &str| s.parse::<DocumentMut>().unwrap();
+        assert!(confirm_network(&parse("[workspace]\n")).unwrap());
+        assert!(!confirm_network(&parse(
+            "[workspace.metadata.cairo-toolchain-xtasks]\nconfirm_network = false\n"
+        ))
+        .unwrap());
+        assert_eq!(
+            confirm_network(&parse(
+                "[workspace.metadata.cairo-toolchain-xtasks]\nconfirm_network = \"yes\"\n"
+            ))
+            .unwrap_err()
+            .to_string(),
+            "`workspace.metadata.cairo-toolchain-xtasks.confirm_network` must be a boolean"
+        );
+    }
+
+    #[test]
+    fn test_anchor() {
+        let parse = |s: &str| s.parse::<DocumentMut>().unwrap();
+        assert_eq!(anchor(&parse("[workspace]\n")).unwrap(), None);
+        assert_eq!(
+            anchor(&parse(
+                "[workspace.metadata.cairo-toolchain-xtasks]\nanchor = \"scarb-toml\"\n"
+            ))
+            .unwrap(),
+            Some(Anchor::ScarbToml)
+        );
+        assert_eq!(
+            anchor(&parse(
+                "[workspace.metadata.cairo-toolchain-xtasks]\nanchor = \"scarb\"\n"
+            ))
+            .unwrap_err()
+            .to_string(),
+            "`workspace.metadata.cairo-toolchain-xtasks.anchor` must be `lockfile` or \
+             `scarb-toml`, found `\"scarb\"`"
+        );
+    }
+}
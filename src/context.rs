@@ -0,0 +1,687 @@
+//! State shared by all steps of a single xtask invocation.
+//!
+//! Xtasks operate on the workspace root, which is found from the starting directory the way Cargo
+//! finds it. This need not be the root of the git repository: in monorepos, the Rust workspace may
+//! live in a subdirectory.
+
+use crate::config::{self, FilesRoot};
+use crate::http::Network;
+use crate::{diff, manifest, workspace};
+use anyhow::{bail, ensure, Result};
+use std::cell::{OnceCell, RefCell};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use toml_edit::DocumentMut;
+use xshell::{cmd, Cmd, Shell};
+
+/// Arguments accepted by all xtasks operating on a project.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct GlobalArgs {
+    /// Run as if started in `<DIR>` instead of the current working directory.
+    ///
+    /// Paths given in other arguments are still resolved relative to the current working directory.
+    #[arg(short = 'C', value_name = "DIR", global = true)]
+    pub directory: Option<PathBuf>,
+
+    /// Run on the workspace of the `Cargo.toml` at `<PATH>`, as with `-C` and its directory.
+    ///
+    /// Like with Cargo's own `--manifest-path`, a manifest of a workspace member stands for the
+    /// whole workspace.
+    #[arg(long, value_name = "PATH", global = true, conflicts_with = "directory")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Print how long each phase of the run took when it finishes.
+    ///
+    /// The durations are also listed as `timings` in the JSON report of `upgrade`.
+    #[arg(long, default_value_t = false, global = true)]
+    pub timings: bool,
+
+    /// Read the `Cargo.lock` at `<FILE>` instead of the one in the workspace root.
+    ///
+    /// Use this in monorepos which keep the lockfile elsewhere than the edited manifest.
+    #[arg(long, value_name = "FILE", global = true)]
+    pub lockfile_path: Option<PathBuf>,
+
+    /// Allow xtasks to access the network, which they refuse by default unless the workspace sets
+    /// `confirm_network = false` in `[workspace.metadata.cairo-toolchain-xtasks]`.
+    ///
+    /// This covers fetching crate lists and files of toolchain repositories, crates.io index
+    /// lookups and `git ls-remote`. Downloads of Cargo itself are controlled by its `--offline`
+    /// setting instead.
+    #[arg(long, default_value_t = false, global = true)]
+    pub confirm_network: bool,
+
+    /// Cargo binary to run Cargo commands with, like `cargo fetch`.
+    ///
+    /// Defaults to the `CARGO` environment variable, which Cargo sets for `cargo xtask`, or to
+    /// `cargo` from `PATH`.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub cargo: Option<PathBuf>,
+
+    /// Rust toolchain to run Cargo commands with, passed as `cargo +<NAME>`.
+    ///
+    /// This is understood by the rustup proxy only, so Cargo is run from `PATH` instead of the
+    /// `CARGO` environment variable, and `--cargo` is rejected unless it names a proxy in `PATH`.
+    #[arg(long, value_name = "NAME", global = true)]
+    pub rust_toolchain: Option<String>,
+}
+
+/// A completed phase of the run.
+#[derive(Clone)]
+pub(crate) struct Phase {
+    pub name: String,
+    pub duration: Duration,
+    /// Whether this is a task run concurrently with others as part of the preceding phase.
+    pub nested: bool,
+}
+
+/// Format of data printed to stdout.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// No data, only human-readable diagnostics on stderr.
+    #[default]
+    Text,
+    /// A single JSON document on stdout. Diagnostics still go to stderr.
+    Json,
+}
+
+/// State shared by all steps of a single xtask invocation.
+pub(crate) struct Context {
+    /// Shell operating in the workspace root.
+    pub sh: Shell,
+    /// Root of the git repository containing the workspace, resolved on first use.
+    repo_root: OnceCell<PathBuf>,
+    /// Working directory the xtask was started in.
+    cwd: PathBuf,
+    /// Whether the workspace was selected with `-C` or `--manifest-path`.
+    relocated: bool,
+    /// `Cargo.lock` given with `--lockfile-path`, resolved against [`Self::cwd`].
+    lockfile: Option<PathBuf>,
+    /// Whether `--confirm-network` was given.
+    confirm_network: bool,
+    /// Cargo binary given with `--cargo`.
+    cargo: Option<PathBuf>,
+    /// Cargo binary from the `CARGO` environment variable.
+    env_cargo: Option<OsString>,
+    /// Toolchain given with `--rust-toolchain`, as the `+<NAME>` argument selecting it.
+    rust_toolchain: Option<String>,
+    /// Commands that would have been run, if running them is disabled.
+    recorded: Option<RefCell<Vec<String>>>,
+    /// Data and diagnostics printed so far, captured along with recorded commands.
+    captured: RefCell<(String, Vec<String>)>,
+    /// Input read instead of stdin.
+    stdin: Option<String>,
+    /// Phases of the run completed so far, with their durations.
+    phases: RefCell<Vec<Phase>>,
+    /// Whether to print the phase durations when the context is dropped.
+    timings: bool,
+    /// Whether to print the name of each phase as it starts.
+    progress: bool,
+    /// Whether to color diffs, when stderr is a terminal and `NO_COLOR` is not set.
+    color: bool,
+}
+
+impl Context {
+    pub fn new(args: &GlobalArgs) -> Result<Self> {
+        Self::with_shell(Shell::new()?, args)
+    }
+
+    /// Creates a context on top of an existing shell, entering the root of the workspace
+    /// containing the `-C` directory or the `--manifest-path`, or the current one.
+    pub fn with_shell(sh: Shell, args: &GlobalArgs) -> Result<Self> {
+        let cwd = sh.current_dir();
+        let mut start = cwd.clone();
+        if let Some(directory) = &args.directory {
+            start = cwd.join(directory);
+            ensure!(
+                start.is_dir(),
+                "directory does not exist: {}",
+                start.display()
+            );
+        }
+        if let Some(manifest_path) = &args.manifest_path {
+            let manifest_path = cwd.join(manifest_path);
+            ensure!(
+                manifest_path.file_name() == Some(OsStr::new("Cargo.toml")),
+                "--manifest-path must point to a Cargo.toml file, not {}",
+                manifest_path.display()
+            );
+            ensure!(
+                manifest_path.is_file(),
+                "manifest does not exist: {}",
+                manifest_path.display()
+            );
+            start = manifest_path
+                .parent()
+                .expect("it has a file name")
+                .to_path_buf();
+        }
+        if let Some(toolchain) = &args.rust_toolchain {
+            ensure!(
+                !toolchain.is_empty() && !toolchain.starts_with('+'),
+                "invalid --rust-toolchain `{toolchain}`, expected a toolchain name like `nightly`"
+            );
+            if let Some(cargo) = args
+                .cargo
+                .as_ref()
+                .filter(|cargo| cargo.components().count() > 1)
+            {
+                bail!(
+                    "--rust-toolchain cannot be used with --cargo {}, `+{toolchain}` is only \
+                    understood by the rustup proxy, pass the path of the toolchain's Cargo alone",
+                    cargo.display()
+                );
+            }
+        }
+        sh.change_dir(workspace::find_root(&start));
+        Ok(Self {
+            sh,
+            repo_root: OnceCell::new(),
+            relocated: args.directory.is_some() || args.manifest_path.is_some(),
+            lockfile: args.lockfile_path.as_ref().map(|path| cwd.join(path)),
+            confirm_network: args.confirm_network,
+            cargo: args.cargo.as_ref().map(|cargo| cli_program(&cwd, cargo)),
+            env_cargo: std::env::var_os("CARGO"),
+            rust_toolchain: args
+                .rust_toolchain
+                .as_ref()
+                .map(|toolchain| format!("+{toolchain}")),
+            cwd,
+            recorded: None,
+            captured: RefCell::default(),
+            stdin: None,
+            phases: RefCell::default(),
+            timings: args.timings,
+            progress: std::io::stderr().is_terminal(),
+            color: std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        })
+    }
+
+    /// Creates a context which records commands instead of running them.
+    #[cfg(test)]
+    pub fn recording(sh: Shell) -> Self {
+        Self::with_shell(sh, &GlobalArgs::default())
+            .unwrap()
+            .record()
+    }
+
+    /// Makes this context record commands instead of running them.
+    #[cfg(test)]
+    pub fn record(mut self) -> Self {
+        self.recorded = Some(RefCell::default());
+        self.color = false;
+        // Set by `cargo test`, which would make recorded commands depend on the toolchain.
+        self.env_cargo = None;
+        self
+    }
+
+    /// Makes this context read `input` instead of stdin.
+    #[cfg(test)]
+    pub fn with_stdin(mut self, input: &str) -> Self {
+        self.stdin = Some(input.to_owned());
+        self
+    }
+
+    /// Reads all of stdin.
+    pub fn read_stdin(&self) -> Result<String> {
+        if let Some(input) = &self.stdin {
+            return Ok(input.clone());
+        }
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        Ok(input)
+    }
+
+    /// Cargo binary to run Cargo commands with, see [`GlobalArgs::cargo`].
+    ///
+    /// The `CARGO` environment variable points at the binary of a specific toolchain, so it is
+    /// ignored if another toolchain is requested.
+    pub fn cargo(&self) -> &OsStr {
+        match (&self.cargo, &self.env_cargo) {
+            (Some(cargo), _) => cargo.as_os_str(),
+            (None, Some(cargo)) if self.rust_toolchain.is_none() => cargo,
+            (None, _) => OsStr::new("cargo"),
+        }
+    }
+
+    /// The `+<NAME>` argument selecting the toolchain given with `--rust-toolchain`, to follow
+    /// [`Self::cargo`] in Cargo commands.
+    pub fn rust_toolchain(&self) -> Option<&str> {
+        self.rust_toolchain.as_deref()
+    }
+
+    /// Global arguments selecting the same workspace, lockfile and network access, to pass on to
+    /// xtasks chained by this one.
+    ///
+    /// Arguments which were not given are left out, and paths are passed as absolute ones.
+    pub fn forwarded_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if self.relocated {
+            args.push("--manifest-path".into());
+            args.push(self.workspace_root().join("Cargo.toml").into());
+        }
+        if let Some(lockfile) = &self.lockfile {
+            args.push("--lockfile-path".into());
+            args.push(lockfile.into());
+        }
+        if self.confirm_network {
+            args.push("--confirm-network".into());
+        }
+        args
+    }
+
+    /// Resolves a path given on the command line against the original working directory.
+    pub fn cli_path(&self, path: &Path) -> PathBuf {
+        self.cwd.join(path)
+    }
+
+    /// Root of the Cargo workspace, which is also the working directory of [`Self::sh`].
+    pub fn workspace_root(&self) -> PathBuf {
+        self.sh.current_dir()
+    }
+
+    /// Path of `Cargo.lock`, which is in the workspace root unless `--lockfile-path` is given.
+    pub fn lockfile(&self) -> PathBuf {
+        match &self.lockfile {
+            Some(lockfile) => lockfile.clone(),
+            None => self.workspace_root().join("Cargo.lock"),
+        }
+    }
+
+    /// Root of the git repository containing the workspace, as reported by
+    /// `git rev-parse --show-toplevel`.
+    ///
+    /// Falls back to the workspace root if it is not in a git repository.
+    pub fn repo_root(&self) -> PathBuf {
+        self.repo_root
+            .get_or_init(|| {
+                let sh = &self.sh;
+                cmd!(sh, "git rev-parse --show-toplevel")
+                    .quiet()
+                    .ignore_stderr()
+                    .read()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| self.workspace_root())
+            })
+            .clone()
+    }
+
+    /// Directory repository-level files, like the vendored corelib, are resolved against.
+    ///
+    /// This is the workspace root, unless the workspace configures `files_root = "repository"`.
+    pub fn files_root(&self) -> Result<PathBuf> {
+        let cargo_toml = self.workspace_root().join("Cargo.toml");
+        if !cargo_toml.is_file() {
+            return Ok(self.workspace_root());
+        }
+        Ok(match config::files_root(&manifest::read(&cargo_toml)?)? {
+            FilesRoot::Workspace => self.workspace_root(),
+            FilesRoot::Repository => self.repo_root(),
+        })
+    }
+
+    /// Whether xtasks may access the network, which needs `--confirm-network` unless the workspace
+    /// configures `confirm_network = false`.
+    pub fn network(&self) -> Result<Network> {
+        let cargo_toml = self.workspace_root().join("Cargo.toml");
+        if self.confirm_network {
+            return Ok(Network::Allowed);
+        }
+        if !cargo_toml.is_file() {
+            return Ok(Network::Unconfirmed);
+        }
+        self.network_of(&manifest::read(&cargo_toml)?)
+    }
+
+    /// Like [`Self::network`], for the already parsed root manifest.
+    pub(crate) fn network_of(&self, cargo_toml: &DocumentMut) -> Result<Network> {
+        Ok(
+            match config::confirm_network(cargo_toml)? && !self.confirm_network {
+                true => Network::Unconfirmed,
+                false => Network::Allowed,
+            },
+        )
+    }
+
+    /// Runs a command, or records it if running commands is disabled.
+    pub fn run(&self, cmd: Cmd<'_>) -> Result<()> {
+        match &self.recorded {
+            Some(recorded) => recorded.borrow_mut().push(cmd.to_string()),
+            None => cmd.run()?,
+        }
+        Ok(())
+    }
+
+    /// Prints a warning to stderr.
+    pub fn warn(&self, message: impl Display) {
+        self.diagnostic(format!("warn: {message}"));
+    }
+
+    /// Prints an error to stderr, for failures which do not stop the run.
+    pub fn error(&self, message: impl Display) {
+        self.diagnostic(format!("error: {message}"));
+    }
+
+    /// Prints a note to stderr.
+    pub fn note(&self, message: impl Display) {
+        self.diagnostic(format!("note: {message}"));
+    }
+
+    /// Prints a line of the human-readable summary of a run to stderr.
+    pub fn summary(&self, line: impl Display) {
+        self.diagnostic(line.to_string());
+    }
+
+    fn diagnostic(&self, line: String) {
+        match &self.recorded {
+            Some(_) => self.captured.borrow_mut().1.push(line),
+            None => eprintln!("{line}"),
+        }
+    }
+
+    /// Prints a command that a real run would execute, for dry runs.
+    pub fn would_run(&self, cmd: Cmd<'_>) {
+        self.diagnostic(format!("would run: {cmd}"));
+    }
+
+    /// Prints a unified diff of the file at `path` from `old` to `new` contents to stderr, for dry
+    /// runs. Nothing is printed if they are equal.
+    pub fn diff(&self, path: &str, old: &str, new: &str) {
+        for line in diff::unified(path, old, new) {
+            match self.color {
+                true => self.diagnostic(diff::colorize(&line)),
+                false => self.diagnostic(line),
+            }
+        }
+    }
+
+    /// Prints data to stdout, which is reserved for it so that it stays machine-readable.
+    pub fn output(&self, data: &str) {
+        match &self.recorded {
+            Some(_) => self.captured.borrow_mut().0.push_str(data),
+            None => print!("{data}"),
+        }
+    }
+
+    /// Runs `f` as a named phase of the run, measuring how long it takes.
+    ///
+    /// The phase name is printed as a progress line when stderr is a terminal.
+    pub fn phase<T>(&self, name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if self.progress {
+            eprintln!("==> {name}");
+        }
+        let start = Instant::now();
+        let result = f();
+        self.phases.borrow_mut().push(Phase {
+            name: name.to_owned(),
+            duration: start.elapsed(),
+            nested: false,
+        });
+        result
+    }
+
+    /// Runs `f` on all `items` as a named phase, on up to `jobs` threads at once.
+    ///
+    /// Results are returned in the order of `items`. The duration of each item is recorded under
+    /// its `label`, so that timings show the gain over running them one after another. With a
+    /// single job, all items are processed on the current thread.
+    pub fn parallel_phase<T: Sync, R: Send>(
+        &self,
+        name: &str,
+        jobs: usize,
+        items: &[T],
+        label: impl Fn(&T) -> String,
+        f: impl Fn(&T) -> R + Sync,
+    ) -> Vec<R> {
+        let timed = |item: &T| {
+            let start = Instant::now();
+            let result = f(item);
+            (result, start.elapsed())
+        };
+
+        let results = self
+            .phase(name, || {
+                if jobs <= 1 || items.len() <= 1 {
+                    return Ok(items.iter().map(timed).collect::<Vec<_>>());
+                }
+                let next = AtomicUsize::new(0);
+                let results = Mutex::new(items.iter().map(|_| None).collect::<Vec<_>>());
+                thread::scope(|scope| {
+                    for _ in 0..jobs.min(items.len()) {
+                        scope.spawn(|| loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(item) = items.get(i) else {
+                                break;
+                            };
+                            let result = timed(item);
+                            results.lock().unwrap()[i] = Some(result);
+                        });
+                    }
+                });
+                Ok(results
+                    .into_inner()
+                    .unwrap()
+                    .into_iter()
+                    .map(Option::unwrap)
+                    .collect())
+            })
+            .unwrap();
+
+        let mut phases = self.phases.borrow_mut();
+        results
+            .into_iter()
+            .zip(items)
+            .map(|((result, duration), item)| {
+                phases.push(Phase {
+                    name: label(item),
+                    duration,
+                    nested: true,
+                });
+                result
+            })
+            .collect()
+    }
+
+    /// Phases completed so far, if `--timings` was given, e.g. to include them in reports.
+    pub(crate) fn timings(&self) -> Option<Vec<Phase>> {
+        self.timings.then(|| self.phases.borrow().clone())
+    }
+
+    /// Data printed to stdout and diagnostic lines printed to stderr so far, when recording.
+    #[cfg(test)]
+    pub fn captured(&self) -> (String, Vec<String>) {
+        self.captured.borrow().clone()
+    }
+
+    /// Commands recorded so far.
+    #[cfg(test)]
+    pub fn recorded(&self) -> Vec<String> {
+        self.recorded
+            .as_ref()
+            .map(|recorded| recorded.borrow().clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        if self.timings {
+            eprint!("{}", format_timings(&self.phases.borrow()));
+        }
+    }
+}
+
+/// Resolves a program given on the command line, which is looked up in `PATH` if it is a bare
+/// name like `cargo`, and relative to `cwd` otherwise.
+fn cli_program(cwd: &Path, program: &Path) -> PathBuf {
+    if program.components().count() > 1 {
+        cwd.join(program)
+    } else {
+        program.to_path_buf()
+    }
+}
+
+/// Formats phase durations as a table, followed by the total.
+///
+/// Tasks run concurrently within a phase are listed indented below it, and are not counted
+/// towards the total again.
+fn format_timings(phases: &[Phase]) -> String {
+    let total = phases
+        .iter()
+        .filter(|phase| !phase.nested)
+        .map(|phase| phase.duration)
+        .sum::<Duration>();
+    let rows = phases
+        .iter()
+        .map(|phase| {
+            let indent = if phase.nested { "  " } else { "" };
+            (format!("{indent}{}", phase.name), phase.duration)
+        })
+        .chain([("total".to_owned(), total)])
+        .collect::<Vec<_>>();
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["phase".len()])
+        .max()
+        .unwrap();
+
+    let mut table = format!("{:width$}  {:>8}\n", "phase", "time");
+    for (name, duration) in rows {
+        table.push_str(&format!(
+            "{name:width$}  {:>7.2}s\n",
+            duration.as_secs_f64()
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_workspace() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let repo = temp_dir.path().canonicalize().unwrap();
+        sh.write_file(
+            repo.join("rust/Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        sh.write_file(repo.join("rust/crates/a/Cargo.toml"), "[package]\n")
+            .unwrap();
+        sh.change_dir(&repo);
+        cmd!(sh, "git init -q").run().unwrap();
+
+        sh.change_dir(repo.join("rust/crates/a"));
+        let ctx = Context::with_shell(sh.clone(), &GlobalArgs::default()).unwrap();
+        assert_eq!(ctx.workspace_root(), repo.join("rust"));
+        assert_eq!(ctx.repo_root(), repo);
+        assert_eq!(ctx.files_root().unwrap(), repo.join("rust"));
+        assert_eq!(ctx.cli_path(Path::new("x")), repo.join("rust/crates/a/x"));
+
+        sh.write_file(
+            repo.join("rust/Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.metadata.cairo-toolchain-xtasks]\nfiles_root = \"repository\"\n",
+        )
+        .unwrap();
+        sh.change_dir(&repo);
+        let args = GlobalArgs {
+            directory: Some("rust/crates".into()),
+            ..Default::default()
+        };
+        let ctx = Context::with_shell(sh.clone(), &args).unwrap();
+        assert_eq!(ctx.workspace_root(), repo.join("rust"));
+        assert_eq!(ctx.files_root().unwrap(), repo);
+
+        let with_manifest = |path: &str| {
+            let args = GlobalArgs {
+                manifest_path: Some(path.into()),
+                ..Default::default()
+            };
+            Context::with_shell(sh.clone(), &args)
+        };
+        for path in ["rust/Cargo.toml", "rust/crates/a/Cargo.toml"] {
+            let ctx = with_manifest(path).unwrap();
+            assert_eq!(ctx.workspace_root(), repo.join("rust"));
+            assert_eq!(ctx.cli_path(Path::new("x")), repo.join("x"));
+        }
+        let err = |path: &str| with_manifest(path).err().unwrap().to_string();
+        assert_eq!(
+            err("rust"),
+            format!(
+                "--manifest-path must point to a Cargo.toml file, not {}",
+                repo.join("rust").display()
+            )
+        );
+        assert_eq!(
+            err("rust/crates/b/Cargo.toml"),
+            format!(
+                "manifest does not exist: {}",
+                repo.join("rust/crates/b/Cargo.toml").display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_timings() {
+        let phase = |name: &str, millis, nested| Phase {
+            name: name.to_owned(),
+            duration: Duration::from_millis(millis),
+            nested,
+        };
+        let phases = [
+            phase("resolve", 120, false),
+            phase("cairo", 110, true),
+            phase("cairols", 40, true),
+            phase("cargo fetch", 3400, false),
+        ];
+        assert_eq!(
+            format_timings(&phases),
+            "\
+phase            time
+resolve         0.12s
+  cairo         0.11s
+  cairols       0.04s
+cargo fetch     3.40s
+total           3.52s
+"
+        );
+    }
+
+    #[test]
+    fn test_parallel_phase() {
+        let ctx = Context::recording(Shell::new().unwrap());
+        let items = [3, 1, 2];
+        for jobs in [1, 2, 8] {
+            let results = ctx.parallel_phase(
+                "square",
+                jobs,
+                &items,
+                |item| item.to_string(),
+                |item| {
+                    thread::sleep(Duration::from_millis(*item * 5));
+                    item * item
+                },
+            );
+            assert_eq!(results, [9, 1, 4]);
+        }
+        let phases = ctx.phases.borrow();
+        assert_eq!(
+            phases
+                .iter()
+                .map(|p| (p.name.as_str(), p.nested))
+                .collect::<Vec<_>>()[..4],
+            [("square", false), ("3", true), ("1", true), ("2", true)]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Render unified diffs of edited files, for dry runs.
+//!
+//! Files compared here are manifests of at most a few hundred lines, so lines are matched by a
+//! plain longest common subsequence, which is quadratic but yields the shortest diff.
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Renders the lines of a unified diff of `path` from `old` to `new`, none if they are equal.
+///
+/// Differences only in the final newline are not shown.
+pub(crate) fn unified(path: &str, old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let ops = diff_lines(&old, &new);
+
+    // Lines of both files preceding each operation.
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in &ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            Op::Equal => (old_pos, new_pos) = (old_pos + 1, new_pos + 1),
+            Op::Delete => old_pos += 1,
+            Op::Insert => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut hunks = Vec::<(usize, usize)>::new();
+    for (i, _) in ops.iter().enumerate().filter(|(_, op)| **op != Op::Equal) {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + 1 + CONTEXT).min(ops.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lines = vec![format!("--- a/{path}"), format!("+++ b/{path}")];
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        lines.push(format!(
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        ));
+        for (op, (old_pos, new_pos)) in ops[start..end].iter().zip(&positions[start..end]) {
+            lines.push(match op {
+                Op::Equal => format!(" {}", old[*old_pos]),
+                Op::Delete => format!("-{}", old[*old_pos]),
+                Op::Insert => format!("+{}", new[*new_pos]),
+            });
+        }
+    }
+    lines
+}
+
+/// Formats the range of a hunk header, starting after `start` lines of the file.
+///
+/// Empty ranges are numbered by the line they follow, and the length of single lines is omitted,
+/// as in the output of `diff -u`.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// Finds the shortest edit of `old` into `new`, preferring deletions before insertions.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    // `lcs[i * width + j]` is the length of the longest common subsequence of `old[i..]` and
+    // `new[j..]`.
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = match old[i] == new[j] {
+                true => lcs[(i + 1) * width + j + 1] + 1,
+                false => lcs[(i + 1) * width + j].max(lcs[i * width + j + 1]),
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Equal);
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len()
+            || i < old.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]
+        {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Colors a line of a unified diff with ANSI escapes, for terminals.
+pub(crate) fn colorize(line: &str) -> String {
+    let color = if line.starts_with("--- ") || line.starts_with("+++ ") {
+        "1"
+    } else if line.starts_with('@') {
+        "36"
+    } else if line.starts_with('-') {
+        "31"
+    } else if line.starts_with('+') {
+        "32"
+    } else {
+        return line.to_owned();
+    };
+    format!("\x1b[{color}m{line}\x1b[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified() {
+        let old = (1..=12).map(|n| format!("line {n}\n")).collect::<String>();
+        assert!(unified("Cargo.toml", &old, &old).is_empty());
+
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 5\n", "")
+            .replace("line 12\n", "line 12\nline 13\n");
+        assert_eq!(
+            unified("Cargo.toml", &old, &new),
+            [
+                "--- a/Cargo.toml",
+                "+++ b/Cargo.toml",
+                "@@ -1,8 +1,7 @@",
+                " line 1",
+                "-line 2",
+                "+line two",
+                " line 3",
+                " line 4",
+                "-line 5",
+                " line 6",
+                " line 7",
+                " line 8",
+                "@@ -10,3 +9,4 @@",
+                " line 10",
+                " line 11",
+                " line 12",
+                "+line 13",
+            ]
+        );
+
+        assert_eq!(
+            unified("Cargo.toml", "", "[dependencies]\n"),
+            [
+                "--- a/Cargo.toml",
+                "+++ b/Cargo.toml",
+                "@@ -0,0 +1 @@",
+                "+[dependencies]"
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Print the patches Cargo reports as unused, as `upgrade` would purge them.
+
+use crate::context::{Context, Format, GlobalArgs};
+use crate::lockfile;
+use anyhow::{Context as _, Result};
+use clap::Parser;
+use serde_json::json;
+use toml_edit::DocumentMut;
+use xshell::cmd;
+
+/// Print the names of `[patch.crates-io]` entries that Cargo does not use.
+///
+/// Cargo records them in the `[[patch.unused]]` array of `Cargo.lock` when resolving, so the
+/// lockfile is refreshed with `cargo fetch` first. Nothing else is modified.
+#[derive(Parser)]
+pub struct Args {
+    /// Read `Cargo.lock` as it is, without running `cargo fetch`.
+    #[arg(long, default_value_t = false)]
+    no_fetch: bool,
+
+    /// Format of the list printed to stdout.
+    ///
+    /// With `text`, one crate name per line. With `json`, an object with an `unused_patches`
+    /// array.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, &args)
+}
+
+fn run(ctx: &Context, args: &Args) -> Result<()> {
+    let sh = &ctx.sh;
+    if !args.no_fetch {
+        let cargo = ctx.cargo();
+        let toolchain = ctx.rust_toolchain();
+        let offline = ctx.network()?.cargo_args();
+        ctx.phase("cargo fetch", || {
+            ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"))
+        })?;
+    }
+
+    let lockfile = ctx.lockfile();
+    let cargo_lock = sh
+        .read_file(&lockfile)?
+        .parse::<DocumentMut>()
+        .with_context(|| format!("failed to parse {}", lockfile.display()))?;
+    let unused = lockfile::unused_patches(&cargo_lock);
+    match args.format {
+        Format::Text => ctx.output(
+            &unused
+                .iter()
+                .map(|name| format!("{name}\n"))
+                .collect::<String>(),
+        ),
+        Format::Json => ctx.output(&format!("{:#}\n", json!({ "unused_patches": unused }))),
+    }
+    if unused.is_empty() {
+        ctx.note("Cargo uses all patches");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    #[test]
+    fn test_lists_unused_patches() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.toml", "[workspace]\n").unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"foo\"\nversion = \"0.1.0\"\n\n\
+            [[patch.unused]]\nname = \"cairo-lang-lowering\"\nversion = \"2.9.2\"\n\
+            source = \"git+https://github.com/starkware-libs/cairo?rev=0b3bfdc#0b3bfdc\"\n\n\
+            [[patch.unused]]\nname = \"cairo-lint\"\nversion = \"2.12.0\"\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, &Args::parse_from(["dump-unused-patches"])).unwrap();
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+        let (stdout, stderr) = ctx.captured();
+        assert_eq!(stdout, "cairo-lang-lowering\ncairo-lint\n");
+        assert!(stderr.is_empty());
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["dump-unused-patches", "--no-fetch", "--format", "json"]);
+        run(&ctx, &args).unwrap();
+        assert!(ctx.recorded().is_empty());
+        let (stdout, _) = ctx.captured();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&stdout).unwrap(),
+            json!({ "unused_patches": ["cairo-lang-lowering", "cairo-lint"] })
+        );
+
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let ctx = Context::recording(sh.clone());
+        run(
+            &ctx,
+            &Args::parse_from(["dump-unused-patches", "--no-fetch"]),
+        )
+        .unwrap();
+        assert_eq!(
+            ctx.captured(),
+            (
+                String::new(),
+                vec!["note: Cargo uses all patches".to_owned()]
+            )
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Preserve line endings of files edited through `toml_edit`, which always emits `\n`.
+
+/// Line ending convention of a text file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Detects the dominant line ending of `text`, defaulting to `\n`.
+    pub fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    /// Converts all line endings in `text` to this convention.
+    pub fn apply(self, text: &str) -> String {
+        let normalized = text.replace("\r\n", "\n");
+        match self {
+            Self::Lf => normalized,
+            Self::CrLf => normalized.replace('\n', "\r\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(LineEnding::detect(""), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\nb\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\r\nb\nc\n"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(LineEnding::CrLf.apply("a\nb\r\n"), "a\r\nb\r\n");
+        assert_eq!(LineEnding::Lf.apply("a\nb\r\n"), "a\nb\n");
+    }
+}
@@ -0,0 +1,464 @@
+//! Resolve git revisions given on the command line, abstracted so that remotes can be replaced in
+//! tests.
+
+use crate::http::Network;
+use anyhow::{bail, Result};
+use xshell::{cmd, Shell};
+
+/// Lists references of remote repositories.
+///
+/// Listings are shared by threads looking up several dependency groups at once.
+pub(crate) trait Remote: Sync {
+    /// Lists `<sha>\t<ref>` lines of the references of `repo` matching any of `patterns`, in
+    /// the format of `git ls-remote`.
+    fn ls_remote(&self, repo: &str, patterns: &[String]) -> Result<String>;
+}
+
+/// [`Remote`] implementation shelling out to `git`.
+pub(crate) struct LsRemote {
+    pub network: Network,
+}
+
+impl Remote for LsRemote {
+    fn ls_remote(&self, repo: &str, patterns: &[String]) -> Result<String> {
+        self.network
+            .ensure_allowed(format_args!("list references of {repo}"))?;
+        let sh = Shell::new()?;
+        Ok(cmd!(sh, "git ls-remote {repo} {patterns...}")
+            .quiet()
+            .read()?)
+    }
+}
+
+/// Form of a revision given with `--rev`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RevKind {
+    /// A full, 40 character commit hash.
+    FullSha,
+    /// An abbreviated commit hash.
+    ShortSha,
+    /// A name which has yet to be looked up among both the tags and branches.
+    Name,
+    /// A tag name, like `v2.9.2`.
+    Tag,
+    /// A branch name, like `main`.
+    Branch,
+    /// A fully-qualified reference, like `refs/tags/v2.9.2`.
+    Ref,
+}
+
+/// A revision given with `--rev`, along with the commit it points to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rev {
+    /// The revision as given by the user.
+    pub requested: String,
+    pub kind: RevKind,
+    /// The full hash of the commit, unless it is only known by an abbreviated hash.
+    pub sha: Option<String>,
+}
+
+impl RevKind {
+    /// Classifies `rev` by its form only, leaving names to [`resolve`] to tell tags from branches.
+    ///
+    /// Hexadecimal strings of at least 7 characters are taken for commit hashes, even though they
+    /// may also name a tag.
+    pub fn of(rev: &str) -> Self {
+        let hex = rev.chars().all(|c| c.is_ascii_hexdigit());
+        if hex && rev.len() == 40 {
+            Self::FullSha
+        } else if hex && rev.len() >= 7 {
+            Self::ShortSha
+        } else if rev.starts_with("refs/") {
+            Self::Ref
+        } else {
+            Self::Name
+        }
+    }
+}
+
+/// Resolves `rev` to a commit of `repo`, failing if it names a reference `repo` does not have.
+///
+/// Commit hashes are taken as they are, since remotes cannot be asked for arbitrary commits.
+/// Annotated tags resolve to the commit they point to, not the tag object. Names are looked up as
+/// tags first and then as branches, like `git rev-parse` does.
+pub(crate) fn resolve(remote: &dyn Remote, repo: &str, rev: &str) -> Result<Rev> {
+    let kind = RevKind::of(rev);
+    let reference = match kind {
+        RevKind::FullSha => {
+            return Ok(Rev {
+                requested: rev.to_owned(),
+                kind,
+                sha: Some(rev.to_lowercase()),
+            })
+        }
+        RevKind::ShortSha => {
+            return Ok(Rev {
+                requested: rev.to_owned(),
+                kind,
+                sha: None,
+            })
+        }
+        RevKind::Name => format!("refs/tags/{rev}"),
+        RevKind::Tag | RevKind::Branch | RevKind::Ref => rev.to_owned(),
+    };
+
+    let peeled = format!("{reference}^{{}}");
+    let mut wanted = vec![(RevKind::Tag, peeled), (RevKind::Tag, reference)];
+    if kind == RevKind::Name {
+        wanted.push((RevKind::Branch, format!("refs/heads/{rev}")));
+    }
+    let patterns = wanted
+        .iter()
+        .map(|(_, reference)| reference.clone())
+        .collect::<Vec<_>>();
+    let listing = remote.ls_remote(repo, &patterns)?;
+    let refs = listing
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect::<Vec<_>>();
+    let found = wanted.iter().find_map(|(found_kind, wanted)| {
+        let (sha, _) = refs.iter().find(|(_, name)| name == wanted)?;
+        Some((*found_kind, sha.to_string()))
+    });
+    match found {
+        Some((found_kind, sha)) => Ok(Rev {
+            requested: rev.to_owned(),
+            kind: if kind == RevKind::Name {
+                found_kind
+            } else {
+                kind
+            },
+            sha: Some(sha),
+        }),
+        None if kind == RevKind::Name => bail!(
+            "{repo} has no tag or branch `{rev}`{}",
+            suggest(&close_matches(remote, repo, "refs/tags/", rev).unwrap_or_default())
+        ),
+        None => bail!("{repo} has no reference `{rev}`"),
+    }
+}
+
+/// Looks up a full or abbreviated commit hash among the commits the branches and tags of `repo`
+/// point to, returning the full hash.
+///
+/// Other references, like `refs/pull/*`, are not considered, and annotated tags count by the
+/// commit they point to rather than by the tag object. Fails if no branch or tag points to the
+/// commit, even if it is an older one, or if an abbreviated hash matches several commits.
+pub(crate) fn find_commit(remote: &dyn Remote, repo: &str, sha: &str) -> Result<String> {
+    let sha = sha.to_lowercase();
+    let listing = remote.ls_remote(repo, &[])?;
+    let refs = listing
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect::<Vec<_>>();
+    // Annotated tags are listed once with the tag object, and once peeled with the commit.
+    let is_peeled = |name: &str| {
+        refs.iter()
+            .any(|(_, peeled)| *peeled == format!("{name}^{{}}"))
+    };
+    let mut matches = refs
+        .iter()
+        .filter(|(_, name)| {
+            name.starts_with("refs/heads/")
+                || name.starts_with("refs/tags/") && (name.ends_with("^{}") || !is_peeled(name))
+        })
+        .map(|(commit, _)| *commit)
+        .filter(|commit| commit.starts_with(&sha))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.dedup();
+    match matches.as_slice() {
+        [] => bail!("rev {sha} not found in {repo}, no branch or tag points to it"),
+        [commit] => Ok(commit.to_string()),
+        commits => bail!(
+            "commit {sha} is ambiguous in {repo}, it abbreviates {}",
+            commits.join(", ")
+        ),
+    }
+}
+
+/// Fails if `repo` has no branch `branch`, suggesting branches with similar names.
+pub(crate) fn ensure_branch(remote: &dyn Remote, repo: &str, branch: &str) -> Result<()> {
+    branch_head(remote, repo, branch).map(drop)
+}
+
+/// Finds the full hash of the commit at the tip of branch `branch` of `repo`, failing like
+/// [`ensure_branch`] if there is no such branch.
+pub(crate) fn branch_head(remote: &dyn Remote, repo: &str, branch: &str) -> Result<String> {
+    match branch_tip(remote, repo, branch)? {
+        Some(sha) => Ok(sha),
+        None => missing_branch(remote, repo, branch),
+    }
+}
+
+/// Finds the full hash of the commit at the tip of branch `branch` of `repo`, none if there is no
+/// such branch.
+///
+/// Unlike [`branch_head`], this only fails if `repo` cannot be listed.
+pub(crate) fn branch_tip(remote: &dyn Remote, repo: &str, branch: &str) -> Result<Option<String>> {
+    let reference = format!("refs/heads/{branch}");
+    let listing = remote.ls_remote(repo, std::slice::from_ref(&reference))?;
+    Ok(listing
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(_, name)| *name == reference)
+        .map(|(sha, _)| sha.to_owned()))
+}
+
+/// Fails for branch `branch` which [`branch_tip`] did not find in `repo`, suggesting branches with
+/// similar names.
+pub(crate) fn missing_branch<T>(remote: &dyn Remote, repo: &str, branch: &str) -> Result<T> {
+    bail!(
+        "{repo} has no branch `{branch}`{}",
+        suggest(&close_matches(remote, repo, "refs/heads/", branch).unwrap_or_default())
+    )
+}
+
+/// Lists names of references under `prefix`, like `refs/tags/`, which are close to `wanted`,
+/// closest first.
+///
+/// Callers only add these to an error about `wanted`, so they drop failures of this second
+/// request, which would otherwise hide that error.
+fn close_matches(
+    remote: &dyn Remote,
+    repo: &str,
+    prefix: &str,
+    wanted: &str,
+) -> Result<Vec<String>> {
+    const MAX_SUGGESTIONS: usize = 3;
+
+    let listing = remote.ls_remote(repo, &[format!("{prefix}*")])?;
+    let max_distance = (wanted.chars().count() / 4).max(1);
+    let mut matches = listing
+        .lines()
+        .filter_map(|line| line.split_once('\t')?.1.strip_prefix(prefix))
+        .filter(|name| !name.ends_with("^{}"))
+        .map(|name| (edit_distance(name, wanted), name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.dedup();
+    Ok(matches
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name.to_owned())
+        .collect())
+}
+
+/// Formats suggested reference names as the end of an error message.
+fn suggest(matches: &[String]) -> String {
+    if matches.is_empty() {
+        return String::new();
+    }
+    let matches = matches
+        .iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>();
+    format!(", did you mean {}?", matches.join(", "))
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const REPO: &str = "https://github.com/starkware-libs/cairo";
+    const COMMIT: &str = "0b3bfdcf2e4c1a7e8e6e0f7b9a3b2b9c1d2e3f40";
+    const TAG_OBJECT: &str = "9f8e7d6c5b4a39281706f5e4d3c2b1a098765432";
+    const PULL_HEAD: &str = "5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f";
+
+    /// Remote with an annotated tag `v2.9.2` and a branch `main`.
+    #[derive(Default)]
+    struct MockRemote {
+        requests: Mutex<Vec<Vec<String>>>,
+        /// Fails listings of all references under a prefix, as if the connection dropped.
+        fail_listing: bool,
+    }
+
+    impl Remote for MockRemote {
+        fn ls_remote(&self, repo: &str, patterns: &[String]) -> Result<String> {
+            assert_eq!(repo, REPO);
+            self.requests.lock().unwrap().push(patterns.to_vec());
+            if self.fail_listing && patterns.iter().any(|pattern| pattern.ends_with('*')) {
+                bail!("connection reset");
+            }
+            let refs = [
+                (TAG_OBJECT, "refs/tags/v2.9.2"),
+                (COMMIT, "refs/tags/v2.9.2^{}"),
+                (COMMIT, "refs/tags/v2.9.1"),
+                (COMMIT, "refs/tags/v2.10.0"),
+                (COMMIT, "refs/heads/main"),
+                (COMMIT, "refs/heads/release/2.9"),
+                (PULL_HEAD, "refs/pull/1/head"),
+            ];
+            let matches = |pattern: &str, name: &str| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            };
+            Ok(refs
+                .iter()
+                .filter(|(_, name)| {
+                    patterns.is_empty() || patterns.iter().any(|pattern| matches(pattern, name))
+                })
+                .map(|(sha, name)| format!("{sha}\t{name}\n"))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let remote = MockRemote::default();
+        let resolve = |rev: &str| resolve(&remote, REPO, rev);
+
+        let rev = resolve(&COMMIT.to_uppercase()).unwrap();
+        assert_eq!(rev.kind, RevKind::FullSha);
+        assert_eq!(rev.sha.as_deref(), Some(COMMIT));
+
+        let rev = resolve("0b3bfdc").unwrap();
+        assert_eq!(rev.kind, RevKind::ShortSha);
+        assert_eq!(rev.sha, None);
+        assert!(remote.requests.lock().unwrap().is_empty());
+
+        assert_eq!(
+            resolve("v2.9.2").unwrap(),
+            Rev {
+                requested: "v2.9.2".to_owned(),
+                kind: RevKind::Tag,
+                sha: Some(COMMIT.to_owned()),
+            }
+        );
+        assert_eq!(
+            remote.requests.lock().unwrap().pop().unwrap(),
+            [
+                "refs/tags/v2.9.2^{}",
+                "refs/tags/v2.9.2",
+                "refs/heads/v2.9.2"
+            ]
+        );
+        // Names of branches are not taken for tags.
+        let rev = resolve("main").unwrap();
+        assert_eq!(rev.kind, RevKind::Branch);
+        assert_eq!(rev.sha.as_deref(), Some(COMMIT));
+
+        let rev = resolve("refs/tags/v2.9.2").unwrap();
+        assert_eq!(rev.kind, RevKind::Ref);
+        assert_eq!(rev.sha.as_deref(), Some(COMMIT));
+        let rev = resolve("refs/heads/main").unwrap();
+        assert_eq!(rev.sha.as_deref(), Some(COMMIT));
+
+        assert_eq!(
+            resolve("v9.9.9").unwrap_err().to_string(),
+            format!("{REPO} has no tag or branch `v9.9.9`")
+        );
+        assert_eq!(
+            resolve("v2.9.3").unwrap_err().to_string(),
+            format!("{REPO} has no tag or branch `v2.9.3`, did you mean `v2.9.1`, `v2.9.2`?")
+        );
+        assert_eq!(
+            resolve("refs/heads/gone").unwrap_err().to_string(),
+            format!("{REPO} has no reference `refs/heads/gone`")
+        );
+
+        let remote = MockRemote {
+            fail_listing: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            super::resolve(&remote, REPO, "v2.9.3")
+                .unwrap_err()
+                .to_string(),
+            format!("{REPO} has no tag or branch `v2.9.3`")
+        );
+        assert_eq!(
+            ensure_branch(&remote, REPO, "release/2.8")
+                .unwrap_err()
+                .to_string(),
+            format!("{REPO} has no branch `release/2.8`")
+        );
+    }
+
+    #[test]
+    fn test_ensure_branch() {
+        let remote = MockRemote::default();
+        ensure_branch(&remote, REPO, "release/2.9").unwrap();
+        assert_eq!(
+            remote.requests.lock().unwrap().pop().unwrap(),
+            ["refs/heads/release/2.9"]
+        );
+        assert_eq!(branch_head(&remote, REPO, "main").unwrap(), COMMIT);
+        assert_eq!(
+            ensure_branch(&remote, REPO, "release/2.8")
+                .unwrap_err()
+                .to_string(),
+            format!("{REPO} has no branch `release/2.8`, did you mean `release/2.9`?")
+        );
+        assert_eq!(
+            ensure_branch(&remote, REPO, "feature")
+                .unwrap_err()
+                .to_string(),
+            format!("{REPO} has no branch `feature`")
+        );
+    }
+
+    #[test]
+    fn test_find_commit() {
+        let remote = MockRemote::default();
+        assert_eq!(find_commit(&remote, REPO, "0B3BFDC").unwrap(), COMMIT);
+        for sha in [TAG_OBJECT, PULL_HEAD, "1a2b3c4"] {
+            assert_eq!(
+                find_commit(&remote, REPO, sha).unwrap_err().to_string(),
+                format!("rev {sha} not found in {REPO}, no branch or tag points to it")
+            );
+        }
+        assert_eq!(
+            remote.requests.lock().unwrap().pop().unwrap(),
+            Vec::<String>::new()
+        );
+
+        struct Ambiguous;
+
+        impl Remote for Ambiguous {
+            fn ls_remote(&self, _: &str, _: &[String]) -> Result<String> {
+                Ok(format!(
+                    "{COMMIT}\trefs/heads/main\n0b3bfdc000000000000000000000000000000000\t\
+                    refs/heads/old\n"
+                ))
+            }
+        }
+
+        assert_eq!(
+            find_commit(&Ambiguous, REPO, "0b3bfdc")
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "commit 0b3bfdc is ambiguous in {REPO}, it abbreviates \
+                0b3bfdc000000000000000000000000000000000, {COMMIT}"
+            )
+        );
+        assert_eq!(find_commit(&Ambiguous, REPO, "0b3bfdcf").unwrap(), COMMIT);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("v2.9.2", "v2.9.2"), 0);
+        assert_eq!(edit_distance("v2.9.2", "2.9.2"), 1);
+        assert_eq!(edit_distance("main", "mian"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,112 @@
+//! Read the release announced by a GitHub `release` webhook payload.
+//!
+//! Besides webhook payloads, which nest the release under `release` next to the `repository`
+//! it belongs to, release objects as returned by the GitHub REST API are accepted too.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// Webhook actions after which the tag of the release is not usable.
+const WITHDRAWN_ACTIONS: &[&str] = &["deleted", "unpublished"];
+
+/// A release read from a payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Release {
+    /// Name of the git tag of the release, like `v2.9.2`.
+    pub tag: String,
+    /// URL of the repository of the release, if the payload tells.
+    pub repo: Option<String>,
+}
+
+/// Reads the release from a webhook payload or a release object.
+///
+/// Fails if the payload has no tag name, announces a draft release whose tag may not exist yet,
+/// or a release which was deleted or unpublished.
+pub(crate) fn parse(payload: &str) -> Result<Release> {
+    let payload =
+        serde_json::from_str::<Value>(payload).context("the payload is not valid JSON")?;
+    let release = payload.get("release").unwrap_or(&payload);
+    let Some(tag) = release
+        .get("tag_name")
+        .and_then(Value::as_str)
+        .filter(|tag| !tag.is_empty())
+    else {
+        bail!("the payload has no `release.tag_name`, expected a GitHub release event");
+    };
+    if let Some(action) = payload
+        .get("action")
+        .and_then(Value::as_str)
+        .filter(|action| WITHDRAWN_ACTIONS.contains(action))
+    {
+        bail!("the payload announces that release {tag} was {action}");
+    }
+    if release.get("draft").and_then(Value::as_bool) == Some(true) {
+        bail!("release {tag} is a draft, its tag may not exist yet");
+    }
+
+    let repo = payload
+        .get("repository")
+        .and_then(|repository| repository.get("html_url"))
+        .and_then(Value::as_str)
+        .or_else(|| {
+            let url = release.get("html_url")?.as_str()?;
+            Some(url.split_once("/releases/")?.0)
+        })
+        .map(str::to_owned);
+    Ok(Release {
+        tag: tag.to_owned(),
+        repo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let release = parse(
+            r#"{
+                "action": "published",
+                "release": { "tag_name": "v2.9.2", "draft": false, "prerelease": false },
+                "repository": { "html_url": "https://github.com/starkware-libs/cairo" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            release,
+            Release {
+                tag: "v2.9.2".to_owned(),
+                repo: Some("https://github.com/starkware-libs/cairo".to_owned()),
+            }
+        );
+
+        let release = parse(
+            r#"{
+                "tag_name": "v2.10.0-rc.0",
+                "html_url": "https://github.com/software-mansion/cairols/releases/tag/v2.10.0-rc.0"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            release.repo.as_deref(),
+            Some("https://github.com/software-mansion/cairols")
+        );
+        assert_eq!(parse(r#"{ "tag_name": "v1" }"#).unwrap().repo, None);
+
+        let err = |payload: &str| format!("{:#}", parse(payload).unwrap_err());
+        assert!(err("v2.9.2").starts_with("the payload is not valid JSON: "));
+        assert_eq!(
+            err(r#"{ "release": { "tag_name": "" } }"#),
+            "the payload has no `release.tag_name`, expected a GitHub release event"
+        );
+        assert_eq!(
+            err(r#"{ "action": "deleted", "release": { "tag_name": "v2.9.2" } }"#),
+            "the payload announces that release v2.9.2 was deleted"
+        );
+        assert_eq!(
+            err(r#"{ "release": { "tag_name": "v2.9.2", "draft": true } }"#),
+            "release v2.9.2 is a draft, its tag may not exist yet"
+        );
+    }
+}
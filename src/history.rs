@@ -0,0 +1,347 @@
+//! Snapshots of project files taken around each upgrade, so that upgrades can be rolled back.
+//!
+//! Runs of `sync-version` are recorded as well, unless chained by an upgrade run whose snapshot
+//! already covers their edits. Every run stores its snapshot in
+//! `target/cairo-toolchain-xtasks/upgrade-history/<RUN>`: copies of all files as they were before
+//! the run, and a list of their hashes before and after it.
+//!
+//! Independently of the history, both xtasks back up `Cargo.toml` to `Cargo.toml.bak.<TIMESTAMP>`
+//! next to it before editing it, see [`write_backup`].
+
+use anyhow::{bail, ensure, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use xshell::Shell;
+
+const HISTORY_DIR: &str = "target/cairo-toolchain-xtasks/upgrade-history";
+
+/// Environment variable set for xtasks chained by a recorded run, which must not record another.
+pub(crate) const NESTED_ENV: &str = "CAIRO_TOOLCHAIN_XTASKS_RECORDED_RUN";
+
+/// A snapshot of project files at the start of a single run.
+pub(crate) struct Snapshot {
+    pub id: String,
+    dir: PathBuf,
+}
+
+/// A file which was reverted by a rollback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Reverted {
+    Restored(String),
+    Removed(String),
+}
+
+impl Snapshot {
+    /// Records the current state of `files`, relative to the project directory.
+    pub fn begin(sh: &Shell, files: &[String]) -> Result<Self> {
+        let history = sh.current_dir().join(HISTORY_DIR);
+        let id = unique_id(|id| history.join(id).exists())?;
+        let dir = history.join(&id);
+
+        sh.create_dir(dir.join("files"))?;
+        for file in files {
+            if sh.path_exists(file) {
+                sh.write_file(dir.join("files").join(file), sh.read_binary_file(file)?)?;
+            }
+        }
+        sh.write_file(dir.join("before"), hashes(sh, files)?)?;
+        Ok(Self { id, dir })
+    }
+
+    /// Records the state of the files after the run completed.
+    pub fn finish(&self, sh: &Shell) -> Result<()> {
+        let files = self.files(sh)?;
+        sh.write_file(self.dir.join("after"), hashes(sh, &files)?)?;
+        Ok(())
+    }
+
+    /// Drops the snapshot, e.g. because the run failed and restored files itself.
+    pub fn discard(self, sh: &Shell) -> Result<()> {
+        sh.remove_path(&self.dir)?;
+        Ok(())
+    }
+
+    /// Finds the snapshot of run `id`, or of the most recent run.
+    pub fn find(sh: &Shell, id: Option<&str>) -> Result<Self> {
+        let history = sh.current_dir().join(HISTORY_DIR);
+        let mut ids = if history.is_dir() {
+            sh.read_dir(&history)?
+                .iter()
+                .filter_map(|path| path.file_name()?.to_str().map(str::to_owned))
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        ids.sort_by_key(|id| sort_key(id));
+
+        let id = match id {
+            Some(id) if ids.iter().any(|known| known == id) => id.to_owned(),
+            Some(id) => bail!(
+                "no upgrade run `{id}` recorded, known runs: {}",
+                if ids.is_empty() {
+                    "none".to_owned()
+                } else {
+                    ids.join(", ")
+                }
+            ),
+            None => match ids.last() {
+                Some(id) => id.clone(),
+                None => bail!("no upgrade runs recorded in {}", history.display()),
+            },
+        };
+        Ok(Self {
+            dir: history.join(&id),
+            id,
+        })
+    }
+
+    /// Restores all files to their state before the run and drops the snapshot.
+    ///
+    /// Refuses if any file changed after the run, unless `force` is set.
+    pub fn rollback(self, sh: &Shell, force: bool) -> Result<Vec<Reverted>> {
+        let reverted = self.pending(sh, force)?;
+        for file in &reverted {
+            match file {
+                Reverted::Removed(file) => sh.remove_path(file)?,
+                Reverted::Restored(file) => {
+                    let saved = sh.read_binary_file(self.dir.join("files").join(file))?;
+                    sh.write_file(file, saved)?;
+                }
+            }
+        }
+        sh.remove_path(&self.dir)?;
+        Ok(reverted)
+    }
+
+    /// Lists how [`Self::rollback`] would revert files, without changing anything.
+    pub fn pending(&self, sh: &Shell, force: bool) -> Result<Vec<Reverted>> {
+        let after = self.dir.join("after");
+        ensure!(
+            sh.path_exists(&after),
+            "upgrade run {} did not complete, there is nothing to roll back",
+            self.id
+        );
+
+        let files = self.files(sh)?;
+        let recorded = sh.read_file(&after)?;
+        let current = hashes(sh, &files)?;
+        if recorded != current && !force {
+            let changed = recorded
+                .lines()
+                .zip(current.lines())
+                .filter(|(recorded, current)| recorded != current)
+                .filter_map(|(_, current)| current.split_once(' ').map(|(_, path)| path))
+                .collect::<Vec<_>>();
+            bail!(
+                "files were modified after upgrade run {}: {}, pass --force to roll back anyway",
+                self.id,
+                changed.join(", ")
+            );
+        }
+
+        let before = sh.read_file(self.dir.join("before"))?;
+        let mut reverted = Vec::new();
+        for (line, current) in before.lines().zip(current.lines()) {
+            let Some((hash, file)) = line.split_once(' ') else {
+                continue;
+            };
+            if line == current {
+                continue;
+            }
+            reverted.push(match hash {
+                "-" => Reverted::Removed(file.to_owned()),
+                _ => Reverted::Restored(file.to_owned()),
+            });
+        }
+        Ok(reverted)
+    }
+
+    fn files(&self, sh: &Shell) -> Result<Vec<String>> {
+        Ok(sh
+            .read_file(self.dir.join("before"))?
+            .lines()
+            .filter_map(|line| line.split_once(' ').map(|(_, file)| file.to_owned()))
+            .collect())
+    }
+}
+
+/// Writes `contents`, the manifest `manifest` as read before editing it, to
+/// `<manifest>.bak.<TIMESTAMP>` next to it. Returns the path of the backup.
+///
+/// The timestamp is the Unix time in seconds, suffixed with `-<N>` for backups of the same second.
+pub(crate) fn write_backup(sh: &Shell, manifest: &str, contents: &[u8]) -> Result<String> {
+    let id = unique_id(|id| sh.path_exists(format!("{manifest}.bak.{id}")))?;
+    let backup = format!("{manifest}.bak.{id}");
+    sh.write_file(&backup, contents)
+        .with_context(|| format!("failed to back up {manifest} to {backup}"))?;
+    Ok(backup)
+}
+
+/// Lists the backups of `manifest` written by [`write_backup`], the most recent one last.
+pub(crate) fn backups(sh: &Shell, manifest: &str) -> Result<Vec<String>> {
+    let manifest = Path::new(manifest);
+    let dir = manifest.parent().unwrap_or(Path::new(""));
+    let prefix = format!("{}.bak.", manifest.to_string_lossy());
+    let mut backups = sh
+        .read_dir(sh.current_dir().join(dir))?
+        .iter()
+        .filter_map(|path| path.file_name()?.to_str())
+        .map(|name| dir.join(name).to_string_lossy().into_owned())
+        .filter(|backup| {
+            backup.strip_prefix(&prefix).is_some_and(|id| {
+                !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-')
+            })
+        })
+        .collect::<Vec<_>>();
+    backups.sort_by_key(|backup| sort_key(&backup[prefix.len()..]));
+    Ok(backups)
+}
+
+/// Picks the id of something started now: the Unix time in seconds, suffixed with `-<N>` unless
+/// it is the first of that second, i.e. `taken` does not hold for it.
+fn unique_id(taken: impl Fn(&str) -> bool) -> Result<String> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok((0..)
+        .map(|n| match n {
+            0 => secs.to_string(),
+            n => format!("{secs}-{n}"),
+        })
+        .find(|id| !taken(id))
+        .unwrap())
+}
+
+/// Lists `<sha1> <path>` lines for all files, with `-` in place of a hash for missing files.
+fn hashes(sh: &Shell, files: &[String]) -> Result<String> {
+    let mut lines = String::new();
+    for file in files {
+        let hash = if sh.path_exists(file) {
+            let contents = sh
+                .read_binary_file(file)
+                .with_context(|| format!("failed to read {file}"))?;
+            sha1_smol::Sha1::from(contents).digest().to_string()
+        } else {
+            "-".to_owned()
+        };
+        lines.push_str(&format!("{hash} {file}\n"));
+    }
+    Ok(lines)
+}
+
+/// Orders run ids chronologically, including ones of runs started within the same second.
+fn sort_key(id: &str) -> (u64, u64) {
+    let (secs, n) = id.split_once('-').unwrap_or((id, "0"));
+    (secs.parse().unwrap_or(0), n.parse().unwrap_or(0))
+}
+
+impl std::fmt::Display for Reverted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Restored(file) => write!(f, "restored {file}"),
+            Self::Removed(file) => write!(f, "removed {file}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let files = ["Cargo.toml".to_owned(), "Cargo.lock".to_owned()];
+        sh.write_file("Cargo.toml", "[package]\nname = \"foo\"\n")
+            .unwrap();
+
+        let err = Snapshot::find(&sh, None).err().unwrap();
+        assert!(
+            err.to_string().starts_with("no upgrade runs recorded in "),
+            "{err}"
+        );
+
+        let first = Snapshot::begin(&sh, &files).unwrap();
+        let second = Snapshot::begin(&sh, &files).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(Snapshot::find(&sh, None).unwrap().id, second.id);
+        assert_eq!(Snapshot::find(&sh, Some(&first.id)).unwrap().id, first.id);
+        let err = Snapshot::find(&sh, Some("1")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "no upgrade run `1` recorded, known runs: {}, {}",
+                first.id, second.id
+            )
+        );
+        let err = Snapshot::find(&sh, None)
+            .unwrap()
+            .rollback(&sh, false)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "upgrade run {} did not complete, there is nothing to roll back",
+                second.id
+            )
+        );
+        second.discard(&sh).unwrap();
+
+        sh.write_file("Cargo.toml", "[package]\nname = \"bar\"\n")
+            .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        first.finish(&sh).unwrap();
+
+        sh.write_file("Cargo.lock", "version = 3\n").unwrap();
+        let err = Snapshot::find(&sh, None)
+            .unwrap()
+            .rollback(&sh, false)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "files were modified after upgrade run {}: Cargo.lock, pass --force to roll back \
+                anyway",
+                first.id
+            )
+        );
+
+        let reverted = Snapshot::find(&sh, None)
+            .unwrap()
+            .rollback(&sh, true)
+            .unwrap();
+        assert_eq!(
+            reverted,
+            [
+                Reverted::Restored("Cargo.toml".to_owned()),
+                Reverted::Removed("Cargo.lock".to_owned()),
+            ]
+        );
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            "[package]\nname = \"foo\"\n"
+        );
+        assert!(!sh.path_exists("Cargo.lock"));
+        assert!(Snapshot::find(&sh, None).is_err());
+    }
+
+    #[test]
+    fn test_backups() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.toml", "[package]\n").unwrap();
+        sh.write_file("Cargo.toml.bak.old", "").unwrap();
+        assert!(backups(&sh, "Cargo.toml").unwrap().is_empty());
+
+        let first = write_backup(&sh, "Cargo.toml", b"[package]\r\n").unwrap();
+        let second = write_backup(&sh, "Cargo.toml", b"[workspace]\n").unwrap();
+        assert!(first.starts_with("Cargo.toml.bak."), "{first}");
+        assert_eq!(sh.read_binary_file(&first).unwrap(), b"[package]\r\n");
+        assert_eq!(backups(&sh, "Cargo.toml").unwrap(), [first, second]);
+
+        let backup = write_backup(&sh, "crates/foo/Cargo.toml", b"").unwrap();
+        assert!(backup.starts_with("crates/foo/Cargo.toml.bak."), "{backup}");
+        assert_eq!(backups(&sh, "crates/foo/Cargo.toml").unwrap(), [backup]);
+    }
+}
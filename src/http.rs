@@ -0,0 +1,200 @@
+//! Minimal HTTP client, abstracted so that network access can be replaced in tests.
+
+use anyhow::{ensure, Context, Result};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::Path;
+use xshell::{cmd, Shell};
+
+/// Whether xtasks may access the network.
+///
+/// Cargo commands like `cargo fetch` are kept offline with [`Network::cargo_args`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Network {
+    #[default]
+    Allowed,
+    /// `--confirm-network` was not given, and the workspace does not opt out of requiring it.
+    Unconfirmed,
+}
+
+impl Network {
+    /// Fails unless the network may be accessed to `what`, like `fetch <URL>`.
+    pub fn ensure_allowed(self, what: impl Display) -> Result<()> {
+        ensure!(
+            self == Network::Allowed,
+            "refusing to {what}: accessing the network requires --confirm-network, pass it or use \
+            a local checkout with --path"
+        );
+        Ok(())
+    }
+
+    /// Arguments of Cargo commands like `cargo fetch`, which keep them offline unless the network
+    /// may be accessed.
+    pub fn cargo_args(self) -> &'static [&'static str] {
+        match self {
+            Network::Allowed => &[],
+            Network::Unconfirmed => &["--offline"],
+        }
+    }
+}
+
+/// Response to a conditional `GET` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Conditional {
+    /// The resource changed, or no validator was sent.
+    Modified { body: String, etag: Option<String> },
+    /// The resource still matches the sent `ETag`.
+    NotModified,
+}
+
+/// Performs HTTP `GET` requests.
+///
+/// Clients are shared by threads looking up several dependency groups at once.
+pub(crate) trait Http: Sync {
+    /// Fetches the body of `url`, failing on non-success HTTP statuses.
+    fn get(&self, url: &str) -> Result<String>;
+
+    /// Fetches the body of `url` like [`Http::get`], as raw bytes, e.g. to compare its blob id.
+    fn get_bytes(&self, url: &str) -> Result<Vec<u8>> {
+        Ok(self.get(url)?.into_bytes())
+    }
+
+    /// Fetches the body of `url`, unless it still matches `etag`.
+    ///
+    /// Implementations not supporting validators always fetch the body.
+    fn get_if_none_match(&self, url: &str, etag: Option<&str>) -> Result<Conditional> {
+        let _ = etag;
+        Ok(Conditional::Modified {
+            body: self.get(url)?,
+            etag: None,
+        })
+    }
+}
+
+/// [`Http`] implementation shelling out to `curl`.
+///
+/// If [`MIRROR_ENV`] is set, `https://<HOST>/<PATH>` is read from `<MIRROR>/<HOST>/<PATH>`
+/// instead, which lets tests running the xtask binary stand in for remote files.
+pub(crate) struct Curl {
+    pub network: Network,
+}
+
+/// Environment variable naming a directory to serve the requests of [`Curl`] from.
+const MIRROR_ENV: &str = "CAIRO_XTASKS_HTTP_MIRROR";
+
+/// Points `url` to its copy in the directory `mirror`, named by [`MIRROR_ENV`], if set.
+fn mirrored(url: &str, mirror: Option<OsString>) -> String {
+    match (mirror, url.strip_prefix("https://")) {
+        (Some(mirror), Some(path)) => format!("file://{}/{path}", Path::new(&mirror).display()),
+        _ => url.to_owned(),
+    }
+}
+
+impl Http for Curl {
+    fn get(&self, url: &str) -> Result<String> {
+        String::from_utf8(self.get_bytes(url)?)
+            .with_context(|| format!("response of {url} is not valid UTF-8"))
+    }
+
+    fn get_bytes(&self, url: &str) -> Result<Vec<u8>> {
+        self.network.ensure_allowed(format_args!("fetch {url}"))?;
+        let url = mirrored(url, std::env::var_os(MIRROR_ENV));
+        let sh = Shell::new()?;
+        // Not using `read`, as it trims the trailing newline.
+        Ok(cmd!(sh, "curl -sSfL {url}").quiet().output()?.stdout)
+    }
+
+    fn get_if_none_match(&self, url: &str, etag: Option<&str>) -> Result<Conditional> {
+        self.network.ensure_allowed(format_args!("fetch {url}"))?;
+        let url = mirrored(url, std::env::var_os(MIRROR_ENV));
+        let sh = Shell::new()?;
+        let temp_dir = sh.create_temp_dir()?;
+        let headers = temp_dir.path().join("headers");
+        let body = temp_dir.path().join("body");
+        let condition = etag
+            .map(|etag| vec!["-H".to_owned(), format!("If-None-Match: {etag}")])
+            .unwrap_or_default();
+        let write_out = "%{http_code}";
+
+        let status = cmd!(
+            sh,
+            "curl -sSfL -D {headers} -o {body} -w {write_out} {condition...} {url}"
+        )
+        .quiet()
+        .read()?;
+        if status == "304" {
+            return Ok(Conditional::NotModified);
+        }
+        Ok(Conditional::Modified {
+            body: sh.read_file(&body)?,
+            etag: parse_etag(&sh.read_file(&headers)?),
+        })
+    }
+}
+
+/// Extracts the `ETag` of the final response from dumped response headers.
+fn parse_etag(headers: &str) -> Option<String> {
+    headers.lines().rev().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("etag")
+            .then(|| value.trim().to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_etag() {
+        let headers = "HTTP/1.1 301 Moved\r\nETag: \"old\"\r\n\r\n\
+            HTTP/2 200\r\ncontent-type: text/plain\r\netag: \"abc123\"\r\n\r\n";
+        assert_eq!(parse_etag(headers).as_deref(), Some("\"abc123\""));
+        assert_eq!(parse_etag("HTTP/2 200\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_get_keeps_trailing_newline() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let path = temp_dir.path().join("lib.cairo");
+        sh.write_file(&path, "mod array;\n").unwrap();
+        let url = format!("file://{}", path.display());
+        let curl = Curl {
+            network: Network::Allowed,
+        };
+        assert_eq!(curl.get(&url).unwrap(), "mod array;\n");
+        assert_eq!(curl.get_bytes(&url).unwrap(), b"mod array;\n");
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let url = "https://raw.githubusercontent.com/starkware-libs/cairo/main/Cargo.toml";
+        assert_eq!(mirrored(url, None), url);
+        assert_eq!(
+            mirrored(url, Some("/tmp/mirror".into())),
+            "file:///tmp/mirror/raw.githubusercontent.com/starkware-libs/cairo/main/Cargo.toml"
+        );
+        assert_eq!(
+            mirrored("file:///tmp/lib.cairo", Some("/tmp/mirror".into())),
+            "file:///tmp/lib.cairo"
+        );
+    }
+
+    #[test]
+    fn test_unconfirmed_network_is_blocked() {
+        let curl = Curl {
+            network: Network::Unconfirmed,
+        };
+        let url = "https://index.crates.io/ca/ir/cairo-lang-compiler";
+        let message = format!(
+            "refusing to fetch {url}: accessing the network requires --confirm-network, pass it or \
+            use a local checkout with --path"
+        );
+        assert_eq!(curl.get(url).unwrap_err().to_string(), message);
+        assert_eq!(
+            curl.get_if_none_match(url, None).unwrap_err().to_string(),
+            message
+        );
+    }
+}
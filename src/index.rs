@@ -0,0 +1,324 @@
+//! Look up crate metadata in the crates.io sparse index.
+
+use crate::cache::Cache;
+use crate::http::{Conditional, Http};
+use anyhow::{Context, Result};
+use semver::Version;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long cached index responses are considered fresh.
+const INDEX_TTL: Duration = Duration::from_secs(30 * 60);
+
+const INDEX_URL: &str = "https://index.crates.io";
+
+/// Arguments shared by all commands which read the crates.io index.
+#[derive(clap::Args, Clone, Default)]
+pub(crate) struct IndexArgs {
+    /// Directory to cache crates.io sparse index responses in.
+    ///
+    /// Defaults to the `index` directory inside the cache directory.
+    #[arg(long)]
+    pub index_cache_dir: Option<PathBuf>,
+
+    /// Ignore cached crates.io index responses and fetch them again.
+    #[arg(long, default_value_t = false, conflicts_with = "offline")]
+    pub refresh_index: bool,
+
+    /// Do not access the network, serve crates.io index lookups from the cache only.
+    #[arg(long, default_value_t = false)]
+    pub offline: bool,
+}
+
+/// Client of the crates.io sparse index, caching responses on disk.
+pub(crate) struct Index<'a> {
+    http: &'a dyn Http,
+    cache: Cache,
+    ttl: Duration,
+    refresh: bool,
+    offline: bool,
+    /// Failures to write the cache, which do not stop lookups.
+    warnings: Mutex<Vec<String>>,
+}
+
+impl<'a> Index<'a> {
+    pub fn new(args: &IndexArgs, cache: &Cache, http: &'a dyn Http) -> Self {
+        let cache = match &args.index_cache_dir {
+            Some(dir) => Cache::new(dir.clone()),
+            None => Cache::new(cache.dir().join("index")),
+        };
+        Self {
+            http,
+            cache,
+            ttl: INDEX_TTL,
+            refresh: args.refresh_index,
+            offline: args.offline,
+            warnings: Mutex::default(),
+        }
+    }
+
+    /// Takes the warnings collected by lookups so far, to be reported by the caller.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.lock().unwrap())
+    }
+
+    /// Fetches the raw index file of a crate: one JSON object per published version.
+    ///
+    /// Stale cached files are revalidated with their `ETag`, so unchanged files are not downloaded
+    /// again.
+    pub fn entries(&self, crate_name: &str) -> Result<String> {
+        let path = index_path(crate_name);
+        let etag_key = format!("{path}.etag");
+        if self.offline {
+            return self.cache.get(&path).with_context(|| {
+                format!("`{crate_name}` is not in the crates.io index cache, run without --offline")
+            });
+        }
+        if !self.refresh {
+            if let Some(entries) = self.cache.get_fresh(&path, self.ttl) {
+                return Ok(entries);
+            }
+        }
+
+        let cached = self.cache.get(&path).filter(|_| !self.refresh);
+        let etag = cached
+            .as_ref()
+            .and_then(|_| self.cache.get(&etag_key))
+            .filter(|etag| !etag.is_empty());
+        let response = self
+            .http
+            .get_if_none_match(&format!("{INDEX_URL}/{path}"), etag.as_deref())
+            .with_context(|| format!("failed to look up `{crate_name}` in the crates.io index"))?;
+
+        let entries = match (response, cached) {
+            (Conditional::NotModified, Some(cached)) => cached,
+            (Conditional::NotModified, None) => self.http.get(&format!("{INDEX_URL}/{path}"))?,
+            (Conditional::Modified { body, etag }, _) => {
+                let etag = etag.as_deref().unwrap_or_default();
+                if let Err(err) = self.cache.put(&etag_key, etag) {
+                    self.warn(crate_name, err);
+                }
+                body
+            }
+        };
+        // Rewriting also marks revalidated entries as fresh again.
+        if let Err(err) = self.cache.put(&path, &entries) {
+            self.warn(crate_name, err);
+        }
+        Ok(entries)
+    }
+
+    fn warn(&self, crate_name: &str, err: anyhow::Error) {
+        self.warnings.lock().unwrap().push(format!(
+            "cannot cache the crates.io index entries of `{crate_name}`: {err:#}"
+        ));
+    }
+
+    /// Parses the index file of a crate into one JSON object per published version.
+    pub fn records(&self, crate_name: &str) -> Result<Vec<serde_json::Value>> {
+        self.entries(crate_name)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Lists all published versions of a crate, along with whether they are yanked.
+    pub fn versions_of(&self, crate_name: &str) -> Result<Vec<(Version, bool)>> {
+        parse_versions(&self.entries(crate_name)?)
+    }
+}
+
+/// Parses published versions and their yanked state out of a sparse index file.
+fn parse_versions(entries: &str) -> Result<Vec<(Version, bool)>> {
+    entries
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line)?;
+            let version = record["vers"].as_str().unwrap_or_default();
+            let version = version
+                .parse()
+                .with_context(|| format!("invalid version in index entry: {line}"))?;
+            Ok((version, record["yanked"].as_bool().unwrap_or(false)))
+        })
+        .collect()
+}
+
+/// Computes the path of a crate's file in the sparse index.
+fn index_path(crate_name: &str) -> String {
+    let name = crate_name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use xshell::Shell;
+
+    /// Recorded from `https://index.crates.io/ca/ir/cairo-lint-core`, trimmed.
+    const ENTRIES: &str = concat!(
+        r#"{"name":"cairo-lint-core","vers":"2.8.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+        "\n",
+        r#"{"name":"cairo-lint-core","vers":"2.8.1","deps":[],"cksum":"","features":{},"yanked":true}"#,
+        "\n",
+        r#"{"name":"cairo-lint-core","vers":"2.9.0-rc.0","deps":[],"cksum":"","features":{},"yanked":false,"v":2}"#,
+        "\n",
+    );
+
+    /// Serves [`ENTRIES`] with an `ETag`, recording requested URLs and sent `ETag`s.
+    #[derive(Default)]
+    struct MockHttp {
+        requests: Mutex<Vec<(String, Option<String>)>>,
+    }
+
+    impl Http for MockHttp {
+        fn get(&self, url: &str) -> Result<String> {
+            self.requests.lock().unwrap().push((url.to_owned(), None));
+            Ok(ENTRIES.to_owned())
+        }
+
+        fn get_if_none_match(&self, url: &str, etag: Option<&str>) -> Result<Conditional> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_owned(), etag.map(str::to_owned)));
+            if etag == Some("\"v1\"") {
+                return Ok(Conditional::NotModified);
+            }
+            Ok(Conditional::Modified {
+                body: ENTRIES.to_owned(),
+                etag: Some("\"v1\"".to_owned()),
+            })
+        }
+    }
+
+    fn versions(entries: &[(Version, bool)]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|(version, yanked)| match yanked {
+                true => format!("{version} (yanked)"),
+                false => version.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), "1/a");
+        assert_eq!(index_path("ab"), "2/ab");
+        assert_eq!(index_path("abc"), "3/a/abc");
+        assert_eq!(
+            index_path("Cairo-Lang-Compiler"),
+            "ca/ir/cairo-lang-compiler"
+        );
+    }
+
+    #[test]
+    fn test_parse_versions() {
+        assert_eq!(
+            versions(&parse_versions(ENTRIES).unwrap()),
+            ["2.8.0", "2.8.1 (yanked)", "2.9.0-rc.0"]
+        );
+        assert!(parse_versions("").unwrap().is_empty());
+        assert!(parse_versions(r#"{"name":"foo","vers":"latest"}"#).is_err());
+    }
+
+    #[test]
+    fn test_second_lookup_within_ttl_reads_from_cache() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let http = MockHttp::default();
+        let args = IndexArgs {
+            index_cache_dir: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let cache = Cache::new(temp_dir.path().join("unused"));
+        let url = "https://index.crates.io/ca/ir/cairo-lint-core".to_owned();
+
+        let index = Index::new(&args, &cache, &http);
+        let versions = index.versions_of("cairo-lint-core").unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(index.versions_of("cairo-lint-core").unwrap(), versions);
+        assert_eq!(*http.requests.lock().unwrap(), [(url.clone(), None)]);
+
+        // Stale entries are revalidated with their ETag.
+        let expired = Index {
+            ttl: Duration::ZERO,
+            ..Index::new(&args, &cache, &http)
+        };
+        assert_eq!(expired.versions_of("cairo-lint-core").unwrap(), versions);
+        assert_eq!(
+            http.requests.lock().unwrap()[1],
+            (url.clone(), Some("\"v1\"".to_owned()))
+        );
+
+        let refreshing = Index::new(
+            &IndexArgs {
+                refresh_index: true,
+                ..args.clone()
+            },
+            &cache,
+            &http,
+        );
+        refreshing.versions_of("cairo-lint-core").unwrap();
+        assert_eq!(http.requests.lock().unwrap()[2], (url, None));
+    }
+
+    #[test]
+    fn test_offline_serves_from_cache_only() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let http = MockHttp::default();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let offline = IndexArgs {
+            offline: true,
+            ..Default::default()
+        };
+
+        let err = Index::new(&offline, &cache, &http)
+            .versions_of("cairo-lint-core")
+            .unwrap_err();
+        assert!(err.to_string().contains("run without --offline"));
+
+        Index::new(&IndexArgs::default(), &cache, &http)
+            .versions_of("cairo-lint-core")
+            .unwrap();
+        let index = Index {
+            ttl: Duration::ZERO,
+            ..Index::new(&offline, &cache, &http)
+        };
+        assert_eq!(index.versions_of("cairo-lint-core").unwrap().len(), 3);
+        assert_eq!(http.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cache_failure_is_a_warning() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let http = MockHttp::default();
+        // A file in place of the cache directory makes every write fail.
+        let blocked = temp_dir.path().join("index");
+        sh.write_file(&blocked, "").unwrap();
+        let args = IndexArgs {
+            index_cache_dir: Some(blocked),
+            ..Default::default()
+        };
+        let cache = Cache::new(temp_dir.path().join("unused"));
+
+        let index = Index::new(&args, &cache, &http);
+        assert_eq!(index.versions_of("cairo-lint-core").unwrap().len(), 3);
+        let warnings = index.take_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0]
+            .starts_with("cannot cache the crates.io index entries of `cairo-lint-core`: "));
+        assert!(index.take_warnings().is_empty());
+    }
+}
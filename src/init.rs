@@ -0,0 +1,345 @@
+//! Bootstrap the xtask setup in a new repository.
+
+use crate::context::{Context, GlobalArgs};
+use crate::eol::LineEnding;
+use crate::manifest;
+use anyhow::{ensure, Result};
+use clap::Parser;
+use toml_edit::{table, value, Array, DocumentMut, Item};
+
+/// Bootstrap the xtask setup in a new repository.
+///
+/// Must be run from (or pointed with `-C` at) the workspace root. Files that already exist are never overwritten.
+#[derive(Parser)]
+pub struct Args {
+    /// Do not create any files, just list what would be done.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+const XTASK_CARGO_TOML: &str = r#"[package]
+name = "xtask"
+version = "0.0.0"
+edition = "2021"
+publish = false
+
+[dependencies]
+anyhow = "1"
+cairo-toolchain-xtasks = "1"
+clap = { version = "4", features = ["derive"] }
+"#;
+
+const XTASK_MAIN_RS: &str = r#"use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Upgrade(cairo_toolchain_xtasks::upgrade::Args),
+    SyncVersion(cairo_toolchain_xtasks::sync_version::Args),
+    Audit(cairo_toolchain_xtasks::audit::Args),
+    Cache(cairo_toolchain_xtasks::cache::Args),
+    DumpUnusedPatches(cairo_toolchain_xtasks::dump_unused_patches::Args),
+    Normalize(cairo_toolchain_xtasks::normalize::Args),
+    PrintRepo(cairo_toolchain_xtasks::print_repo::Args),
+    Restore(cairo_toolchain_xtasks::restore::Args),
+    SyncCorelib(cairo_toolchain_xtasks::sync_corelib::Args),
+    VerifyFeatures(cairo_toolchain_xtasks::verify_features::Args),
+}
+
+fn main() -> Result<ExitCode> {
+    let result = match Args::parse().command {
+        Command::Upgrade(args) => cairo_toolchain_xtasks::upgrade::main(args),
+        Command::SyncVersion(args) => cairo_toolchain_xtasks::sync_version::main(args),
+        Command::Audit(args) => cairo_toolchain_xtasks::audit::main(args),
+        Command::Cache(args) => cairo_toolchain_xtasks::cache::main(args),
+        Command::DumpUnusedPatches(args) => cairo_toolchain_xtasks::dump_unused_patches::main(args),
+        Command::Normalize(args) => cairo_toolchain_xtasks::normalize::main(args),
+        Command::PrintRepo(args) => cairo_toolchain_xtasks::print_repo::main(args),
+        Command::Restore(args) => cairo_toolchain_xtasks::restore::main(args),
+        Command::SyncCorelib(args) => cairo_toolchain_xtasks::sync_corelib::main(args),
+        Command::VerifyFeatures(args) => cairo_toolchain_xtasks::verify_features::main(args),
+    };
+    cairo_toolchain_xtasks::upgrade::exit_code(result)
+}
+"#;
+
+const XTASK_ALIAS: &str = "run -p xtask --";
+
+/// A single file that has to be written to complete the setup.
+struct Change {
+    path: &'static str,
+    contents: String,
+    description: String,
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    init(&ctx, args.dry_run)
+}
+
+/// Writes all changes needed to complete the xtask setup, or lists them if `dry_run` is set.
+fn init(ctx: &Context, dry_run: bool) -> Result<()> {
+    let changes = plan(ctx)?;
+    if changes.is_empty() {
+        ctx.note("xtask setup is already complete, nothing to do");
+    }
+
+    for change in &changes {
+        if dry_run {
+            ctx.note(format_args!("would {}", change.description));
+        } else {
+            ctx.sh.write_file(change.path, &change.contents)?;
+            ctx.note(&change.description);
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes all changes needed to complete the xtask setup in the workspace root.
+///
+/// Files which are left untouched although they differ from the expected setup are warned about.
+fn plan(ctx: &Context) -> Result<Vec<Change>> {
+    let sh = &ctx.sh;
+    ensure!(
+        sh.path_exists("Cargo.toml"),
+        "Cargo.toml not found in {}, run this command from the workspace root",
+        sh.current_dir().display()
+    );
+
+    let mut changes = Vec::new();
+
+    let original = sh.read_file("Cargo.toml")?;
+    let eol = LineEnding::detect(&original);
+    let mut cargo_toml = original.parse::<DocumentMut>()?;
+    let mut edits = Vec::new();
+    if add_patch_table(&mut cargo_toml) {
+        edits.push("add [patch.crates-io]");
+    }
+    if add_workspace_member(&mut cargo_toml) {
+        edits.push("add xtask to workspace members");
+    }
+    if !edits.is_empty() {
+        changes.push(Change {
+            path: "Cargo.toml",
+            contents: eol.apply(&cargo_toml.to_string()),
+            description: format!("update Cargo.toml ({})", edits.join(", ")),
+        });
+    }
+
+    for (path, contents) in [
+        ("xtask/Cargo.toml", XTASK_CARGO_TOML),
+        ("xtask/src/main.rs", XTASK_MAIN_RS),
+    ] {
+        if !sh.path_exists(path) {
+            changes.push(Change {
+                path,
+                contents: contents.to_owned(),
+                description: format!("create {path}"),
+            });
+        } else if sh.read_file(path)? != contents {
+            ctx.warn(format_args!(
+                "{path} exists and has been modified, leaving it untouched"
+            ));
+        }
+    }
+
+    let config_path = ".cargo/config.toml";
+    let config_exists = sh.path_exists(config_path);
+    let config_original = if config_exists {
+        sh.read_file(config_path)?
+    } else {
+        String::new()
+    };
+    let config_eol = LineEnding::detect(&config_original);
+    let mut config = config_original.parse::<DocumentMut>()?;
+    if add_xtask_alias(ctx, &mut config, config_path) {
+        changes.push(Change {
+            path: config_path,
+            contents: config_eol.apply(&config.to_string()),
+            description: if config_exists {
+                format!("update {config_path} (add xtask alias)")
+            } else {
+                format!("create {config_path}")
+            },
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Creates an empty `[patch.crates-io]` table, unless it exists already.
+fn add_patch_table(cargo_toml: &mut DocumentMut) -> bool {
+    manifest::get_item_mut(cargo_toml, "patch.crates-io").is_none()
+        && manifest::patch_table(cargo_toml).is_some()
+}
+
+/// Adds the `xtask` package to workspace members, creating the `[workspace]` table if needed.
+fn add_workspace_member(cargo_toml: &mut DocumentMut) -> bool {
+    let Some(workspace) = cargo_toml
+        .entry("workspace")
+        .or_insert_with(table)
+        .as_table_like_mut()
+    else {
+        return false;
+    };
+    let Some(members) = workspace
+        .entry("members")
+        .or_insert_with(|| value(Array::new()))
+        .as_array_mut()
+    else {
+        return false;
+    };
+    if members
+        .iter()
+        .any(|member| member.as_str() == Some("xtask"))
+    {
+        return false;
+    }
+    members.push("xtask");
+    true
+}
+
+/// Adds the `cargo xtask` alias, unless any `xtask` alias is defined already.
+fn add_xtask_alias(ctx: &Context, config: &mut DocumentMut, config_path: &str) -> bool {
+    let Some(alias) = config
+        .entry("alias")
+        .or_insert_with(table)
+        .as_table_like_mut()
+    else {
+        return false;
+    };
+    match alias.get("xtask").and_then(Item::as_str) {
+        None if !alias.contains_key("xtask") => {
+            alias.insert("xtask", value(XTASK_ALIAS));
+            true
+        }
+        Some(XTASK_ALIAS) => false,
+        _ => {
+            ctx.warn(format_args!(
+                "{config_path} defines a custom xtask alias, leaving it untouched, expected: \
+                xtask = \"{XTASK_ALIAS}\""
+            ));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    fn apply(ctx: &Context) -> Vec<&'static str> {
+        let changes = plan(ctx).unwrap();
+        for change in &changes {
+            ctx.sh.write_file(change.path, &change.contents).unwrap();
+        }
+        changes.iter().map(|change| change.path).collect()
+    }
+
+    #[test]
+    fn test_init() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.toml", "[workspace]\nmembers = [\"foo\"]\n")
+            .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        init(&ctx, true).unwrap();
+        assert_eq!(
+            ctx.captured().1,
+            [
+                "note: would update Cargo.toml (add [patch.crates-io], add xtask to workspace \
+                members)",
+                "note: would create xtask/Cargo.toml",
+                "note: would create xtask/src/main.rs",
+                "note: would create .cargo/config.toml",
+            ]
+        );
+        assert!(!sh.path_exists("xtask"));
+
+        let ctx = Context::recording(sh.clone());
+        assert_eq!(
+            apply(&ctx),
+            [
+                "Cargo.toml",
+                "xtask/Cargo.toml",
+                "xtask/src/main.rs",
+                ".cargo/config.toml"
+            ]
+        );
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            "[workspace]\nmembers = [\"foo\", \"xtask\"]\n\n[patch.crates-io]\n"
+        );
+        assert_eq!(
+            sh.read_file(".cargo/config.toml").unwrap(),
+            "[alias]\nxtask = \"run -p xtask --\"\n"
+        );
+        assert_eq!(sh.read_file("xtask/src/main.rs").unwrap(), XTASK_MAIN_RS);
+
+        // Running again is a no-op.
+        let ctx = Context::recording(sh.clone());
+        init(&ctx, false).unwrap();
+        assert_eq!(
+            ctx.captured().1,
+            ["note: xtask setup is already complete, nothing to do"]
+        );
+    }
+
+    #[test]
+    fn test_init_does_not_overwrite_modified_files() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.toml", "[package]\nname = \"foo\"\n")
+            .unwrap();
+        sh.write_file("xtask/src/main.rs", "fn main() {}\n")
+            .unwrap();
+        sh.write_file(
+            ".cargo/config.toml",
+            "[alias]\nxtask = \"run -p my-xtask --\"\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        assert_eq!(apply(&ctx), ["Cargo.toml", "xtask/Cargo.toml"]);
+        assert_eq!(
+            ctx.captured().1,
+            [
+                "warn: xtask/src/main.rs exists and has been modified, leaving it untouched",
+                "warn: .cargo/config.toml defines a custom xtask alias, leaving it untouched, \
+                expected: xtask = \"run -p xtask --\"",
+            ]
+        );
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            "[package]\nname = \"foo\"\n\n[patch.crates-io]\n\n[workspace]\nmembers = [\"xtask\"]\n"
+        );
+        assert_eq!(sh.read_file("xtask/src/main.rs").unwrap(), "fn main() {}\n");
+        assert_eq!(
+            sh.read_file(".cargo/config.toml").unwrap(),
+            "[alias]\nxtask = \"run -p my-xtask --\"\n"
+        );
+    }
+
+    #[test]
+    fn test_init_requires_manifest() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        assert!(plan(&Context::recording(sh)).is_err());
+    }
+}
@@ -1,4 +1,29 @@
 //! Build scripts shared between all Cairo Toolchain projects maintained by Software Mansion.
 
+pub mod audit;
+pub mod cache;
+mod compat_check;
+mod config;
+pub mod context;
+mod diff;
+pub mod dump_unused_patches;
+mod eol;
+mod git;
+mod github_release;
+mod history;
+mod http;
+mod index;
+pub mod init;
+pub mod lockfile;
+pub mod manifest;
+pub mod normalize;
+mod patch_sections;
+pub mod print_repo;
+pub mod provenance;
+mod release_script;
+pub mod restore;
+pub mod sync_corelib;
 pub mod sync_version;
 pub mod upgrade;
+pub mod verify_features;
+mod workspace;
@@ -0,0 +1,321 @@
+//! Helpers for inspecting `Cargo.lock` files.
+
+use anyhow::{bail, Context, Result};
+use semver::Version;
+use std::fmt;
+use std::str::FromStr;
+use toml_edit::DocumentMut;
+
+/// A git `source` of a package in `Cargo.lock`, e.g.
+/// `git+https://github.com/starkware-libs/cairo?branch=main#0123abcd`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitSource {
+    /// Repository URL, without the `git+` prefix, query and fragment.
+    pub url: String,
+    /// Reference requested in the manifest.
+    pub reference: GitReference,
+    /// Commit the reference was resolved to.
+    pub resolved_sha: String,
+}
+
+/// Git reference requested for a git dependency.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitReference {
+    Rev(String),
+    Branch(String),
+    Tag(String),
+    /// No reference given, the default branch of the repository is used.
+    DefaultBranch,
+}
+
+/// A package entry in `Cargo.lock` which is sourced from git.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockedGitPackage {
+    pub name: String,
+    pub version: String,
+    pub source: GitSource,
+}
+
+/// A package entry in `Cargo.lock` which is sourced from a registry, e.g. crates.io.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockedRegistryPackage {
+    pub name: String,
+    pub version: String,
+    /// Registry URL, including the `registry+` or `sparse+` prefix.
+    pub source: String,
+}
+
+impl FromStr for GitSource {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let Some(rest) = source.strip_prefix("git+") else {
+            bail!("not a git source: `{source}`");
+        };
+        let Some((rest, resolved_sha)) = rest.rsplit_once('#') else {
+            bail!("git source is missing the resolved commit: `{source}`");
+        };
+        let (url, reference) = match rest.split_once('?') {
+            None => (rest, GitReference::DefaultBranch),
+            Some((url, query)) => {
+                let Some((kind, value)) = query.split_once('=') else {
+                    bail!("malformed git source query `{query}` in `{source}`");
+                };
+                let value = percent_decode(value)
+                    .with_context(|| format!("malformed git source query in `{source}`"))?;
+                let reference = match kind {
+                    "rev" => GitReference::Rev(value),
+                    "branch" => GitReference::Branch(value),
+                    "tag" => GitReference::Tag(value),
+                    _ => bail!("unknown git reference kind `{kind}` in `{source}`"),
+                };
+                (url, reference)
+            }
+        };
+
+        if url.is_empty() || resolved_sha.is_empty() {
+            bail!("malformed git source: `{source}`");
+        }
+
+        Ok(Self {
+            url: url.to_owned(),
+            reference,
+            resolved_sha: resolved_sha.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for GitSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "git+{}", self.url)?;
+        match &self.reference {
+            GitReference::Rev(rev) => write!(f, "?rev={rev}")?,
+            GitReference::Branch(branch) => write!(f, "?branch={branch}")?,
+            GitReference::Tag(tag) => write!(f, "?tag={tag}")?,
+            GitReference::DefaultBranch => {}
+        }
+        write!(f, "#{}", self.resolved_sha)
+    }
+}
+
+/// Lists all packages in a parsed `Cargo.lock` that are sourced from git.
+pub fn git_packages(cargo_lock: &DocumentMut) -> Result<Vec<LockedGitPackage>> {
+    let Some(packages) = cargo_lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+    else {
+        return Ok(Vec::new());
+    };
+
+    packages
+        .iter()
+        .filter_map(|pkg| {
+            let source = pkg.get("source")?.as_str()?;
+            source.starts_with("git+").then_some((pkg, source))
+        })
+        .map(|(pkg, source)| {
+            Ok(LockedGitPackage {
+                name: pkg["name"].as_str().unwrap_or_default().to_owned(),
+                version: pkg["version"].as_str().unwrap_or_default().to_owned(),
+                source: source.parse()?,
+            })
+        })
+        .collect()
+}
+
+/// Lists all packages in a parsed `Cargo.lock` that are sourced from a registry.
+pub fn registry_packages(cargo_lock: &DocumentMut) -> Vec<LockedRegistryPackage> {
+    let Some(packages) = cargo_lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+    else {
+        return Vec::new();
+    };
+
+    packages
+        .iter()
+        .filter_map(|pkg| {
+            let source = pkg.get("source")?.as_str()?;
+            (source.starts_with("registry+") || source.starts_with("sparse+")).then(|| {
+                LockedRegistryPackage {
+                    name: pkg["name"].as_str().unwrap_or_default().to_owned(),
+                    version: pkg["version"].as_str().unwrap_or_default().to_owned(),
+                    source: source.to_owned(),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Finds versions of all packages named `crate_name` in a parsed `Cargo.lock`.
+pub fn locked_versions(cargo_lock: &DocumentMut, crate_name: &str) -> Result<Vec<Version>> {
+    let Some(packages) = cargo_lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+    else {
+        return Ok(Vec::new());
+    };
+    packages
+        .iter()
+        .filter(|pkg| pkg.get("name").and_then(|name| name.as_str()) == Some(crate_name))
+        .map(|pkg| {
+            let version = pkg["version"].as_str().unwrap_or_default();
+            Ok(version.parse()?)
+        })
+        .collect()
+}
+
+/// Lists names of unused patches, recorded by Cargo in the `[[patch.unused]]` array.
+pub fn unused_patches(cargo_lock: &DocumentMut) -> Vec<String> {
+    let Some(unused) = cargo_lock
+        .get("patch")
+        .and_then(|patch| patch.get("unused"))
+        .and_then(|unused| unused.as_array_of_tables())
+    else {
+        return Vec::new();
+    };
+    unused
+        .iter()
+        .filter_map(|table| Some(table.get("name")?.as_str()?.to_owned()))
+        .collect()
+}
+
+/// Decodes `%XX` escapes, which Cargo uses for special characters in branch and tag names.
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let Some(hex) = s.get(i + 1..i + 3) else {
+                bail!("truncated escape sequence in `{s}`");
+            };
+            decoded.push(u8::from_str_radix(hex, 16)?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAIRO: &str = "https://github.com/starkware-libs/cairo";
+    const SHA: &str = "d8b5e1f7c2a3b4d5e6f708192a3b4c5d6e7f8091";
+
+    #[test]
+    fn test_parse_rev() {
+        let source: GitSource = format!("git+{CAIRO}?rev=d8b5e1f#{SHA}").parse().unwrap();
+        assert_eq!(
+            source,
+            GitSource {
+                url: CAIRO.into(),
+                reference: GitReference::Rev("d8b5e1f".into()),
+                resolved_sha: SHA.into(),
+            }
+        );
+        assert_eq!(source.to_string(), format!("git+{CAIRO}?rev=d8b5e1f#{SHA}"));
+    }
+
+    #[test]
+    fn test_parse_branch() {
+        let source: GitSource = format!("git+{CAIRO}?branch=release%2F2.9#{SHA}")
+            .parse()
+            .unwrap();
+        assert_eq!(source.url, CAIRO);
+        assert_eq!(source.reference, GitReference::Branch("release/2.9".into()));
+        assert_eq!(source.resolved_sha, SHA);
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        let source: GitSource = format!("git+{CAIRO}?tag=v2.9.2#{SHA}").parse().unwrap();
+        assert_eq!(source.reference, GitReference::Tag("v2.9.2".into()));
+        assert_eq!(source.resolved_sha, SHA);
+    }
+
+    #[test]
+    fn test_parse_default_branch() {
+        let source: GitSource = format!("git+{CAIRO}#{SHA}").parse().unwrap();
+        assert_eq!(source.url, CAIRO);
+        assert_eq!(source.reference, GitReference::DefaultBranch);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("registry+https://github.com/rust-lang/crates.io-index"
+            .parse::<GitSource>()
+            .is_err());
+        assert!(format!("git+{CAIRO}?rev=abc").parse::<GitSource>().is_err());
+        assert!(format!("git+{CAIRO}?commit=abc#{SHA}")
+            .parse::<GitSource>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_git_packages() {
+        let cargo_lock = format!(
+            r#"
+version = 4
+
+[[package]]
+name = "anyhow"
+version = "1.0.95"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "cairo-lang-compiler"
+version = "2.9.2"
+source = "git+{CAIRO}?rev=d8b5e1f#{SHA}"
+"#
+        )
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        let packages = git_packages(&cargo_lock).unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].name, "cairo-lang-compiler");
+        assert_eq!(packages[0].version, "2.9.2");
+        assert_eq!(packages[0].source.resolved_sha, SHA);
+    }
+
+    #[test]
+    fn test_registry_queries() {
+        let cargo_lock = format!(
+            r#"
+version = 4
+
+[[package]]
+name = "cairo-lang-compiler"
+version = "2.9.2"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "cairo-lang-compiler"
+version = "2.8.0"
+source = "git+{CAIRO}?rev=d8b5e1f#{SHA}"
+
+[[patch.unused]]
+name = "cairo-lang-lowering"
+version = "2.9.2"
+source = "git+{CAIRO}?rev=d8b5e1f#{SHA}"
+"#
+        )
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        let packages = registry_packages(&cargo_lock);
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].version, "2.9.2");
+        assert_eq!(
+            locked_versions(&cargo_lock, "cairo-lang-compiler").unwrap(),
+            [Version::new(2, 9, 2), Version::new(2, 8, 0)]
+        );
+        assert_eq!(unused_patches(&cargo_lock), ["cairo-lang-lowering"]);
+        assert!(unused_patches(&"version = 4\n".parse().unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,590 @@
+//! Primitives for editing `Cargo.toml` files while preserving their formatting.
+//!
+//! These are the building blocks of the `upgrade` xtask, exposed for projects implementing their
+//! own dependency tweaks. Queries on `Cargo.lock` live in [`crate::lockfile`].
+//!
+//! ```
+//! use cairo_toolchain_xtasks::manifest;
+//! use toml_edit::{DocumentMut, InlineTable};
+//!
+//! let mut cargo_toml = r#"
+//! [dependencies]
+//! cairo-lang-compiler = { version = "2.8.0", features = ["serde"] }
+//! "#
+//! .parse::<DocumentMut>()
+//! .unwrap();
+//!
+//! let deps = manifest::get_item_mut(&mut cargo_toml, "dependencies").unwrap();
+//! manifest::set_registry_version(&mut deps["cairo-lang-compiler"], "2.9.0");
+//!
+//! let patch = manifest::patch_table(&mut cargo_toml).unwrap();
+//! let mut source = InlineTable::new();
+//! source.insert("git", "https://github.com/starkware-libs/cairo".into());
+//! source.insert("branch", "main".into());
+//! manifest::upsert_patch(patch, "cairo-lang-compiler", source);
+//!
+//! assert_eq!(
+//!     cargo_toml.to_string(),
+//!     r#"
+//! [dependencies]
+//! cairo-lang-compiler = { version = "2.9.0", features = ["serde"] }
+//!
+//! [patch.crates-io]
+//! cairo-lang-compiler = { git = "https://github.com/starkware-libs/cairo", branch = "main" }
+//! "#
+//! );
+//! ```
+
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::mem;
+use std::path::Path;
+use toml_edit::{Decor, DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+/// Keys selecting the source of a dependency, replaced when repointing a patch entry.
+pub const SOURCE_KEYS: &[&str] = &["git", "branch", "tag", "rev", "path", "version", "registry"];
+
+/// Keys of a dependency spec kept by [`set_registry_version`], in the order they are written in
+/// around `version`, which follows `package`.
+pub const PRESERVED_KEYS: &[&str] = &["package", "default-features", "features", "optional"];
+
+/// Reads and parses a manifest.
+pub fn read(path: &Path) -> Result<DocumentMut> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .parse::<DocumentMut>()
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Gets a table-like item at a `.`-separated path, if it exists.
+pub fn get_table_like<'a>(
+    cargo_toml: &'a DocumentMut,
+    table_path: &str,
+) -> Option<&'a dyn TableLike> {
+    table_path
+        .split('.')
+        .try_fold(cargo_toml.as_item(), |doc, key| doc.get(key))
+        .and_then(|deps| deps.as_table_like())
+}
+
+/// Gets a mutable item at a `.`-separated path, if it exists.
+///
+/// Unlike indexing, this never inserts missing tables.
+pub fn get_item_mut<'a>(cargo_toml: &'a mut DocumentMut, table_path: &str) -> Option<&'a mut Item> {
+    table_path
+        .split('.')
+        .try_fold(cargo_toml.as_item_mut(), |doc, key| {
+            doc.as_table_like_mut()?.get_mut(key)
+        })
+}
+
+/// Renders a dependency spec on a single line, also if it is written as a table.
+pub fn display_dependency(dep: &Item) -> String {
+    match dep.clone().into_value() {
+        Ok(dep) => dep.to_string().trim().to_owned(),
+        Err(dep) => dep.to_string().trim().to_owned(),
+    }
+}
+
+/// Name of the crate the dependency `key = dep` is on, which differs from the key of renamed
+/// dependencies declaring a `package`.
+pub fn crate_name<'a>(key: &'a str, dep: &'a Item) -> &'a str {
+    dep.get("package").and_then(Item::as_str).unwrap_or(key)
+}
+
+/// Checks whether a dependency spec inherits from `[workspace.dependencies]` with
+/// `workspace = true`.
+pub fn is_inherited(dep: &Item) -> bool {
+    dep.get("workspace").and_then(Item::as_bool) == Some(true)
+}
+
+/// Replaces a dependency spec with a crates.io requirement on `version`, keeping its
+/// [`PRESERVED_KEYS`], like features or `optional`.
+///
+/// All other keys are dropped, as they may select another source. The spec keeps its form, see
+/// [`replace_dependency`], and is shortened to a bare version string if possible.
+///
+/// Specs inheriting from the workspace are left untouched, including the features they add on
+/// top of the workspace ones: their version follows `[workspace.dependencies]`. So are specs
+/// already requiring `"*"` with no other keys than [`PRESERVED_KEYS`], which makes repeating an
+/// upgrade to a git or path patch byte-identical.
+pub fn set_registry_version(dep: &mut Item, version: impl Into<Value>) {
+    let version = version.into();
+    if is_inherited(dep) || requires_only(dep, &version) {
+        return;
+    }
+    let kept = |key: &str| {
+        dep.as_table_like()
+            .and_then(|dep| dep.get(key))
+            .and_then(Item::as_value)
+            .cloned()
+    };
+    let mut new_dep = InlineTable::new();
+    for &key in PRESERVED_KEYS {
+        if key == "default-features" {
+            new_dep.insert("version", version.clone());
+        }
+        if let Some(value) = kept(key) {
+            new_dep.insert(key, value);
+        }
+    }
+    new_dep.fmt();
+
+    let mut new_dep = Value::from(new_dep);
+    simplify_dependency_table(&mut new_dep);
+    replace_dependency(dep, new_dep);
+}
+
+/// Checks whether a dependency spec requires `version` from crates.io, with no keys other than
+/// [`PRESERVED_KEYS`], for the `"*"` requirement of patched crates.
+///
+/// Other versions are rewritten anyway, so that a spec like `{ version = "2.9.0" }` is still
+/// shortened.
+fn requires_only(dep: &Item, version: &Value) -> bool {
+    let Some(version) = version.as_str().filter(|version| *version == "*") else {
+        return false;
+    };
+    match dep.as_table_like() {
+        Some(dep) => {
+            dep.get("version").and_then(Item::as_str) == Some(version)
+                && dep
+                    .iter()
+                    .all(|(key, _)| key == "version" || PRESERVED_KEYS.contains(&key))
+        }
+        None => dep.as_str() == Some(version),
+    }
+}
+
+/// Replaces a dependency spec, keeping the form of specs written as a table.
+///
+/// This way, both `[dependencies.foo]` sections and dotted keys like `foo.version = "1"` are
+/// rewritten in place, instead of being turned into inline tables. Manifests use sections for long
+/// feature lists, which would not read well inline, and moving an entry into `[dependencies]`
+/// would leave the comments of its section behind.
+pub fn replace_dependency(dep: &mut Item, new_dep: Value) {
+    match dep {
+        Item::Table(table) => {
+            let mut new_table = match new_dep {
+                Value::InlineTable(new_dep) => new_dep.into_table(),
+                version => Table::from_iter([("version", version)]),
+            };
+            new_table.set_dotted(table.is_dotted());
+            if let Some(position) = table.position() {
+                new_table.set_position(position);
+            }
+            *new_table.decor_mut() = table.decor().clone();
+            new_table.fmt();
+            *table = new_table;
+        }
+        Item::Value(value) => {
+            let mut new_dep = new_dep;
+            *new_dep.decor_mut() = value.decor().clone();
+            *value = new_dep;
+        }
+        dep => *dep = Item::Value(new_dep),
+    }
+}
+
+/// Simplifies a `{ version = "V" }` dependency spec to shorthand `"V"` if possible.
+pub fn simplify_dependency_table(dep: &mut Value) {
+    *dep = match mem::replace(dep, false.into()) {
+        Value::InlineTable(mut table) => {
+            if table.len() == 1 {
+                table.remove("version").unwrap_or_else(|| table.into())
+            } else {
+                table.into()
+            }
+        }
+
+        dep => dep,
+    }
+}
+
+/// Gets the `[patch.crates-io]` table, creating it if missing.
+///
+/// A table written only as dotted keys, like `patch.crates-io.foo = { ... }`, or only as
+/// `[patch.crates-io.foo]` sub-tables, is turned into a `[patch.crates-io]` section, so that
+/// entries inserted into it are written in the same form as in a fresh one.
+///
+/// Returns `None` if the manifest has a `patch` or `patch.crates-io` item which is not a table.
+pub fn patch_table(cargo_toml: &mut DocumentMut) -> Option<&mut dyn TableLike> {
+    if get_item_mut(cargo_toml, "patch.crates-io").is_none() {
+        let patch = cargo_toml.entry("patch").or_insert_with(|| {
+            let mut patch = Table::new();
+            patch.set_implicit(true);
+            Item::Table(patch)
+        });
+        patch
+            .as_table_like_mut()?
+            .insert("crates-io", toml_edit::table());
+    }
+    if let Some(patch) = cargo_toml.get_mut("patch").and_then(Item::as_table_mut) {
+        let mut dotted = patch.is_dotted();
+        patch.set_dotted(false);
+        if let Some(crates_io) = patch.get_mut("crates-io").and_then(Item::as_table_mut) {
+            dotted |= crates_io.is_dotted();
+            crates_io.set_dotted(false);
+        }
+        // Without its dotted keys, `[patch]` may be left without entries of its own.
+        if dotted {
+            patch.set_implicit(true);
+        }
+    }
+    get_item_mut(cargo_toml, "patch.crates-io").and_then(|patch| patch.as_table_like_mut())
+}
+
+/// Describes how the `patch` or `patch.crates-io` item deviates from a `[patch.crates-io]` table,
+/// if it exists and is malformed.
+///
+/// ```
+/// use cairo_toolchain_xtasks::manifest;
+/// use toml_edit::DocumentMut;
+///
+/// let cargo_toml = "[[patch]]\n".parse::<DocumentMut>().unwrap();
+/// assert_eq!(
+///     manifest::malformed_patch_table(&cargo_toml).unwrap(),
+///     "`patch` is an array of tables (`[[patch]]`), expected a table of sources with a \
+///     `[patch.crates-io]` sub-table"
+/// );
+/// ```
+pub fn malformed_patch_table(cargo_toml: &DocumentMut) -> Option<String> {
+    let describe = |key: &str, item: &Item| match item {
+        Item::ArrayOfTables(_) => format!("an array of tables (`[[{key}]]`)"),
+        item => format!("a {}", item.type_name()),
+    };
+    let patch = cargo_toml.get("patch")?;
+    let Some(patch) = patch.as_table_like() else {
+        return Some(format!(
+            "`patch` is {}, expected a table of sources with a `[patch.crates-io]` sub-table",
+            describe("patch", patch)
+        ));
+    };
+    let crates_io = patch.get("crates-io")?;
+    if crates_io.is_table_like() {
+        return None;
+    }
+    Some(format!(
+        "`patch.crates-io` is {}, expected a `[patch.crates-io]` table mapping crate names to \
+        their sources",
+        describe("patch.crates-io", crates_io)
+    ))
+}
+
+/// Points the patch entry of `crate_name` to `source`, adding it if missing.
+///
+/// Existing entries keep all keys other than [`SOURCE_KEYS`]. Those written on a single line also
+/// keep their position and comments, while those written as sub-tables or dotted keys are turned
+/// into a single line like new entries.
+pub fn upsert_patch(patch: &mut dyn TableLike, crate_name: &str, mut source: InlineTable) {
+    let Some(entry) = patch.get_mut(crate_name) else {
+        patch.insert(crate_name, Item::Value(source.into()));
+        return;
+    };
+
+    if let Some(old) = entry.as_table_like() {
+        for (key, value) in old.iter().filter(|(key, _)| !SOURCE_KEYS.contains(key)) {
+            if let Some(value) = value.as_value() {
+                source.insert(key, value.clone());
+            }
+        }
+    }
+    let Some(value) = entry.as_value_mut() else {
+        source.fmt();
+        *entry = Item::Value(source.into());
+        if let Some(mut key) = patch.key_mut(crate_name) {
+            key.fmt();
+        }
+        return;
+    };
+    let decor = value.decor().clone();
+    *value = source.into();
+    *value.decor_mut() = decor;
+}
+
+/// Removes the `[patch.crates-io]` entry of `crate_name`, returning it if it existed.
+///
+/// Never creates the table.
+pub fn remove_patch(cargo_toml: &mut DocumentMut, crate_name: &str) -> Option<Item> {
+    get_item_mut(cargo_toml, "patch.crates-io")?
+        .as_table_like_mut()?
+        .remove(crate_name)
+}
+
+/// Sorts the entries of `table` for which `selected` holds among themselves, keeping other entries
+/// in place.
+///
+/// Entries written as sections keep their position in the file regardless of the order of keys,
+/// so only entries written on a single line are moved, along with their comments.
+pub(crate) fn sort_selected(
+    table: &mut Table,
+    selected: impl Fn(&str, &Item) -> bool,
+    compare: impl Fn(&str, &str) -> Ordering,
+) {
+    let keys = table
+        .iter()
+        .map(|(key, _)| key.to_owned())
+        .collect::<Vec<_>>();
+    let owned = keys
+        .iter()
+        .filter(|key| {
+            let item = &table[key.as_str()];
+            item.is_value() && selected(key, item)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut sorted = owned.clone();
+    sorted.sort_by(|a, b| compare(a, b));
+    if sorted == owned {
+        return;
+    }
+    let is_owned = |key: &str| owned.iter().any(|owned| owned == key);
+    let (mut moved, kept): (Vec<_>, Vec<_>) = keys
+        .iter()
+        .filter_map(|key| table.remove_entry(key))
+        .partition(|(key, _)| is_owned(key.get()));
+    moved.sort_by(|(a, _), (b, _)| compare(a.get(), b.get()));
+    // Selected entries take the places of selected entries, in order.
+    let (mut moved, mut kept) = (moved.into_iter(), kept.into_iter());
+    for key in &keys {
+        let entry = match is_owned(key) {
+            true => moved.next(),
+            false => kept.next(),
+        };
+        if let Some((key, item)) = entry {
+            table.insert_formatted(&key, item);
+        }
+    }
+}
+
+/// Removes the `[patch.crates-io]` table if it has no entries left, along with the `[patch]`
+/// table if it has no other sources.
+pub fn remove_empty_patch_table(cargo_toml: &mut DocumentMut) {
+    let Some(patch) = cargo_toml
+        .get_mut("patch")
+        .and_then(Item::as_table_like_mut)
+    else {
+        return;
+    };
+    if patch
+        .get("crates-io")
+        .and_then(Item::as_table_like)
+        .is_some_and(|crates_io| crates_io.is_empty())
+    {
+        patch.remove("crates-io");
+    }
+    if patch.is_empty() {
+        cargo_toml.remove("patch");
+    }
+}
+
+/// Removes blank lines before the first table header, if no entries precede it.
+///
+/// These are left at the start of the file once entries written before the first header, like
+/// dotted `patch.crates-io.foo` keys, are removed.
+pub(crate) fn trim_leading_blank_lines(cargo_toml: &mut DocumentMut) {
+    let root = cargo_toml.as_table_mut();
+    if root
+        .iter()
+        .any(|(_, item)| item.is_value() || item.as_table().is_some_and(Table::is_dotted))
+    {
+        return;
+    }
+    let Some(decor) = first_header(root).and_then(|position| header_decor(root, position)) else {
+        return;
+    };
+    let prefix = decor.prefix().and_then(|prefix| prefix.as_str());
+    if let Some(prefix) = prefix.filter(|prefix| prefix.starts_with(['\n', '\r'])) {
+        let trimmed = prefix.trim_start_matches(['\n', '\r']).to_owned();
+        decor.set_prefix(trimmed);
+    }
+}
+
+/// Position of the first table header within `table`, including nested tables.
+fn first_header(table: &Table) -> Option<usize> {
+    let of_table = |table: &Table| match table.is_implicit() || table.is_dotted() {
+        true => first_header(table),
+        false => table.position(),
+    };
+    table
+        .iter()
+        .filter_map(|(_, item)| match item {
+            Item::Table(table) => of_table(table),
+            Item::ArrayOfTables(tables) => tables.iter().filter_map(of_table).min(),
+            _ => None,
+        })
+        .min()
+}
+
+/// Decor of the header at `position` within `table`, see [`first_header`].
+fn header_decor(table: &mut Table, position: usize) -> Option<&mut Decor> {
+    fn of_table(table: &mut Table, position: usize) -> Option<&mut Decor> {
+        match table.position() == Some(position) {
+            true => Some(table.decor_mut()),
+            false => header_decor(table, position),
+        }
+    }
+    table.iter_mut().find_map(|(_, item)| match item {
+        Item::Table(table) => of_table(table, position),
+        Item::ArrayOfTables(tables) => tables
+            .iter_mut()
+            .find_map(|table| of_table(table, position)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_registry_version() {
+        let mut cargo_toml = r#"
+[dependencies]
+a = { git = "https://example.com/a", features = ["x"] }
+b = { path = "../b" }
+
+[dependencies.c]
+version = "1"
+default-features = false
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        for name in ["a", "b", "c"] {
+            set_registry_version(&mut cargo_toml["dependencies"][name], "2.0.0");
+        }
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"
+[dependencies]
+a = { version = "2.0.0", features = ["x"] }
+b = "2.0.0"
+
+[dependencies.c]
+version = "2.0.0"
+default-features = false
+"#
+        );
+    }
+
+    #[test]
+    fn test_set_registry_version_keeps_preserved_keys() {
+        let mut cargo_toml = r#"
+[dependencies]
+a = { optional = true, git = "https://example.com/a", features = ["x"], package = "a-fork", default-features = false }
+
+[dependencies.b]
+path = "../b"
+optional = true
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        for name in ["a", "b"] {
+            set_registry_version(&mut cargo_toml["dependencies"][name], "2.0.0");
+        }
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"
+[dependencies]
+a = { package = "a-fork", version = "2.0.0", default-features = false, features = ["x"], optional = true }
+
+[dependencies.b]
+version = "2.0.0"
+optional = true
+"#
+        );
+
+        // Only a bare version is shortened.
+        let mut dep = Item::Value(r#"{ version = "1", optional = true }"#.parse().unwrap());
+        set_registry_version(&mut dep, "2.0.0");
+        assert_eq!(dep.to_string(), r#"{ version = "2.0.0", optional = true }"#);
+        let mut dep = Item::Value(r#"{ path = "../a" }"#.parse().unwrap());
+        set_registry_version(&mut dep, "2.0.0");
+        assert_eq!(dep.to_string(), r#""2.0.0""#);
+        let mut dep = Item::Value(r#"{ version = "2.0.0" }"#.parse().unwrap());
+        set_registry_version(&mut dep, "2.0.0");
+        assert_eq!(dep.to_string(), r#""2.0.0""#);
+    }
+
+    #[test]
+    fn test_set_registry_version_keeps_inherited_features() {
+        let mut cargo_toml = r#"
+[workspace.dependencies]
+a = { version = "1.0.0", features = ["base"] }
+
+[dependencies]
+a = { workspace = true, features = ["extra"] }
+
+[dev-dependencies.a]
+workspace = true
+features = ["testing"]
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        set_registry_version(&mut cargo_toml["workspace"]["dependencies"]["a"], "2.0.0");
+        set_registry_version(&mut cargo_toml["dependencies"]["a"], "2.0.0");
+        set_registry_version(&mut cargo_toml["dev-dependencies"]["a"], "2.0.0");
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"
+[workspace.dependencies]
+a = { version = "2.0.0", features = ["base"] }
+
+[dependencies]
+a = { workspace = true, features = ["extra"] }
+
+[dev-dependencies.a]
+workspace = true
+features = ["testing"]
+"#
+        );
+    }
+
+    #[test]
+    fn test_get_item_mut_does_not_insert() {
+        let mut cargo_toml = "[package]\n".parse::<DocumentMut>().unwrap();
+        assert!(get_item_mut(&mut cargo_toml, "workspace.dependencies").is_none());
+        assert!(get_item_mut(&mut cargo_toml, "package").is_some());
+        assert_eq!(cargo_toml.to_string(), "[package]\n");
+        assert!(get_table_like(&cargo_toml, "package").is_some());
+    }
+
+    #[test]
+    fn test_upsert_and_remove_patch() {
+        let mut cargo_toml = r#"[patch.crates-io]
+a = { git = "https://example.com/a", rev = "abc", package = "a-fork" } # pinned
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        let patch = patch_table(&mut cargo_toml).unwrap();
+        upsert_patch(
+            patch,
+            "a",
+            InlineTable::from_iter([("path", Value::from("../a"))]),
+        );
+        upsert_patch(
+            patch,
+            "b",
+            InlineTable::from_iter([("path", Value::from("../b"))]),
+        );
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[patch.crates-io]
+a = { path = "../a", package = "a-fork" } # pinned
+b = { path = "../b" }
+"#
+        );
+
+        assert!(remove_patch(&mut cargo_toml, "a").is_some());
+        assert!(remove_patch(&mut cargo_toml, "a").is_none());
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[patch.crates-io]\nb = { path = \"../b\" }\n"
+        );
+
+        let mut cargo_toml = "[package]\n".parse::<DocumentMut>().unwrap();
+        assert!(remove_patch(&mut cargo_toml, "a").is_none());
+        assert_eq!(cargo_toml.to_string(), "[package]\n");
+    }
+}
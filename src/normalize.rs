@@ -0,0 +1,314 @@
+//! Canonicalize the formatting of the dependency specs and patches of a toolchain dependency group.
+
+use crate::context::{Context, GlobalArgs};
+use crate::eol::LineEnding;
+use crate::manifest::{self, get_item_mut};
+use crate::upgrade::{DepName, DEPENDENCY_TABLES};
+use crate::workspace;
+use anyhow::{bail, Context as _, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use toml_edit::{Array, DocumentMut, Item, Key, Table, Value};
+
+/// Keys of dependency specs in the order they are written in, others follow in their original
+/// order.
+const KEY_ORDER: &[&str] = &[
+    "package",
+    "version",
+    "registry",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "path",
+    "workspace",
+    "default-features",
+    "features",
+    "optional",
+];
+
+/// Bring the entries of a toolchain dependency group in `Cargo.toml` and the manifests of the
+/// workspace members to the form `upgrade` writes.
+///
+/// Within each dependency table and `[patch.crates-io]`, the entries of the group are sorted by
+/// name, the keys of their specs are put in a canonical order, feature lists are sorted and
+/// deduplicated, and `{ version = "V" }` is shortened to `"V"`. Versions and sources are never
+/// changed, and neither are entries of other crates. Crates are attributed to the group by their
+/// names, so this needs no network access.
+#[derive(Parser)]
+pub struct Args {
+    /// Name of the toolchain dependency group to normalize.
+    group: DepName,
+
+    /// Do not modify `Cargo.toml`, fail if it is not normalized.
+    #[arg(long, default_value_t = false)]
+    check: bool,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, &args)
+}
+
+fn run(ctx: &Context, args: &Args) -> Result<()> {
+    let sh = &ctx.sh;
+    let root = sh.current_dir();
+    let cargo_toml = manifest::read(&root.join("Cargo.toml"))?;
+    let mut manifests = vec![PathBuf::from("Cargo.toml")];
+    manifests.extend(
+        workspace::members(&root, &cargo_toml)?
+            .into_iter()
+            .map(|member| member.join("Cargo.toml")),
+    );
+
+    let mut edits = Vec::new();
+    let mut tables = Vec::new();
+    for path in manifests {
+        let original = sh.read_file(&path)?;
+        let eol = LineEnding::detect(&original);
+        let mut manifest = original
+            .parse::<DocumentMut>()
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let changed = normalize(&mut manifest, args.group);
+        if !changed.is_empty() {
+            tables.extend(
+                changed
+                    .iter()
+                    .map(|table_path| format!("{} [{table_path}]", path.display())),
+            );
+            edits.push((path, eol.apply(&manifest.to_string())));
+        }
+    }
+    let name = args.group.name();
+    if tables.is_empty() {
+        ctx.note(format_args!("entries of {name} are normalized"));
+        return Ok(());
+    }
+
+    let tables = tables.join(", ");
+    if args.check {
+        bail!(
+            "entries of {name} are not normalized in {tables}, run `cargo xtask normalize {name}` \
+            to fix them"
+        );
+    }
+    for (path, contents) in edits {
+        sh.write_file(path, contents)?;
+    }
+    ctx.note(format_args!("normalized entries of {name} in {tables}"));
+    Ok(())
+}
+
+/// Normalizes the entries of `group` in all dependency tables and `[patch.crates-io]`, returning
+/// the paths of the tables that changed.
+///
+/// Tables written inline, like `dependencies = { ... }`, are left alone.
+fn normalize(cargo_toml: &mut DocumentMut, group: DepName) -> Vec<String> {
+    let mut changed = Vec::new();
+    for &table_path in DEPENDENCY_TABLES.iter().chain(&["patch.crates-io"]) {
+        let Some(table) = get_item_mut(cargo_toml, table_path).and_then(Item::as_table_mut) else {
+            continue;
+        };
+        let before = table.to_string();
+        normalize_table(table, |crate_name| group.owns(crate_name));
+        if table.to_string() != before {
+            changed.push(table_path.to_owned());
+        }
+    }
+    changed
+}
+
+/// Normalizes the specs of crates in `table` for which `owns` holds, and sorts those written on a
+/// single line among themselves, keeping other entries in place.
+fn normalize_table(table: &mut Table, owns: impl Fn(&str) -> bool) {
+    let owns = |key: &str, dep: &Item| owns(manifest::crate_name(key, dep));
+    for (key, dep) in table.iter_mut() {
+        if owns(key.get(), dep) {
+            normalize_dependency(dep);
+        }
+    }
+    manifest::sort_selected(table, owns, str::cmp);
+}
+
+/// Puts the keys of a dependency spec in [`KEY_ORDER`], sorts its features and shortens it to a
+/// version string if it has no other keys.
+fn normalize_dependency(dep: &mut Item) {
+    let by_key_order = |a: &Key, b: &Key| rank(a).cmp(&rank(b));
+    match dep {
+        Item::Value(Value::InlineTable(spec)) => {
+            spec.sort_values_by(|a, _, b, _| by_key_order(a, b));
+            if let Some(features) = spec.get_mut("features").and_then(Value::as_array_mut) {
+                normalize_features(features);
+            }
+            spec.fmt();
+        }
+        Item::Table(spec) => {
+            spec.sort_values_by(|a, _, b, _| by_key_order(a, b));
+            if let Some(features) = spec.get_mut("features").and_then(Item::as_array_mut) {
+                normalize_features(features);
+            }
+        }
+        _ => {}
+    }
+    if let Some(spec) = dep.as_value_mut() {
+        let decor = spec.decor().clone();
+        manifest::simplify_dependency_table(spec);
+        *spec.decor_mut() = decor;
+    }
+}
+
+/// Position of a key in [`KEY_ORDER`], with unknown keys last.
+fn rank(key: &Key) -> usize {
+    KEY_ORDER
+        .iter()
+        .position(|known| *known == key.get())
+        .unwrap_or(KEY_ORDER.len())
+}
+
+/// Sorts and deduplicates a list of features written as plain strings, on a single line.
+fn normalize_features(features: &mut Array) {
+    let Some(mut names) = features
+        .iter()
+        .map(|feature| feature.as_str().map(str::to_owned))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    names.sort();
+    names.dedup();
+    let decor = features.decor().clone();
+    *features = names.into_iter().collect();
+    *features.decor_mut() = decor;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    const MESSY: &str = r#"[workspace.dependencies]
+cairo-lang-utils = { features = ["tracing", "serde",  "serde"], version = "2.9.2" }
+anyhow = "1"
+cairo-lang-compiler = {version="2.9.2"}
+cairo-lang-casm = { default-features = false, version = "2.9.2" }
+serde = { features = ["std", "derive"], version = "1" }
+
+[workspace.dependencies.cairo-lang-sierra]
+features = ["serde", "schemars"]
+version = "2.9.2"
+
+[patch.crates-io]
+cairo-lang-utils = { rev = "0b3bfdc", git = "https://github.com/starkware-libs/cairo" }
+# Local fork.
+cairo-lang-casm = { path = "../cairo/crates/cairo-lang-casm" }
+cairo-language-server = { rev = "1a2b3c4", git = "https://github.com/software-mansion/cairols" }
+"#;
+
+    #[test]
+    fn test_normalize() {
+        let mut cargo_toml = MESSY.parse::<DocumentMut>().unwrap();
+        assert_eq!(
+            normalize(&mut cargo_toml, DepName::Cairo),
+            ["workspace.dependencies", "patch.crates-io"]
+        );
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[workspace.dependencies]
+cairo-lang-casm = { version = "2.9.2", default-features = false }
+anyhow = "1"
+cairo-lang-compiler = "2.9.2"
+cairo-lang-utils = { version = "2.9.2", features = ["serde", "tracing"] }
+serde = { features = ["std", "derive"], version = "1" }
+
+[workspace.dependencies.cairo-lang-sierra]
+version = "2.9.2"
+features = ["schemars", "serde"]
+
+[patch.crates-io]
+# Local fork.
+cairo-lang-casm = { path = "../cairo/crates/cairo-lang-casm" }
+cairo-lang-utils = { git = "https://github.com/starkware-libs/cairo", rev = "0b3bfdc" }
+cairo-language-server = { rev = "1a2b3c4", git = "https://github.com/software-mansion/cairols" }
+"#
+        );
+
+        let normalized = cargo_toml.to_string();
+        assert!(normalize(&mut cargo_toml, DepName::Cairo).is_empty());
+        assert_eq!(cargo_toml.to_string(), normalized);
+    }
+
+    #[test]
+    fn test_check() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.toml", MESSY.replace('\n', "\r\n"))
+            .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["normalize", "cairols", "--check"]);
+        assert_eq!(
+            run(&ctx, &args).unwrap_err().to_string(),
+            "entries of cairols are not normalized in Cargo.toml [patch.crates-io], run \
+            `cargo xtask normalize cairols` to fix them"
+        );
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            MESSY.replace('\n', "\r\n")
+        );
+
+        let args = Args::parse_from(["normalize", "cairols"]);
+        run(&ctx, &args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(cargo_toml.contains(
+            "cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+            rev = \"1a2b3c4\" }\r\n"
+        ));
+
+        let args = Args::parse_from(["normalize", "cairols", "--check"]);
+        run(&ctx, &args).unwrap();
+        let (_, stderr) = ctx.captured();
+        assert_eq!(
+            stderr,
+            [
+                "note: normalized entries of cairols in Cargo.toml [patch.crates-io]",
+                "note: entries of cairols are normalized",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_members() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.dependencies]\n\
+            cairo-lint-core = \"2.9.2\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "crates/a/Cargo.toml",
+            "[package]\nname = \"a\"\n\n[dependencies]\n\
+            cairo-lint-core = { version = \"2.9.2\" }\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["normalize", "cairolint"]);
+        run(&ctx, &args).unwrap();
+        assert_eq!(
+            sh.read_file("crates/a/Cargo.toml").unwrap(),
+            "[package]\nname = \"a\"\n\n[dependencies]\ncairo-lint-core = \"2.9.2\"\n"
+        );
+        let (_, stderr) = ctx.captured();
+        assert_eq!(
+            stderr,
+            ["note: normalized entries of cairolint in crates/a/Cargo.toml [dependencies]"]
+        );
+    }
+}
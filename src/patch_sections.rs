@@ -0,0 +1,421 @@
+//! Detect structural anomalies of the `[patch]` sections of a manifest, as left behind by messy
+//! merges, and repair the ones with an obvious fix.
+//!
+//! Duplicate tables and keys make the whole manifest fail to parse, so this works on its text:
+//! the manifest is split at table headers and each section is parsed on its own.
+
+use std::fmt;
+use std::ops::Range;
+use toml_edit::{ImDocument, Item, Key, TableLike};
+
+/// Keys of a patch entry, one of which must select where the crate is taken from.
+const SOURCE_KEYS: &[&str] = &["git", "path", "registry"];
+
+/// A structural problem of the patch sections of a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Anomaly {
+    /// Lines involved, counted from 1.
+    pub lines: Vec<usize>,
+    pub description: String,
+    /// Whether [`fix`] repairs this anomaly.
+    pub fixable: bool,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self
+            .lines
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let plural = if lines.len() == 1 { "" } else { "s" };
+        write!(f, "line{plural} {}: {}", lines.join(", "), self.description)
+    }
+}
+
+/// A table header along with the lines up to the next one.
+struct Section {
+    /// Keys of the header, empty for the lines before the first header.
+    path: Vec<String>,
+    /// Whether the header is one of an array of tables, like `[[bin]]`.
+    array: bool,
+    /// Indices of the lines of the section, starting with the header.
+    lines: Range<usize>,
+}
+
+impl Section {
+    fn has_header(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    fn is_patch(&self) -> bool {
+        !self.array && self.path.first().is_some_and(|key| key == "patch")
+    }
+}
+
+/// Line defining a table, and whether it is a table header rather than a dotted key.
+type TableDefinition = (usize, bool);
+
+/// Places a table or entry under `patch` is defined at.
+#[derive(Default)]
+struct Definitions {
+    /// Paths of `patch` and registry tables, with their definitions.
+    tables: Vec<(Vec<String>, Vec<TableDefinition>)>,
+    /// Paths of patch entries, with the lines defining them.
+    entries: Vec<(Vec<String>, Vec<usize>)>,
+}
+
+impl Definitions {
+    fn table(&mut self, path: Vec<String>, line: usize, header: bool) {
+        match self.tables.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, lines)) => lines.push((line, header)),
+            None => self.tables.push((path, vec![(line, header)])),
+        }
+    }
+
+    fn entry(&mut self, path: Vec<String>, line: usize) {
+        match self.entries.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, lines)) => lines.push(line),
+            None => self.entries.push((path, vec![line])),
+        }
+    }
+}
+
+/// Lists structural anomalies of the patch sections of the manifest `text`.
+///
+/// These are tables or entries defined more than once, `[patch]` headers without any entries,
+/// and entries with both a `path` and a `git` source, or none at all.
+pub(crate) fn check(text: &str) -> Vec<Anomaly> {
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let mut anomalies = Vec::new();
+    let mut definitions = Definitions::default();
+
+    for section in sections(&lines) {
+        if section.has_header() && !section.is_patch() {
+            continue;
+        }
+        let offset = section.lines.start;
+        let raw = lines[section.lines.clone()].concat();
+        let doc = match ImDocument::parse(raw.as_str()) {
+            Ok(doc) => doc,
+            Err(err) => {
+                if section.is_patch() {
+                    let start = err.span().map_or(0, |span| span.start);
+                    anomalies.push(Anomaly {
+                        lines: vec![offset + line_of(&raw, start)],
+                        description: err.message().to_owned(),
+                        fixable: false,
+                    });
+                }
+                continue;
+            }
+        };
+        let line = |key: &Key| offset + key.span().map_or(1, |span| line_of(&raw, span.start));
+
+        let Some((key, patch)) = doc.as_table().get_key_value("patch") else {
+            continue;
+        };
+        let Some(patch) = patch.as_table_like() else {
+            continue;
+        };
+        if section.path.len() <= 1 {
+            definitions.table(vec!["patch".to_owned()], line(key), section.has_header());
+        }
+        if section.path.len() == 1 && patch.is_empty() {
+            anomalies.push(Anomaly {
+                lines: vec![line(key)],
+                description: "`[patch]` has no entries".to_owned(),
+                fixable: true,
+            });
+        }
+
+        for (registry, entries) in iter(patch) {
+            let Some(entries) = entries.as_table_like() else {
+                continue;
+            };
+            if section.path.len() <= 2 {
+                let path = vec!["patch".to_owned(), registry.get().to_owned()];
+                definitions.table(path, line(registry), section.path.len() == 2);
+            }
+            if section.path.len() > 3 {
+                continue;
+            }
+            for (crate_name, entry) in iter(entries) {
+                let path = ["patch", registry.get(), crate_name.get()].map(str::to_owned);
+                let line = line(crate_name);
+                if let Some(description) = invalid_source(&path.join("."), entry) {
+                    anomalies.push(Anomaly {
+                        lines: vec![line],
+                        description,
+                        fixable: false,
+                    });
+                }
+                definitions.entry(path.to_vec(), line);
+            }
+        }
+    }
+
+    for (path, lines) in definitions.tables {
+        if lines.len() > 1 {
+            let fixable = lines.iter().all(|(_, header)| *header);
+            let description = if fixable {
+                format!("`[{}]` is declared {} times", path.join("."), lines.len())
+            } else {
+                format!(
+                    "`{}` is defined {} times, by both table headers and dotted keys",
+                    path.join("."),
+                    lines.len()
+                )
+            };
+            anomalies.push(Anomaly {
+                lines: lines.into_iter().map(|(line, _)| line).collect(),
+                description,
+                fixable,
+            });
+        }
+    }
+    for (path, lines) in definitions.entries {
+        if lines.len() > 1 {
+            anomalies.push(Anomaly {
+                description: format!(
+                    "`{}` is declared {} times, keep only one of them",
+                    path.join("."),
+                    lines.len()
+                ),
+                lines,
+                fixable: false,
+            });
+        }
+    }
+    anomalies.sort_by_key(|anomaly| anomaly.lines.clone());
+    anomalies
+}
+
+/// Repairs the fixable anomalies of the manifest `text`.
+///
+/// Tables declared more than once are merged into the first declaration, appending the entries
+/// of later ones, and `[patch]` headers without entries are dropped. Anything else is left as is.
+pub(crate) fn fix(text: &str) -> String {
+    let fixable = check(text)
+        .into_iter()
+        .filter(|anomaly| anomaly.fixable)
+        .flat_map(|anomaly| anomaly.lines)
+        .collect::<Vec<_>>();
+    if fixable.is_empty() {
+        return text.to_owned();
+    }
+
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let sections = sections(&lines);
+    let mut merged = sections
+        .iter()
+        .map(|section| lines[section.lines.clone()].to_vec())
+        .collect::<Vec<_>>();
+    for (index, section) in sections.iter().enumerate() {
+        let header = section.lines.start + 1;
+        if !section.is_patch() || !fixable.contains(&header) {
+            continue;
+        }
+        let first = sections[..index]
+            .iter()
+            .position(|other| other.is_patch() && other.path == section.path);
+        match first {
+            Some(first) => {
+                let body = trim_blank(&merged[index][1..]).to_vec();
+                let target = &mut merged[first];
+                let end = target.len() - trailing_blank(target);
+                target.splice(end..end, body);
+                merged[index].clear();
+            }
+            // An empty `[patch]` header, only its comments are kept.
+            None if section.path.len() == 1 => {
+                merged[index].remove(0);
+            }
+            None => {}
+        }
+    }
+
+    let mut fixed = String::new();
+    for line in merged.concat() {
+        fixed.push_str(line);
+        if !line.ends_with('\n') {
+            // The last line of the file may have moved into an earlier section.
+            fixed.push('\n');
+        }
+    }
+    fixed.truncate(fixed.trim_end().len());
+    fixed.push('\n');
+    fixed
+}
+
+/// Splits the lines of a manifest at table headers.
+fn sections(lines: &[&str]) -> Vec<Section> {
+    let mut sections = vec![Section {
+        path: Vec::new(),
+        array: false,
+        lines: 0..0,
+    }];
+    for (index, line) in lines.iter().enumerate() {
+        if let Some((path, array)) = parse_header(line) {
+            sections.push(Section {
+                path,
+                array,
+                lines: index..index,
+            });
+        }
+        sections.last_mut().unwrap().lines.end = index + 1;
+    }
+    sections
+}
+
+/// Parses a `[table]` or `[[array]]` header line into its keys.
+///
+/// Lines of multi-line arrays are told apart by not forming a valid header.
+fn parse_header(line: &str) -> Option<(Vec<String>, bool)> {
+    let line = line.trim();
+    let ((keys, rest), array) = match line.strip_prefix("[[") {
+        Some(line) => (line.split_once("]]")?, true),
+        None => (line.strip_prefix('[')?.split_once(']')?, false),
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return None;
+    }
+    let keys = Key::parse(keys).ok()?;
+    Some((keys.iter().map(|key| key.get().to_owned()).collect(), array))
+}
+
+/// Describes why a patch entry does not select exactly one source, if it does not.
+fn invalid_source(path: &str, entry: &Item) -> Option<String> {
+    let keys = entry
+        .as_table_like()
+        .map(|entry| entry.iter().map(|(key, _)| key).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if keys.contains(&"path") && keys.contains(&"git") {
+        Some(format!("`{path}` has both a `path` and a `git` source"))
+    } else if !keys.iter().any(|key| SOURCE_KEYS.contains(key)) {
+        Some(format!(
+            "`{path}` has no source, expected one of `{}`",
+            SOURCE_KEYS.join("`, `")
+        ))
+    } else {
+        None
+    }
+}
+
+/// Iterates over keys and items of a table, keeping key spans.
+fn iter(table: &dyn TableLike) -> impl Iterator<Item = (&Key, &Item)> {
+    table.iter().filter_map(|(key, _)| table.get_key_value(key))
+}
+
+/// Finds the line, counted from 1, of byte `offset` of `text`.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+fn trailing_blank(lines: &[&str]) -> usize {
+    lines
+        .iter()
+        .rev()
+        .take_while(|line| line.trim().is_empty())
+        .count()
+}
+
+fn trim_blank<'a, 'b>(lines: &'b [&'a str]) -> &'b [&'a str] {
+    let start = lines
+        .iter()
+        .take_while(|line| line.trim().is_empty())
+        .count();
+    let lines = &lines[start..];
+    &lines[..lines.len() - trailing_blank(lines)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let text = r#"[package]
+name = "foo"
+
+[patch]
+
+[patch.crates-io]
+a = { path = "../a" }
+b = { path = "../b", git = "https://example.com/b" }
+
+[dependencies]
+a = "1"
+
+[patch.crates-io]
+c = { version = "1" }
+a = { git = "https://example.com/a" }
+"#;
+        let anomalies = check(text)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            anomalies,
+            [
+                "line 4: `[patch]` has no entries",
+                "lines 6, 13: `[patch.crates-io]` is declared 2 times",
+                "lines 7, 15: `patch.crates-io.a` is declared 2 times, keep only one of them",
+                "line 8: `patch.crates-io.b` has both a `path` and a `git` source",
+                "line 14: `patch.crates-io.c` has no source, expected one of `git`, `path`, \
+                `registry`",
+            ]
+        );
+        assert!(check("[patch.crates-io]\n\n[patch.crates-io.a]\npath = \"a\"\n").is_empty());
+    }
+
+    #[test]
+    fn test_check_mixed_definitions() {
+        assert_eq!(
+            check("[patch]\ncrates-io.a = { path = \"a\" }\n\n[patch.crates-io]\n"),
+            [Anomaly {
+                lines: vec![2, 4],
+                description: "`patch.crates-io` is defined 2 times, by both table headers and \
+                    dotted keys"
+                    .to_owned(),
+                fixable: false,
+            }]
+        );
+        assert_eq!(
+            check("[patch.crates-io]\na = { path = \"a\" }\na = { path = \"b\" }\n")[0].to_string(),
+            "line 3: duplicate key `a` in table `patch.crates-io`"
+        );
+    }
+
+    #[test]
+    fn test_fix() {
+        let text = r#"[patch]
+# Kept for later.
+
+[patch.crates-io]
+a = { path = "../a" }
+
+[dependencies]
+a = "1"
+
+[patch.crates-io]
+
+b = { path = "../b" }"#;
+        let fixed = fix(text);
+        assert_eq!(
+            fixed,
+            r#"# Kept for later.
+
+[patch.crates-io]
+a = { path = "../a" }
+b = { path = "../b" }
+
+[dependencies]
+a = "1"
+"#
+        );
+        assert!(check(&fixed).is_empty());
+        assert_eq!(fix(&fixed), fixed);
+    }
+}
@@ -0,0 +1,52 @@
+//! Print the git repository a toolchain dependency group is sourced from.
+
+use crate::context::{Context, GlobalArgs};
+use crate::upgrade::DepName;
+use anyhow::Result;
+use clap::Parser;
+
+/// Print the URL of the git repository of a toolchain dependency group.
+///
+/// This is the repository `upgrade` points `[patch.crates-io]` entries to when upgrading with
+/// `--rev` or `--branch`, and looks tags and branches up in.
+#[derive(Parser)]
+pub struct Args {
+    /// Name of the toolchain dependency group.
+    group: DepName,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, &args);
+    Ok(())
+}
+
+fn run(ctx: &Context, args: &Args) {
+    ctx.output(&format!("{}\n", args.group.repo()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    #[test]
+    fn test_prints_repo() {
+        let sh = Shell::new().unwrap();
+        let ctx = Context::recording(sh);
+        run(&ctx, &Args::parse_from(["print-repo", "cairolint"]));
+        run(&ctx, &Args::parse_from(["print-repo", "cairo"]));
+        assert_eq!(
+            ctx.captured(),
+            (
+                "https://github.com/software-mansion/cairo-lint\n\
+                https://github.com/starkware-libs/cairo\n"
+                    .to_owned(),
+                Vec::new()
+            )
+        );
+    }
+}
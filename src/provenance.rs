@@ -0,0 +1,266 @@
+//! Record which toolchain a project is built against in its `Cargo.toml`.
+//!
+//! The `upgrade` and `sync-version` xtasks maintain a `[workspace.metadata.cairo-toolchain]` table
+//! (or `[package.metadata.cairo-toolchain]` in manifests without a `[workspace]`), with one
+//! sub-table per toolchain dependency group:
+//!
+//! ```toml
+//! [workspace.metadata.cairo-toolchain.cairo]
+//! version = "2.9.2"
+//! source = "git"
+//! repo = "https://github.com/starkware-libs/cairo"
+//! rev = "1a2b3c4"
+//! ```
+//!
+//! Other tools can consume it using [`read`] or [`read_manifest`] without parsing lockfiles.
+
+use anyhow::{ensure, Context, Result};
+use semver::Version;
+use std::path::Path;
+use toml_edit::{value, DocumentMut, InlineTable, Item, Table, Value};
+
+const TABLE: &str = "cairo-toolchain";
+
+/// Provenance of a single toolchain dependency group.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Name of the dependency group, as accepted by the `upgrade` xtask.
+    pub group: String,
+    /// Version of the group's main crate, as resolved in `Cargo.lock`.
+    pub version: Option<Version>,
+    /// Kind of dependency source: `registry`, `git` or `path`.
+    pub source: String,
+    /// Upstream repository of the group.
+    pub repo: String,
+    /// Git revision, if sourced from a specific revision.
+    pub rev: Option<String>,
+    /// Git branch, if sourced from a branch.
+    pub branch: Option<String>,
+    /// Local path, if sourced from the local filesystem.
+    pub path: Option<String>,
+}
+
+/// Reads all provenance records from a manifest.
+pub fn read(cargo_toml: &DocumentMut) -> Vec<Provenance> {
+    let Some(groups) = ["workspace", "package"].iter().find_map(|root| {
+        cargo_toml
+            .get(root)?
+            .get("metadata")?
+            .get(TABLE)?
+            .as_table_like()
+    }) else {
+        return Vec::new();
+    };
+
+    groups
+        .iter()
+        .filter_map(|(group, record)| {
+            let record = record.as_table_like()?;
+            let get = |key: &str| record.get(key)?.as_str().map(str::to_owned);
+            Some(Provenance {
+                group: group.to_owned(),
+                version: get("version").and_then(|v| v.parse().ok()),
+                source: get("source").unwrap_or_default(),
+                repo: get("repo").unwrap_or_default(),
+                rev: get("rev"),
+                branch: get("branch"),
+                path: get("path"),
+            })
+        })
+        .collect()
+}
+
+/// Reads all provenance records from the manifest at `path`.
+pub fn read_manifest(path: impl AsRef<Path>) -> Result<Vec<Provenance>> {
+    let path = path.as_ref();
+    let cargo_toml = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .parse::<DocumentMut>()?;
+    Ok(read(&cargo_toml))
+}
+
+/// Writes (or replaces) the provenance record of a single group.
+///
+/// Fails if a table on the way to the record is not a table, like `metadata = "..."`.
+pub(crate) fn write(cargo_toml: &mut DocumentMut, provenance: &Provenance) -> Result<()> {
+    let mut record = Table::new();
+    if let Some(version) = &provenance.version {
+        record.insert("version", value(version.to_string()));
+    }
+    record.insert("source", value(&provenance.source));
+    record.insert("repo", value(&provenance.repo));
+    for (key, field) in [
+        ("rev", &provenance.rev),
+        ("branch", &provenance.branch),
+        ("path", &provenance.path),
+    ] {
+        if let Some(field) = field {
+            record.insert(key, value(field));
+        }
+    }
+
+    match metadata_table(cargo_toml)? {
+        Item::Value(Value::InlineTable(groups)) => {
+            groups.insert(&provenance.group, record.into_inline_table().into());
+        }
+        Item::Table(groups) => {
+            groups.insert(&provenance.group, Item::Table(record));
+        }
+        _ => unreachable!("metadata_table only returns tables"),
+    }
+    Ok(())
+}
+
+/// Updates the recorded version of a group, if it has a record.
+pub(crate) fn set_version(
+    cargo_toml: &mut DocumentMut,
+    group: &str,
+    version: &Version,
+) -> Result<bool> {
+    let mut records = read(cargo_toml);
+    let Some(record) = records.iter_mut().find(|record| record.group == group) else {
+        return Ok(false);
+    };
+    record.version = Some(version.clone());
+    write(cargo_toml, record)?;
+    Ok(true)
+}
+
+/// Finds the table holding the records, creating it if needed, within inline tables too.
+fn metadata_table(cargo_toml: &mut DocumentMut) -> Result<&mut Item> {
+    let root = if cargo_toml.contains_key("workspace") || !cargo_toml.contains_key("package") {
+        "workspace"
+    } else {
+        "package"
+    };
+
+    let mut item = cargo_toml.as_item_mut();
+    let mut path = Vec::new();
+    for key in [root, "metadata", TABLE] {
+        let inline = item.is_inline_table();
+        let parent = item
+            .as_table_like_mut()
+            .with_context(|| format!("`{}` in Cargo.toml is not a table", path.join(".")))?;
+        if inline && !parent.contains_key(key) {
+            parent.insert(key, Item::Value(InlineTable::new().into()));
+            // Otherwise the space before the closing brace would follow the previous last value.
+            parent.fmt();
+        }
+        item = parent.entry(key).or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        });
+        path.push(key);
+    }
+    ensure!(
+        item.is_table_like(),
+        "`{}` in Cargo.toml is not a table",
+        path.join(".")
+    );
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut cargo_toml = "[workspace]\nmembers = []\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+
+        let provenance = Provenance {
+            group: "cairo".into(),
+            version: Some("2.9.2".parse().unwrap()),
+            source: "git".into(),
+            repo: "https://github.com/starkware-libs/cairo".into(),
+            rev: Some("1a2b3c4".into()),
+            ..Default::default()
+        };
+        write(&mut cargo_toml, &provenance).unwrap();
+
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[workspace]
+members = []
+
+[workspace.metadata.cairo-toolchain.cairo]
+version = "2.9.2"
+source = "git"
+repo = "https://github.com/starkware-libs/cairo"
+rev = "1a2b3c4"
+"#
+        );
+        assert_eq!(read(&cargo_toml), std::slice::from_ref(&provenance));
+
+        let reparsed = cargo_toml.to_string().parse::<DocumentMut>().unwrap();
+        assert_eq!(read(&reparsed), [provenance]);
+    }
+
+    #[test]
+    fn test_package_metadata_without_workspace() {
+        let mut cargo_toml = "[package]\nname = \"foo\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        write(
+            &mut cargo_toml,
+            &Provenance {
+                group: "cairols".into(),
+                source: "registry".into(),
+                repo: "https://github.com/software-mansion/cairols".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(cargo_toml
+            .to_string()
+            .contains("[package.metadata.cairo-toolchain.cairols]"));
+        assert!(!cargo_toml.contains_key("workspace"));
+
+        assert!(set_version(&mut cargo_toml, "cairols", &"2.9.0".parse().unwrap()).unwrap());
+        assert!(!set_version(&mut cargo_toml, "cairo", &"2.9.0".parse().unwrap()).unwrap());
+        assert_eq!(read(&cargo_toml)[0].version, Some("2.9.0".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_inline_tables() {
+        let provenance = Provenance {
+            group: "cairo".into(),
+            source: "registry".into(),
+            repo: "https://github.com/starkware-libs/cairo".into(),
+            ..Default::default()
+        };
+        let write = |original: &str| {
+            let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+            write(&mut cargo_toml, &provenance).map(|()| cargo_toml)
+        };
+
+        let cargo_toml = write("workspace = { members = [] }\n").unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            "workspace = { members = [], metadata = { cairo-toolchain = { cairo = { source = \
+            \"registry\", repo = \"https://github.com/starkware-libs/cairo\" } } } }\n"
+        );
+        assert_eq!(read(&cargo_toml), std::slice::from_ref(&provenance));
+
+        let cargo_toml = write("[workspace]\nmetadata = { other = 1 }\n").unwrap();
+        assert_eq!(read(&cargo_toml), std::slice::from_ref(&provenance));
+        let reparsed = cargo_toml.to_string().parse::<DocumentMut>().unwrap();
+        assert_eq!(read(&reparsed), std::slice::from_ref(&provenance));
+
+        assert_eq!(
+            write("[workspace]\nmetadata = \"none\"\n")
+                .unwrap_err()
+                .to_string(),
+            "`workspace.metadata` in Cargo.toml is not a table"
+        );
+        assert_eq!(
+            write("[workspace.metadata]\ncairo-toolchain = []\n")
+                .unwrap_err()
+                .to_string(),
+            "`workspace.metadata.cairo-toolchain` in Cargo.toml is not a table"
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Read the list of published crates from the release script of the Cairo repository.
+//!
+//! The script is a bash file, which is read with a small line-oriented parser instead of being
+//! executed. It understands comments, blank lines, quoted entries and several arrays in one file,
+//! and rejects anything it cannot interpret faithfully, like `$(...)` entries.
+
+use anyhow::{bail, Result};
+
+/// Words allowed in front of an array assignment, e.g. `readonly CRATES=(...)`.
+const ASSIGNMENT_KEYWORDS: &[&str] = &["export", "readonly", "declare", "local", "typeset"];
+
+/// A word of a script line, after removing quotes.
+struct Word {
+    text: String,
+    quoted: bool,
+}
+
+/// An array assignment currently being parsed.
+struct Array {
+    name: String,
+    line: usize,
+    entries: Vec<String>,
+}
+
+/// Reads the entries of the bash array `name` assigned in `script`.
+///
+/// Appending assignments (`NAME+=(...)`) extend the array. Fails if the array is not assigned,
+/// assigned twice, not terminated, or if an entry is not a plain crate name, e.g. because it uses
+/// shell expansions the parser cannot evaluate.
+pub(crate) fn parse_array(script: &str, name: &str) -> Result<Vec<String>> {
+    let mut assigned_at = None;
+    let mut entries = Vec::new();
+    let mut current: Option<Array> = None;
+
+    for (index, line) in script.lines().enumerate() {
+        let number = index + 1;
+        let words = match split_words(line) {
+            Ok(words) => words,
+            Err(err) => bail!("{err} in line {number}: `{}`", line.trim()),
+        };
+        let mut words = words.into_iter().peekable();
+
+        if current.is_none() {
+            while words
+                .peek()
+                .is_some_and(|w| !w.quoted && ASSIGNMENT_KEYWORDS.contains(&w.text.as_str()))
+            {
+                words.next();
+                while words
+                    .peek()
+                    .is_some_and(|w| !w.quoted && w.text.starts_with('-'))
+                {
+                    words.next();
+                }
+            }
+            let Some(word) = words.next().filter(|w| !w.quoted) else {
+                continue;
+            };
+            let Some((target, rest)) = word.text.split_once("=(") else {
+                continue;
+            };
+            let (target, append) = match target.strip_suffix('+') {
+                Some(target) => (target, true),
+                None => (target, false),
+            };
+            if target == name && !append {
+                if let Some(first) = assigned_at {
+                    bail!("`{name}` is assigned more than once, in lines {first} and {number}");
+                }
+                assigned_at = Some(number);
+            }
+            current = Some(Array {
+                name: target.to_owned(),
+                line: number,
+                entries: Vec::new(),
+            });
+            if !rest.is_empty() {
+                let rest = Word {
+                    text: rest.to_owned(),
+                    quoted: false,
+                };
+                if push_entry(&mut current, rest, name, number, line)? {
+                    entries.extend(current.take().into_iter().flat_map(|a| finish(a, name)));
+                    continue;
+                }
+            }
+        }
+
+        for word in words {
+            if push_entry(&mut current, word, name, number, line)? {
+                entries.extend(current.take().into_iter().flat_map(|a| finish(a, name)));
+                break;
+            }
+        }
+    }
+
+    if let Some(array) = current {
+        bail!(
+            "array `{}` assigned in line {} is never terminated with `)`",
+            array.name,
+            array.line
+        );
+    }
+    if assigned_at.is_none() {
+        bail!("array `{name}` is not assigned");
+    }
+    Ok(entries)
+}
+
+/// Adds a word to the array being parsed, returning whether it terminated the array.
+fn push_entry(
+    current: &mut Option<Array>,
+    word: Word,
+    name: &str,
+    number: usize,
+    line: &str,
+) -> Result<bool> {
+    let Some(array) = current else {
+        return Ok(false);
+    };
+    let (text, closed) = match word.text.strip_suffix(')').filter(|_| !word.quoted) {
+        Some(text) => (text.to_owned(), true),
+        None => (word.text, false),
+    };
+    if text.is_empty() && !word.quoted {
+        return Ok(closed);
+    }
+    if array.name == name && !is_plain_name(&text) {
+        bail!(
+            "unsupported entry `{text}` of `{name}` in line {number}, expected a crate name: `{}`",
+            line.trim()
+        );
+    }
+    array.entries.push(text);
+    Ok(closed)
+}
+
+/// Keeps the entries of the array if it is the requested one.
+fn finish(array: Array, name: &str) -> Vec<String> {
+    if array.name == name {
+        array.entries
+    } else {
+        Vec::new()
+    }
+}
+
+/// Checks whether an entry is a crate name, and so contains no shell syntax.
+fn is_plain_name(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Splits a line into words, removing quotes and a trailing comment.
+fn split_words(line: &str) -> Result<Vec<Word>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => return Ok(words),
+            Some(_) => {}
+        }
+
+        let mut word = Word {
+            text: String::new(),
+            quoted: false,
+        };
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' | '"' => {
+                    word.quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some(q) if q == c => break,
+                            Some(q) => word.text.push(q),
+                            None => return Err("unterminated quote"),
+                        }
+                    }
+                }
+                c => word.text.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Warns if the number of crates changed drastically since the `previous` list, which more likely
+/// means the format of the release script changed than that half of the crates were added or
+/// removed.
+pub(crate) fn drift_warning(previous: &[String], current: &[String]) -> Option<String> {
+    let (previous_len, current_len) = (previous.len(), current.len());
+    if previous_len == 0 || (current_len * 2 >= previous_len && current_len * 2 <= previous_len * 3)
+    {
+        return None;
+    }
+    Some(format!(
+        "the release script lists {current_len} crates, while the last one read listed \
+        {previous_len}, check whether its format changed"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRATES_TO_PUBLISH: &str = "CRATES_TO_PUBLISH";
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/testdata/release_crates/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    /// The `v*.sh` fixtures are synthetic scripts laid out like the release script of those
+    /// versions, not copies of it.
+    #[test]
+    fn test_release_script_layouts() {
+        let crates = parse_array(&fixture("v2.6.0.sh"), CRATES_TO_PUBLISH).unwrap();
+        assert_eq!(crates.len(), 30);
+        assert_eq!(crates[0], "cairo-lang-utils");
+        assert_eq!(crates.last().unwrap(), "cairo-lang-test-runner");
+
+        let crates = parse_array(&fixture("v2.9.2.sh"), CRATES_TO_PUBLISH).unwrap();
+        assert_eq!(crates.len(), 33);
+        assert!(crates.contains(&"cairo-lang-executable".to_owned()));
+    }
+
+    #[test]
+    fn test_selects_array_by_name() {
+        let script = fixture("multiple-arrays.sh");
+        assert_eq!(
+            parse_array(&script, CRATES_TO_PUBLISH).unwrap(),
+            [
+                "cairo-lang-utils",
+                "cairo-lang-macro",
+                "cairo-lang-compiler",
+                "cairo-lang-starknet",
+                "cairo-lang-test-plugin",
+            ]
+        );
+        assert_eq!(
+            parse_array(&script, "CRATES_TO_SKIP").unwrap(),
+            ["cairo-lang-test-utils"]
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_scripts() {
+        let error = |name: &str| {
+            parse_array(&fixture(name), CRATES_TO_PUBLISH)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("command-substitution.sh"),
+            "unsupported entry `$(ls` of `CRATES_TO_PUBLISH` in line 5, expected a crate name: \
+            `$(ls crates | grep cairo-lang-plugin)`"
+        );
+        assert_eq!(
+            error("unterminated.sh"),
+            "array `CRATES_TO_PUBLISH` assigned in line 3 is never terminated with `)`"
+        );
+        assert_eq!(
+            error("assigned-twice.sh"),
+            "`CRATES_TO_PUBLISH` is assigned more than once, in lines 3 and 8"
+        );
+        assert_eq!(
+            parse_array("CRATES=(a b)\n", CRATES_TO_PUBLISH)
+                .unwrap_err()
+                .to_string(),
+            "array `CRATES_TO_PUBLISH` is not assigned"
+        );
+        assert_eq!(
+            parse_array("CRATES_TO_PUBLISH=(\n  \"a\n)\n", CRATES_TO_PUBLISH)
+                .unwrap_err()
+                .to_string(),
+            "unterminated quote in line 2: `\"a`"
+        );
+    }
+
+    #[test]
+    fn test_drift_warning() {
+        let crates = |n: usize| {
+            (0..n)
+                .map(|i| format!("cairo-lang-{i}"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(drift_warning(&[], &crates(3)), None);
+        assert_eq!(drift_warning(&crates(30), &crates(31)), None);
+        assert_eq!(drift_warning(&crates(30), &crates(45)), None);
+        assert_eq!(drift_warning(&crates(30), &crates(15)), None);
+        assert_eq!(
+            drift_warning(&crates(30), &crates(1)).unwrap(),
+            "the release script lists 1 crates, while the last one read listed 30, check whether \
+            its format changed"
+        );
+        assert!(drift_warning(&crates(30), &crates(46)).is_some());
+    }
+}
@@ -0,0 +1,208 @@
+//! Undo an upgrade by restoring `Cargo.toml` from a backup written before it was edited.
+
+use crate::context::{Context, GlobalArgs};
+use crate::history::{self, Reverted, Snapshot};
+use anyhow::{bail, ensure, Result};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use xshell::cmd;
+
+/// Restore `Cargo.toml` from a `Cargo.toml.bak.<TIMESTAMP>` backup, by default the most recent one,
+/// and run `cargo fetch`.
+///
+/// `upgrade` and `sync-version` write these backups next to the manifest before editing it, unless
+/// run with `--no-backup`. `upgrade --rollback` restores `Cargo.lock` and the member manifests
+/// from the history of upgrade runs as well.
+#[derive(Parser)]
+pub struct Args {
+    /// Backup to restore, like `Cargo.toml.bak.1728900000`.
+    backup: Option<PathBuf>,
+
+    /// Do not restore any files, just inform what would be restored.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    restore(&ctx, args.backup.as_deref(), args.dry_run)
+}
+
+/// Restores `Cargo.toml` from `backup`, or from its most recent backup.
+fn restore(ctx: &Context, backup: Option<&Path>, dry_run: bool) -> Result<()> {
+    let sh = &ctx.sh;
+    let backup = match backup {
+        Some(backup) => {
+            let backup = ctx.cli_path(backup);
+            ensure!(
+                backup.is_file(),
+                "backup does not exist: {}",
+                backup.display()
+            );
+            backup
+        }
+        None => match history::backups(sh, "Cargo.toml")?.pop() {
+            Some(backup) => ctx.workspace_root().join(backup),
+            None => bail!(
+                "no backup of Cargo.toml found in {}, `upgrade` and `sync-version` write \
+                Cargo.toml.bak.<TIMESTAMP> unless run with --no-backup",
+                ctx.workspace_root().display()
+            ),
+        },
+    };
+    let root = ctx.workspace_root();
+    let name = backup.strip_prefix(&root).unwrap_or(&backup).display();
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network()?.cargo_args();
+    if dry_run {
+        ctx.note(format_args!("would restore Cargo.toml from {name}"));
+        ctx.would_run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"));
+        return Ok(());
+    }
+
+    sh.write_file("Cargo.toml", sh.read_binary_file(&backup)?)?;
+    ctx.note(format_args!("restored Cargo.toml from {name}"));
+    ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"))
+}
+
+/// Restores files from the snapshot of run `run`, or of the most recent one, for
+/// `upgrade --rollback`.
+pub(crate) fn rollback(ctx: &Context, run: Option<&str>, force: bool, dry_run: bool) -> Result<()> {
+    let sh = &ctx.sh;
+    let snapshot = Snapshot::find(sh, run)?;
+    let id = snapshot.id.clone();
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network()?.cargo_args();
+    if dry_run {
+        for file in snapshot.pending(sh, force)? {
+            match file {
+                Reverted::Restored(file) => ctx.note(format_args!("would restore {file}")),
+                Reverted::Removed(file) => ctx.note(format_args!("would remove {file}")),
+            }
+        }
+        ctx.would_run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"));
+        return Ok(());
+    }
+
+    let reverted = snapshot.rollback(sh, force)?;
+    for file in &reverted {
+        ctx.note(file);
+    }
+    if reverted.is_empty() {
+        ctx.note(format_args!("upgrade run {id} made no changes"));
+    }
+    ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"))?;
+    ctx.note(format_args!("rolled back upgrade run {id}"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    #[test]
+    fn test_restore() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let err = restore(&ctx, None, false).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("no backup of Cargo.toml found in "),
+            "{err}"
+        );
+        let err = restore(&ctx, Some(Path::new("Cargo.toml.bak.1")), false).unwrap_err();
+        assert!(
+            err.to_string().starts_with("backup does not exist: "),
+            "{err}"
+        );
+
+        let older = history::write_backup(&sh, "Cargo.toml", original.as_bytes()).unwrap();
+        let upgraded = original.replace("2.8.0", "2.9.0");
+        let newest = history::write_backup(&sh, "Cargo.toml", upgraded.as_bytes()).unwrap();
+        sh.write_file("Cargo.toml", original.replace("2.8.0", "2.10.0"))
+            .unwrap();
+
+        restore(&ctx, None, true).unwrap();
+        assert_eq!(
+            ctx.captured().1,
+            [
+                format!("note: would restore Cargo.toml from {newest}"),
+                "would run: cargo fetch --offline".to_owned(),
+            ]
+        );
+        assert!(ctx.recorded().is_empty());
+
+        let ctx = Context::recording(sh.clone());
+        restore(&ctx, None, false).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), upgraded);
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+        assert_eq!(
+            ctx.captured().1,
+            [format!("note: restored Cargo.toml from {newest}")]
+        );
+
+        let ctx = Context::recording(sh.clone());
+        restore(&ctx, Some(Path::new(&older)), false).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let err = rollback(&ctx, None, false, false).unwrap_err();
+        assert!(
+            err.to_string().starts_with("no upgrade runs recorded in "),
+            "{err}"
+        );
+
+        let files = ["Cargo.toml".to_owned(), "Cargo.lock".to_owned()];
+        let snapshot = Snapshot::begin(&sh, &files).unwrap();
+        sh.write_file("Cargo.toml", original.replace("2.8.0", "2.9.0"))
+            .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        snapshot.finish(&sh).unwrap();
+        let id = snapshot.id;
+
+        rollback(&ctx, Some(&id), false, true).unwrap();
+        assert_eq!(
+            ctx.captured().1,
+            [
+                "note: would restore Cargo.toml",
+                "note: would remove Cargo.lock",
+                "would run: cargo fetch --offline",
+            ]
+        );
+        assert!(ctx.recorded().is_empty());
+
+        let ctx = Context::recording(sh.clone());
+        rollback(&ctx, None, false, false).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+        assert!(!sh.path_exists("Cargo.lock"));
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+        assert_eq!(
+            ctx.captured().1,
+            [
+                "note: restored Cargo.toml".to_owned(),
+                "note: removed Cargo.lock".to_owned(),
+                format!("note: rolled back upgrade run {id}"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Keep the vendored copy of the Cairo corelib in step with the compiler in `Cargo.lock`.
+
+use crate::cache::{Cache, CacheArgs};
+use crate::context::{Context, GlobalArgs};
+use crate::http::{Curl, Http};
+use crate::lockfile::GitSource;
+use anyhow::{bail, ensure, Context as _, Result};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+use xshell::{cmd, Shell};
+
+const CAIRO_REPO: &str = "starkware-libs/cairo";
+
+/// Keep the vendored copy of the Cairo corelib in step with the compiler in `Cargo.lock`.
+///
+/// The upstream corelib is taken from the `starkware-libs/cairo` revision `cairo-lang-compiler` is
+/// resolved to: the `v<VERSION>` tag for crates.io sources, or the locked commit for git sources.
+#[derive(Parser)]
+pub struct Args {
+    /// Do not modify the local corelib, fail if it differs from the upstream one.
+    #[arg(long, default_value_t = false)]
+    check: bool,
+
+    /// Local corelib directory, relative to the workspace root.
+    ///
+    /// Set `files_root = "repository"` in the xtasks configuration to resolve it against the root
+    /// of the git repository instead, when the workspace is nested in a larger one.
+    #[arg(long, value_name = "DIR", default_value = "corelib")]
+    corelib_dir: PathBuf,
+
+    /// Read the upstream corelib from this clone of the Cairo repository instead of GitHub.
+    ///
+    /// Point this to the `.git` directory, or the clone itself.
+    #[arg(long, value_name = "DIR")]
+    git_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+/// Files of a corelib tree, mapped from `/`-separated paths to git blob ids of their contents.
+type Tree = BTreeMap<String, String>;
+
+/// Source of upstream corelib files at a fixed revision.
+trait Upstream {
+    fn tree(&self) -> Result<Tree>;
+    fn read(&self, path: &str) -> Result<Vec<u8>>;
+}
+
+/// A difference between the local and upstream corelib.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Difference {
+    Modified(String),
+    Missing(String),
+    Extra(String),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Modified(path) => write!(f, "modified: {path}"),
+            Self::Missing(path) => write!(f, "missing:  {path}"),
+            Self::Extra(path) => write!(f, "extra:    {path}"),
+        }
+    }
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    let sh = &ctx.sh;
+
+    let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+    let rev = upstream_rev(&cargo_lock)?;
+    let cache = args.cache.cache();
+    let curl = Curl {
+        network: ctx.network()?,
+    };
+    let upstream: Box<dyn Upstream> = match &args.git_dir {
+        Some(git_dir) => Box::new(GitDir {
+            sh: sh.clone(),
+            git_dir: resolve_git_dir(ctx.cli_path(git_dir)),
+            rev: rev.clone(),
+        }),
+        None => Box::new(GitHub {
+            http: &curl,
+            cache,
+            rev: rev.clone(),
+        }),
+    };
+
+    run(sh, &ctx.files_root()?, &args, &rev, upstream.as_ref())
+}
+
+fn run(sh: &Shell, root: &Path, args: &Args, rev: &str, upstream: &dyn Upstream) -> Result<()> {
+    let corelib_dir = root.join(&args.corelib_dir);
+    let local = local_tree(&corelib_dir)?;
+    let upstream_tree = upstream.tree()?;
+    eprintln!("corelib fingerprint: {}", fingerprint(&local));
+
+    let differences = compare(&local, &upstream_tree);
+    if differences.is_empty() {
+        eprintln!("corelib matches {CAIRO_REPO}@{rev}");
+        return Ok(());
+    }
+
+    if args.check {
+        for difference in &differences {
+            eprintln!("{difference}");
+        }
+        bail!(
+            "corelib differs from {CAIRO_REPO}@{rev} (upstream fingerprint: {}) in {} files, \
+            run `cargo xtask sync-corelib` to update it",
+            fingerprint(&upstream_tree),
+            differences.len()
+        );
+    }
+
+    for difference in &differences {
+        match difference {
+            Difference::Modified(path) | Difference::Missing(path) => {
+                sh.write_file(corelib_dir.join(path), upstream.read(path)?)?;
+            }
+            Difference::Extra(path) => sh.remove_path(corelib_dir.join(path))?,
+        }
+        eprintln!("{difference}");
+    }
+    eprintln!(
+        "updated {} corelib files to {CAIRO_REPO}@{rev}, new fingerprint: {}",
+        differences.len(),
+        fingerprint(&upstream_tree)
+    );
+    Ok(())
+}
+
+/// Finds the Cairo repository revision matching the `cairo-lang-compiler` locked in `Cargo.lock`.
+fn upstream_rev(cargo_lock: &DocumentMut) -> Result<String> {
+    let packages = cargo_lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+        .into_iter()
+        .flatten()
+        .filter(|pkg| pkg.get("name").and_then(|name| name.as_str()) == Some("cairo-lang-compiler"))
+        .collect::<Vec<_>>();
+    ensure!(
+        packages.len() == 1,
+        "expected exactly one cairo-lang-compiler package in Cargo.lock, found: {}",
+        packages.len()
+    );
+    let pkg = packages[0];
+
+    match pkg.get("source").and_then(|source| source.as_str()) {
+        Some(source) if source.starts_with("git+") => Ok(source.parse::<GitSource>()?.resolved_sha),
+        Some(_) => Ok(format!(
+            "v{}",
+            pkg.get("version")
+                .and_then(|version| version.as_str())
+                .unwrap_or_default()
+        )),
+        None => bail!(
+            "cairo-lang-compiler is sourced from a local path, \
+            its corelib cannot be matched to an upstream revision"
+        ),
+    }
+}
+
+/// Lists files of a local corelib directory.
+fn local_tree(dir: &Path) -> Result<Tree> {
+    fn walk(dir: &Path, prefix: &str, tree: &mut Tree) -> Result<()> {
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("{prefix}{name}");
+            if entry.file_type()?.is_dir() {
+                walk(&entry.path(), &format!("{path}/"), tree)?;
+            } else {
+                let contents = std::fs::read(entry.path())?;
+                tree.insert(path, blob_id(&contents));
+            }
+        }
+        Ok(())
+    }
+
+    ensure!(
+        dir.is_dir(),
+        "corelib directory not found: {}",
+        dir.display()
+    );
+    let mut tree = Tree::new();
+    walk(dir, "", &mut tree)?;
+    Ok(tree)
+}
+
+/// Computes the git blob id of file contents, so that local files can be compared with upstream
+/// trees without downloading them.
+fn blob_id(contents: &[u8]) -> String {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()).as_bytes());
+    hasher.update(contents);
+    hasher.digest().to_string()
+}
+
+/// Computes a short, stable fingerprint of a whole corelib tree.
+fn fingerprint(tree: &Tree) -> String {
+    let mut hasher = sha1_smol::Sha1::new();
+    for (path, blob) in tree {
+        hasher.update(format!("{blob} {path}\n").as_bytes());
+    }
+    hasher.digest().to_string()[..12].to_owned()
+}
+
+fn compare(local: &Tree, upstream: &Tree) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (path, blob) in upstream {
+        match local.get(path) {
+            Some(local_blob) if local_blob == blob => {}
+            Some(_) => differences.push(Difference::Modified(path.clone())),
+            None => differences.push(Difference::Missing(path.clone())),
+        }
+    }
+    for path in local.keys().filter(|path| !upstream.contains_key(*path)) {
+        differences.push(Difference::Extra(path.clone()));
+    }
+    differences
+}
+
+/// Reads the upstream corelib from a local clone of the Cairo repository.
+struct GitDir {
+    sh: Shell,
+    git_dir: PathBuf,
+    rev: String,
+}
+
+impl Upstream for GitDir {
+    fn tree(&self) -> Result<Tree> {
+        let Self { sh, git_dir, rev } = self;
+        let listing = cmd!(sh, "git --git-dir {git_dir} ls-tree -r {rev}:corelib")
+            .quiet()
+            .read()
+            .with_context(|| format!("failed to list corelib at {rev} in {}", git_dir.display()))?;
+        Ok(listing
+            .lines()
+            .filter_map(|line| {
+                let (meta, path) = line.split_once('\t')?;
+                let mut meta = meta.split_whitespace();
+                if meta.nth(1)? != "blob" {
+                    return None;
+                }
+                Some((path.to_owned(), meta.next()?.to_owned()))
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let Self { sh, git_dir, rev } = self;
+        // Not using `read`, as it trims the trailing newline.
+        Ok(cmd!(
+            sh,
+            "git --git-dir {git_dir} cat-file blob {rev}:corelib/{path}"
+        )
+        .quiet()
+        .output()?
+        .stdout)
+    }
+}
+
+/// Accepts both a clone and its `.git` directory.
+fn resolve_git_dir(dir: PathBuf) -> PathBuf {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        dot_git
+    } else {
+        dir
+    }
+}
+
+/// Reads the upstream corelib from GitHub, caching tree listings.
+struct GitHub<'a> {
+    http: &'a dyn Http,
+    cache: Cache,
+    rev: String,
+}
+
+impl Upstream for GitHub<'_> {
+    fn tree(&self) -> Result<Tree> {
+        let rev = &self.rev;
+        let key = format!("corelib-trees/cairo/{rev}");
+        if let Some(cached) = self.cache.get(&key) {
+            return Ok(parse_listing(&cached));
+        }
+
+        let root: serde_json::Value = serde_json::from_str(&self.http.get(&format!(
+            "https://api.github.com/repos/{CAIRO_REPO}/git/trees/{rev}"
+        ))?)?;
+        let Some(corelib) = root["tree"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|entry| entry["path"] == "corelib" && entry["type"] == "tree")
+        else {
+            bail!("corelib not found in {CAIRO_REPO}@{rev}");
+        };
+        let corelib: serde_json::Value = serde_json::from_str(&self.http.get(&format!(
+            "https://api.github.com/repos/{CAIRO_REPO}/git/trees/{}?recursive=1",
+            corelib["sha"].as_str().unwrap_or_default()
+        ))?)?;
+        ensure!(
+            corelib["truncated"] != true,
+            "corelib listing of {CAIRO_REPO}@{rev} is truncated, use --git-dir instead"
+        );
+
+        let tree = corelib["tree"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry["type"] == "blob")
+            .filter_map(|entry| {
+                Some((
+                    entry["path"].as_str()?.to_owned(),
+                    entry["sha"].as_str()?.to_owned(),
+                ))
+            })
+            .collect::<Tree>();
+
+        // Revisions are either tags or full commit hashes, so listings never change.
+        let listing = tree
+            .iter()
+            .map(|(path, blob)| format!("{blob} {path}\n"))
+            .collect::<String>();
+        self.cache.put(&key, &listing)?;
+        Ok(tree)
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        self.http.get_bytes(&format!(
+            "https://raw.githubusercontent.com/{CAIRO_REPO}/{}/corelib/{path}",
+            self.rev
+        ))
+    }
+}
+
+fn parse_listing(listing: &str) -> Tree {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let (blob, path) = line.split_once(' ')?;
+            Some((path.to_owned(), blob.to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "d8b5e1f7c2a3b4d5e6f708192a3b4c5d6e7f8091";
+
+    struct FixtureUpstream(Tree, BTreeMap<String, &'static str>);
+
+    impl FixtureUpstream {
+        fn new(files: &[(&str, &'static str)]) -> Self {
+            Self(
+                files
+                    .iter()
+                    .map(|(path, contents)| (path.to_string(), blob_id(contents.as_bytes())))
+                    .collect(),
+                files
+                    .iter()
+                    .map(|(path, contents)| (path.to_string(), *contents))
+                    .collect(),
+            )
+        }
+    }
+
+    impl Upstream for FixtureUpstream {
+        fn tree(&self) -> Result<Tree> {
+            Ok(self.0.clone())
+        }
+
+        fn read(&self, path: &str) -> Result<Vec<u8>> {
+            Ok(self.1[path].as_bytes().to_vec())
+        }
+    }
+
+    fn setup(sh: &Shell) {
+        sh.write_file("corelib/Scarb.toml", "[package]\nname = \"core\"\n")
+            .unwrap();
+        sh.write_file("corelib/src/lib.cairo", "mod array;\n")
+            .unwrap();
+        sh.write_file("corelib/src/local.cairo", "// edited\n")
+            .unwrap();
+    }
+
+    fn args(extra: &[&str]) -> Args {
+        Args::parse_from(["sync-corelib"].iter().chain(extra))
+    }
+
+    #[test]
+    fn test_blob_id_matches_git() {
+        assert_eq!(
+            blob_id(b"hello\n"),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    fn test_upstream_rev() {
+        let lock = |source: &str| {
+            format!("[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n{source}")
+                .parse::<DocumentMut>()
+                .unwrap()
+        };
+        assert_eq!(
+            upstream_rev(&lock(
+                "source = \"registry+https://github.com/rust-lang/crates.io-index\"\n"
+            ))
+            .unwrap(),
+            "v2.9.2"
+        );
+        assert_eq!(
+            upstream_rev(&lock(&format!(
+                "source = \"git+https://github.com/starkware-libs/cairo?rev=d8b5e1f#{SHA}\"\n"
+            )))
+            .unwrap(),
+            SHA
+        );
+        assert!(upstream_rev(&lock("")).is_err());
+    }
+
+    #[test]
+    fn test_check_reports_differences() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        setup(&sh);
+        let upstream = FixtureUpstream::new(&[
+            ("Scarb.toml", "[package]\nname = \"core\"\n"),
+            ("src/lib.cairo", "mod array;\nmod option;\n"),
+            ("src/option.cairo", "enum Option {}\n"),
+        ]);
+
+        let local = local_tree(&temp_dir.path().join("corelib")).unwrap();
+        assert_eq!(
+            compare(&local, &upstream.0),
+            [
+                Difference::Modified("src/lib.cairo".into()),
+                Difference::Missing("src/option.cairo".into()),
+                Difference::Extra("src/local.cairo".into()),
+            ]
+        );
+        assert_ne!(fingerprint(&local), fingerprint(&upstream.0));
+
+        assert!(run(
+            &sh,
+            &sh.current_dir(),
+            &args(&["--check"]),
+            "v2.9.2",
+            &upstream
+        )
+        .is_err());
+        assert_eq!(
+            sh.read_file("corelib/src/local.cairo").unwrap(),
+            "// edited\n"
+        );
+
+        run(&sh, &sh.current_dir(), &args(&[]), "v2.9.2", &upstream).unwrap();
+        assert!(!sh.path_exists("corelib/src/local.cairo"));
+        assert_eq!(
+            sh.read_file("corelib/src/option.cairo").unwrap(),
+            "enum Option {}\n"
+        );
+        let local = local_tree(&temp_dir.path().join("corelib")).unwrap();
+        assert_eq!(fingerprint(&local), fingerprint(&upstream.0));
+        run(
+            &sh,
+            &sh.current_dir(),
+            &args(&["--check"]),
+            "v2.9.2",
+            &upstream,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_git_dir() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        setup(&sh);
+        cmd!(sh, "git init -q").run().unwrap();
+        cmd!(sh, "git add corelib").run().unwrap();
+        cmd!(
+            sh,
+            "git -c user.name=test -c user.email=test@example.com commit -q -m corelib"
+        )
+        .run()
+        .unwrap();
+
+        let upstream = GitDir {
+            sh: sh.clone(),
+            git_dir: resolve_git_dir(temp_dir.path().to_path_buf()),
+            rev: "HEAD".into(),
+        };
+        let tree = upstream.tree().unwrap();
+        assert_eq!(tree, local_tree(&temp_dir.path().join("corelib")).unwrap());
+        assert_eq!(upstream.read("src/lib.cairo").unwrap(), b"mod array;\n");
+    }
+}
@@ -1,9 +1,17 @@
 //! Synchronise this crate's version with the `cairo-lang-*` crates.
 
-use anyhow::{ensure, Result};
-use clap::Parser;
-use semver::{Prerelease, Version};
-use toml_edit::{value, DocumentMut};
+use crate::context::{Context, Format, GlobalArgs};
+use crate::eol::LineEnding;
+use crate::history::{self, Snapshot};
+use crate::lockfile::locked_versions;
+use crate::{config, provenance};
+use crate::{manifest, workspace};
+use anyhow::{ensure, Context as _, Result};
+use clap::{Parser, ValueEnum};
+use semver::{Prerelease, Version, VersionReq};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, DocumentMut, Item};
 use xshell::{cmd, Shell};
 
 /// Synchronise this crate's version with the `cairo-lang-*` crates.
@@ -20,12 +28,137 @@ pub struct Args {
     /// Clear the pre-release identifier from the version.
     #[arg(long, default_value_t = false)]
     pub no_pre_release: bool,
+
+    /// Where to read the Cairo version the crate version is derived from.
+    ///
+    /// Defaults to `anchor` in `[workspace.metadata.cairo-toolchain-xtasks]`, or to `lockfile`.
+    #[arg(long, value_enum)]
+    pub anchor: Option<Anchor>,
+
+    /// Back up `Cargo.toml` to `Cargo.toml.bak.<TIMESTAMP>` next to it before editing it, and
+    /// record this run in the history of upgrade runs. This is the default.
+    ///
+    /// The backup holds the exact contents of the manifest as read, which `cargo xtask restore`
+    /// restores. Runs chained by `upgrade` are covered by its own backup and record instead.
+    #[arg(long, default_value_t = false, overrides_with = "no_backup")]
+    pub backup: bool,
+
+    /// Neither back up `Cargo.toml` nor record this run.
+    #[arg(long, default_value_t = false, overrides_with = "backup")]
+    pub no_backup: bool,
+
+    /// Do not edit any files, verify that everything this xtask maintains is consistent instead.
+    ///
+    /// All discrepancies are reported in one run, with a summary per category, and the xtask fails
+    /// if there are any. This makes it usable as a single merge gate.
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    pub check: bool,
+
+    /// Skip a category of `--check`, for projects not using the feature it verifies. Can be
+    /// repeated.
+    #[arg(long = "skip", value_name = "CATEGORY", requires = "check")]
+    pub skip: Vec<Check>,
+
+    /// Format of the `--check` report printed to stdout.
+    ///
+    /// With `json`, stdout only contains the report, listing the discrepancies of each category.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+/// Source of the Cairo version the crate version is derived from.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Anchor {
+    /// The version of `cairo-lang-compiler` locked in `Cargo.lock`.
+    #[default]
+    Lockfile,
+    /// The `cairo-version` of the `Scarb.toml` next to `Cargo.toml`, for projects which must match
+    /// the Cairo version of a Scarb package.
+    ///
+    /// It is read from `[tool.cairo-toolchain-xtasks]`, `[package]` or `[workspace.package]`, in
+    /// this order, and must be an exact version.
+    ScarbToml,
+}
+
+impl Anchor {
+    /// Describes the source of `version` read from this anchor, for messages.
+    pub fn describe(self, version: &Version) -> String {
+        match self {
+            Anchor::Lockfile => format!("cairo-lang-compiler {version}"),
+            Anchor::ScarbToml => format!("the cairo-version {version} of Scarb.toml"),
+        }
+    }
+}
+
+/// Result of synchronising the crate version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// Path of the table holding the version, `package` or `workspace.package`.
+    pub table_path: &'static str,
+    /// Where [`Self::compiler_version`] was read from.
+    pub anchor: Anchor,
+    /// Version the crate version is anchored on, of `cairo-lang-compiler` unless another
+    /// [`Anchor`] is selected.
+    pub compiler_version: Version,
+    /// Crate version before synchronising, if it was set and valid.
+    pub previous_version: Option<Version>,
+    /// Crate version after synchronising.
+    pub version: Version,
+}
+
+impl Outcome {
+    /// Checks whether the version deviates from the compiler one by a custom build metadata or a
+    /// cleared pre-release identifier.
+    pub fn adjusted(&self) -> bool {
+        self.version != self.compiler_version
+    }
+}
+
+/// Category of consistency checks of `sync-version --check`.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// The crate version is derived from the locked `cairo-lang-compiler` one.
+    Version,
+    /// Published workspace members not inheriting the workspace version declare the same one.
+    Members,
+    /// Requirements on workspace members, e.g. in `[workspace.dependencies]`, match the version.
+    Dependencies,
+    /// The toolchain version recorded in `[workspace.metadata.cairo-toolchain]` is the locked one.
+    Provenance,
+    /// Requirements on `cairo-lang-*` crates name the major and minor version of the crate
+    /// version, e.g. `2.8.0` or `~2.8` for `2.8.1`, rather than `*` or another release.
+    Requirements,
 }
 
 pub fn main(args: Args) -> Result<()> {
-    let sh = Shell::new()?;
+    let ctx = Context::new(&args.global)?;
+    if args.check {
+        return check(&ctx, &args);
+    }
+    run(&ctx, args).map(|_| ())
+}
 
-    let mut cargo_toml = sh.read_file("Cargo.toml")?.parse::<DocumentMut>()?;
+/// Runs the xtask like [`main`], returning what it changed.
+pub fn sync(args: Args) -> Result<Outcome> {
+    ensure!(
+        !args.check,
+        "--check does not synchronise the version, call `main` instead"
+    );
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, args)
+}
+
+pub(crate) fn run(ctx: &Context, args: Args) -> Result<Outcome> {
+    let sh = &ctx.sh;
+
+    let original = sh.read_file("Cargo.toml")?;
+    let eol = LineEnding::detect(&original);
+    let mut cargo_toml = original.parse::<DocumentMut>()?;
+    let previous_version = crate_version(&cargo_toml).map(|(_, version)| version);
+    let anchor = anchor(&cargo_toml, args.anchor)?;
 
     let (package, table_path) = if let Some(workspace_package) = cargo_toml
         .get_mut("workspace")
@@ -37,48 +170,843 @@ pub fn main(args: Args) -> Result<()> {
         (cargo_toml["package"].as_table_mut().unwrap(), "package")
     };
 
-    let mut version = expected_version()?;
+    let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+    let locked_version = compiler_version(&cargo_lock)?;
+    let compiler_version = anchor_version(ctx, anchor, &locked_version)?;
+    let version = target_version(&compiler_version, &args)?;
+
+    package["version"] = value(version.to_string());
 
-    if let Some(build) = args.build {
+    ctx.summary(format_args!("[{table_path}]\n{package}"));
+    ctx.summary(format_args!(
+        "anchored on {}",
+        anchor.describe(&compiler_version)
+    ));
+    for (family, crates) in version_families(&cargo_lock)? {
+        if family != compiler_version {
+            ctx.note(format_args!(
+                "versioned independently of the compiler: {} {family}",
+                crates.join(", ")
+            ));
+        }
+    }
+
+    if provenance::set_version(&mut cargo_toml, "cairo", &locked_version)? {
+        ctx.summary(format_args!(
+            "recorded cairo toolchain version: {locked_version}"
+        ));
+    }
+
+    let cargo = ctx.cargo();
+
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network_of(&cargo_toml)?.cargo_args();
+    if !args.dry_run {
+        let backup =
+            (args.backup || !args.no_backup) && std::env::var_os(history::NESTED_ENV).is_none();
+        if backup {
+            let backup = history::write_backup(sh, "Cargo.toml", original.as_bytes())?;
+            ctx.note(format_args!("backed up Cargo.toml to {backup}"));
+        }
+        let snapshot = if !backup {
+            None
+        } else {
+            let mut files = vec!["Cargo.toml".to_owned()];
+            if let Ok(lockfile) = ctx.lockfile().strip_prefix(ctx.workspace_root()) {
+                files.push(lockfile.to_string_lossy().into_owned());
+            }
+            Some(Snapshot::begin(sh, &files)?)
+        };
+        let written = sh
+            .write_file("Cargo.toml", eol.apply(&cargo_toml.to_string()))
+            .map_err(Into::into)
+            .and_then(|()| ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}")));
+        // Even if `cargo fetch` failed, the edited manifest is left behind to roll back.
+        if let Some(snapshot) = snapshot {
+            snapshot.finish(sh)?;
+            ctx.note(format_args!(
+                "recorded sync-version run {}, undo it with `cargo xtask upgrade --rollback`",
+                snapshot.id
+            ));
+        }
+        written?;
+    } else {
+        ctx.would_run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"));
+    }
+
+    Ok(Outcome {
+        table_path,
+        anchor,
+        compiler_version,
+        previous_version,
+        version,
+    })
+}
+
+/// Selects the anchor given with `--anchor`, or the one configured in `cargo_toml`.
+pub(crate) fn anchor(cargo_toml: &DocumentMut, anchor: Option<Anchor>) -> Result<Anchor> {
+    match anchor {
+        Some(anchor) => Ok(anchor),
+        None => Ok(config::anchor(cargo_toml)?.unwrap_or_default()),
+    }
+}
+
+/// Reads the version selected by `anchor`, given the version of `cairo-lang-compiler` locked.
+pub(crate) fn anchor_version(
+    ctx: &Context,
+    anchor: Anchor,
+    locked_version: &Version,
+) -> Result<Version> {
+    match anchor {
+        Anchor::Lockfile => Ok(locked_version.clone()),
+        Anchor::ScarbToml => {
+            let path = ctx.workspace_root().join("Scarb.toml");
+            scarb_cairo_version(&manifest::read(&path)?)
+                .with_context(|| format!("failed to read the Cairo version of {}", path.display()))
+        }
+    }
+}
+
+/// Reads the exact `cairo-version` a `Scarb.toml` declares, see [`Anchor::ScarbToml`].
+fn scarb_cairo_version(scarb_toml: &DocumentMut) -> Result<Version> {
+    let (table_path, cairo_version) = [
+        "tool.cairo-toolchain-xtasks",
+        "package",
+        "workspace.package",
+    ]
+    .into_iter()
+    .find_map(|table_path| {
+        let item = manifest::get_table_like(scarb_toml, table_path)?.get("cairo-version")?;
+        Some((table_path, item))
+    })
+    .context("no `cairo-version` is set")?;
+    let cairo_version = cairo_version
+        .as_str()
+        .with_context(|| format!("`{table_path}.cairo-version` must be a string"))?;
+    cairo_version
+        .strip_prefix('=')
+        .unwrap_or(cairo_version)
+        .trim()
+        .parse()
+        .with_context(|| {
+            format!(
+                "`{table_path}.cairo-version` must be an exact version like `2.9.2`, found \
+                `{cairo_version}`"
+            )
+        })
+}
+
+/// Derives the crate version from the compiler version, applying `--build` and `--no-pre-release`.
+fn target_version(compiler_version: &Version, args: &Args) -> Result<Version> {
+    let mut version = compiler_version.clone();
+    if let Some(build) = &args.build {
         version.build = build.parse()?;
     }
     if args.no_pre_release {
         version.pre = Prerelease::EMPTY;
     }
+    Ok(version)
+}
 
-    package["version"] = value(version.to_string());
-
-    eprintln!("[{table_path}]\n{package}");
+/// Verifies all enabled [`Check`]s, reporting every discrepancy before failing.
+fn check(ctx: &Context, args: &Args) -> Result<()> {
+    let sh = &ctx.sh;
+    let cargo_toml = sh.read_file("Cargo.toml")?.parse::<DocumentMut>()?;
+    let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+    let compiler_version = compiler_version(&cargo_lock)?;
+    let anchor = anchor(&cargo_toml, args.anchor)?;
+    let version = target_version(&anchor_version(ctx, anchor, &compiler_version)?, args)?;
 
-    if !args.dry_run {
-        sh.write_file("Cargo.toml", cargo_toml.to_string())?;
+    let mut results = Vec::new();
+    for &category in Check::value_variants() {
+        let discrepancies = if args.skip.contains(&category) {
+            None
+        } else {
+            Some(match category {
+                Check::Version => check_version(&cargo_toml, &version),
+                Check::Members => check_members(&ctx.workspace_root(), &cargo_toml, &version)?,
+                Check::Dependencies => {
+                    check_dependencies(&ctx.workspace_root(), &cargo_toml, &version)?
+                }
+                Check::Provenance => check_provenance(&cargo_toml, &compiler_version),
+                Check::Requirements => {
+                    check_requirements(&ctx.workspace_root(), &cargo_toml, &cargo_lock)?
+                }
+            })
+        };
+        results.push((category, discrepancies));
+    }
 
-        cmd!(sh, "cargo fetch").run()?;
+    for discrepancy in results.iter().filter_map(|(_, d)| d.as_ref()).flatten() {
+        ctx.error(discrepancy);
+    }
+    for (category, discrepancies) in &results {
+        let status = match discrepancies {
+            None => "skipped".to_owned(),
+            Some(discrepancies) if discrepancies.is_empty() => "ok".to_owned(),
+            Some(discrepancies) => format!("failed ({})", discrepancies.len()),
+        };
+        ctx.summary(format_args!("{}: {status}", check_name(*category)));
+    }
+    if args.format == Format::Json {
+        let report = check_report(&version, &results);
+        ctx.output(&format!("{report:#}\n"));
     }
 
+    let failed = results
+        .iter()
+        .filter(|(_, discrepancies)| discrepancies.as_ref().is_some_and(|d| !d.is_empty()))
+        .map(|(category, _)| check_name(*category))
+        .collect::<Vec<_>>();
+    ensure!(
+        failed.is_empty(),
+        "version consistency checks failed: {}",
+        failed.join(", ")
+    );
     Ok(())
 }
 
+fn check_name(category: Check) -> String {
+    category.to_possible_value().unwrap().get_name().to_owned()
+}
+
+/// Describes the results of `--check` as the JSON report of `--format json`.
+fn check_report(version: &Version, results: &[(Check, Option<Vec<String>>)]) -> serde_json::Value {
+    let checks = results
+        .iter()
+        .map(|(category, discrepancies)| {
+            let status = match discrepancies {
+                None => "skipped",
+                Some(discrepancies) if discrepancies.is_empty() => "ok",
+                Some(_) => "failed",
+            };
+            serde_json::json!({
+                "check": check_name(*category),
+                "status": status,
+                "discrepancies": discrepancies.clone().unwrap_or_default(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let ok = results
+        .iter()
+        .all(|(_, discrepancies)| discrepancies.as_ref().is_none_or(Vec::is_empty));
+    serde_json::json!({ "ok": ok, "version": version.to_string(), "checks": checks })
+}
+
+/// Compares the crate version with the derived one.
+fn check_version(cargo_toml: &DocumentMut, version: &Version) -> Vec<String> {
+    match crate_version(cargo_toml) {
+        Some((_, current)) if current == *version => Vec::new(),
+        Some((table_path, current)) => {
+            vec![format!(
+                "[{table_path}] version is {current}, expected {version}"
+            )]
+        }
+        None => vec![format!(
+            "Cargo.toml has no valid version in [workspace.package] or [package], expected \
+            {version}"
+        )],
+    }
+}
+
+/// Reads the manifests of all workspace members, along with their paths relative to `root`.
+fn member_manifests(root: &Path, cargo_toml: &DocumentMut) -> Result<Vec<(PathBuf, DocumentMut)>> {
+    let mut manifests = Vec::new();
+    for member in workspace::members(root, cargo_toml)? {
+        let manifest_path = member.join("Cargo.toml");
+        let manifest = manifest::read(&root.join(&manifest_path))?;
+        manifests.push((manifest_path, manifest));
+    }
+    Ok(manifests)
+}
+
+/// Finds published members declaring a version of their own, which differs from `version`.
+///
+/// Members with `publish = false`, like xtasks, are versioned independently.
+fn check_members(root: &Path, cargo_toml: &DocumentMut, version: &Version) -> Result<Vec<String>> {
+    let mut discrepancies = Vec::new();
+    for (manifest_path, manifest) in member_manifests(root, cargo_toml)? {
+        let Some(package) = manifest.get("package") else {
+            continue;
+        };
+        if package.get("publish").and_then(Item::as_bool) == Some(false) {
+            continue;
+        }
+        let Some(current) = package.get("version").and_then(Item::as_str) else {
+            continue;
+        };
+        if current.parse::<Version>().ok().as_ref() != Some(version) {
+            discrepancies.push(format!(
+                "{} [package] version is {current}, expected {version} or `version.workspace = \
+                true`",
+                manifest_path.display()
+            ));
+        }
+    }
+    Ok(discrepancies)
+}
+
+/// Finds requirements on workspace members which `version` does not satisfy.
+fn check_dependencies(
+    root: &Path,
+    cargo_toml: &DocumentMut,
+    version: &Version,
+) -> Result<Vec<String>> {
+    let mut manifests = vec![(PathBuf::from("Cargo.toml"), cargo_toml.clone())];
+    manifests.extend(member_manifests(root, cargo_toml)?);
+    let members = manifests
+        .iter()
+        .filter_map(|(_, manifest)| {
+            let name = manifest.get("package")?.get("name")?.as_str()?;
+            Some(name.to_owned())
+        })
+        .collect::<Vec<_>>();
+
+    let mut discrepancies = Vec::new();
+    for (manifest_path, manifest) in &manifests {
+        for (table_path, deps) in workspace::dependency_tables(manifest) {
+            for (key, dep) in deps.iter() {
+                let name = manifest::crate_name(key, dep);
+                if !members.iter().any(|member| member == name) {
+                    continue;
+                }
+                let requirement = dep
+                    .as_str()
+                    .or_else(|| dep.get("version").and_then(Item::as_str));
+                let Some(requirement) = requirement else {
+                    continue;
+                };
+                let matches = requirement
+                    .parse::<VersionReq>()
+                    .is_ok_and(|req| req.matches(version));
+                if !matches {
+                    discrepancies.push(format!(
+                        "{} [{table_path}] requires {key} `{requirement}`, which {version} does \
+                        not match",
+                        manifest_path.display()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(discrepancies)
+}
+
+/// Compares the recorded Cairo toolchain version with the locked compiler.
+fn check_provenance(cargo_toml: &DocumentMut, compiler_version: &Version) -> Vec<String> {
+    provenance::read(cargo_toml)
+        .into_iter()
+        .filter(|record| record.group == "cairo")
+        .filter_map(|record| {
+            let recorded = record.version?;
+            (recorded != *compiler_version).then(|| {
+                format!(
+                    "the recorded cairo toolchain version is {recorded}, but Cargo.lock locks \
+                    cairo-lang-compiler {compiler_version}"
+                )
+            })
+        })
+        .collect()
+}
+
+/// Finds requirements on `cairo-lang-*` crates whose major and minor version differ from the crate
+/// version.
+///
+/// Crates released independently of the compiler, as locked in `cargo_lock`, are not compared.
+/// Neither are crates patched in `[patch.crates-io]`, whose version is decided by the patch, and
+/// which `upgrade` requires as `*` for git and path sources.
+fn check_requirements(
+    root: &Path,
+    cargo_toml: &DocumentMut,
+    cargo_lock: &DocumentMut,
+) -> Result<Vec<String>> {
+    let Some((_, version)) = crate_version(cargo_toml) else {
+        return Ok(Vec::new());
+    };
+    let compiler_version = compiler_version(cargo_lock)?;
+    let independent = version_families(cargo_lock)?
+        .into_iter()
+        .filter(|(family, _)| *family != compiler_version)
+        .flat_map(|(_, crates)| crates)
+        .collect::<Vec<_>>();
+    let patched = manifest::get_table_like(cargo_toml, "patch.crates-io")
+        .map(|patch| {
+            patch
+                .iter()
+                .map(|(key, _)| key.to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut manifests = vec![(PathBuf::from("Cargo.toml"), cargo_toml.clone())];
+    manifests.extend(member_manifests(root, cargo_toml)?);
+    let mut discrepancies = Vec::new();
+    for (manifest_path, manifest) in &manifests {
+        for (table_path, deps) in workspace::dependency_tables(manifest) {
+            for (key, dep) in deps.iter() {
+                let name = manifest::crate_name(key, dep);
+                if !name.starts_with("cairo-lang-")
+                    || independent.iter().any(|c| c == name)
+                    || patched.iter().any(|c| c == name)
+                {
+                    continue;
+                }
+                let requirement = dep
+                    .as_str()
+                    .or_else(|| dep.get("version").and_then(Item::as_str));
+                let Some(requirement) = requirement else {
+                    continue;
+                };
+                let Ok(req) = requirement.parse::<VersionReq>() else {
+                    continue;
+                };
+                let pins_version = req.comparators.first().is_some_and(|comparator| {
+                    comparator.major == version.major && comparator.minor == Some(version.minor)
+                });
+                if !pins_version {
+                    discrepancies.push(format!(
+                        "{} [{table_path}] requires {key} `{requirement}`, while the crate \
+                        version is {version}",
+                        manifest_path.display()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(discrepancies)
+}
+
+/// Reads the crate version from `[workspace.package]`, or `[package]` if there is no such table.
+pub(crate) fn crate_version(cargo_toml: &DocumentMut) -> Option<(&'static str, Version)> {
+    let (package, table_path) = match cargo_toml.get("workspace").and_then(|w| w.get("package")) {
+        Some(package) => (package, "workspace.package"),
+        None => (cargo_toml.get("package")?, "package"),
+    };
+    let version = package.get("version")?.as_str()?.parse().ok()?;
+    Some((table_path, version))
+}
+
 /// Gets the version of the `cairo-lang-compiler` crate from `Cargo.lock`, which is the expected
 /// version for the crate this script is being run on.
 pub fn expected_version() -> Result<Version> {
+    let sh = Shell::new()?;
+    let root = crate::workspace::find_root(&sh.current_dir());
+    expected_version_from(&root.join("Cargo.lock"))
+}
+
+/// Like [`expected_version`], but reads the `Cargo.lock` at `cargo_lock`, for lockfiles kept
+/// outside the workspace root.
+pub fn expected_version_from(cargo_lock: &Path) -> Result<Version> {
     // NOTE: We are deliberately not using cargo_metadata to reduce build times of xtasks.
 
-    let sh = Shell::new()?;
-    let cargo_lock = sh.read_file("Cargo.lock")?.parse::<DocumentMut>()?;
-    let packages = cargo_lock["package"].as_array_of_tables().unwrap();
-    let compiler = {
-        let pkgs = packages
-            .into_iter()
-            .filter(|pkg| pkg["name"].as_str().unwrap() == "cairo-lang-compiler")
-            .collect::<Vec<_>>();
-        ensure!(
-            pkgs.len() == 1,
-            "expected exactly one cairo-lang-compiler package in Cargo.lock, found: {}",
-            pkgs.len()
+    let cargo_lock = std::fs::read_to_string(cargo_lock)
+        .with_context(|| format!("failed to read {}", cargo_lock.display()))?
+        .parse::<DocumentMut>()?;
+    compiler_version(&cargo_lock)
+}
+
+pub(crate) fn compiler_version(cargo_lock: &DocumentMut) -> Result<Version> {
+    let versions = locked_versions(cargo_lock, "cairo-lang-compiler")?;
+    ensure!(
+        versions.len() == 1,
+        "expected exactly one cairo-lang-compiler package in Cargo.lock, found: {}",
+        versions.len()
+    );
+    Ok(versions.into_iter().next().unwrap())
+}
+
+/// Groups locked `cairo-lang-*` crates by version.
+///
+/// Most Cairo crates share the compiler version, but some are released on their own cadence.
+fn version_families(cargo_lock: &DocumentMut) -> Result<BTreeMap<Version, Vec<String>>> {
+    let mut families = BTreeMap::<Version, Vec<String>>::new();
+    let packages = cargo_lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+        .into_iter()
+        .flatten();
+    for pkg in packages {
+        let name = pkg.get("name").and_then(|name| name.as_str());
+        let Some(name) = name.filter(|name| name.starts_with("cairo-lang-")) else {
+            continue;
+        };
+        let version = pkg["version"].as_str().unwrap_or_default().parse()?;
+        families.entry(version).or_default().push(name.to_owned());
+    }
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_all_discrepancies() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_toml = r#"[workspace]
+members = ["crates/*", "xtask"]
+
+[workspace.package]
+version = "2.9.0"
+
+[workspace.dependencies]
+a = { path = "crates/a", version = "=2.9.0" }
+b = { path = "crates/b", version = "2.9" }
+
+[workspace.metadata.cairo-toolchain.cairo]
+version = "2.9.0"
+source = "registry"
+"#;
+        for (path, contents) in [
+            ("Cargo.toml", cargo_toml),
+            (
+                "crates/a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion.workspace = true\n",
+            ),
+            (
+                "crates/b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"2.8.0\"\n",
+            ),
+            (
+                "xtask/Cargo.toml",
+                "[package]\nname = \"xtask\"\nversion = \"0.1.0\"\npublish = false\n",
+            ),
+            (
+                "Cargo.lock",
+                "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n",
+            ),
+        ] {
+            sh.write_file(path, contents).unwrap();
+        }
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["sync-version", "--check"]);
+        let err = check(&ctx, &args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "version consistency checks failed: version, members, dependencies, provenance"
         );
-        pkgs.into_iter().next().unwrap()
-    };
-    let compiler_version = compiler["version"].as_str().unwrap();
-    Ok(compiler_version.parse()?)
+        let (stdout, stderr) = ctx.captured();
+        assert_eq!(stdout, "");
+        assert_eq!(
+            stderr,
+            [
+                "error: [workspace.package] version is 2.9.0, expected 2.9.2",
+                "error: crates/b/Cargo.toml [package] version is 2.8.0, expected 2.9.2 or \
+                 `version.workspace = true`",
+                "error: Cargo.toml [workspace.dependencies] requires a `=2.9.0`, which 2.9.2 does \
+                 not match",
+                "error: the recorded cairo toolchain version is 2.9.0, but Cargo.lock locks \
+                 cairo-lang-compiler 2.9.2",
+                "version: failed (1)",
+                "members: failed (1)",
+                "dependencies: failed (1)",
+                "provenance: failed (1)",
+                "requirements: ok",
+            ]
+        );
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), cargo_toml);
+
+        // Once synced, only the categories sync-version does not write are left.
+        run(&ctx, Args::default()).unwrap();
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from([
+            "sync-version",
+            "--check",
+            "--skip",
+            "members",
+            "--format",
+            "json",
+        ]);
+        assert!(check(&ctx, &args).is_err());
+        let (stdout, _) = ctx.captured();
+        let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "ok": false,
+                "version": "2.9.2",
+                "checks": [
+                    { "check": "version", "status": "ok", "discrepancies": [] },
+                    { "check": "members", "status": "skipped", "discrepancies": [] },
+                    {
+                        "check": "dependencies",
+                        "status": "failed",
+                        "discrepancies": [
+                            "Cargo.toml [workspace.dependencies] requires a `=2.9.0`, which \
+                             2.9.2 does not match",
+                        ],
+                    },
+                    { "check": "provenance", "status": "ok", "discrepancies": [] },
+                    { "check": "requirements", "status": "ok", "discrepancies": [] },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_toml = r#"[workspace]
+members = ["crates/*"]
+
+[workspace.package]
+version = "2.8.0"
+
+[workspace.dependencies]
+cairo-lang-compiler = "*"
+cairo-lang-utils = "2.7.0"
+cairo-lang-parser = { version = "~2.8", default-features = false }
+cairo-lang-syntax = "*"
+cairo-lang-sierra = "1.0.0"
+
+[patch.crates-io]
+cairo-lang-syntax = { git = "https://github.com/starkware-libs/cairo", rev = "0b3bfdc" }
+"#;
+        for (path, contents) in [
+            ("Cargo.toml", cargo_toml),
+            (
+                "crates/a/Cargo.toml",
+                "[package]\nname = \"a\"\n\n[dependencies]\nlowering = { package = \
+                \"cairo-lang-lowering\", version = \"2\" }\n\
+                cairo-lang-compiler.workspace = true\n",
+            ),
+            (
+                "Cargo.lock",
+                "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.8.0\"\n\n\
+                [[package]]\nname = \"cairo-lang-sierra\"\nversion = \"1.0.0\"\n",
+            ),
+        ] {
+            sh.write_file(path, contents).unwrap();
+        }
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["sync-version", "--check", "--skip", "provenance"]);
+        assert_eq!(
+            check(&ctx, &args).unwrap_err().to_string(),
+            "version consistency checks failed: requirements"
+        );
+        let (_, stderr) = ctx.captured();
+        assert_eq!(
+            stderr[..3],
+            [
+                "error: Cargo.toml [workspace.dependencies] requires cairo-lang-compiler `*`, \
+                 while the crate version is 2.8.0",
+                "error: Cargo.toml [workspace.dependencies] requires cairo-lang-utils `2.7.0`, \
+                 while the crate version is 2.8.0",
+                "error: crates/a/Cargo.toml [dependencies] requires lowering `2`, while the crate \
+                 version is 2.8.0",
+            ]
+        );
+        assert_eq!(stderr[7], "requirements: failed (3)");
+    }
+
+    #[test]
+    fn test_records_history() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[package]\nname = \"foo\"\nversion = \"2.9.0\" # Synced.\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n",
+        )
+        .unwrap();
+
+        let backups = || {
+            sh.read_dir(".")
+                .unwrap()
+                .into_iter()
+                .filter_map(|path| path.file_name()?.to_str().map(str::to_owned))
+                .filter(|name| name.starts_with("Cargo.toml.bak."))
+                .collect::<Vec<_>>()
+        };
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, Args::parse_from(["sync-version", "--no-backup"])).unwrap();
+        assert!(!sh.path_exists("target"));
+        assert!(backups().is_empty());
+
+        sh.write_file("Cargo.toml", original).unwrap();
+        let ctx = Context::recording(sh.clone());
+        run(
+            &ctx,
+            Args::parse_from(["sync-version", "--no-backup", "--backup"]),
+        )
+        .unwrap();
+        let synced = sh.read_file("Cargo.toml").unwrap();
+        assert!(synced.contains("version = \"2.9.2\""), "{synced}");
+        let [backup] = &backups()[..] else {
+            panic!("expected one backup: {:?}", backups());
+        };
+        assert_eq!(sh.read_file(backup).unwrap(), original);
+        assert!(ctx
+            .captured()
+            .1
+            .contains(&format!("note: backed up Cargo.toml to {backup}")));
+        let snapshot = Snapshot::find(&sh, None).unwrap();
+        snapshot.rollback(&sh, false).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+    }
+
+    #[test]
+    fn test_lockfile_outside_workspace() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let lockfile = temp_dir.path().join("locks/Cargo.lock");
+        sh.write_file(
+            &lockfile,
+            "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n",
+        )
+        .unwrap();
+        sh.change_dir(temp_dir.path().join("project"));
+        let cargo_features = "cargo-features = [ \"edition2024\" ] # Nightly only.\n\n";
+        sh.write_file(
+            "Cargo.toml",
+            format!("{cargo_features}[package]\nname = \"foo\"\nversion = \"2.9.0\"\n"),
+        )
+        .unwrap();
+
+        let global = GlobalArgs {
+            lockfile_path: Some(lockfile.clone()),
+            ..Default::default()
+        };
+        let ctx = Context::with_shell(sh.clone(), &global).unwrap().record();
+        let outcome = run(&ctx, Args::default()).unwrap();
+        assert_eq!(outcome.version, Version::new(2, 9, 2));
+        assert_eq!(outcome.previous_version, Some(Version::new(2, 9, 0)));
+        assert_eq!(
+            ctx.captured().1[..2],
+            [
+                "[package]\nname = \"foo\"\nversion = \"2.9.2\"\n",
+                "anchored on cairo-lang-compiler 2.9.2",
+            ]
+        );
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(cargo_toml.contains("version = \"2.9.2\""));
+        assert!(cargo_toml.starts_with(cargo_features), "{cargo_toml}");
+        assert_eq!(
+            expected_version_from(&lockfile).unwrap(),
+            Version::new(2, 9, 2)
+        );
+    }
+
+    #[test]
+    fn test_scarb_toml_anchor() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[package]\nname = \"foo\"\nversion = \"2.9.0\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Scarb.toml",
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\ncairo-version = \"=2.9.1\"\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["sync-version", "--anchor", "scarb-toml"]);
+        let outcome = run(&ctx, args).unwrap();
+        assert_eq!(outcome.compiler_version, Version::new(2, 9, 1));
+        assert_eq!(outcome.version, Version::new(2, 9, 1));
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(cargo_toml.contains("version = \"2.9.1\""), "{cargo_toml}");
+
+        let args = Args::parse_from(["sync-version", "--anchor", "scarb-toml", "--check"]);
+        check(&ctx, &args).unwrap();
+
+        // Configured for runs without `--anchor`, like the ones chained by `upgrade`.
+        sh.write_file(
+            "Cargo.toml",
+            "[package]\nname = \"foo\"\nversion = \"2.9.0\"\n\n\
+            [package.metadata.cairo-toolchain-xtasks]\nanchor = \"scarb-toml\"\n",
+        )
+        .unwrap();
+        let outcome = run(&ctx, Args::default()).unwrap();
+        assert_eq!(outcome.anchor, Anchor::ScarbToml);
+        assert_eq!(outcome.version, Version::new(2, 9, 1));
+        check(&ctx, &Args::parse_from(["sync-version", "--check"])).unwrap();
+        let args = Args::parse_from(["sync-version", "--anchor", "lockfile"]);
+        assert_eq!(run(&ctx, args).unwrap().version, Version::new(2, 9, 2));
+
+        let parse = |s: &str| scarb_cairo_version(&s.parse().unwrap());
+        assert_eq!(
+            parse(
+                "[package]\ncairo-version = \"2.9.1\"\n\n\
+                [tool.cairo-toolchain-xtasks]\ncairo-version = \"2.9.2\"\n"
+            )
+            .unwrap(),
+            Version::new(2, 9, 2)
+        );
+        assert_eq!(
+            parse("[workspace.package]\ncairo-version = \"2.9.1\"\n").unwrap(),
+            Version::new(2, 9, 1)
+        );
+        assert_eq!(
+            parse("[package]\ncairo-version = \"^2.9\"\n")
+                .unwrap_err()
+                .to_string(),
+            "`package.cairo-version` must be an exact version like `2.9.2`, found `^2.9`"
+        );
+        assert_eq!(
+            parse("[package]\nname = \"foo\"\n")
+                .unwrap_err()
+                .to_string(),
+            "no `cairo-version` is set"
+        );
+    }
+
+    #[test]
+    fn test_version_families() {
+        let cargo_lock = r#"version = 4
+
+[[package]]
+name = "cairo-lang-compiler"
+version = "2.9.2"
+
+[[package]]
+name = "cairo-lang-macro"
+version = "0.1.1"
+
+[[package]]
+name = "cairo-lang-utils"
+version = "2.9.2"
+
+[[package]]
+name = "anyhow"
+version = "1.0.95"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        assert_eq!(
+            compiler_version(&cargo_lock).unwrap(),
+            Version::new(2, 9, 2)
+        );
+        assert_eq!(
+            version_families(&cargo_lock).unwrap(),
+            BTreeMap::from([
+                (Version::new(0, 1, 1), vec!["cairo-lang-macro".to_owned()]),
+                (
+                    Version::new(2, 9, 2),
+                    vec![
+                        "cairo-lang-compiler".to_owned(),
+                        "cairo-lang-utils".to_owned()
+                    ]
+                ),
+            ])
+        );
+    }
 }
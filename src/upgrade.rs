@@ -1,49 +1,606 @@
 //! Update toolchain crates properly.
 
-use anyhow::{bail, Result};
-use clap::{Parser, ValueEnum};
-use semver::Version;
-use std::mem;
-use std::path::PathBuf;
+use crate::cache::{Cache, CacheArgs};
+use crate::compat_check;
+use crate::config::{self, Channel, GroupConfig};
+use crate::context::{Context, Format, GlobalArgs};
+use crate::eol::LineEnding;
+use crate::git::{self, LsRemote, Remote, RevKind};
+use crate::github_release;
+use crate::history::{self, Snapshot};
+use crate::http::{Curl, Http, Network};
+use crate::index::{Index, IndexArgs};
+use crate::lockfile::{self, locked_versions, GitReference};
+use crate::manifest::{self, display_dependency, get_item_mut, get_table_like, patch_table};
+use crate::patch_sections;
+use crate::provenance::{self, Provenance};
+use crate::release_script;
+use crate::restore;
+use crate::sync_version;
+use crate::workspace;
+use anyhow::{bail, ensure, Context as _, Result};
+use clap::{ArgAction, Parser, ValueEnum};
+use regex::Regex;
+use semver::{Version, VersionReq};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::OnceLock;
-use toml_edit::{DocumentMut, InlineTable, Value};
-use xshell::{cmd, Shell};
+use toml_edit::{DocumentMut, InlineTable, Item, Key, TableLike};
+use xshell::{cmd, Cmd, Shell};
 
 /// Update toolchain crates properly.
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 pub struct Args {
-    /// Name of toolchain dependency (group) to update.
-    dep: DepName,
+    /// Names of the toolchain dependency groups to update, separated by commas, e.g.
+    /// `cairo,cairols,cairolint`.
+    ///
+    /// All groups are upgraded to the same source in one run, with a single `cargo fetch` and
+    /// `sync-version`.
+    #[arg(
+        value_name = "GROUP",
+        num_args = 1,
+        action = ArgAction::Set,
+        value_delimiter = ',',
+        required_unless_present_any = [
+            "rollback",
+            "all",
+            "crate_name",
+            "apply_to_dependabot",
+            "from_github_release",
+        ]
+    )]
+    dep: Vec<DepName>,
+
+    /// Update the group owning this crate, e.g. `cairo-language-server`, instead of naming the
+    /// group.
+    ///
+    /// The crate is looked up in the crate lists of the groups whose crates are named like it,
+    /// e.g. `cairo-lang-*` for Cairo, once their sources are resolved.
+    #[arg(long = "crate", value_name = "CRATE", conflicts_with_all = ["dep", "all"])]
+    crate_name: Option<String>,
+
+    /// Apply an update proposed by Dependabot for one crate, e.g. `cairo-lang-compiler@2.9.2`, to
+    /// the whole group owning it.
+    ///
+    /// Dependabot bumps crates one at a time, which leaves the other crates of the group at their
+    /// old version. This upgrades all of them to `<VERSION>` from crates.io instead, as if the
+    /// group was named with that version, so the result can replace the changes of the
+    /// Dependabot PR.
+    #[arg(
+        long,
+        value_name = "CRATE@VERSION",
+        value_parser = parse_crate_version,
+        conflicts_with_all = [
+            "dep",
+            "crate_name",
+            "all",
+            "version",
+            "rev",
+            "branch",
+            "path",
+            "latest",
+            "channel",
+            "rollback",
+            "patch_from_lock",
+            "lockfile_only",
+        ]
+    )]
+    apply_to_dependabot: Option<(String, Version)>,
+
+    /// Upgrade to the tag of the release announced by a GitHub `release` webhook payload, as if
+    /// it was given with `--rev`.
+    ///
+    /// The group is inferred from the repository of the release, unless it is named, in which
+    /// case the release must belong to it. Draft, deleted and unpublished releases are rejected.
+    #[arg(
+        long,
+        value_name = "PAYLOAD",
+        conflicts_with_all = [
+            "crate_name",
+            "all",
+            "version",
+            "rev",
+            "branch",
+            "path",
+            "latest",
+            "channel",
+            "rollback",
+            "patch_from_lock",
+            "apply_to_dependabot",
+        ]
+    )]
+    from_github_release: Option<PathBuf>,
 
     #[command(flatten)]
     spec: Spec,
 
+    #[command(flatten)]
+    filter: CrateFilter,
+
+    /// Update every dependency group the project depends on.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["dep", "version", "rev", "path", "group_versions", "patch_from_lock"]
+    )]
+    all: bool,
+
+    /// Source the dependency from a release channel: the latest stable release on crates.io, or
+    /// the default branch of the group.
+    ///
+    /// An explicit `--branch` takes precedence. Defaults to the `channel` configured in
+    /// `[workspace.metadata.cairo-toolchain-xtasks.<GROUP>]` if no source is given.
+    #[arg(long, conflicts_with_all = ["version", "rev", "path", "latest"])]
+    channel: Option<Channel>,
+
+    /// Let `--latest` pick prereleases, like `2.10.0-rc.0`, too.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "latest",
+        conflicts_with = "branch"
+    )]
+    prerelease: bool,
+
+    /// Also update another dependency group to a crates.io version, e.g. `cairolint=2.8.1`.
+    ///
+    /// Can be repeated. A mapping for the main group can be given in place of `<VERSION>`.
+    #[arg(long = "group-version", value_name = "GROUP=VERSION", value_parser = parse_group_version)]
+    group_versions: Vec<(DepName, Version)>,
+
+    /// Write the commit a `--rev` tag or reference points to into `[patch.crates-io]`, instead of
+    /// the revision as given.
+    #[arg(long, default_value_t = false, requires = "rev")]
+    pin: bool,
+
+    /// Also write the version into `[patch.crates-io]` entries sourced from git, e.g.
+    /// `{ version = "2.9.2", git = "...", rev = "v2.9.2" }`, to constrain which package of the
+    /// repository they match.
+    ///
+    /// With `--rev` or `--branch`, the version must be given as well, e.g.
+    /// `upgrade cairo 2.9.2 --rev v2.9.2`. With `--patch-from-lock`, the locked version is written.
+    #[arg(long, default_value_t = false)]
+    patch_with_version: bool,
+
+    /// Require exactly the upgraded version, i.e. write `=<VERSION>` instead of a caret requirement.
+    ///
+    /// Only applies to groups sourced from crates.io.
+    #[arg(long, default_value_t = false)]
+    exact: bool,
+
+    /// Only move `Cargo.lock` to the newest versions of the group allowed by the manifest
+    /// requirements, or to `<VERSION>` if given, without editing any requirements.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["rev", "branch", "path", "channel", "patch_from_lock", "rollback", "exact"]
+    )]
+    lockfile_only: bool,
+
+    /// Do not sort the crates of the group, only update them in place.
+    ///
+    /// Use this to keep toolchain crates grouped by purpose, e.g. under comments. Other
+    /// dependencies are never moved.
+    #[arg(long, default_value_t = false)]
+    no_sort: bool,
+
+    /// Sort the crates of the group ignoring case, e.g. a dependency renamed to `lint` before one
+    /// renamed to `LS`.
+    ///
+    /// By default, names are sorted case-sensitively, uppercase first. The crates of the group
+    /// are sorted among themselves, within the slots they take in each dependency table.
+    /// `[patch.crates-io]` is never sorted, new entries are appended in the order of the crates of
+    /// the group.
+    #[arg(long, default_value_t = false, conflicts_with = "no_sort")]
+    sort_case_insensitive: bool,
+
     /// Do not edit any files, just inform what would be done.
+    ///
+    /// This includes printing a unified diff of each manifest that would be edited and the cargo
+    /// commands a real run would execute.
     #[arg(long, default_value_t = false)]
     dry_run: bool,
+
+    /// Do not check that the requested version of the group is published on crates.io.
+    ///
+    /// By default, upgrading to a version that is not published, or yanked, fails before any file
+    /// is edited, and only warns with `--dry-run`. Pass this to upgrade offline to versions missing
+    /// from the index cache.
+    #[arg(long, default_value_t = false)]
+    no_verify: bool,
+
+    /// Check that the workspace still compiles with the upgrade before writing any file.
+    ///
+    /// The workspace is copied to a temporary directory, where the edited manifests are written
+    /// and `cargo check --workspace` is run, so this works with `--dry-run` too. The upgrade is
+    /// not applied if the check fails. This builds the whole workspace from scratch, so it is slow.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["lockfile_only", "list_tables", "rollback"]
+    )]
+    compat_check: bool,
+
+    /// Do not run `cargo xtask sync-version` after upgrading.
+    ///
+    /// Use this if the project manages its version independently. Note that this may leave the
+    /// crate version out of step with the toolchain.
+    #[arg(long, default_value_t = false)]
+    no_sync_version: bool,
+
+    /// Append the resolved source of the dependency group to this file as `KEY=value` lines.
+    ///
+    /// Keys are prefixed with the group name, e.g. `CAIRO_VERSION` and `CAIRO_REV`. This is meant
+    /// for passing values to later CI steps, e.g. via `$GITHUB_ENV`. With `--dry-run`, the lines
+    /// are only printed.
+    #[arg(long, value_name = "FILE")]
+    emit_env: Option<PathBuf>,
+
+    /// Write a dated markdown fragment summarizing the upgrade into this directory, for release
+    /// notes, e.g. `- Upgraded Cairo to 2.8.0 (was 2.7.1)`.
+    #[arg(long, value_name = "DIR")]
+    changelog_fragment: Option<PathBuf>,
+
+    /// Format of the report printed to stdout once the upgrade completes.
+    ///
+    /// With `json`, stdout only contains the report, listing the previous and resolved source of
+    /// each group, while all diagnostics go to stderr.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Also write the JSON report of `--format json` to this file, whatever the `--format` of
+    /// stdout.
+    ///
+    /// Use this to keep the summary on the console while saving the report as a CI artifact. An
+    /// existing file is overwritten.
+    #[arg(long, value_name = "FILE")]
+    report_file: Option<PathBuf>,
+
+    /// Print the version `Cargo.lock` resolved for each crate of the upgraded groups, to confirm
+    /// they moved together.
+    ///
+    /// The crates are printed as a table on stdout, or listed in the report of `--format json`.
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    print_resolved_crates_with_versions: bool,
+
+    /// Print a hash of the packages `Cargo.lock` resolved for the crates of the upgraded groups,
+    /// with their versions and sources.
+    ///
+    /// Two machines which resolved identically print the same hash, regardless of where local
+    /// checkouts are. The hash is printed on stdout, or as `state_hash` in the report of
+    /// `--format json`.
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    print_state_hash: bool,
+
+    /// Number of dependency groups to look up concurrently, all of them by default.
+    ///
+    /// Pass `1` to look groups up one after another, e.g. to make logs easier to follow.
+    #[arg(long, short = 'j', value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Print which dependency tables were found in the manifest and those of workspace members, and
+    /// exit without editing.
+    ///
+    /// With `--package`, only the tables of that member are listed.
+    #[arg(long, default_value_t = false)]
+    list_tables: bool,
+
+    /// Write `[patch.crates-io]` entries matching the git sources of the group in `Cargo.lock`.
+    ///
+    /// Use this to repair a manifest whose patches got lost while `Cargo.lock` still points to the
+    /// right commits. No other changes are made.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["version", "rev", "branch", "path", "latest", "group_versions"]
+    )]
+    patch_from_lock: bool,
+
+    /// Restore all files to their state before a previous run, by default the most recent one.
+    ///
+    /// Runs are recorded in `target/cairo-toolchain-xtasks/upgrade-history`. Refuses if files were
+    /// modified since the run, unless `--force` is given. Unlike `cargo xtask restore`, this also
+    /// restores `Cargo.lock` and the member manifests.
+    #[arg(
+        long,
+        value_name = "RUN",
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["dep", "version", "rev", "branch", "path", "latest", "group_versions", "patch_from_lock", "all", "channel"]
+    )]
+    rollback: Option<String>,
+
+    /// Upgrade groups even if no manifest of the workspace depends on any of their crates.
+    ///
+    /// Only the `[patch.crates-io]` entries are then written, which is useful to patch a crate the
+    /// project depends on transitively only. Without this, such an upgrade exits with
+    /// [`NothingToDo::EXIT_CODE`].
+    #[arg(long, default_value_t = false)]
+    force_patch: bool,
+
+    /// Repair patch sections mangled by a merge before upgrading, by merging tables declared more
+    /// than once and dropping `[patch]` headers without entries.
+    ///
+    /// Without this, any structural problem of the patch sections aborts the upgrade.
+    #[arg(long, default_value_t = false)]
+    fix: bool,
+
+    /// Remove patches of the crates of the group from patch tables other than
+    /// `[patch.crates-io]`, like `[patch."https://github.com/starkware-libs/cairo"]`, so that the
+    /// entry written by the upgrade is the only one.
+    ///
+    /// Each removal is reported in a warning. Tables left without entries are removed as well.
+    #[arg(long, default_value_t = false)]
+    dedupe_patches: bool,
+
+    /// What to do with `[patch.crates-io]` entries of the group written by hand, like patches to a
+    /// fork, which the upgrade would replace or remove.
+    ///
+    /// Entries pointing to the repository of the group, or into the local checkout it is upgraded
+    /// from or was last upgraded from, are never considered written by hand.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = OnConflict::Ours)]
+    on_conflict: OnConflict,
+
+    /// Keep the `[patch.crates-io]` table when the upgrade removes its last entry, instead of
+    /// removing it along with an emptied `[patch]`.
+    ///
+    /// This avoids diff noise in projects switching between sources often.
+    #[arg(long, default_value_t = false)]
+    keep_empty_patch_table: bool,
+
+    /// Fail if the manifest does not depend on every crate of the group, for projects which must
+    /// use all of them.
+    ///
+    /// Only the dependency tables edited by the upgrade are checked. The `[patch.crates-io]`
+    /// entries do not count, as they are written for all crates of the group anyway.
+    #[arg(long, default_value_t = false)]
+    strict_group: bool,
+
+    /// Check that the `--branch` of each group exists with `git ls-remote` before editing, instead
+    /// of leaving a missing one to fail `cargo fetch`.
+    ///
+    /// Tags, branches and references given with `--rev` are always checked. With this flag, commit
+    /// hashes are looked up among the commits branches and tags point to, which also completes
+    /// abbreviated ones and rejects ambiguous ones. Remotes are not asked for arbitrary commits, so
+    /// this also rejects older commits no branch or tag points to, which Cargo could still fetch.
+    #[arg(long, default_value_t = false)]
+    validate_revs: bool,
+
+    /// Upgrade the other crates of a group if some are missing from its `--path` checkout,
+    /// instead of failing.
+    ///
+    /// Missing crates are left as they were, and listed in a warning.
+    #[arg(long, default_value_t = false)]
+    best_effort: bool,
+
+    /// Leave crates of the group declared as `path` dependencies, e.g. vendored forks, as they are.
+    ///
+    /// Their requirements and `[patch.crates-io]` entries are not touched, as if they were
+    /// excluded with `--exclude`.
+    #[arg(long, default_value_t = false)]
+    preserve_path: bool,
+
+    /// Replace git dependencies on crates of the group which `[patch.crates-io]` also patches with
+    /// a `*` requirement, relying on the patch for the source.
+    ///
+    /// Without it, such dependencies are only reported and left as they are. Manifests of workspace
+    /// members are edited as well.
+    #[arg(long, default_value_t = false)]
+    consolidate: bool,
+
+    /// Edit the dependency tables of the workspace member with this `package.name`, instead of
+    /// those of the root manifest.
+    ///
+    /// `[patch.crates-io]` entries are still written to the root manifest, as Cargo only reads
+    /// them there. Other members are left untouched.
+    #[arg(long, value_name = "NAME", conflicts_with = "patch_from_lock")]
+    package: Option<String>,
+
+    /// Copy `Cargo.toml` to `Cargo.toml.<EXT>` before editing it, e.g. `--backup-ext bak`.
+    ///
+    /// Manifests of workspace members edited by the upgrade are backed up next to them the same
+    /// way. This is a plain copy, independent of the history used by `--rollback`. An existing
+    /// backup is overwritten.
+    #[arg(long, value_name = "EXT")]
+    backup_ext: Option<String>,
+
+    /// Back up `Cargo.toml` to `Cargo.toml.bak.<TIMESTAMP>` next to it before editing it, and
+    /// record this run in the history of upgrade runs. This is the default.
+    ///
+    /// The backup holds the exact contents of the manifest as read, which `cargo xtask restore`
+    /// restores. The history saves the manifests and `Cargo.lock` in
+    /// `target/cairo-toolchain-xtasks/upgrade-history`, which `--rollback` restores them from.
+    #[arg(long, default_value_t = false, overrides_with = "no_backup")]
+    backup: bool,
+
+    /// Neither back up `Cargo.toml` nor record this run, so that it cannot be rolled back.
+    #[arg(long, default_value_t = false, overrides_with = "backup")]
+    no_backup: bool,
+
+    /// Read the manifest from stdin and print the upgraded one to stdout, without writing files or
+    /// running Cargo.
+    ///
+    /// This is meant for previews in editors. `Cargo.lock` is not updated, so versions only known
+    /// after resolution are not recorded, and unused patches are kept.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "rollback",
+            "backup_ext",
+            "backup",
+            "no_backup",
+            "patch_from_lock",
+            "lockfile_only",
+            "list_tables",
+            "consolidate",
+            "package",
+            "emit_env",
+            "changelog_fragment",
+            "format",
+            "report_file",
+            "print_resolved_crates_with_versions",
+            "print_state_hash",
+            "compat_check",
+        ]
+    )]
+    stdin: bool,
+
+    /// Roll back even if files were modified since the run, or edit the manifest of this crate
+    /// itself.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Where to read the list of crates published from the Cairo repository from.
+    #[arg(long, value_enum, default_value_t = CrateListSource::ReleaseScript)]
+    crate_list_source: CrateListSource,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    index: IndexArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    /// Versions of crates in the group which are released independently of its anchor crate.
+    #[arg(skip)]
+    crate_versions: Vec<(String, Version)>,
+
+    /// Crates of the group the manifest depends on, from which `--crate-list-source index` starts
+    /// looking for the other crates of Cairo.
+    #[arg(skip)]
+    used_crates: Vec<String>,
+
+    /// Whether the network may be accessed, as resolved from `--confirm-network`.
+    #[arg(skip)]
+    network: Network,
+}
+
+/// Selection of the crates of a group which are upgraded, by default all of them.
+///
+/// A crate is selected if it is named by `--only` or matches `--only-regex`, or if neither is
+/// given. Crates named by `--exclude` or matching `--exclude-regex` are never selected, even if
+/// they are also selected by `--only` or `--only-regex`. Unselected crates keep their
+/// requirements and `[patch.crates-io]` entries untouched.
+#[derive(clap::Args, Clone, Default)]
+struct CrateFilter {
+    /// Only upgrade this crate of the group. Can be repeated.
+    #[arg(long, value_name = "CRATE")]
+    only: Vec<String>,
+
+    /// Do not upgrade this crate of the group. Can be repeated.
+    #[arg(long, value_name = "CRATE")]
+    exclude: Vec<String>,
+
+    /// Only upgrade crates of the group whose names match this regex, in addition to `--only`
+    /// ones.
+    ///
+    /// The regex is unanchored, e.g. `sierra` matches `cairo-lang-sierra-to-casm`.
+    #[arg(long, value_name = "REGEX")]
+    only_regex: Option<Regex>,
+
+    /// Do not upgrade crates of the group whose names match this regex, in addition to
+    /// `--exclude` ones.
+    #[arg(long, value_name = "REGEX")]
+    exclude_regex: Option<Regex>,
+}
+
+impl CrateFilter {
+    /// Checks whether `crate_name` is selected, see [`CrateFilter`] for the precedence of the
+    /// options.
+    fn selects(&self, crate_name: &str) -> bool {
+        let only = self.only.iter().any(|c| c == crate_name)
+            || self
+                .only_regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(crate_name));
+        let excluded = self.exclude.iter().any(|c| c == crate_name)
+            || self
+                .exclude_regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(crate_name));
+        (only || (self.only.is_empty() && self.only_regex.is_none())) && !excluded
+    }
 }
 
-#[derive(ValueEnum, Copy, Clone, Debug)]
-enum DepName {
+/// Paths of the common manifest tables declaring dependencies, which `--list-tables` reports on
+/// even if they are missing.
+///
+/// `upgrade` edits all dependency tables, see [`workspace::dependency_tables`].
+pub(crate) const DEPENDENCY_TABLES: &[&str] =
+    &["dependencies", "dev-dependencies", "workspace.dependencies"];
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DepName {
     Cairo,
     #[value(name = "cairols")]
     CairoLS,
     #[value(name = "cairolint")]
     CairoLint,
+    Scarb,
+}
+
+/// Source of the list of crates published from the Cairo repository.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum CrateListSource {
+    /// The `scripts/release_crates.sh` script of the repository, or of its local checkout.
+    #[default]
+    ReleaseScript,
+    /// The crates.io index, by following dependencies between `cairo-lang-*` crates published at
+    /// the upgraded version, starting from those any manifest of the workspace depends on. This
+    /// requires upgrading to a crates.io version.
+    Index,
+}
+
+/// Handling of `[patch.crates-io]` entries written by hand, for `--on-conflict`.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum OnConflict {
+    /// Replace or remove them like entries written by `upgrade`.
+    #[default]
+    Ours,
+    /// Keep them as they are.
+    Theirs,
+    /// Fail, listing them.
+    Error,
 }
 
+/// Source of the dependency group.
+///
+/// At least one of these is required. If none is given on the command line, the path is read from
+/// the `CAIRO_XTASKS_PATH_<GROUP>` environment variable (e.g. `CAIRO_XTASKS_PATH_CAIRO`), or the
+/// configured release channel is used.
 #[derive(clap::Args, Clone, Default)]
-#[group(required = true, multiple = true)]
+#[group(required = false, multiple = true)]
 struct Spec {
     /// Source the dependency from crates.io and use a specific version.
+    #[arg(id = "version", value_name = "VERSION", value_parser = parse_version)]
+    version_arg: Option<VersionArg>,
+
+    // Version taken from `version_arg` once the groups are known, see `Spec::take_version_arg`.
+    #[arg(skip)]
     version: Option<Version>,
 
     /// Source the dependency from the GitHub repository and use a specific commit/ref.
+    ///
+    /// Besides commit hashes, this accepts tag names like `v2.9.2` and fully-qualified references
+    /// like `refs/tags/v2.9.2`, which are looked up with `git ls-remote`.
     #[arg(short, long, conflicts_with = "branch")]
     rev: Option<String>,
 
     /// Source the dependency from the GitHub repository and use a specific branch.
-    #[arg(short, long)]
+    ///
+    /// Without a value, uses the `branch` configured in
+    /// `[workspace.metadata.cairo-toolchain-xtasks.<GROUP>]`, or `main`.
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "")]
     branch: Option<String>,
 
     /// Source the dependency from a local filesystem.
@@ -51,265 +608,7689 @@ struct Spec {
     /// This is useful for local development, but avoid commiting this to the repository.
     #[arg(short, long, conflicts_with_all = ["rev", "branch"])]
     path: Option<PathBuf>,
-}
 
-pub fn main(args: Args) -> Result<()> {
-    let sh = Shell::new()?;
-
-    let mut cargo_toml = sh.read_file("Cargo.toml")?.parse::<DocumentMut>()?;
+    /// Source the dependency from crates.io and use the highest version of its anchor crate which
+    /// is not yanked, as if it was given as `<VERSION>`.
+    ///
+    /// Prereleases are skipped unless `--prerelease` is given. Along with `--branch`, this pins
+    /// the commit at the tip of the branch instead, written as `rev` so that it does not move.
+    #[arg(long, conflicts_with_all = ["version", "rev", "path"])]
+    latest: bool,
 
-    edit_dependencies(&mut cargo_toml, "dependencies", &args);
-    edit_dependencies(&mut cargo_toml, "dev-dependencies", &args);
-    edit_dependencies(&mut cargo_toml, "workspace.dependencies", &args);
-    edit_patch(&mut cargo_toml, &args);
+    /// Commit `rev` points to, once looked up, unless it is an abbreviated commit hash.
+    #[arg(skip)]
+    commit: Option<String>,
 
-    if !args.dry_run {
-        sh.write_file("Cargo.toml", cargo_toml.to_string())?;
+    /// Branch files of the repository are read from if no source is given, like with
+    /// `--lockfile-only`, see [`GroupConfig::branch`].
+    #[arg(skip)]
+    default_branch: Option<String>,
+}
 
-        cmd!(sh, "cargo fetch").run()?;
+/// Error of an upgrade of groups the project does not depend on.
+///
+/// [`main`] returns it as an error, which [`exit_code`] turns into [`NothingToDo::EXIT_CODE`], so
+/// that scripts upgrading many projects can tell it apart from failures.
+#[derive(Debug)]
+pub struct NothingToDo {
+    groups: Vec<&'static str>,
+    manifests: Vec<PathBuf>,
+}
 
-        purge_unused_patches(&mut cargo_toml)?;
-        sh.write_file("Cargo.toml", cargo_toml.to_string())?;
+impl NothingToDo {
+    /// Exit code of an upgrade which had nothing to do.
+    pub const EXIT_CODE: u8 = 3;
+}
 
-        cmd!(sh, "cargo xtask sync-version").run()?;
+impl std::fmt::Display for NothingToDo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let manifests = self
+            .manifests
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "nothing to do: no dependency table of {} contains a crate of {}, pass --force-patch \
+            to patch it as a transitive dependency anyway",
+            manifests.join(", "),
+            self.groups.join(", ")
+        )
     }
-
-    Ok(())
 }
 
-fn edit_dependencies(cargo_toml: &mut DocumentMut, table_path: &str, args: &Args) {
-    let Some(deps) = table_path
-        .split('.')
-        .try_fold(cargo_toml.as_item_mut(), |doc, key| doc.get_mut(key))
-    else {
-        return;
-    };
-    if deps.is_none() {
-        return;
-    }
-    let deps = deps.as_table_mut().unwrap();
+impl std::error::Error for NothingToDo {}
 
-    for (_, dep) in deps.iter_mut().filter(|(key, _)| args.tool_owns_crate(key)) {
-        let dep = dep.as_value_mut().unwrap();
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    run(&ctx, args)
+}
 
-        // Always use crates.io requirements so that we can reliably patch them with the
-        // `[patch.crates-io]` table.
-        let mut new_dep = InlineTable::from_iter([(
-            "version",
-            match &args.spec.version {
-                Some(version) => Value::from(version.to_string()),
-                None => Value::from("*"),
-            },
-        )]);
+/// Turns the result of an xtask into the exit code of the binary running it.
+///
+/// A [`NothingToDo`] error of [`main`] is reported and exits with [`NothingToDo::EXIT_CODE`],
+/// other errors are returned to fail the binary as usual.
+pub fn exit_code(result: Result<()>) -> Result<ExitCode> {
+    match result {
+        Err(err) => match err.downcast_ref::<NothingToDo>() {
+            Some(nothing_to_do) => {
+                eprintln!("{nothing_to_do}");
+                Ok(ExitCode::from(NothingToDo::EXIT_CODE))
+            }
+            None => Err(err),
+        },
+        Ok(()) => Ok(ExitCode::SUCCESS),
+    }
+}
 
-        copy_dependency_features(&mut new_dep, dep);
+fn run(ctx: &Context, mut args: Args) -> Result<()> {
+    let sh = &ctx.sh;
 
-        *dep = new_dep.into();
-        simplify_dependency_table(dep)
+    if let Some(run) = &args.rollback {
+        return restore::rollback(
+            ctx,
+            Some(run.as_str()).filter(|run| !run.is_empty()),
+            args.force,
+            args.dry_run,
+        );
     }
 
-    deps.fmt();
-    deps.sort_values();
+    if let Some((crate_name, version)) = args.apply_to_dependabot.clone() {
+        ctx.note(format_args!(
+            "applying the update of {crate_name} to {version} to its whole group"
+        ));
+        args.crate_name = Some(crate_name);
+        args.spec.version = Some(version);
+    }
+    if let Some(path) = args.from_github_release.clone() {
+        apply_github_release(ctx, &mut args, &path)?;
+    }
+    if let Some(crate_name) = &args.crate_name {
+        args.dep = DepName::candidates_of_crate(crate_name)?;
+    }
 
-    eprintln!("[{table_path}]");
-    for (key, dep) in deps.iter().filter(|(key, _)| args.tool_owns_crate(key)) {
-        eprintln!("{key} = {dep}");
+    let mut original = match args.stdin {
+        true => ctx.read_stdin()?,
+        false => sh.read_file("Cargo.toml")?,
+    };
+    if !args.list_tables {
+        original = check_patch_sections(ctx, &args, original)?;
     }
-}
+    let eol = LineEnding::detect(&original);
+    let mut cargo_toml = original.parse::<DocumentMut>()?;
+    args.network = ctx.network_of(&cargo_toml)?;
 
-fn edit_patch(cargo_toml: &mut DocumentMut, args: &Args) {
-    let patch = cargo_toml["patch"].as_table_mut().unwrap()["crates-io"]
-        .as_table_mut()
-        .unwrap();
+    if !args.dry_run && !args.list_tables && !args.stdin && is_own_manifest(&cargo_toml) {
+        let name = env!("CARGO_PKG_NAME");
+        ensure!(
+            args.force,
+            "refusing to upgrade the manifest of {name} itself, which does not depend on \
+            toolchain crates, run this from the project to upgrade or pass --force"
+        );
+        ctx.warn(format_args!("upgrading the manifest of {name} itself"));
+    }
 
-    // Clear any existing entries for this dependency.
-    for crate_name in args.tool_crates() {
-        patch.remove(crate_name);
+    if args.patch_from_lock {
+        return patch_from_lock(ctx, &args, &original);
     }
 
-    // Leave this section as-if if we are requested to just use a specific version.
-    if args.spec.rev.is_some() || args.spec.branch.is_some() || args.spec.path.is_some() {
-        // Patch all Cairo crates that exist, even if this project does not directly depend on them,
-        // to avoid any duplicates in transient dependencies.
-        for &dep_name in args.tool_crates() {
-            let mut dep = InlineTable::new();
+    // Manifest of the `--package` member, with its original contents, to edit the dependency
+    // tables of instead of the root ones.
+    let mut member = match &args.package {
+        Some(name) => {
+            let manifest_path =
+                workspace::find_member(&sh.current_dir(), &cargo_toml, name)?.join("Cargo.toml");
+            let original = sh.read_file(&manifest_path)?;
+            let manifest = manifest::read(&sh.current_dir().join(&manifest_path))?;
+            Some((manifest_path, original, manifest))
+        }
+        None => None,
+    };
 
-            // Add a Git branch or revision reference if requested.
-            if args.spec.rev.is_some() || args.spec.branch.is_some() {
-                dep.insert("git", args.tool_repo().into());
+    let curl = Curl {
+        network: args.network,
+    };
+    let index = Index::new(&args.index, &args.cache.cache(), &curl);
+    let remote = LsRemote {
+        network: args.network,
+    };
+    let cargo_lock = sh
+        .read_file(ctx.lockfile())
+        .ok()
+        .and_then(|cargo_lock| cargo_lock.parse::<DocumentMut>().ok());
+    let mut warnings = Vec::new();
+    let mut groups = args.groups(&cargo_toml, |key| std::env::var_os(key), &mut warnings)?;
+    for warning in &warnings {
+        ctx.warn(warning);
+    }
+    if !args.list_tables && !args.lockfile_only && !args.force_patch {
+        groups = skip_unused_groups(ctx, &cargo_toml, groups)?;
+    }
+    for group in &mut groups {
+        if group.dep() == DepName::Cairo && group.crate_list_source == CrateListSource::Index {
+            group.used_crates = used_crates(&sh.current_dir(), &cargo_toml, group.dep())?;
+        }
+        if let Some(path) = &group.spec.path {
+            let path = ctx.cli_path(path);
+            // The crates of Cairo and Scarb are listed by files of the checkout.
+            ensure!(
+                !matches!(group.dep(), DepName::Cairo | DepName::Scarb) || path.is_dir(),
+                "local checkout {} of {} does not exist",
+                path.display(),
+                group.dep().name()
+            );
+            group.spec.path = Some(path);
+        }
+    }
+    let jobs = args.jobs.map_or(groups.len(), NonZeroUsize::get);
+    let results = ctx.parallel_phase(
+        "resolve sources",
+        jobs,
+        &groups,
+        |group| group.dep().name().to_owned(),
+        |group| discover(group, &cargo_toml, cargo_lock.as_ref(), &index, &remote),
+    );
+    let mut discovered = Vec::new();
+    let mut errors = Vec::new();
+    for (group, result) in groups.iter().zip(results) {
+        match result {
+            Ok(group) => discovered.push(group),
+            Err(err) => errors.push(format!("{}: {err:#}", group.dep().name())),
+        }
+    }
+    match errors.as_slice() {
+        [] => {}
+        [error] => bail!("failed to resolve {error}"),
+        errors => bail!(
+            "failed to resolve {} groups:\n{}",
+            errors.len(),
+            errors.join("\n")
+        ),
+    }
+    for note in discovered.iter().flat_map(|d| &d.notes) {
+        ctx.note(note);
+    }
+    for warning in discovered.iter().flat_map(|d| &d.warnings) {
+        ctx.warn(warning);
+    }
+    for warning in index.take_warnings() {
+        ctx.warn(warning);
+    }
+    if let Some(crate_name) = &args.crate_name {
+        let owners = discovered
+            .iter()
+            .filter(|d| d.group.known_crates().any(|known| known == crate_name))
+            .map(|d| d.group.dep().name())
+            .collect::<Vec<_>>();
+        match owners.as_slice() {
+            [owner] => ctx.note(format_args!("{crate_name} belongs to {owner}")),
+            [] => bail!(
+                "{} does not publish a crate named {crate_name}",
+                args.dep
+                    .iter()
+                    .map(|dep| dep.name())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            owners => bail!(
+                "{crate_name} belongs to several groups ({}), name the group to upgrade instead",
+                owners.join(", ")
+            ),
+        }
+        discovered.retain(|d| d.group.known_crates().any(|known| known == crate_name));
+    }
+    if let (Some(crate_name), Some(main)) = (&args.crate_name, discovered.first()) {
+        if let Some((_, proposed)) = &args.apply_to_dependabot {
+            if let Some(version) = main
+                .group
+                .crate_version(crate_name)
+                .filter(|version| *version != proposed)
+            {
+                ctx.warn(format_args!(
+                    "{crate_name} is released independently of {}, upgrading it to {version} \
+                    instead of the proposed {proposed}",
+                    main.group.dep().name()
+                ));
             }
+        }
+    }
+    let previous = discovered
+        .iter()
+        .map(|d| d.previous.clone())
+        .collect::<Vec<_>>();
+    let stale_checks = discovered
+        .iter()
+        .map(|d| d.previous_crates.clone())
+        .collect::<Vec<_>>();
+    let mut groups = discovered.into_iter().map(|d| d.group).collect::<Vec<_>>();
 
-            if let Some(branch) = &args.spec.branch {
-                dep.insert("branch", branch.as_str().into());
+    if args.list_tables {
+        let mut manifests = Vec::new();
+        match &member {
+            Some((manifest_path, _, manifest)) => {
+                manifests.push((manifest_path.clone(), manifest.clone()))
             }
-
-            if let Some(rev) = &args.spec.rev {
-                dep.insert("rev", rev.as_str().into());
+            None => {
+                manifests.push((PathBuf::from("Cargo.toml"), cargo_toml.clone()));
+                for path in workspace::members(&sh.current_dir(), &cargo_toml)? {
+                    let manifest_path = path.join("Cargo.toml");
+                    let manifest = manifest::read(&sh.current_dir().join(&manifest_path))?;
+                    manifests.push((manifest_path, manifest));
+                }
             }
-
-            // Add local path reference if requested.
-            // For local path sources, Cargo is not looking for crates recursively therefore, we
-            // need to manually provide full paths to Cairo workspace member crates.
-            if let Some(path) = &args.spec.path {
-                dep.insert(
-                    "path",
-                    path.join("crates")
-                        .join(dep_name)
-                        .to_string_lossy()
-                        .into_owned()
-                        .into(),
-                );
+        }
+        for group in &groups {
+            for (manifest_path, manifest) in &manifests {
+                for line in list_tables(manifest_path, manifest, group) {
+                    ctx.output(&format!("{line}\n"));
+                }
             }
-
-            patch.insert(dep_name, dep.into());
         }
+        return Ok(());
     }
 
-    patch.fmt();
-    patch.sort_values();
-
-    eprintln!("[patch.crates-io]");
-    for (key, dep) in patch.iter() {
-        eprintln!("{key} = {dep}");
+    if args.lockfile_only {
+        return update_lockfile(ctx, &cargo_toml, &groups, &index, args.dry_run);
     }
-}
 
-impl Args {
-    fn tool_crates(&self) -> &'static [&'static str] {
-        static CAIRO_CACHE: OnceLock<Vec<&str>> = OnceLock::new();
-        match self.dep {
-            DepName::Cairo => CAIRO_CACHE.get_or_init(|| {
-                pull_cairo_packages_from_cairo_repository(&self.spec)
-                    .unwrap()
-                    .into_iter()
-                    .map(|s| s.leak() as &str)
-                    .collect()
-            }),
-            DepName::CairoLS => &["cairo-language-server"],
-            DepName::CairoLint => &["cairo-lint-core"],
+    let mut unpatchable = Vec::new();
+    for group in &mut groups {
+        let missing = missing_local_crates(group, group.tool_crates());
+        unpatchable.extend(missing.iter().map(|(crate_name, path)| {
+            format!(
+                "  {}: {crate_name}, no package at {}",
+                group.dep().name(),
+                path.display()
+            )
+        }));
+        if args.best_effort {
+            group
+                .filter
+                .exclude
+                .extend(missing.into_iter().map(|(crate_name, _)| crate_name));
         }
     }
-
-    fn tool_owns_crate(&self, crate_name: &str) -> bool {
-        self.tool_crates().contains(&crate_name)
+    if !unpatchable.is_empty() {
+        let plural = if unpatchable.len() == 1 { "" } else { "s" };
+        let message = format!(
+            "{} crate{plural} cannot be patched to local checkouts:\n{}",
+            unpatchable.len(),
+            unpatchable.join("\n")
+        );
+        ensure!(
+            args.best_effort,
+            "{message}\npass --best-effort to upgrade the other crates"
+        );
+        ctx.warn(format_args!("{message}\nleaving them as they were"));
     }
-
-    fn tool_repo(&self) -> &'static str {
-        match self.dep {
-            DepName::Cairo => "https://github.com/starkware-libs/cairo",
-            DepName::CairoLS => "https://github.com/software-mansion/cairols",
-            DepName::CairoLint => "https://github.com/software-mansion/cairo-lint",
+    if args.preserve_path {
+        for group in &mut groups {
+            let preserved = path_crates(&cargo_toml, group);
+            if !preserved.is_empty() {
+                ctx.note(format_args!(
+                    "{}: keeping path dependencies on {}",
+                    group.dep().name(),
+                    preserved.join(", ")
+                ));
+                group.filter.exclude.extend(preserved);
+            }
         }
     }
-}
 
-/// Copies features from source dependency spec to new dependency table, if exists.
-fn copy_dependency_features(dest: &mut InlineTable, src: &Value) {
-    if let Some(dep) = src.as_inline_table() {
-        if let Some(features) = dep.get("features") {
-            dest.insert("features", features.clone());
-        }
-    }
-}
+    let (migrations, warnings) = ctx.phase("edit manifest", || {
+        let mut migrations = Vec::new();
+        let mut warnings = Vec::new();
+        for (group, previous_crates) in groups.iter().zip(&stale_checks) {
+            let (deps_path, deps_toml) = match member.as_mut() {
+                Some((manifest_path, _, manifest)) => (manifest_path.as_path(), manifest),
+                None => (Path::new("Cargo.toml"), &mut cargo_toml),
+            };
+            if let Some(previous_crates) = previous_crates {
+                check_stale_crates(deps_path, deps_toml, group, previous_crates)?;
+            }
 
-/// Simplifies a `{ version = "V" }` dependency spec to shorthand `"V"` if possible.
-fn simplify_dependency_table(dep: &mut Value) {
-    *dep = match mem::replace(dep, false.into()) {
-        Value::InlineTable(mut table) => {
-            if table.len() == 1 {
-                table.remove("version").unwrap_or_else(|| table.into())
-            } else {
-                table.into()
+            migrations.extend(migrate_renamed_crates(deps_toml, group));
+            if args.strict_group {
+                check_strict_group(deps_toml, group, group.tool_crates())?;
+            }
+            edit_dependencies(deps_toml, group);
+            if args.dedupe_patches {
+                warnings.extend(dedupe_patches(&mut cargo_toml, group));
             }
+            warnings.extend(edit_patch(&mut cargo_toml, group)?);
+            record_provenance(&mut cargo_toml, group)?;
         }
+        Ok((migrations, warnings))
+    })?;
 
-        dep => dep,
+    for migration in &migrations {
+        ctx.note(migration);
+    }
+    for warning in &warnings {
+        ctx.warn(warning);
+    }
+    if args.stdin {
+        ctx.output(&eol.apply(&cargo_toml.to_string()));
+        return Ok(());
+    }
+    let patched = get_table_like(&cargo_toml, "patch.crates-io")
+        .map(|patch| {
+            patch
+                .iter()
+                .map(|(key, _)| key.to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let is_patched = |crate_name: &str| {
+        patched.iter().any(|key| key == crate_name)
+            && groups.iter().any(|group| group.tool_owns_crate(crate_name))
+    };
+    let mut git_deps = patched_git_deps(
+        Path::new("Cargo.toml"),
+        &mut cargo_toml,
+        &is_patched,
+        args.consolidate,
+    );
+    let mut member_edits = Vec::new();
+    for path in workspace::members(&sh.current_dir(), &cargo_toml)? {
+        let manifest_path = path.join("Cargo.toml");
+        let edited = member
+            .as_ref()
+            .filter(|(edited_path, _, _)| *edited_path == manifest_path);
+        let (original, mut manifest) = match edited {
+            Some((_, original, manifest)) => (original.clone(), manifest.clone()),
+            None => (
+                sh.read_file(&manifest_path)?,
+                manifest::read(&sh.current_dir().join(&manifest_path))?,
+            ),
+        };
+        // Members declaring their own requirements instead of inheriting the workspace ones would
+        // keep resolving the previous version otherwise. With `--package`, only that member is
+        // upgraded.
+        let mut upgraded = false;
+        if member.is_none() {
+            let before = manifest.to_string();
+            for (group, previous_crates) in groups.iter().zip(&stale_checks) {
+                if let Some(previous_crates) = previous_crates {
+                    check_stale_crates(&manifest_path, &manifest, group, previous_crates)?;
+                }
+                for migration in migrate_renamed_crates(&mut manifest, group) {
+                    ctx.note(format_args!("{}: {migration}", manifest_path.display()));
+                }
+                let mut group = Cow::Borrowed(group);
+                if args.preserve_path {
+                    let preserved = path_crates(&manifest, &group);
+                    if !preserved.is_empty() {
+                        ctx.note(format_args!(
+                            "{}: {}: keeping path dependencies on {}",
+                            manifest_path.display(),
+                            group.dep().name(),
+                            preserved.join(", ")
+                        ));
+                        group.to_mut().filter.exclude.extend(preserved);
+                    }
+                }
+                for (_, deps) in workspace::dependency_items_mut(&mut manifest) {
+                    rewrite_dependencies(deps, &group);
+                }
+            }
+            upgraded = manifest.to_string() != before;
+        }
+        let found = patched_git_deps(&manifest_path, &mut manifest, &is_patched, args.consolidate);
+        if edited.is_some() || upgraded || args.consolidate && !found.is_empty() {
+            let contents = LineEnding::detect(&original).apply(&manifest.to_string());
+            member_edits.push((manifest_path.to_string_lossy().into_owned(), contents));
+        }
+        git_deps.extend(found);
+    }
+    for git_dep in &git_deps {
+        if args.consolidate {
+            ctx.note(format_args!(
+                "{git_dep}, replaced it with `*` relying on [patch.crates-io]"
+            ));
+        } else {
+            ctx.warn(format_args!(
+                "{git_dep}, while [patch.crates-io] also patches it, pass --consolidate to require \
+                `*` and rely on the patch"
+            ));
+        }
     }
-}
-
-/// Remove any unused patches from the `[patch.crates-io]` table.
-///
-/// We are adding patch entries for **all** Cairo crates existing, and some may end up being unused.
-/// Cargo is emitting warnings about unused patches and keeps a record of them in the `Cargo.lock`.
-/// The goal of this function is to resolve these warnings.
-fn purge_unused_patches(cargo_toml: &mut DocumentMut) -> Result<()> {
-    let sh = Shell::new()?;
-    let cargo_lock = sh.read_file("Cargo.lock")?.parse::<DocumentMut>()?;
-
-    if let Some(unused_patches) = find_unused_patches(&cargo_lock) {
-        let patch = cargo_toml["patch"].as_table_mut().unwrap()["crates-io"]
-            .as_table_mut()
-            .unwrap();
 
-        // Remove any patches that are not for Cairo crates.
-        patch.retain(|key, _| !unused_patches.contains(&key.to_owned()));
+    if args.compat_check {
+        compat_check::check(ctx, &eol.apply(&cargo_toml.to_string()), &member_edits)?;
+        ctx.note("the workspace compiles with the upgrade");
     }
 
-    Ok(())
-}
+    let (resolved, purged, synced) = if !args.dry_run {
+        write_backup(ctx, &args, &member_edits)?;
+        let files = history_files(ctx, &cargo_toml)?;
+        with_history(ctx, &files, args.backup(), || {
+            apply(ctx, cargo_toml, eol, &groups, &member_edits)
+        })?
+    } else {
+        let cargo = ctx.cargo();
+        let toolchain = ctx.rust_toolchain();
+        let offline = args.network.cargo_args();
+        ctx.diff(
+            "Cargo.toml",
+            &sh.read_file("Cargo.toml")?,
+            &eol.apply(&cargo_toml.to_string()),
+        );
+        for (path, contents) in &member_edits {
+            ctx.diff(path, &sh.read_file(path)?, contents);
+        }
+        ctx.would_run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"));
+        if let Some(sync) = sync_version_cmd(ctx, &groups) {
+            ctx.would_run(sync);
+        }
+        let resolved = groups
+            .iter()
+            .map(|group| Resolved {
+                version: group.spec.version.clone(),
+                commit: group.spec.commit.clone(),
+            })
+            .collect();
+        (resolved, Vec::new(), None)
+    };
 
-/// Extracts names of unused patches from the `[[patch.unused]]` array from the `Cargo.lock` file.
-fn find_unused_patches(cargo_lock: &DocumentMut) -> Option<Vec<String>> {
-    Some(
-        cargo_lock
-            .get("patch")?
-            .get("unused")?
-            .as_array_of_tables()?
+    if let Some(path) = &args.emit_env {
+        let path = ctx.cli_path(path);
+        let lines = groups
             .iter()
-            .flat_map(|table| Some(table.get("name")?.as_str()?.to_owned()))
-            .collect(),
-    )
+            .zip(&resolved)
+            .flat_map(|(group, resolved)| env_vars(group, resolved))
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect::<String>();
+        if args.dry_run {
+            ctx.note(format_args!("would append {}:\n{lines}", path.display()));
+        } else {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            file.write_all(lines.as_bytes())?;
+        }
+    }
+
+    let crates = if args.print_resolved_crates_with_versions {
+        let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+        let crates = groups
+            .iter()
+            .map(|group| resolved_crates(&cargo_lock, group.known_crates()))
+            .collect::<Result<Vec<_>>>()?;
+        Some(crates)
+    } else {
+        None
+    };
+    let state_hash = if args.print_state_hash {
+        let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+        Some(state_hash(&cargo_lock, &groups))
+    } else {
+        None
+    };
+
+    for line in summary(&groups, &previous, &resolved, synced.as_ref()) {
+        ctx.summary(line);
+    }
+    let mut report = report(
+        &groups,
+        &previous,
+        &resolved,
+        crates.as_deref(),
+        &purged,
+        synced.as_ref(),
+        args.dry_run,
+    );
+    if let Some(state_hash) = &state_hash {
+        report["state_hash"] = state_hash.as_str().into();
+    }
+    if let Some(phases) = ctx.timings() {
+        report["timings"] = phases
+            .iter()
+            .map(|phase| {
+                serde_json::json!({
+                    "phase": phase.name,
+                    "duration_ms": phase.duration.as_millis() as u64,
+                    "nested": phase.nested,
+                })
+            })
+            .collect();
+    }
+    match args.format {
+        Format::Json => ctx.output(&format!("{report:#}\n")),
+        Format::Text => {
+            if let Some(crates) = &crates {
+                ctx.output(&resolved_crates_table(crates.iter().flatten()));
+            }
+            if let Some(state_hash) = &state_hash {
+                ctx.output(&format!("{state_hash}\n"));
+            }
+        }
+    }
+    if let Some(path) = &args.report_file {
+        let path = ctx.cli_path(path);
+        sh.write_file(&path, format!("{report:#}\n"))?;
+        ctx.note(format_args!("wrote report {}", path.display()));
+    }
+
+    if let Some(dir) = &args.changelog_fragment {
+        let fragment = changelog_fragment(&groups, &previous, &resolved);
+        let path = fragment_path(&ctx.cli_path(dir), &today()?, &groups);
+        if args.dry_run {
+            ctx.note(format_args!("would write {}:\n{fragment}", path.display()));
+        } else {
+            sh.write_file(&path, fragment)?;
+            ctx.note(format_args!("wrote changelog fragment {}", path.display()));
+        }
+    }
+
+    Ok(())
 }
 
-/// Pulls names of crates published from the `starkware-libs/cairo` repository.
+/// Summarizes the version changes of all groups and of the crate itself, if it was synced.
+fn summary(
+    groups: &[Args],
+    previous: &[Option<Version>],
+    resolved: &[Resolved],
+    synced: Option<&sync_version::Outcome>,
+) -> Vec<String> {
+    let version = |version: Option<&Version>| match version {
+        Some(version) => version.to_string(),
+        None => "?".to_owned(),
+    };
+    let mut lines = vec!["summary:".to_owned()];
+    for ((group, previous), resolved) in groups.iter().zip(previous).zip(resolved) {
+        lines.push(format!(
+            "  {}: {} -> {}",
+            group.dep().name(),
+            version(previous.as_ref()),
+            version(resolved.version.as_ref())
+        ));
+    }
+    if let Some(synced) = synced {
+        let mut line = format!(
+            "  [{}] version: {} -> {}",
+            synced.table_path,
+            version(synced.previous_version.as_ref()),
+            synced.version
+        );
+        if synced.adjusted() {
+            line.push_str(&format!(
+                " (adjusted from {})",
+                synced.anchor.describe(&synced.compiler_version)
+            ));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Lists the versions of the crates of a group locked in `cargo_lock`, sorted by crate name.
 ///
-/// The list is obtained by parsing the `scripts/release_crates.sh` script in that repo.
-/// The resulting vector is sorted alphabetically.
-fn pull_cairo_packages_from_cairo_repository(spec: &Spec) -> Result<Vec<String>> {
-    let sh = Shell::new()?;
+/// Crates which are not locked are left out, and crates locked at several versions are listed
+/// once for each of them.
+fn resolved_crates<'a>(
+    cargo_lock: &DocumentMut,
+    crates: impl Iterator<Item = &'a str>,
+) -> Result<Vec<(String, Version)>> {
+    let mut crates = crates.collect::<Vec<_>>();
+    crates.sort();
+    crates.dedup();
+    let mut resolved = Vec::new();
+    for crate_name in crates {
+        for version in locked_versions(cargo_lock, crate_name)? {
+            resolved.push((crate_name.to_owned(), version));
+        }
+    }
+    Ok(resolved)
+}
 
-    let release_crates_sh = if let Some(path) = &spec.path {
-        sh.read_file(path.join("scripts").join("release_crates.sh"))?
-    } else {
-        let rev = if let Some(version) = &spec.version {
-            format!("refs/tags/v{version}")
-        } else if let Some(rev) = &spec.rev {
-            rev.to_string()
-        } else if let Some(branch) = &spec.branch {
-            format!("refs/heads/{branch}")
-        } else {
-            "refs/heads/main".to_string()
+/// Formats resolved crates as a table of names and versions, for
+/// `--print-resolved-crates-with-versions`.
+fn resolved_crates_table<'a>(
+    crates: impl Iterator<Item = &'a (String, Version)> + Clone,
+) -> String {
+    let width = crates
+        .clone()
+        .map(|(name, _)| name.len())
+        .chain(["crate".len()])
+        .max()
+        .unwrap_or_default();
+    let mut table = format!("{:width$}  version\n", "crate");
+    for (name, version) in crates {
+        table.push_str(&format!("{name:width$}  {version}\n"));
+    }
+    table
+}
+
+/// Computes a hash of the packages locked in `cargo_lock` for the crates of `groups`, for
+/// `--print-state-hash`.
+///
+/// Each package contributes its name, version and source, in sorted order, so the hash does not
+/// depend on the order of `Cargo.lock`. Packages from local checkouts have no source there, and all
+/// of them hash with the literal source `path`, so checkouts at different paths hash the same.
+///
+/// The hash is a SHA-256 in hexadecimal.
+fn state_hash(cargo_lock: &DocumentMut, groups: &[Args]) -> String {
+    let crates = groups
+        .iter()
+        .flat_map(Args::known_crates)
+        .collect::<BTreeSet<_>>();
+    let packages = cargo_lock
+        .get("package")
+        .and_then(Item::as_array_of_tables)
+        .into_iter()
+        .flatten()
+        .filter_map(|pkg| {
+            let name = pkg.get("name")?.as_str()?;
+            crates.contains(name).then(|| {
+                let version = pkg
+                    .get("version")
+                    .and_then(Item::as_str)
+                    .unwrap_or_default();
+                let source = pkg.get("source").and_then(Item::as_str).unwrap_or("path");
+                format!("{name} {version} {source}\n")
+            })
+        })
+        .collect::<BTreeSet<_>>();
+    let mut hasher = Sha256::new();
+    for package in &packages {
+        hasher.update(package.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Describes the previous and resolved source of each group as the JSON report of `--format json`,
+/// along with the change of the crate version, if it was synced.
+///
+/// With `crates`, each group also lists the versions resolved for its crates. Each group lists the
+/// `purged` patches of its crates.
+fn report(
+    groups: &[Args],
+    previous: &[Option<Version>],
+    resolved: &[Resolved],
+    crates: Option<&[Vec<(String, Version)>]>,
+    purged: &[String],
+    synced: Option<&sync_version::Outcome>,
+    dry_run: bool,
+) -> serde_json::Value {
+    let groups = groups
+        .iter()
+        .zip(previous)
+        .zip(resolved)
+        .enumerate()
+        .map(|(i, ((group, previous), resolved))| {
+            let mut report = serde_json::json!({
+                "group": group.dep().name(),
+                "source": group.spec.source_kind(),
+                "previous_version": previous.as_ref().map(Version::to_string),
+                "version": resolved.version.as_ref().map(Version::to_string),
+                "commit": resolved.commit,
+                "purged_patches": purged
+                    .iter()
+                    .filter(|crate_name| group.tool_owns_crate(crate_name))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(crates) = crates {
+                report["crates"] = crates[i]
+                    .iter()
+                    .map(|(name, version)| {
+                        serde_json::json!({ "name": name, "version": version.to_string() })
+                    })
+                    .collect();
+            }
+            report
+        })
+        .collect::<Vec<_>>();
+    let synced = synced.map(|synced| {
+        serde_json::json!({
+            "table": synced.table_path,
+            "previous_version": synced.previous_version.as_ref().map(Version::to_string),
+            "version": synced.version.to_string(),
+            "compiler_version": synced.compiler_version.to_string(),
+            "adjusted": synced.adjusted(),
+        })
+    });
+    serde_json::json!({ "dry_run": dry_run, "groups": groups, "version_sync": synced })
+}
+
+/// Path of the changelog fragment for upgrading `groups` on `date`, e.g.
+/// `<DIR>/2024-10-01-upgrade-cairo.md`.
+fn fragment_path(dir: &Path, date: &str, groups: &[Args]) -> PathBuf {
+    let names = groups
+        .iter()
+        .map(|group| group.dep().name())
+        .collect::<Vec<_>>();
+    dir.join(format!("{date}-upgrade-{}.md", names.join("-")))
+}
+
+/// Summarizes the change of each group's version as markdown list items.
+///
+/// `previous` are the versions locked before the upgrade, see [`locked_anchor_version`].
+fn changelog_fragment(
+    groups: &[Args],
+    previous: &[Option<Version>],
+    resolved: &[Resolved],
+) -> String {
+    let mut fragment = String::new();
+    for ((group, previous), resolved) in groups.iter().zip(previous).zip(resolved) {
+        let mut target = match (&resolved.version, &resolved.commit) {
+            (Some(version), Some(commit)) => format!("{version} ({})", short_commit(commit)),
+            (Some(version), None) => version.to_string(),
+            (None, Some(commit)) => short_commit(commit).to_owned(),
+            (None, None) => group.spec.source_kind().to_owned(),
         };
-        let url = format!("https://raw.githubusercontent.com/starkware-libs/cairo/{rev}/scripts/release_crates.sh");
-        cmd!(sh, "curl -sSfL {url}").read()?
+        if let Some(previous) = previous
+            .as_ref()
+            .filter(|&p| Some(p) != resolved.version.as_ref())
+        {
+            target.push_str(&format!(" (was {previous})"));
+        }
+        fragment.push_str(&format!("- Upgraded {} to {target}\n", group.dep().title()));
+    }
+    fragment
+}
+
+fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
+
+/// Formats the current UTC date as `YYYY-MM-DD`.
+fn today() -> Result<String> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    Ok(format_date(secs / 86400))
+}
+
+/// Formats a number of days since the Unix epoch as a `YYYY-MM-DD` date.
+fn format_date(days: u64) -> String {
+    // Converts to the proleptic Gregorian calendar, counting in 400-year eras which start on the
+    // 1st of March, so that leap days fall at the end of each year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// A dependency group after looking up its source, along with the outcome of its validation.
+struct Discovered {
+    group: Args,
+    /// Version of the group's anchor crate locked before the upgrade.
+    previous: Option<Version>,
+    /// Crates of the group at the previous version, if they have to be checked for removals.
+    previous_crates: Option<Vec<String>>,
+    notes: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Looks up the source of a dependency group and validates it, which may access the network.
+///
+/// This runs concurrently for all groups, so notes and warnings are collected instead of printed.
+fn discover(
+    group: &Args,
+    cargo_toml: &DocumentMut,
+    cargo_lock: Option<&DocumentMut>,
+    index: &Index<'_>,
+    remote: &dyn Remote,
+) -> Result<Discovered> {
+    let mut group = group.clone();
+    let mut notes = Vec::new();
+    let mut warnings = Vec::new();
+    if let Some(rev) = &group.spec.rev {
+        let repo = group.tool_repo();
+        let mut rev = git::resolve(remote, repo, rev)?;
+        if group.validate_revs && matches!(rev.kind, RevKind::FullSha | RevKind::ShortSha) {
+            rev.sha = Some(git::find_commit(remote, repo, &rev.requested)?);
+        }
+        group.spec.commit = rev.sha;
+    }
+    let config = config::group(cargo_toml, group.dep().name())?;
+    group.spec.default_branch = Some(config.branch().to_owned());
+    group.resolve_channel(&config, &mut notes, |anchor, prerelease| {
+        let kind = if prerelease { "unyanked" } else { "stable" };
+        latest_release(&index.versions_of(anchor)?, prerelease)
+            .with_context(|| format!("no {kind} release of {anchor} is published on crates.io"))
+    })?;
+    if group.spec.latest {
+        resolve_branch_head(&mut group, remote, &mut notes, &mut warnings)?;
+    }
+    if let Some(branch) = group.spec.branch.as_ref().filter(|_| group.validate_revs) {
+        git::ensure_branch(remote, group.tool_repo(), branch)?;
+    }
+    if group.dep() == DepName::Cairo && group.crate_list_source == CrateListSource::Index {
+        ensure!(
+            group.spec.version.is_some(),
+            "--crate-list-source index requires upgrading cairo to a crates.io version"
+        );
+    }
+    group.load_tool_crates(&mut warnings)?;
+    group.discover_crate_versions(&mut notes, &mut warnings)?;
+
+    // Scarb itself is not published on crates.io, its versions name releases of the repository.
+    if let Some(version) = group
+        .spec
+        .version
+        .as_ref()
+        .filter(|_| group.dep() != DepName::Scarb && !group.no_verify)
+    {
+        match check_published(index, &group, version) {
+            Err(err) if group.dry_run => warnings.push(format!("{err:#}")),
+            result => result?,
+        }
+    }
+    let previous = cargo_lock.and_then(|cargo_lock| locked_anchor_version(cargo_lock, &group));
+    let previous_crates = match previous_tool_crates(&group, previous.as_ref(), &mut warnings) {
+        Ok(crates) => crates,
+        Err(err) => {
+            warnings.push(format!("cannot check for crates removed upstream: {err:#}"));
+            None
+        }
     };
+    Ok(Discovered {
+        group,
+        previous,
+        previous_crates,
+        notes,
+        warnings,
+    })
+}
 
-    let Some((_, source_list)) = release_crates_sh.split_once("CRATES_TO_PUBLISH=(") else {
-        bail!("failed to extract start of `CRATES_TO_PUBLISH` from `scripts/release_crates.sh`");
+/// Replaces the `--branch` of a group upgraded with `--latest` with the commit at its tip, so that
+/// it is written as `rev` instead of the floating branch.
+///
+/// Fails if the repository has no such branch. If it cannot be reached, e.g. offline, the branch
+/// is kept with a warning.
+fn resolve_branch_head(
+    group: &mut Args,
+    remote: &dyn Remote,
+    notes: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let Some(branch) = group.spec.branch.clone() else {
+        return Ok(());
     };
-    let Some((source_list, _)) = source_list.split_once(")") else {
-        bail!("failed to extract end of `CRATES_TO_PUBLISH` from `scripts/release_crates.sh`");
+    let name = group.dep().name();
+    let repo = group.tool_repo();
+    let sha = match git::branch_tip(remote, repo, &branch) {
+        Ok(Some(sha)) => sha,
+        Ok(None) => return git::missing_branch(remote, repo, &branch),
+        Err(err) => {
+            warnings.push(format!(
+                "cannot look up the tip of branch `{branch}` of {name}, keeping the floating \
+                branch instead: {err:#}"
+            ));
+            return Ok(());
+        }
     };
+    notes.push(format!(
+        "using commit {sha} at the tip of branch `{branch}` of {name}"
+    ));
+    group.spec.branch = None;
+    group.spec.rev = Some(sha.clone());
+    group.spec.commit = Some(sha);
+    Ok(())
+}
 
-    let mut crates: Vec<String> = source_list
-        .split_whitespace()
-        .filter(|s| s.starts_with("cairo-lang-"))
-        .map(|s| s.into())
-        .collect();
-    crates.sort();
-    Ok(crates)
+/// Source of the dependency group, as resolved by Cargo.
+struct Resolved {
+    /// Version of the group's anchor crate.
+    version: Option<Version>,
+    /// Commit of the group's anchor crate, for git sources.
+    commit: Option<String>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Describes the resolved source of the dependency group as environment variables.
+///
+/// Variables which do not apply to the kind of source are skipped.
+fn env_vars(args: &Args, resolved: &Resolved) -> Vec<(String, String)> {
+    let prefix = args.dep().name().to_uppercase();
+    let spec = &args.spec;
+    [
+        ("SOURCE", Some(spec.source_kind().to_owned())),
+        ("VERSION", resolved.version.as_ref().map(Version::to_string)),
+        (
+            "REV",
+            resolved
+                .commit
+                .clone()
+                .or_else(|| spec.commit.clone())
+                .or_else(|| spec.rev.clone()),
+        ),
+        ("BRANCH", spec.branch.clone()),
+        (
+            "PATH",
+            spec.path.as_ref().map(|p| p.to_string_lossy().into_owned()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((format!("{prefix}_{key}"), value?)))
+    .collect()
+}
 
-    #[test]
-    fn test_pull_cairo_packages_from_cairo_repository() {
-        let list = pull_cairo_packages_from_cairo_repository(&Spec::default()).unwrap();
-        assert!(!list.is_empty());
-        assert!(list.contains(&"cairo-lang-compiler".to_owned()));
-        assert!(!list.contains(&"cairo-test".to_owned()));
-        assert!(list.is_sorted());
+/// Writes the edited manifest, lets Cargo resolve it and runs all follow-up steps.
+///
+/// The manifest is written with `eol` line endings. Returns the resolved source of each group, and
+/// the change of the crate version if it was synced.
+///
+/// `member_edits` are the new contents of member manifests, written along with the workspace one.
+fn apply(
+    ctx: &Context,
+    mut cargo_toml: DocumentMut,
+    eol: LineEnding,
+    groups: &[Args],
+    member_edits: &[(String, String)],
+) -> Result<(Vec<Resolved>, Vec<String>, Option<sync_version::Outcome>)> {
+    let sh = &ctx.sh;
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network_of(&cargo_toml)?.cargo_args();
+    let previous_version = sync_version::crate_version(&cargo_toml).map(|(_, version)| version);
+    let lockfile = lockfile_name(ctx);
+    let mut files = vec!["Cargo.toml", lockfile.as_str()];
+    files.extend(member_edits.iter().map(|(path, _)| path.as_str()));
+    transaction(ctx, &files, || {
+        sh.write_file("Cargo.toml", eol.apply(&cargo_toml.to_string()))?;
+        for (path, contents) in member_edits {
+            sh.write_file(path, contents)?;
+        }
+
+        ctx.phase("cargo fetch", || {
+            ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch {offline...}"))
+        })?;
+        for args in groups {
+            refresh_checkout_lock(ctx, args)?;
+        }
+
+        let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+        let git_packages = lockfile::git_packages(&cargo_lock)?;
+        let mut resolved = Vec::new();
+        for args in groups {
+            let anchor = args.tool_anchor();
+            let version = match locked_versions(&cargo_lock, anchor)?.as_slice() {
+                [version] => Some(version.clone()),
+                _ => None,
+            };
+            if let Some(version) = &version {
+                provenance::set_version(&mut cargo_toml, args.dep().name(), version)?;
+            }
+            let commit = git_packages
+                .iter()
+                .find(|pkg| pkg.name == anchor)
+                .map(|pkg| pkg.source.resolved_sha.clone());
+            resolved.push(Resolved { version, commit });
+        }
+
+        let purged = ctx.phase("purge patches", || {
+            purge_unused_patches(sh, &ctx.lockfile(), &mut cargo_toml, groups)
+        })?;
+        if !purged.is_empty() {
+            ctx.note(format_args!(
+                "removed unused patches: {}",
+                purged.join(", ")
+            ));
+        }
+        sh.write_file("Cargo.toml", eol.apply(&cargo_toml.to_string()))?;
+
+        let Some(sync) = sync_version_cmd(ctx, groups) else {
+            return Ok((resolved, purged, None));
+        };
+        ctx.phase("sync version", || ctx.run(sync))?;
+
+        // The version is synced by the project's own xtask, so read back what it did.
+        let cargo_toml = sh.read_file("Cargo.toml")?.parse::<DocumentMut>()?;
+        let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+        let anchor = sync_version::anchor(&cargo_toml, None)?;
+        let synced = match sync_version::crate_version(&cargo_toml)
+            .zip(sync_version::compiler_version(&cargo_lock).ok())
+        {
+            Some(((table_path, version), locked_version)) => Some(sync_version::Outcome {
+                table_path,
+                anchor,
+                compiler_version: sync_version::anchor_version(ctx, anchor, &locked_version)?,
+                previous_version,
+                version,
+            }),
+            None => None,
+        };
+        Ok((resolved, purged, synced))
+    })
+}
+
+/// Checks whether `cargo_toml` is the manifest of this crate, e.g. because the xtask was run from
+/// its own repository.
+fn is_own_manifest(cargo_toml: &DocumentMut) -> bool {
+    cargo_toml
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(Item::as_str)
+        == Some(env!("CARGO_PKG_NAME"))
+}
+
+/// Runs `f`, recording a snapshot of `files` if `record` is set, so that the run can be undone
+/// with `--rollback`.
+fn with_history<T>(
+    ctx: &Context,
+    files: &[String],
+    record: bool,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    if !record {
+        return f();
+    }
+    let sh = &ctx.sh;
+    let snapshot = Snapshot::begin(sh, files)?;
+    match f() {
+        Ok(value) => {
+            snapshot.finish(sh)?;
+            ctx.note(format_args!(
+                "recorded upgrade run {}, undo it with `cargo xtask upgrade --rollback`",
+                snapshot.id
+            ));
+            Ok(value)
+        }
+        Err(err) => {
+            snapshot.discard(sh)?;
+            Err(err)
+        }
+    }
+}
+
+/// Lists files recorded in the history of upgrade runs: the workspace manifests and `Cargo.lock`.
+///
+/// A lockfile outside the workspace root, given with `--lockfile-path`, is not recorded.
+fn history_files(ctx: &Context, cargo_toml: &DocumentMut) -> Result<Vec<String>> {
+    let mut files = vec!["Cargo.toml".to_owned()];
+    let lockfile = lockfile_name(ctx);
+    if Path::new(&lockfile).is_relative() {
+        files.push(lockfile);
+    }
+    for member in workspace::members(&ctx.workspace_root(), cargo_toml)? {
+        files.push(member.join("Cargo.toml").to_string_lossy().into_owned());
+    }
+    Ok(files)
+}
+
+/// Path of `Cargo.lock` as listed in transactions and snapshots, relative to the workspace root if
+/// it is inside it.
+fn lockfile_name(ctx: &Context) -> String {
+    let lockfile = ctx.lockfile();
+    lockfile
+        .strip_prefix(ctx.workspace_root())
+        .unwrap_or(&lockfile)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Moves locked crates of all groups to new versions, leaving manifest requirements untouched.
+fn update_lockfile(
+    ctx: &Context,
+    cargo_toml: &DocumentMut,
+    groups: &[Args],
+    index: &Index<'_>,
+    dry_run: bool,
+) -> Result<()> {
+    let sh = &ctx.sh;
+    let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+    let requirements = requirements(&sh.current_dir(), cargo_toml)?;
+
+    let mut plans = Vec::new();
+    for group in groups {
+        let plan = LockPlan::new(&cargo_lock, group, &requirements, |crate_name| {
+            index.versions_of(crate_name)
+        })?;
+        if plan.moves.is_empty() {
+            eprintln!(
+                "{} is already locked at {}",
+                group.dep().name(),
+                plan.target
+            );
+        }
+        for step in &plan.moves {
+            let verb = if dry_run { "would move" } else { "moving" };
+            eprintln!("{verb} {step}");
+        }
+        plans.push(plan);
+    }
+    for warning in index.take_warnings() {
+        ctx.warn(warning);
+    }
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = ctx.network_of(cargo_toml)?.cargo_args();
+    if dry_run {
+        for step in plans.iter().flat_map(|plan| &plan.moves) {
+            let cargo_args = step.cargo_args();
+            ctx.would_run(cmd!(
+                sh,
+                "{cargo} {toolchain...} update {cargo_args...} {offline...}"
+            ));
+        }
+        if let Some(sync) = sync_version_cmd(ctx, groups) {
+            ctx.would_run(sync);
+        }
+        return Ok(());
+    }
+
+    let files = history_files(ctx, cargo_toml)?;
+    let record = groups.iter().all(Args::backup);
+    with_history(ctx, &files, record, || {
+        transaction(ctx, &["Cargo.toml", &lockfile_name(ctx)], || {
+            ctx.phase("cargo update", || {
+                for step in plans.iter().flat_map(|plan| &plan.moves) {
+                    let cargo_args = step.cargo_args();
+                    ctx.run(cmd!(
+                        sh,
+                        "{cargo} {toolchain...} update {cargo_args...} {offline...}"
+                    ))?;
+                }
+                Ok(())
+            })?;
+
+            let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+            for plan in &plans {
+                plan.verify(&cargo_lock)?;
+                eprintln!("{} is locked at {}", plan.group.name(), plan.target);
+            }
+
+            if let Some(sync) = sync_version_cmd(ctx, groups) {
+                ctx.phase("sync version", || ctx.run(sync))?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Makes sure `Cargo.lock` locks the compiler at the version of a local Cairo checkout.
+///
+/// The lockfile may still lock the version from before switching to the checkout, from which
+/// `sync-version` would then take the wrong version. If so, the compiler is updated explicitly.
+fn refresh_checkout_lock(ctx: &Context, args: &Args) -> Result<()> {
+    let Some(checkout) = &args.spec.path else {
+        return Ok(());
+    };
+    if args.dep() != DepName::Cairo {
+        return Ok(());
+    }
+    let sh = &ctx.sh;
+    let anchor = args.tool_anchor();
+    // Left at its previous source, e.g. by `--best-effort`.
+    if !args.filter.selects(anchor) {
+        return Ok(());
+    }
+    let curl = Curl {
+        network: args.network,
+    };
+    let (_, expected) =
+        cairo_crate_versions(&args.spec, &args.cache.cache(), &curl, &[anchor])?.remove(0);
+
+    let locked = || -> Result<Vec<Version>> {
+        locked_versions(&sh.read_file(ctx.lockfile())?.parse()?, anchor)
+    };
+    if locked()? == [expected.clone()] {
+        return Ok(());
+    }
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let offline = args.network.cargo_args();
+    ctx.run(cmd!(
+        sh,
+        "{cargo} {toolchain...} update -p {anchor} {offline...}"
+    ))?;
+    let locked = locked()?;
+    ensure!(
+        locked == [expected.clone()],
+        "Cargo.lock locks {anchor} {}, but the checkout in {} is at {expected}",
+        locked
+            .iter()
+            .map(Version::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        checkout.display()
+    );
+    Ok(())
+}
+
+/// Command syncing the crate version with the upgraded toolchain, unless disabled by any group.
+fn sync_version_cmd<'a>(ctx: &'a Context, groups: &[Args]) -> Option<Cmd<'a>> {
+    if groups.iter().any(|args| args.no_sync_version) {
+        return None;
+    }
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let global = ctx.forwarded_args();
+    // Edits of the chained run are covered by the snapshot of this one, or not recorded at all.
+    Some(
+        cmd!(
+            ctx.sh,
+            "{cargo} {toolchain...} xtask sync-version {global...}"
+        )
+        .env(history::NESTED_ENV, "1"),
+    )
+}
+
+/// Locked crates of a dependency group to move to other versions.
+struct LockPlan {
+    group: DepName,
+    /// Version the crates released together with the group's anchor crate move to.
+    target: Version,
+    /// Crates released together with the group's anchor crate.
+    family: Vec<String>,
+    moves: Vec<LockMove>,
+}
+
+/// A locked crate to move to another version.
+#[derive(Debug, PartialEq, Eq)]
+struct LockMove {
+    crate_name: String,
+    from: Version,
+    /// Version to move to, or `None` for the newest version allowed by the requirements.
+    to: Option<Version>,
+}
+
+impl LockPlan {
+    /// Plans moving the crates of `group` locked in `cargo_lock`.
+    ///
+    /// Crates locked at the version of the anchor crate move to the requested version, or to the
+    /// newest one satisfying all `requirements` on them, as published according to `versions_of`.
+    /// Crates released independently move to their own discovered version, if known, or to the
+    /// newest version allowed by the requirements otherwise.
+    fn new(
+        cargo_lock: &DocumentMut,
+        group: &Args,
+        requirements: &[(String, VersionReq)],
+        versions_of: impl FnOnce(&str) -> Result<Vec<(Version, bool)>>,
+    ) -> Result<Self> {
+        let dep = group.dep();
+        let anchor = group.tool_anchor();
+        let packages = lockfile::registry_packages(cargo_lock)
+            .into_iter()
+            .filter(|pkg| dep.owns(&pkg.name))
+            .map(|pkg| Ok((pkg.name, pkg.version.parse::<Version>()?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let anchor_versions = packages
+            .iter()
+            .filter(|(name, _)| name == anchor)
+            .map(|(_, version)| version)
+            .collect::<Vec<_>>();
+        let [anchor_version] = anchor_versions.as_slice() else {
+            bail!(
+                "expected exactly one {anchor} package from crates.io in Cargo.lock, found: {}",
+                anchor_versions.len()
+            );
+        };
+        let family = packages
+            .iter()
+            .filter(|(_, version)| version == *anchor_version)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+
+        let target = match &group.spec.version {
+            Some(version) => version.clone(),
+            None => {
+                let requirements = requirements
+                    .iter()
+                    .filter(|(name, _)| family.contains(name))
+                    .map(|(_, req)| req.clone())
+                    .collect::<Vec<_>>();
+                newest_matching(&versions_of(anchor)?, &requirements).with_context(|| {
+                    format!(
+                        "no published version of {anchor} satisfies the requirements on {} crates",
+                        dep.name()
+                    )
+                })?
+            }
+        };
+
+        let mut moves = Vec::new();
+        for (crate_name, from) in packages {
+            let to = if family.contains(&crate_name) {
+                Some(target.clone())
+            } else {
+                group
+                    .crate_versions
+                    .iter()
+                    .find(|(name, _)| *name == crate_name)
+                    .map(|(_, version)| version.clone())
+            };
+            if to.as_ref() != Some(&from) {
+                moves.push(LockMove {
+                    crate_name,
+                    from,
+                    to,
+                });
+            }
+        }
+        moves.sort_by(|a, b| a.crate_name.cmp(&b.crate_name));
+
+        Ok(Self {
+            group: dep,
+            target,
+            family,
+            moves,
+        })
+    }
+
+    /// Checks that all crates released together with the anchor crate ended up on the target.
+    fn verify(&self, cargo_lock: &DocumentMut) -> Result<()> {
+        let mismatched = lockfile::registry_packages(cargo_lock)
+            .into_iter()
+            .filter(|pkg| self.family.contains(&pkg.name) && pkg.version != self.target.to_string())
+            .map(|pkg| format!("{} {}", pkg.name, pkg.version))
+            .collect::<Vec<_>>();
+        ensure!(
+            mismatched.is_empty(),
+            "{} crates did not all move to {}: {}",
+            self.group.name(),
+            self.target,
+            mismatched.join(", ")
+        );
+        Ok(())
+    }
+}
+
+impl LockMove {
+    fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-p".to_owned(),
+            format!("{}@{}", self.crate_name, self.from),
+        ];
+        if let Some(to) = &self.to {
+            args.extend(["--precise".to_owned(), to.to_string()]);
+        }
+        args
+    }
+}
+
+impl std::fmt::Display for LockMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.to {
+            Some(to) => write!(f, "{} {} -> {to}", self.crate_name, self.from),
+            None => write!(f, "{} {} -> newest compatible", self.crate_name, self.from),
+        }
+    }
+}
+
+/// Collects version requirements on crates from the root manifest and all workspace members.
+///
+/// Dependencies inheriting from `[workspace.dependencies]` are covered by the root manifest.
+fn requirements(root: &Path, cargo_toml: &DocumentMut) -> Result<Vec<(String, VersionReq)>> {
+    let mut manifests = vec![cargo_toml.clone()];
+    for member in workspace::members(root, cargo_toml)? {
+        manifests.push(manifest::read(&root.join(member).join("Cargo.toml"))?);
+    }
+
+    let mut requirements = Vec::new();
+    for manifest in &manifests {
+        for (_, deps) in workspace::dependency_tables(manifest) {
+            for (key, dep) in deps.iter() {
+                let name = manifest::crate_name(key, dep);
+                let req = dep
+                    .as_str()
+                    .or_else(|| dep.get("version").and_then(Item::as_str));
+                if let Some(req) = req.and_then(|req| req.parse().ok()) {
+                    requirements.push((name.to_owned(), req));
+                }
+            }
+        }
+    }
+    Ok(requirements)
+}
+
+/// Picks the highest version which is not yanked and satisfies all `requirements`.
+fn newest_matching(versions: &[(Version, bool)], requirements: &[VersionReq]) -> Option<Version> {
+    versions
+        .iter()
+        .filter(|(version, yanked)| !yanked && requirements.iter().all(|req| req.matches(version)))
+        .map(|(version, _)| version)
+        .max()
+        .cloned()
+}
+
+/// Picks the highest version which is not yanked, nor a prerelease unless `prerelease` is set.
+fn latest_release(versions: &[(Version, bool)], prerelease: bool) -> Option<Version> {
+    versions
+        .iter()
+        .filter(|(version, yanked)| (prerelease || version.pre.is_empty()) && !yanked)
+        .map(|(version, _)| version)
+        .max()
+        .cloned()
+}
+
+/// Checks that the requested version of the group's anchor crate is published on crates.io and
+/// not yanked, listing the closest published versions otherwise.
+fn check_published(index: &Index<'_>, args: &Args, version: &Version) -> Result<()> {
+    let anchor = args.tool_anchor();
+    let versions = index.versions_of(anchor).with_context(|| {
+        format!(
+            "cannot check that {anchor} {version} is published on crates.io, pass --no-verify to \
+            skip the check"
+        )
+    })?;
+    let problem = match versions.iter().find(|(v, _)| v == version) {
+        None => "is not published on crates.io",
+        Some((_, true)) => "has been yanked from crates.io",
+        Some((_, false)) => return Ok(()),
+    };
+    let closest = closest_versions(&versions, version);
+    if closest.is_empty() {
+        bail!("{anchor} {version} {problem}, and no other version of it is");
+    }
+    bail!(
+        "{anchor} {version} {problem}, the closest published versions are {}",
+        closest
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Picks up to two versions which are not yanked right below `version` and two right above it, in
+/// ascending order.
+fn closest_versions<'a>(versions: &'a [(Version, bool)], version: &Version) -> Vec<&'a Version> {
+    let mut published = versions
+        .iter()
+        .filter(|(v, yanked)| !yanked && v != version)
+        .map(|(v, _)| v)
+        .collect::<Vec<_>>();
+    published.sort();
+    let split = published.partition_point(|v| *v < version);
+    published[split.saturating_sub(2)..(split + 2).min(published.len())].to_vec()
+}
+
+/// Runs `f`, restoring all `files` to their original contents if it fails.
+///
+/// Files which did not exist before are removed again.
+fn transaction<T>(ctx: &Context, files: &[&str], f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let sh = &ctx.sh;
+    let snapshot = files
+        .iter()
+        .map(|&file| {
+            Ok((
+                file,
+                sh.path_exists(file)
+                    .then(|| sh.read_file(file))
+                    .transpose()?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    f().map_err(|err| {
+        for (file, contents) in snapshot {
+            let restored = match contents {
+                Some(contents) => sh.write_file(file, contents),
+                None if sh.path_exists(file) => sh.remove_path(file),
+                None => Ok(()),
+            };
+            if let Err(restore_err) = restored {
+                return err.context(format!("failed to restore {file}: {restore_err}"));
+            }
+        }
+        ctx.error(format_args!(
+            "restored {} to their original state",
+            files.join(", ")
+        ));
+        err
+    })
+}
+
+/// Upgrades the dependencies on the group in all dependency tables of `cargo_toml`, including
+/// `[build-dependencies]` and target-specific ones, and reports them by table path.
+fn edit_dependencies(cargo_toml: &mut DocumentMut, args: &Args) {
+    for (table_path, item) in workspace::dependency_items_mut(cargo_toml) {
+        rewrite_dependencies(item, args);
+
+        let deps = item.as_table_like().expect("only tables are listed");
+        eprintln!("[{table_path}]");
+        for (key, dep) in deps.iter() {
+            let crate_name = manifest::crate_name(key, dep);
+            if args.tool_owns_crate(crate_name) {
+                eprintln!("{key} = {}", display_dependency(dep));
+            }
+        }
+    }
+}
+
+/// Points the dependencies of the dependency table `item` on selected crates of the group to
+/// their new version on crates.io, and sorts them unless `--no-sort` is given.
+fn rewrite_dependencies(item: &mut Item, args: &Args) {
+    let Some(deps) = item.as_table_like_mut() else {
+        return;
+    };
+
+    for (key, dep) in deps.iter_mut() {
+        // Renamed dependencies are matched by the crate they rename.
+        let crate_name = manifest::crate_name(key.get(), dep).to_owned();
+        if !args.tool_selects_crate(&crate_name) {
+            continue;
+        }
+        // Git dependencies on crates the patch is going to cover are up to `--consolidate`, see
+        // `patched_git_deps`.
+        if args.patches_source() && dep.get("git").is_some() {
+            continue;
+        }
+        // Always use crates.io requirements so that we can reliably patch them with the
+        // `[patch.crates-io]` table.
+        let requirement = match args.crate_version(&crate_name) {
+            Some(version) if args.exact => format!("={version}"),
+            Some(version) => version.to_string(),
+            None => "*".to_owned(),
+        };
+        manifest::set_registry_version(dep, requirement);
+    }
+
+    if !args.no_sort {
+        sort_dependencies(item, args);
+    }
+}
+
+/// Finds git dependencies in any dependency table of `manifest` on crates for which `is_patched`
+/// holds, described for diagnostics. With `consolidate`, they are replaced with a `*` requirement
+/// keeping their features.
+fn patched_git_deps(
+    manifest_path: &Path,
+    manifest: &mut DocumentMut,
+    is_patched: &dyn Fn(&str) -> bool,
+    consolidate: bool,
+) -> Vec<String> {
+    let mut found = Vec::new();
+    for (table_path, deps) in workspace::dependency_tables_mut(manifest) {
+        for (key, dep) in deps.iter_mut() {
+            let crate_name = manifest::crate_name(key.get(), dep);
+            let Some(url) = dep.get("git").and_then(Item::as_str) else {
+                continue;
+            };
+            if !is_patched(crate_name) {
+                continue;
+            }
+            found.push(format!(
+                "{} [{table_path}] {} depends on git {url}",
+                manifest_path.display(),
+                key.get()
+            ));
+            if consolidate {
+                manifest::set_registry_version(dep, "*");
+            }
+        }
+    }
+    found
+}
+
+/// Sorts the entries of the group in a dependency table by name among themselves, see
+/// [`Args::sort_case_insensitive`].
+///
+/// Other entries, along with their comments and blank lines, are left where they are. Tables
+/// written inline, like `dependencies = { ... }`, are not sorted.
+fn sort_dependencies(deps: &mut Item, args: &Args) {
+    let compare = |a: &str, b: &str| match args.sort_case_insensitive {
+        true => a
+            .to_lowercase()
+            .cmp(&b.to_lowercase())
+            .then_with(|| a.cmp(b)),
+        false => a.cmp(b),
+    };
+    if let Item::Table(table) = deps {
+        manifest::sort_selected(
+            table,
+            |key, dep| args.tool_owns_crate(manifest::crate_name(key, dep)),
+            compare,
+        );
+    }
+}
+
+/// Drops groups which no manifest of the workspace depends on, as upgrading them would only churn
+/// `[patch.crates-io]` and `Cargo.lock`.
+///
+/// Fails with [`NothingToDo`] if no group is left.
+fn skip_unused_groups(
+    ctx: &Context,
+    cargo_toml: &DocumentMut,
+    groups: Vec<Args>,
+) -> Result<Vec<Args>> {
+    let root = ctx.sh.current_dir();
+    let mut manifests = vec![(PathBuf::from("Cargo.toml"), cargo_toml.clone())];
+    for member in workspace::members(&root, cargo_toml)? {
+        let manifest_path = member.join("Cargo.toml");
+        let manifest = manifest::read(&root.join(&manifest_path))?;
+        manifests.push((manifest_path, manifest));
+    }
+
+    let (used, unused): (Vec<_>, Vec<_>) = groups.into_iter().partition(|group| {
+        manifests
+            .iter()
+            .any(|(_, manifest)| group.dep().is_used_by(manifest))
+    });
+    let unused = unused
+        .iter()
+        .map(|group| group.dep().name())
+        .collect::<Vec<_>>();
+    if used.is_empty() {
+        return Err(NothingToDo {
+            groups: unused,
+            manifests: manifests.into_iter().map(|(path, _)| path).collect(),
+        }
+        .into());
+    }
+    for group in unused {
+        ctx.warn(format_args!(
+            "skipping {group}, as no manifest of the workspace depends on any of its crates"
+        ));
+    }
+    Ok(used)
+}
+
+/// Gets the version of the group's anchor crate locked in `Cargo.lock`.
+///
+/// Returns `None` if the lockfile does not lock exactly one version of the crate.
+fn locked_anchor_version(cargo_lock: &DocumentMut, args: &Args) -> Option<Version> {
+    let [version] = locked_versions(cargo_lock, args.tool_anchor())
+        .ok()?
+        .try_into()
+        .ok()?;
+    Some(version)
+}
+
+/// Lists crates of the Cairo group at its `previous` version, locked before the upgrade.
+///
+/// Returns `None` if there is no previous version, or when upgrading other groups, whose crate
+/// lists are not versioned.
+fn previous_tool_crates(
+    args: &Args,
+    previous: Option<&Version>,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Vec<String>>> {
+    let Some(previous) = previous.filter(|_| matches!(args.dep(), DepName::Cairo)) else {
+        return Ok(None);
+    };
+    if args.spec.version.as_ref() == Some(previous) {
+        return Ok(None);
+    }
+    let spec = Spec {
+        version: Some(previous.clone()),
+        ..Default::default()
+    };
+    cairo_crates(args, &spec, warnings).map(Some)
+}
+
+/// Lists the crates managed by any dependency group, including all names of renamed crates.
+///
+/// Crates of the Cairo group are read from its release script at the version locked in
+/// `cargo_lock`, or on the default branch configured in `cargo_toml` if none is locked. As this may
+/// access the network, they are only listed if `with_cairo` is set, and fetched as `network`
+/// allows. Crates of Scarb are not listed.
+pub(crate) fn managed_crates(
+    cargo_toml: &DocumentMut,
+    cargo_lock: Option<&DocumentMut>,
+    cache: &CacheArgs,
+    network: Network,
+    with_cairo: bool,
+) -> Result<Vec<String>> {
+    let mut crates = Vec::new();
+    for &dep in DepName::value_variants() {
+        let mut args = Args::parse_from(["upgrade", dep.name()]);
+        args.cache = cache.clone();
+        if matches!(dep, DepName::Cairo) {
+            if with_cairo {
+                let version = cargo_lock.and_then(|lock| locked_anchor_version(lock, &args));
+                let branch = match version {
+                    Some(_) => None,
+                    None => Some(config::group(cargo_toml, dep.name())?.branch().to_owned()),
+                };
+                let spec = Spec {
+                    version,
+                    branch,
+                    ..Default::default()
+                };
+                crates.extend(pull_cairo_packages_from_cairo_repository(
+                    &spec,
+                    &args.cache.cache(),
+                    &Curl { network },
+                )?);
+            }
+            continue;
+        }
+        // Scarb crates are never Cairo-like, and listing them needs the network.
+        if matches!(dep, DepName::Scarb) {
+            continue;
+        }
+        crates.extend(args.known_crates().map(str::to_owned));
+    }
+    Ok(crates)
+}
+
+/// Fails if the manifest depends on crates that belonged to the group at its previous version, but
+/// are neither published at the target version nor renamed.
+fn check_stale_crates(
+    manifest_path: &Path,
+    cargo_toml: &DocumentMut,
+    args: &Args,
+    previous_crates: &[String],
+) -> Result<()> {
+    let mut stale = Vec::new();
+    for (table_path, deps) in workspace::dependency_tables(cargo_toml) {
+        for (key, dep) in deps.iter() {
+            let crate_name = manifest::crate_name(key, dep);
+            if previous_crates.iter().any(|c| c == crate_name)
+                && !args.tool_owns_crate(crate_name)
+                && args.renamed(crate_name).is_none()
+            {
+                stale.push(format!(
+                    "  {key} ({} [{table_path}])",
+                    manifest_path.display()
+                ));
+            }
+        }
+    }
+
+    if !stale.is_empty() {
+        bail!(
+            "the following dependencies are no longer published by {} at the requested version, \
+            remove them or replace them with their successors:\n{}",
+            args.dep().name(),
+            stale.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Lists the selected `crates` of the group which have no package in its `--path` checkout, along
+/// with the directory they were expected in.
+fn missing_local_crates(args: &Args, crates: &[&str]) -> Vec<(String, PathBuf)> {
+    let Some(path) = &args.spec.path else {
+        return Vec::new();
+    };
+    crates
+        .iter()
+        .filter(|&&crate_name| args.filter.selects(crate_name))
+        .map(|&crate_name| (crate_name, path.join(args.crate_dir(crate_name))))
+        .filter(|(_, dir)| !dir.join("Cargo.toml").is_file())
+        .map(|(crate_name, dir)| (crate_name.to_owned(), dir))
+        .collect()
+}
+
+/// Lists the selected crates of the group which a dependency table of `cargo_toml` declares with a
+/// `path`, for `--preserve-path`.
+fn path_crates(cargo_toml: &DocumentMut, args: &Args) -> Vec<String> {
+    let mut crates = workspace::dependency_tables(cargo_toml)
+        .iter()
+        .flat_map(|(_, table)| table.iter())
+        .filter(|(_, dep)| dep.get("path").is_some())
+        .map(|(key, dep)| manifest::crate_name(key, dep))
+        .filter(|crate_name| args.tool_selects_crate(crate_name))
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    crates.sort();
+    crates.dedup();
+    crates
+}
+
+/// Fails if any of the selected `crates` of the group is missing from all dependency tables, for
+/// `--strict-group`.
+fn check_strict_group(cargo_toml: &DocumentMut, args: &Args, crates: &[&str]) -> Result<()> {
+    let mut present = Vec::new();
+    for (_, deps) in workspace::dependency_tables(cargo_toml) {
+        for (key, dep) in deps.iter() {
+            present.push(manifest::crate_name(key, dep));
+        }
+    }
+
+    let missing = crates
+        .iter()
+        .filter(|&&crate_name| args.filter.selects(crate_name) && !present.contains(&crate_name))
+        .map(|crate_name| format!("  {crate_name}"))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        let plural = if missing.len() == 1 { "" } else { "s" };
+        bail!(
+            "--strict-group: Cargo.toml does not depend on {} crate{plural} of {}:\n{}",
+            missing.len(),
+            args.dep().name(),
+            missing.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Renames dependencies on crates that were renamed upstream between the current and target version
+/// of the group, including references to them in the `[features]` table.
+///
+/// Returns human-readable descriptions of the performed migrations.
+fn migrate_renamed_crates(cargo_toml: &mut DocumentMut, args: &Args) -> Vec<String> {
+    let mut migrations = Vec::new();
+    let mut renamed_keys = Vec::new();
+
+    for (table_path, deps) in workspace::dependency_tables_mut(cargo_toml) {
+        let keys = deps
+            .iter()
+            .map(|(key, _)| key.to_owned())
+            .collect::<Vec<_>>();
+        for key in keys {
+            let dep = deps.get_mut(&key).unwrap();
+            let package = dep
+                .get("package")
+                .and_then(|p| p.as_str())
+                .map(str::to_owned);
+            let crate_name = package.as_deref().unwrap_or(&key);
+            let Some(new_name) = args.renamed(crate_name) else {
+                continue;
+            };
+
+            migrations.push(format!(
+                "{crate_name} is called {new_name} at the requested version, \
+                updated [{table_path}]"
+            ));
+            if package.is_some() {
+                dep["package"] = new_name.into();
+            } else {
+                let dep = deps.remove(&key).unwrap();
+                deps.insert(new_name, dep);
+                renamed_keys.push((key, new_name));
+            }
+        }
+    }
+
+    if let Some(features) = cargo_toml
+        .get_mut("features")
+        .and_then(|features| features.as_table_like_mut())
+    {
+        for (_, feature) in features.iter_mut() {
+            let Some(feature) = feature.as_array_mut() else {
+                continue;
+            };
+            for entry in feature.iter_mut() {
+                let Some(old) = entry.as_str() else {
+                    continue;
+                };
+                let new = renamed_keys
+                    .iter()
+                    .find_map(|(from, to)| rename_feature_reference(old, from, to));
+                if let Some(new) = new {
+                    let decor = entry.decor().clone();
+                    *entry = new.into();
+                    *entry.decor_mut() = decor;
+                }
+            }
+        }
+    }
+
+    migrations
+}
+
+/// Rewrites a `[features]` entry referencing dependency `from` to reference `to` instead.
+fn rename_feature_reference(entry: &str, from: &str, to: &str) -> Option<String> {
+    if entry == from {
+        return Some(to.to_owned());
+    }
+    if entry.strip_prefix("dep:") == Some(from) {
+        return Some(format!("dep:{to}"));
+    }
+    let (dep, feature) = entry.split_once('/')?;
+    match dep.strip_suffix('?') {
+        Some(dep) if dep == from => Some(format!("{to}?/{feature}")),
+        None if dep == from => Some(format!("{to}/{feature}")),
+        _ => None,
+    }
+}
+
+/// Describes, for each table in [`DEPENDENCY_TABLES`], whether it exists in the manifest at
+/// `manifest_path` and which crates owned by the selected tool it contains, followed by the other
+/// dependency tables of the manifest, like `[build-dependencies]` or target-specific ones.
+fn list_tables(manifest_path: &Path, cargo_toml: &DocumentMut, args: &Args) -> Vec<String> {
+    let manifest_path = manifest_path.display();
+    let describe = |table_path: &str, deps: &dyn TableLike| {
+        let owned = deps
+            .iter()
+            .filter(|(key, dep)| args.tool_owns_crate(manifest::crate_name(key, dep)))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        if owned.is_empty() {
+            format!("{manifest_path} [{table_path}] no owned crates")
+        } else {
+            format!(
+                "{manifest_path} [{table_path}] owned crates: {}",
+                owned.join(", ")
+            )
+        }
+    };
+    let mut lines = DEPENDENCY_TABLES
+        .iter()
+        .map(|table_path| match get_table_like(cargo_toml, table_path) {
+            Some(deps) => describe(table_path, deps),
+            None => format!("{manifest_path} [{table_path}] not found"),
+        })
+        .collect::<Vec<_>>();
+    for (table_path, deps) in workspace::dependency_tables(cargo_toml) {
+        if !DEPENDENCY_TABLES.contains(&table_path.as_str()) {
+            lines.push(describe(&table_path, deps));
+        }
+    }
+    lines
+}
+
+/// Removes entries of the group's crates from patch tables other than `[patch.crates-io]`, leaving
+/// it as the only place [`edit_patch`] patches them in.
+///
+/// Returns descriptions of the removals. Tables emptied by them are removed too.
+fn dedupe_patches(cargo_toml: &mut DocumentMut, args: &Args) -> Vec<String> {
+    let Some(patch) = cargo_toml
+        .get_mut("patch")
+        .and_then(Item::as_table_like_mut)
+    else {
+        return Vec::new();
+    };
+    let registries = patch
+        .iter()
+        .map(|(key, _)| key.to_owned())
+        .filter(|key| key != "crates-io")
+        .collect::<Vec<_>>();
+    let mut removals = Vec::new();
+    for registry in registries {
+        let Some(table) = patch.get_mut(&registry).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        let duplicates = table
+            .iter()
+            .map(|(key, _)| key.to_owned())
+            .filter(|key| args.filter.selects(key) && args.known_crates().any(|known| known == key))
+            .collect::<Vec<_>>();
+        if duplicates.is_empty() {
+            continue;
+        }
+        for crate_name in &duplicates {
+            table.remove(crate_name);
+        }
+        if table.is_empty() {
+            patch.remove(&registry);
+        }
+        removals.push(format!(
+            "removed patches of {} from [patch.{}], only [patch.crates-io] patches {}",
+            duplicates.join(", "),
+            Key::new(registry).display_repr(),
+            args.dep().name()
+        ));
+    }
+    removals
+}
+
+/// Points `[patch.crates-io]` entries of the group's crates to the requested source.
+///
+/// Entries of other crates are left as they are, including their order and formatting.
+/// Rewritten entries keep all keys other than [`manifest::SOURCE_KEYS`].
+///
+/// Returns warnings about entries which were left untouched.
+fn edit_patch(cargo_toml: &mut DocumentMut, args: &Args) -> Result<Vec<String>> {
+    if let Some(problem) = manifest::malformed_patch_table(cargo_toml) {
+        bail!("malformed Cargo.toml: {problem}");
+    }
+    let patches_git = args.spec.rev.is_some() || args.spec.branch.is_some();
+    if patches_git {
+        check_git_host(cargo_toml, args.tool_repo())?;
+    }
+    if patches_git && args.patch_with_version && args.spec.version.is_none() {
+        let name = args.dep().name();
+        bail!(
+            "--patch-with-version needs the version of {name} to write into [patch.crates-io], \
+            pass it along with the git source, e.g. `cargo xtask upgrade {name} <VERSION> --rev \
+            <REV>`"
+        );
+    }
+
+    let patches_source = args.patches_source();
+    if !patches_source && get_item_mut(cargo_toml, "patch.crates-io").is_none() {
+        return Ok(Vec::new());
+    }
+    let recorded_path = provenance::read(cargo_toml)
+        .into_iter()
+        .find(|record| record.group == args.dep().name())
+        .and_then(|record| record.path);
+    let starts_blank = |cargo_toml: &DocumentMut| cargo_toml.to_string().starts_with(['\n', '\r']);
+    let was_blank = starts_blank(cargo_toml);
+    let patch = patch_table(cargo_toml).expect("patch table is well-formed");
+
+    let manual = args
+        .known_crates()
+        .filter(|crate_name| args.filter.selects(crate_name))
+        .filter_map(|crate_name| {
+            let dep = patch.get(crate_name)?;
+            is_manual_patch(dep, args, recorded_path.as_deref())
+                .then(|| (crate_name, display_dependency(dep)))
+        })
+        .collect::<Vec<_>>();
+    let mut warnings = Vec::new();
+    let kept = match args.on_conflict {
+        OnConflict::Ours => Vec::new(),
+        _ if manual.is_empty() => Vec::new(),
+        OnConflict::Theirs => {
+            warnings.push(format!(
+                "keeping patches of {} in [patch.crates-io] written by hand",
+                manual
+                    .iter()
+                    .map(|(crate_name, _)| *crate_name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            manual
+                .into_iter()
+                .map(|(crate_name, _)| crate_name)
+                .collect()
+        }
+        OnConflict::Error => bail!(
+            "[patch.crates-io] has patches of {} crates written by hand, pass --on-conflict ours \
+            to replace them or --on-conflict theirs to keep them:{}",
+            args.dep().name(),
+            manual
+                .iter()
+                .map(|(crate_name, dep)| format!("\n  {crate_name} = {dep}"))
+                .collect::<String>()
+        ),
+    };
+
+    let foreign = patch
+        .iter()
+        .map(|(key, _)| key)
+        .filter(|key| !args.known_crates().any(|known| known == *key))
+        .collect::<Vec<_>>();
+    if !foreign.is_empty() {
+        warnings.push(format!(
+            "[patch.crates-io] contains entries not managed by {}, leaving them untouched: {}",
+            args.dep().name(),
+            foreign.join(", ")
+        ));
+    }
+
+    // Clear existing entries for this dependency, including ones under a previous name, unless
+    // they are going to be repointed.
+    let mut cleared = false;
+    for crate_name in args.known_crates() {
+        if !args.filter.selects(crate_name) || kept.contains(&crate_name) {
+            continue;
+        }
+        if !patches_source || !args.tool_owns_crate(crate_name) {
+            cleared |= patch.remove(crate_name).is_some();
+        }
+    }
+
+    // Leave this section as-is if we are requested to just use a specific version.
+    if patches_source {
+        // Patch all Cairo crates that exist, even if this project does not directly depend on them,
+        // to avoid any duplicates in transient dependencies.
+        for &dep_name in args.tool_crates() {
+            if !args.filter.selects(dep_name) || kept.contains(&dep_name) {
+                continue;
+            }
+            let mut dep = InlineTable::new();
+
+            // Add a Git branch or revision reference if requested.
+            if patches_git {
+                if let Some(version) = args
+                    .crate_version(dep_name)
+                    .filter(|_| args.patch_with_version)
+                {
+                    dep.insert("version", version.to_string().into());
+                }
+                dep.insert("git", args.tool_repo().into());
+            }
+
+            if let Some(branch) = &args.spec.branch {
+                dep.insert("branch", branch.as_str().into());
+            }
+
+            if let Some(rev) = &args.spec.rev {
+                let rev = match &args.spec.commit {
+                    Some(commit) if args.pin => commit,
+                    _ => rev,
+                };
+                dep.insert("rev", rev.as_str().into());
+            }
+
+            // Add local path reference if requested.
+            // For local path sources, Cargo is not looking for crates recursively therefore, we
+            // need to manually provide full paths to Cairo workspace member crates.
+            if let Some(path) = &args.spec.path {
+                dep.insert(
+                    "path",
+                    path.join(args.crate_dir(dep_name))
+                        .to_string_lossy()
+                        .into_owned()
+                        .into(),
+                );
+            }
+
+            manifest::upsert_patch(patch, dep_name, dep);
+        }
+    }
+
+    eprintln!("[patch.crates-io]");
+    for (key, dep) in patch.iter().filter(|(key, _)| args.tool_owns_crate(key)) {
+        eprintln!("{key} = {}", display_dependency(dep));
+    }
+    // Do not leave an empty stub behind once the last patch is cleared.
+    if cleared && !args.keep_empty_patch_table {
+        manifest::remove_empty_patch_table(cargo_toml);
+    }
+    // Patches written as dotted keys before the first table leave the blank line after them.
+    if !was_blank && starts_blank(cargo_toml) {
+        manifest::trim_leading_blank_lines(cargo_toml);
+    }
+    Ok(warnings)
+}
+
+/// Checks whether a `[patch.crates-io]` entry of the group was written by hand, i.e. it points
+/// neither to the repository of the group nor into the checkout it is upgraded from or was last
+/// upgraded from, as recorded in `recorded_path`.
+fn is_manual_patch(dep: &Item, args: &Args, recorded_path: Option<&str>) -> bool {
+    if let Some(git) = dep.get("git").and_then(Item::as_str) {
+        return normalize_repo_url(git) != normalize_repo_url(args.tool_repo());
+    }
+    let Some(path) = dep.get("path").and_then(Item::as_str) else {
+        return false;
+    };
+    let checkouts = [args.spec.path.as_deref(), recorded_path.map(Path::new)];
+    !checkouts
+        .into_iter()
+        .flatten()
+        .any(|checkout| Path::new(path).starts_with(checkout))
+}
+
+/// Brings a repository URL to a form in which different spellings of it compare equal, ignoring
+/// case and a trailing `/` or `.git`.
+fn normalize_repo_url(url: &str) -> String {
+    url.trim_end_matches('/')
+        .trim_end_matches(".git")
+        .to_lowercase()
+}
+
+/// Fails if the host of git `url` is not in the configured `allowed_git_hosts`.
+fn check_git_host(cargo_toml: &DocumentMut, url: &str) -> Result<()> {
+    let Some(allowed) = config::allowed_git_hosts(cargo_toml)? else {
+        return Ok(());
+    };
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split('/').next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split(':').next().unwrap_or_default();
+    ensure!(
+        allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host)),
+        "refusing to patch with git source {url}, host {host} is not in allowed_git_hosts ({})",
+        allowed.join(", ")
+    );
+    Ok(())
+}
+
+/// Refuses to upgrade a manifest whose patch sections are structurally broken, returning the
+/// manifest repaired with `--fix` otherwise.
+///
+/// Repairs are only written along with the upgrade, so that they are rolled back with it.
+fn check_patch_sections(ctx: &Context, args: &Args, original: String) -> Result<String> {
+    let anomalies = patch_sections::check(&original);
+    if anomalies.is_empty() {
+        return Ok(original);
+    }
+    let list = anomalies
+        .iter()
+        .map(|anomaly| format!("\n  {anomaly}"))
+        .collect::<String>();
+    if anomalies.iter().any(|anomaly| !anomaly.fixable) {
+        bail!("malformed patch sections in Cargo.toml, repair them by hand:{list}");
+    }
+    ensure!(
+        args.fix,
+        "malformed patch sections in Cargo.toml, pass --fix to repair them:{list}"
+    );
+
+    let fixed = patch_sections::fix(&original);
+    ensure!(
+        patch_sections::check(&fixed).is_empty(),
+        "failed to repair patch sections in Cargo.toml:{list}"
+    );
+    ctx.note(format_args!(
+        "repairing patch sections in Cargo.toml:{list}"
+    ));
+    Ok(fixed)
+}
+
+/// Reconstructs `[patch.crates-io]` entries of the group from git sources in `Cargo.lock`.
+///
+/// Crates are attributed to the group by their repository, and entries use the same git reference
+/// as the lock, so that Cargo keeps the locked commits.
+fn patch_from_lock(ctx: &Context, args: &Args, original: &str) -> Result<()> {
+    let sh = &ctx.sh;
+    let cargo_lock = sh.read_file(ctx.lockfile())?.parse::<DocumentMut>()?;
+    let repo = normalize_repo_url(args.tool_repo());
+    let packages = lockfile::git_packages(&cargo_lock)?
+        .into_iter()
+        .filter(|pkg| normalize_repo_url(&pkg.source.url) == repo)
+        .collect::<Vec<_>>();
+    if packages.is_empty() {
+        bail!(
+            "Cargo.lock has no packages sourced from {}, nothing to patch",
+            args.tool_repo()
+        );
+    }
+    if let Some(other) = packages
+        .iter()
+        .find(|pkg| pkg.source.reference != packages[0].source.reference)
+    {
+        bail!(
+            "Cargo.lock sources {} crates from different git references: {} and {}",
+            args.dep().name(),
+            packages[0].source,
+            other.source
+        );
+    }
+
+    let eol = LineEnding::detect(original);
+    let mut cargo_toml = original.parse::<DocumentMut>()?;
+    if let Some(problem) = manifest::malformed_patch_table(&cargo_toml) {
+        bail!("malformed Cargo.toml: {problem}");
+    }
+    let patch = patch_table(&mut cargo_toml).expect("patch table is well-formed");
+
+    eprintln!("[patch.crates-io]");
+    for pkg in &packages {
+        let mut dep = InlineTable::new();
+        if args.patch_with_version {
+            dep.insert("version", pkg.version.as_str().into());
+        }
+        dep.insert("git", pkg.source.url.as_str().into());
+        match &pkg.source.reference {
+            GitReference::Rev(rev) => dep.insert("rev", rev.as_str().into()),
+            GitReference::Branch(branch) => dep.insert("branch", branch.as_str().into()),
+            GitReference::Tag(tag) => dep.insert("tag", tag.as_str().into()),
+            GitReference::DefaultBranch => None,
+        };
+        manifest::upsert_patch(patch, &pkg.name, dep);
+        if let Some(dep) = patch.get(&pkg.name) {
+            eprintln!("{} = {}", pkg.name, display_dependency(dep));
+        }
+    }
+
+    if args.dry_run {
+        ctx.diff(
+            "Cargo.toml",
+            &sh.read_file("Cargo.toml")?,
+            &eol.apply(&cargo_toml.to_string()),
+        );
+        return Ok(());
+    }
+    write_backup(ctx, args, &[])?;
+    transaction(ctx, &["Cargo.toml", &lockfile_name(ctx)], || {
+        sh.write_file("Cargo.toml", eol.apply(&cargo_toml.to_string()))?;
+        // Confirm the reconstructed patches resolve to the locked commits.
+        let cargo = ctx.cargo();
+        let toolchain = ctx.rust_toolchain();
+        ctx.run(cmd!(sh, "{cargo} {toolchain...} fetch --locked"))
+    })
+}
+
+/// Copies `Cargo.toml` as it is on disk to `Cargo.toml.bak.<TIMESTAMP>` unless `--no-backup` is
+/// given, and it and the edited member manifests to their `--backup-ext` backups, if requested.
+fn write_backup(ctx: &Context, args: &Args, member_edits: &[(String, String)]) -> Result<()> {
+    if args.backup() {
+        let original = ctx.sh.read_binary_file("Cargo.toml")?;
+        let backup = history::write_backup(&ctx.sh, "Cargo.toml", &original)?;
+        ctx.note(format_args!("backed up Cargo.toml to {backup}"));
+    }
+    let Some(ext) = &args.backup_ext else {
+        return Ok(());
+    };
+    let manifests = ["Cargo.toml"]
+        .into_iter()
+        .chain(member_edits.iter().map(|(path, _)| path.as_str()));
+    for manifest in manifests {
+        let backup = format!("{manifest}.{ext}");
+        ctx.sh
+            .copy_file(manifest, &backup)
+            .with_context(|| format!("failed to back up {manifest} to {backup}"))?;
+        ctx.note(format_args!("backed up {manifest} to {backup}"));
+    }
+    Ok(())
+}
+
+/// Records the requested source of the dependency group in the manifest metadata.
+///
+/// The version is only known upfront for crates.io sources, for others it is filled in from
+/// `Cargo.lock` after fetching.
+fn record_provenance(cargo_toml: &mut DocumentMut, args: &Args) -> Result<()> {
+    let spec = &args.spec;
+    provenance::write(
+        cargo_toml,
+        &Provenance {
+            group: args.dep().name().to_owned(),
+            version: spec.version.clone(),
+            source: spec.source_kind().to_owned(),
+            repo: args.tool_repo().to_owned(),
+            rev: spec.rev.clone(),
+            branch: spec.branch.clone(),
+            path: spec
+                .path
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        },
+    )
+}
+
+/// Positional `<VERSION>` of the command line.
+#[derive(Clone)]
+enum VersionArg {
+    Version(Version),
+    /// A group listed after another one with a space instead of a comma.
+    Group(DepName),
+}
+
+impl Spec {
+    /// Takes the positional `<VERSION>` as the version to upgrade to.
+    ///
+    /// The names of several groups separated by spaces instead of commas end up there, and are
+    /// rejected with a hint listing the `groups` given before.
+    fn take_version_arg(&mut self, groups: &[DepName]) -> Result<()> {
+        match self.version_arg.take() {
+            Some(VersionArg::Version(version)) => self.version = Some(version),
+            Some(VersionArg::Group(group)) => {
+                let names = groups
+                    .iter()
+                    .chain([&group])
+                    .map(|dep| dep.name())
+                    .collect::<Vec<_>>();
+                bail!(
+                    "expected a version, got the group {}, separate the names of several groups \
+                    with commas, e.g. `{}`",
+                    group.name(),
+                    names.join(",")
+                );
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Checks whether any source was given.
+    fn has_source(&self) -> bool {
+        self.version.is_some()
+            || self.rev.is_some()
+            || self.branch.is_some()
+            || self.path.is_some()
+            || self.latest
+    }
+
+    /// Kind of the source, as recorded in provenance metadata: `registry`, `git` or `path`.
+    fn source_kind(&self) -> &'static str {
+        if self.path.is_some() {
+            "path"
+        } else if self.rev.is_some() || self.branch.is_some() {
+            "git"
+        } else {
+            "registry"
+        }
+    }
+}
+
+/// A crate of a dependency group that has been renamed upstream.
+struct Rename {
+    from: &'static str,
+    to: &'static str,
+    /// First version published under the new name.
+    since: &'static str,
+}
+
+impl DepName {
+    fn renames(self) -> &'static [Rename] {
+        match self {
+            DepName::CairoLint => &[Rename {
+                from: "cairo-lint-core",
+                to: "cairo-lint",
+                since: "2.12.0-rc.0",
+            }],
+            DepName::Cairo | DepName::CairoLS | DepName::Scarb => &[],
+        }
+    }
+
+    /// Checks whether any dependency table of `cargo_toml` contains a crate of this group.
+    ///
+    /// This does not need the full list of crates of the group, which may require network access.
+    fn is_used_by(self, cargo_toml: &DocumentMut) -> bool {
+        workspace::dependency_tables(cargo_toml)
+            .iter()
+            .any(|(_, table)| table.iter().any(|(crate_name, _)| self.owns(crate_name)))
+    }
+
+    /// Finds the groups which may own `crate_name`, for `--crate`.
+    ///
+    /// The crates of Cairo and Scarb are only known once the source of the group is resolved, so
+    /// this only rules out groups by the names their crates have. Which of the returned groups
+    /// owns the crate is decided by their crate lists, after discovery.
+    fn candidates_of_crate(crate_name: &str) -> Result<Vec<Self>> {
+        let groups = Self::value_variants()
+            .iter()
+            .copied()
+            .filter(|dep| dep.owns(crate_name))
+            .collect::<Vec<_>>();
+        ensure!(
+            !groups.is_empty(),
+            "{crate_name} does not belong to any toolchain dependency group, expected a \
+            `cairo-lang-*` crate, cairo-language-server, cairo-lint or a `scarb-*` crate"
+        );
+        Ok(groups)
+    }
+
+    /// Finds the group whose repository is at `url`.
+    fn of_repo(url: &str) -> Option<Self> {
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|dep| normalize_repo_url(dep.repo()) == normalize_repo_url(url))
+    }
+
+    /// Checks whether `crate_name` belongs to this group, judging by its name only.
+    pub(crate) fn owns(self, crate_name: &str) -> bool {
+        match self {
+            DepName::Cairo => crate_name.starts_with("cairo-lang-"),
+            DepName::CairoLS => crate_name == "cairo-language-server",
+            DepName::CairoLint => matches!(crate_name, "cairo-lint-core" | "cairo-lint"),
+            DepName::Scarb => crate_name == "scarb" || crate_name.starts_with("scarb-"),
+        }
+    }
+
+    /// URL of the GitHub repository of this dependency group.
+    pub(crate) fn repo(self) -> &'static str {
+        match self {
+            DepName::Cairo => "https://github.com/starkware-libs/cairo",
+            DepName::CairoLS => "https://github.com/software-mansion/cairols",
+            DepName::CairoLint => "https://github.com/software-mansion/cairo-lint",
+            DepName::Scarb => "https://github.com/software-mansion/scarb",
+        }
+    }
+
+    /// Name of this dependency group, as written in prose.
+    fn title(self) -> &'static str {
+        match self {
+            DepName::Cairo => "Cairo",
+            DepName::CairoLS => "CairoLS",
+            DepName::CairoLint => "Cairo Lint",
+            DepName::Scarb => "Scarb",
+        }
+    }
+
+    /// Name of this dependency group, as accepted on the command line.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DepName::Cairo => "cairo",
+            DepName::CairoLS => "cairols",
+            DepName::CairoLint => "cairolint",
+            DepName::Scarb => "scarb",
+        }
+    }
+}
+
+/// Parses a `GROUP=VERSION` mapping of `--group-version`.
+fn parse_group_version(s: &str) -> Result<(DepName, Version)> {
+    let Some((group, version)) = s.split_once('=') else {
+        bail!("expected GROUP=VERSION, got `{s}`");
+    };
+    let group = DepName::from_str(group, true).map_err(|err| anyhow::anyhow!(err))?;
+    let version = version
+        .parse()
+        .with_context(|| format!("invalid version in `{s}`"))?;
+    Ok((group, version))
+}
+
+/// Parses the version to upgrade to, keeping the names of groups to point them out once the
+/// groups given before are known.
+fn parse_version(s: &str) -> Result<VersionArg> {
+    match DepName::from_str(s, false) {
+        Ok(group) => Ok(VersionArg::Group(group)),
+        Err(_) => Ok(VersionArg::Version(s.parse()?)),
+    }
+}
+
+/// Takes the group and tag to upgrade to from the GitHub release payload at `path`, for
+/// `--from-github-release`.
+fn apply_github_release(ctx: &Context, args: &mut Args, path: &Path) -> Result<()> {
+    let path = ctx.cli_path(path);
+    let release = github_release::parse(&ctx.sh.read_file(&path)?)
+        .with_context(|| format!("invalid GitHub release payload {}", path.display()))?;
+    let tag = &release.tag;
+    let inferred = match &release.repo {
+        Some(repo) => Some(DepName::of_repo(repo).with_context(|| {
+            format!("release {tag} is of {repo}, which is not the repository of any group")
+        })?),
+        None => None,
+    };
+    let dep = match (args.dep.as_slice(), inferred) {
+        ([], Some(inferred)) => inferred,
+        ([], None) => bail!(
+            "the payload does not tell the repository of release {tag}, name the group to upgrade"
+        ),
+        ([dep], Some(inferred)) if *dep != inferred => bail!(
+            "release {tag} is of {}, not of {}",
+            inferred.name(),
+            dep.name()
+        ),
+        ([dep], _) => *dep,
+        _ => bail!("--from-github-release upgrades a single group, name only one"),
+    };
+    ctx.note(format_args!(
+        "using tag {tag} of {} from the GitHub release",
+        dep.name()
+    ));
+    args.dep = vec![dep];
+    args.spec.rev = Some(release.tag);
+    Ok(())
+}
+
+/// Parses a `--apply-to-dependabot` update.
+fn parse_crate_version(s: &str) -> Result<(String, Version)> {
+    let Some((crate_name, version)) = s.split_once('@') else {
+        bail!("expected CRATE@VERSION, got `{s}`");
+    };
+    let version = version
+        .parse()
+        .with_context(|| format!("invalid version in `{s}`"))?;
+    Ok((crate_name.to_owned(), version))
+}
+
+impl Args {
+    /// The dependency group to upgrade, which is only missing when rolling back.
+    fn dep(&self) -> DepName {
+        *self
+            .dep
+            .first()
+            .expect("dependency group is required unless rolling back")
+    }
+
+    /// Splits the arguments into one set per upgraded group, the named groups first and in order,
+    /// without duplicates.
+    ///
+    /// Groups mapped only with `--group-version` are sourced from crates.io at the mapped version.
+    /// With `--all`, there is one set for each group `cargo_toml` depends on. Sources taken from
+    /// the environment are reported in `warnings`.
+    fn groups(
+        &self,
+        cargo_toml: &DocumentMut,
+        var: impl Fn(&str) -> Option<OsString>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<Args>> {
+        if self.all {
+            let mut groups = Vec::new();
+            for &dep in DepName::value_variants() {
+                if !dep.is_used_by(cargo_toml) {
+                    continue;
+                }
+                let mut group = Args {
+                    dep: vec![dep],
+                    all: false,
+                    ..self.clone()
+                };
+                group.spec.take_version_arg(&[])?;
+                group.spec_from_env(&config::group(cargo_toml, dep.name())?, &var, warnings)?;
+                groups.push(group);
+            }
+            ensure!(
+                !groups.is_empty(),
+                "the project does not depend on any toolchain dependency group"
+            );
+            return Ok(groups);
+        }
+
+        let mapped = |dep: DepName| {
+            self.group_versions
+                .iter()
+                .rev()
+                .find(|(group, _)| *group == dep)
+                .map(|(_, version)| version.clone())
+        };
+
+        let mut groups = Vec::<Args>::new();
+        for &dep in &self.dep {
+            if groups.iter().any(|group| group.dep() == dep) {
+                continue;
+            }
+            let mut group = Args {
+                dep: vec![dep],
+                ..self.clone()
+            };
+            group.spec.take_version_arg(&self.dep)?;
+            if let Some(version) = mapped(dep) {
+                let spec = &group.spec;
+                if spec.version.as_ref().is_some_and(|v| *v != version)
+                    || spec.latest
+                    || spec.rev.is_some()
+                    || spec.branch.is_some()
+                    || spec.path.is_some()
+                {
+                    bail!(
+                        "--group-version {}={version} conflicts with the source given for {}",
+                        dep.name(),
+                        dep.name()
+                    );
+                }
+                group.spec.version = Some(version);
+            }
+            group.spec_from_env(&config::group(cargo_toml, dep.name())?, &var, warnings)?;
+            groups.push(group);
+        }
+        if groups.len() > 1 && self.spec.path.is_some() {
+            let names = groups
+                .iter()
+                .map(|group| group.dep().name())
+                .collect::<Vec<_>>();
+            bail!(
+                "--path points to the checkout of a single group, upgrade {} one at a time",
+                names.join(", ")
+            );
+        }
+
+        for &(dep, _) in &self.group_versions {
+            if groups.iter().any(|group| group.dep() == dep) {
+                continue;
+            }
+            groups.push(Args {
+                dep: vec![dep],
+                spec: Spec {
+                    version: mapped(dep),
+                    ..Default::default()
+                },
+                channel: None,
+                ..self.clone()
+            });
+        }
+        Ok(groups)
+    }
+
+    /// Fills in the spec from the environment if no source was given on the command line, or
+    /// falls back to the configured channel. Using a path from the environment adds a warning to
+    /// `warnings`.
+    fn spec_from_env(
+        &mut self,
+        config: &GroupConfig,
+        var: impl Fn(&str) -> Option<OsString>,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        if self.spec.has_source() || self.channel.is_some() || self.lockfile_only {
+            return Ok(());
+        }
+
+        let key = format!("CAIRO_XTASKS_PATH_{}", self.dep().name().to_uppercase());
+        let Some(path) = var(&key).filter(|path| !path.is_empty()) else {
+            if let Some(channel) = config.channel {
+                self.channel = Some(channel);
+                return Ok(());
+            }
+            bail!(
+                "one of <VERSION>, --rev, --branch, --path, --latest, --channel or --group-version \
+                {}=<VERSION> is required, alternatively set {key} to source the dependency from a \
+                local checkout",
+                self.dep().name()
+            );
+        };
+
+        let path = PathBuf::from(path);
+        warnings.push(format!(
+            "using path {} from {key}, avoid committing path sources to the repository",
+            path.display()
+        ));
+        self.spec.path = Some(path);
+        Ok(())
+    }
+
+    /// Replaces a `--branch` without a value, `--latest` and the release channel with the source
+    /// they stand for, adding the effective choice to `notes`.
+    ///
+    /// `latest` looks up the latest version of a crate, including prereleases if asked to.
+    fn resolve_channel(
+        &mut self,
+        config: &GroupConfig,
+        notes: &mut Vec<String>,
+        latest: impl FnOnce(&str, bool) -> Result<Version>,
+    ) -> Result<()> {
+        let group = self.dep().name();
+        if self.spec.latest && self.spec.branch.is_none() {
+            ensure!(
+                self.dep() != DepName::Scarb,
+                "scarb is not published on crates.io, so --latest cannot look it up, pass the \
+                version of a release instead"
+            );
+            let version = latest(self.tool_anchor(), self.prerelease)?;
+            notes.push(format!(
+                "using version {version} of {group}, the latest on crates.io"
+            ));
+            self.spec.version = Some(version);
+            return Ok(());
+        }
+        if self.spec.branch.as_deref() == Some("") {
+            let branch = config.branch();
+            notes.push(format!("using the default branch `{branch}` of {group}"));
+            self.spec.branch = Some(branch.to_owned());
+        }
+        if self.spec.has_source() {
+            return Ok(());
+        }
+
+        match self.channel {
+            Some(Channel::Dev) => {
+                let branch = config.branch();
+                notes.push(format!(
+                    "using branch `{branch}` of {group} for the dev channel"
+                ));
+                self.spec.branch = Some(branch.to_owned());
+            }
+            Some(Channel::Stable) => {
+                ensure!(
+                    self.dep() != DepName::Scarb,
+                    "scarb is not published on crates.io, so it has no stable channel, pass the \
+                    version of a release instead"
+                );
+                let version = latest(self.tool_anchor(), false)?;
+                notes.push(format!(
+                    "using version {version} of {group} for the stable channel"
+                ));
+                self.spec.version = Some(version);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Looks up versions of crates which are released independently of the group's anchor crate,
+    /// if sourcing the group from crates.io.
+    ///
+    /// Failing that, all Cairo crates are assumed to share the requested version. Scarb versions
+    /// name releases of the repository rather than of its crates, which are not verified against
+    /// crates.io either, so failing to read them is an error.
+    fn discover_crate_versions(
+        &mut self,
+        notes: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        let Some(version) = &self.spec.version else {
+            return Ok(());
+        };
+        if !matches!(self.dep(), DepName::Cairo | DepName::Scarb) {
+            return Ok(());
+        }
+
+        let curl = Curl {
+            network: self.network,
+        };
+        let manifests = self
+            .tool_crates()
+            .iter()
+            .map(|&crate_name| {
+                (
+                    crate_name,
+                    format!("{}/Cargo.toml", self.crate_dir(crate_name)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let group = self.dep().name();
+        match crate_versions(
+            &self.spec,
+            &self.cache.cache(),
+            &curl,
+            self.dep(),
+            &manifests,
+        ) {
+            Ok(crate_versions) => {
+                for (crate_name, crate_version) in &crate_versions {
+                    if crate_version != version {
+                        notes.push(format!(
+                            "{crate_name} is released independently of {group}, using version \
+                            {crate_version}"
+                        ));
+                    }
+                }
+                self.crate_versions = crate_versions;
+            }
+            Err(err) if self.dep() == DepName::Scarb => {
+                return Err(err.context(format!(
+                    "failed to read versions of individual {group} crates at {version}"
+                )));
+            }
+            Err(err) => warnings.push(format!(
+                "failed to read versions of individual {group} crates, assuming all are at \
+                {version}: {err:#}"
+            )),
+        }
+        Ok(())
+    }
+
+    /// Version to require of `crate_name`, or `None` if the group is not sourced from crates.io.
+    fn crate_version(&self, crate_name: &str) -> Option<&Version> {
+        let version = self.spec.version.as_ref()?;
+        Some(
+            self.crate_versions
+                .iter()
+                .find(|(name, _)| name == crate_name)
+                .map_or(version, |(_, version)| version),
+        )
+    }
+
+    /// Reads the crate list of Cairo or Scarb unless it is already known, so that failing to read
+    /// it is reported as an error instead of a panic in [`Self::tool_crates`].
+    fn load_tool_crates(&self, warnings: &mut Vec<String>) -> Result<()> {
+        if self.dep() == DepName::Cairo && CAIRO_CRATES.get().is_none() {
+            let crates = cairo_crates(self, &self.spec, warnings)?;
+            // Local checkouts may be work in progress, so only compare lists read from upstream.
+            if self.crate_list_source == CrateListSource::ReleaseScript && self.spec.path.is_none()
+            {
+                warnings.extend(remember_crate_list(&self.cache.cache(), &crates));
+            }
+            let _ = CAIRO_CRATES.set(crates.into_iter().map(|s| s.leak() as &str).collect());
+        }
+        if self.dep() == DepName::Scarb && SCARB_MEMBERS.get().is_none() {
+            let members = self.pull_scarb_members()?;
+            let _ = SCARB_MEMBERS.set(
+                members
+                    .into_iter()
+                    .map(|(name, dir)| (name.leak() as &str, dir.leak() as &str))
+                    .collect(),
+            );
+        }
+        Ok(())
+    }
+
+    fn tool_crates(&self) -> &'static [&'static str] {
+        match self.dep() {
+            DepName::Cairo => CAIRO_CRATES.get_or_init(|| {
+                cairo_crates(self, &self.spec, &mut Vec::new())
+                    .unwrap()
+                    .into_iter()
+                    .map(|s| s.leak() as &str)
+                    .collect()
+            }),
+            DepName::CairoLS => &["cairo-language-server"],
+            DepName::CairoLint => match self.renamed("cairo-lint-core") {
+                Some(_) => &["cairo-lint"],
+                None => &["cairo-lint-core"],
+            },
+            DepName::Scarb => SCARB_CRATES.get_or_init(|| {
+                self.scarb_members()
+                    .iter()
+                    .map(|(crate_name, _)| *crate_name)
+                    .collect()
+            }),
+        }
+    }
+
+    fn tool_owns_crate(&self, crate_name: &str) -> bool {
+        self.tool_crates().contains(&crate_name)
+    }
+
+    /// Checks whether `crate_name` belongs to the group and is selected to be upgraded.
+    fn tool_selects_crate(&self, crate_name: &str) -> bool {
+        self.tool_owns_crate(crate_name) && self.filter.selects(crate_name)
+    }
+
+    /// Whether to back up `Cargo.toml` and record the run, see [`Args::backup`].
+    fn backup(&self) -> bool {
+        self.backup || !self.no_backup
+    }
+
+    /// Checks whether `[patch.crates-io]` is going to point the selected crates to a git or path
+    /// source.
+    fn patches_source(&self) -> bool {
+        self.spec.rev.is_some() || self.spec.branch.is_some() || self.spec.path.is_some()
+    }
+
+    /// Names of all crates owned by the tool, including names they had before or after a rename.
+    fn known_crates(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tool_crates().iter().copied().chain(
+            self.dep()
+                .renames()
+                .iter()
+                .flat_map(|rename| [rename.from, rename.to]),
+        )
+    }
+
+    /// Returns the name a crate has at the requested version, if it differs from `crate_name`.
+    ///
+    /// Git and local sources are assumed to track the newest names.
+    fn renamed(&self, crate_name: &str) -> Option<&'static str> {
+        for rename in self.dep().renames() {
+            let renamed = match &self.spec.version {
+                Some(version) => *version >= rename.since.parse().unwrap(),
+                None => true,
+            };
+            match crate_name {
+                name if renamed && name == rename.from => return Some(rename.to),
+                name if !renamed && name == rename.to => return Some(rename.from),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The crate whose version represents the version of the whole group.
+    fn tool_anchor(&self) -> &'static str {
+        match self.dep() {
+            DepName::Cairo => "cairo-lang-compiler",
+            DepName::CairoLS => "cairo-language-server",
+            DepName::CairoLint => self.renamed("cairo-lint-core").unwrap_or("cairo-lint-core"),
+            DepName::Scarb => "scarb",
+        }
+    }
+
+    fn tool_repo(&self) -> &'static str {
+        self.dep().repo()
+    }
+
+    /// Directory of the package of `crate_name` in the repository of the group.
+    fn crate_dir(&self, crate_name: &str) -> String {
+        match self.dep() {
+            DepName::Scarb => self
+                .scarb_members()
+                .iter()
+                .find(|(name, _)| *name == crate_name)
+                .map_or_else(|| crate_name.to_owned(), |(_, dir)| dir.to_string()),
+            _ => format!("crates/{crate_name}"),
+        }
+    }
+
+    /// Workspace members of Scarb owned by the group, see [`pull_scarb_members`].
+    ///
+    /// They are read by [`Self::load_tool_crates`], which must have been called before.
+    fn scarb_members(&self) -> &'static [(&'static str, &'static str)] {
+        SCARB_MEMBERS.get().expect("loaded by load_tool_crates")
+    }
+
+    fn pull_scarb_members(&self) -> Result<Vec<(String, String)>> {
+        let curl = Curl {
+            network: self.network,
+        };
+        pull_scarb_members(&self.spec, &self.cache.cache(), &curl)
+    }
+}
+
+/// Remove any unused patches of the upgraded groups from the `[patch.crates-io]` table.
+///
+/// We are adding patch entries for **all** Cairo crates existing, and some may end up being unused.
+/// Cargo is emitting warnings about unused patches and keeps a record of them in the `Cargo.lock`.
+/// The goal of this function is to resolve these warnings. Unused patches of other crates are kept.
+///
+/// Returns the names of the removed patches.
+fn purge_unused_patches(
+    sh: &Shell,
+    lockfile: &Path,
+    cargo_toml: &mut DocumentMut,
+    groups: &[Args],
+) -> Result<Vec<String>> {
+    let cargo_lock = sh.read_file(lockfile)?.parse::<DocumentMut>()?;
+
+    let mut purged = Vec::new();
+    for unused in lockfile::unused_patches(&cargo_lock) {
+        if groups.iter().any(|args| args.tool_selects_crate(&unused))
+            && manifest::remove_patch(cargo_toml, &unused).is_some()
+        {
+            purged.push(unused);
+        }
+    }
+    if !purged.is_empty() && !groups.iter().any(|args| args.keep_empty_patch_table) {
+        manifest::remove_empty_patch_table(cargo_toml);
+    }
+
+    Ok(purged)
+}
+
+/// Crates of the Cairo group, read once per process.
+static CAIRO_CRATES: OnceLock<Vec<&str>> = OnceLock::new();
+
+/// Crates of the Scarb group with their directories in the repository, read once per process.
+static SCARB_MEMBERS: OnceLock<Vec<(&str, &str)>> = OnceLock::new();
+
+/// Names of the crates of [`SCARB_MEMBERS`].
+static SCARB_CRATES: OnceLock<Vec<&str>> = OnceLock::new();
+
+/// Lists crates published from the `starkware-libs/cairo` repository at the source `spec`, read
+/// from where `--crate-list-source` selects.
+fn cairo_crates(args: &Args, spec: &Spec, warnings: &mut Vec<String>) -> Result<Vec<String>> {
+    let curl = Curl {
+        network: args.network,
+    };
+    match args.crate_list_source {
+        CrateListSource::ReleaseScript => {
+            pull_cairo_packages_from_cairo_repository(spec, &args.cache.cache(), &curl)
+        }
+        CrateListSource::Index => {
+            let version = spec
+                .version
+                .as_ref()
+                .context("the crates.io index only lists crates of published versions")?;
+            let index = Index::new(&args.index, &args.cache.cache(), &curl);
+            let crates = pull_cairo_packages_from_index(&index, version, &args.used_crates);
+            warnings.extend(index.take_warnings());
+            crates
+        }
+    }
+}
+
+/// Names of crates of `dep` the dependency tables of the root manifest and all workspace members
+/// depend on.
+fn used_crates(root: &Path, cargo_toml: &DocumentMut, dep: DepName) -> Result<Vec<String>> {
+    let mut manifests = vec![cargo_toml.clone()];
+    for member in workspace::members(root, cargo_toml)? {
+        manifests.push(manifest::read(&root.join(member).join("Cargo.toml"))?);
+    }
+
+    let mut crates = manifests
+        .iter()
+        .flat_map(workspace::dependency_tables)
+        .flat_map(|(_, table)| {
+            table
+                .iter()
+                .map(|(key, spec)| manifest::crate_name(key, spec).to_owned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|crate_name| dep.owns(crate_name))
+        .collect::<Vec<_>>();
+    crates.sort();
+    crates.dedup();
+    Ok(crates)
+}
+
+/// Pulls names of crates published from the `starkware-libs/cairo` repository at `version` from
+/// the crates.io index.
+///
+/// The sparse index cannot be searched by name, so crates are found by following dependencies on
+/// `cairo-lang-*` crates, starting from the compiler and `seeds`, which should contain the crates
+/// the project depends on. Each is taken at its newest version matching the requirement on it, so
+/// that crates released independently of the compiler are found as well. Yanked releases are
+/// ignored, and seeds which are not published at `version` are skipped. The resulting vector is
+/// sorted alphabetically.
+fn pull_cairo_packages_from_index(
+    index: &Index<'_>,
+    version: &Version,
+    seeds: &[String],
+) -> Result<Vec<String>> {
+    const ANCHOR: &str = "cairo-lang-compiler";
+    let exact = VersionReq::parse(&format!("={version}"))?;
+    let mut pending = seeds
+        .iter()
+        .map(|seed| (seed.clone(), exact.clone()))
+        .collect::<Vec<_>>();
+    pending.push((ANCHOR.to_owned(), exact));
+
+    let mut crates = Vec::new();
+    while let Some((crate_name, req)) = pending.pop() {
+        if crates.contains(&crate_name) {
+            continue;
+        }
+        let records = index.records(&crate_name)?;
+        let record = records
+            .iter()
+            .filter(|record| record["yanked"].as_bool() != Some(true))
+            .filter_map(|record| Some((record["vers"].as_str()?.parse::<Version>().ok()?, record)))
+            .filter(|(version, _)| req.matches(version))
+            .max_by(|(a, _), (b, _)| a.cmp(b));
+        let Some((_, record)) = record else {
+            ensure!(
+                crate_name != ANCHOR,
+                "{ANCHOR} {version} is not published on crates.io, or yanked"
+            );
+            continue;
+        };
+
+        for dep in record["deps"].as_array().into_iter().flatten() {
+            let Some(dep_name) = dep["package"].as_str().or(dep["name"].as_str()) else {
+                continue;
+            };
+            if dep_name.starts_with("cairo-lang-") {
+                let req = dep["req"].as_str().unwrap_or("*");
+                pending.push((dep_name.to_owned(), VersionReq::parse(req)?));
+            }
+        }
+        crates.push(crate_name);
+    }
+    crates.sort();
+    Ok(crates)
+}
+
+/// Pulls names of crates published from the `starkware-libs/cairo` repository.
+///
+/// The list is obtained by parsing the `scripts/release_crates.sh` script in that repo.
+/// Scripts fetched for immutable refs (version tags and full commit hashes) are cached.
+/// The resulting vector is sorted alphabetically.
+fn pull_cairo_packages_from_cairo_repository(
+    spec: &Spec,
+    cache: &Cache,
+    http: &dyn Http,
+) -> Result<Vec<String>> {
+    let release_crates_sh = read_repo_file(
+        spec,
+        cache,
+        http,
+        DepName::Cairo,
+        "scripts/release_crates.sh",
+        |rev| format!("release-crates/cairo/{rev}.sh"),
+    )?;
+
+    let mut crates = release_script::parse_array(&release_crates_sh, "CRATES_TO_PUBLISH")
+        .context("failed to read `CRATES_TO_PUBLISH` from `scripts/release_crates.sh`")?;
+    crates.retain(|s| s.starts_with("cairo-lang-"));
+    crates.sort();
+    Ok(crates)
+}
+
+/// Remembers the crate list `crates` of the Cairo release script upgraded to, returning warnings
+/// about how it drifted from the list remembered before.
+///
+/// The list is only remembered for these warnings, so failing to do so is a warning as well.
+fn remember_crate_list(cache: &Cache, crates: &[String]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(previous) = cache.get(LAST_CRATE_LIST) {
+        let previous = previous.lines().map(str::to_owned).collect::<Vec<_>>();
+        warnings.extend(release_script::drift_warning(&previous, crates));
+    }
+    if let Err(err) = cache.put(LAST_CRATE_LIST, &crates.join("\n")) {
+        warnings.push(format!(
+            "cannot remember the crate list for the next upgrade: {err:#}"
+        ));
+    }
+    warnings
+}
+
+/// Cache key of the crate list read from the Cairo release script most recently.
+const LAST_CRATE_LIST: &str = "release-crates/cairo/last-list";
+
+/// Reads versions of Cairo crates from their manifests in the Cairo repository.
+///
+/// Most crates inherit the workspace version, but some are released on their own cadence, so the
+/// version of the repository tag does not apply to them.
+fn cairo_crate_versions(
+    spec: &Spec,
+    cache: &Cache,
+    http: &dyn Http,
+    crates: &[&str],
+) -> Result<Vec<(String, Version)>> {
+    let manifests = crates
+        .iter()
+        .map(|&crate_name| (crate_name, format!("crates/{crate_name}/Cargo.toml")))
+        .collect::<Vec<_>>();
+    crate_versions(spec, cache, http, DepName::Cairo, &manifests)
+}
+
+/// Reads versions of crates of `dep` from their `manifests` in its repository, given as crate
+/// names and paths of the manifests.
+///
+/// Crates inheriting the workspace version get the one of the root manifest.
+fn crate_versions(
+    spec: &Spec,
+    cache: &Cache,
+    http: &dyn Http,
+    dep: DepName,
+    manifests: &[(&str, String)],
+) -> Result<Vec<(String, Version)>> {
+    let group = dep.name();
+    let read_manifest = |path: &str| {
+        read_repo_file(spec, cache, http, dep, path, |rev| {
+            format!("{group}-manifests/{rev}/{path}")
+        })?
+        .parse::<DocumentMut>()
+        .with_context(|| format!("failed to parse {path} of the {group} repository"))
+    };
+
+    let mut workspace_version = None;
+    let mut versions = Vec::new();
+    for (crate_name, path) in manifests {
+        let manifest = read_manifest(path)?;
+        let version = match manifest.get("package").and_then(|p| p.get("version")) {
+            Some(version) if version.get("workspace").and_then(Item::as_bool) == Some(true) => {
+                match &workspace_version {
+                    Some(version) => Version::clone(version),
+                    None => {
+                        let root = read_manifest("Cargo.toml")?;
+                        let version = root
+                            .get("workspace")
+                            .and_then(|w| w.get("package"))
+                            .and_then(|p| p.get("version"))
+                            .and_then(Item::as_str)
+                            .with_context(|| {
+                                format!("{group} repository does not set workspace.package.version")
+                            })?
+                            .parse::<Version>()?;
+                        workspace_version.insert(version).clone()
+                    }
+                }
+            }
+            Some(version) => version
+                .as_str()
+                .with_context(|| format!("{path} of the {group} repository has no version"))?
+                .parse()?,
+            None => bail!("{path} of the {group} repository has no version"),
+        };
+        versions.push((crate_name.to_string(), version));
+    }
+    Ok(versions)
+}
+
+/// Lists the workspace members of the Scarb repository which belong to the Scarb group, as crate
+/// names and their directories, sorted by name.
+///
+/// Members are read from `workspace.members` of the root manifest, taking the name of each
+/// directory for the name of its package, as throughout the Scarb repository. Glob patterns cannot
+/// be listed remotely, so they are skipped.
+fn pull_scarb_members(
+    spec: &Spec,
+    cache: &Cache,
+    http: &dyn Http,
+) -> Result<Vec<(String, String)>> {
+    let cargo_toml = read_repo_file(spec, cache, http, DepName::Scarb, "Cargo.toml", |rev| {
+        format!("scarb-manifests/{rev}/Cargo.toml")
+    })?
+    .parse::<DocumentMut>()
+    .context("failed to parse Cargo.toml of the scarb repository")?;
+    let members = cargo_toml
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(Item::as_array)
+        .context("Cargo.toml of the scarb repository has no workspace.members")?;
+
+    let mut crates = members
+        .iter()
+        .filter_map(|member| member.as_str())
+        .filter(|dir| !dir.contains('*'))
+        .filter_map(|dir| {
+            let crate_name = dir.trim_end_matches('/').rsplit('/').next()?;
+            DepName::Scarb
+                .owns(crate_name)
+                .then(|| (crate_name.to_owned(), dir.trim_end_matches('/').to_owned()))
+        })
+        .collect::<Vec<_>>();
+    crates.sort();
+    Ok(crates)
+}
+
+/// Reads a file of the repository of `dep` at the revision requested by `spec`, or from its local
+/// checkout.
+///
+/// Files at tags and full commit hashes never change, so they are cached under `cache_key(rev)`.
+fn read_repo_file(
+    spec: &Spec,
+    cache: &Cache,
+    http: &dyn Http,
+    dep: DepName,
+    path: &str,
+    cache_key: impl FnOnce(&str) -> String,
+) -> Result<String> {
+    let sh = Shell::new()?;
+    if let Some(checkout) = &spec.path {
+        return Ok(sh.read_file(checkout.join(path))?);
+    }
+
+    let rev = if let Some(version) = &spec.version {
+        format!("refs/tags/v{version}")
+    } else if let Some(rev) = &spec.rev {
+        spec.commit.clone().unwrap_or_else(|| rev.to_string())
+    } else if let Some(branch) = &spec.branch {
+        format!("refs/heads/{branch}")
+    } else {
+        let branch = spec.default_branch.as_deref();
+        format!("refs/heads/{}", branch.unwrap_or(config::DEFAULT_BRANCH))
+    };
+    let immutable = spec.version.is_some()
+        || spec.commit.is_some()
+        || spec
+            .rev
+            .as_ref()
+            .is_some_and(|rev| RevKind::of(rev) == RevKind::FullSha);
+    let cache_key = cache_key(&rev);
+
+    match cache.get(&cache_key).filter(|_| immutable) {
+        Some(contents) => Ok(contents),
+        None => {
+            let repo = dep
+                .repo()
+                .strip_prefix("https://github.com/")
+                .expect("repositories are hosted on GitHub");
+            let url = format!("https://raw.githubusercontent.com/{repo}/{rev}/{path}");
+            let contents = http.get(&url)?;
+            if immutable {
+                cache.put(&cache_key, &contents)?;
+            }
+            Ok(contents)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    /// Parses `upgrade` arguments which neither verify versions against the crates.io index nor
+    /// use the user's cache, for upgrades to versions which must not access the network.
+    fn offline_args(temp_dir: &Path, args: &[&str]) -> Args {
+        let cache_dir = temp_dir.join("cache");
+        Args::parse_from(args.iter().map(OsStr::new).chain([
+            "--no-verify".as_ref(),
+            "--cache-dir".as_ref(),
+            cache_dir.as_os_str(),
+        ]))
+    }
+
+    /// Parses `upgrade` arguments with their version taken, as [`Args::groups`] does for [`run`].
+    fn parse_args<'a>(args: impl IntoIterator<Item = &'a str>) -> Args {
+        let mut args = Args::parse_from(args);
+        args.spec.take_version_arg(&args.dep).unwrap();
+        args
+    }
+
+    #[test]
+    fn test_pull_cairo_packages_from_cairo_repository() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let list = pull_cairo_packages_from_cairo_repository(
+            &Spec::default(),
+            &cache,
+            &Curl {
+                network: Network::Allowed,
+            },
+        )
+        .unwrap();
+        assert!(!list.is_empty());
+        assert!(list.contains(&"cairo-lang-compiler".to_owned()));
+        assert!(!list.contains(&"cairo-test".to_owned()));
+        assert!(list.is_sorted());
+    }
+
+    /// Commit at the tip of all branches of [`MockRemote`].
+    const BRANCH_HEAD: &str = "0b3bfdcf2e4c1a7e8e6e0f7b9a3b2b9c1d2e3f40";
+
+    /// Remote of Cairo Language Server with branches `main` and `release/2.9`.
+    struct MockRemote;
+
+    impl Remote for MockRemote {
+        fn ls_remote(&self, repo: &str, patterns: &[String]) -> Result<String> {
+            assert_eq!(repo, "https://github.com/software-mansion/cairols");
+            let refs = ["refs/heads/main", "refs/heads/release/2.9"];
+            Ok(refs
+                .iter()
+                .filter(|name| {
+                    patterns.is_empty()
+                        || patterns
+                            .iter()
+                            .any(|pattern| match pattern.strip_suffix('*') {
+                                Some(prefix) => name.starts_with(prefix),
+                                None => pattern == *name,
+                            })
+                })
+                .map(|name| format!("{BRANCH_HEAD}\t{name}\n"))
+                .collect())
+        }
+    }
+
+    /// Index with a few releases of Cairo crates, where `cairo-lang-executable` is versioned
+    /// independently and `cairo-lang-old` was last published at 2.8.0.
+    struct MockIndex;
+
+    impl Http for MockIndex {
+        fn get(&self, url: &str) -> Result<String> {
+            let record = |name: &str, version: &str, deps: &[(&str, &str)]| {
+                let deps = deps
+                    .iter()
+                    .map(|(name, req)| {
+                        format!(r#"{{"name":"{name}","req":"{req}","kind":"normal"}}"#)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(r#"{{"name":"{name}","vers":"{version}","deps":[{deps}],"yanked":false}}"#)
+            };
+            let crate_name = url.rsplit('/').next().unwrap();
+            let records = match crate_name {
+                "cairo-lang-compiler" => vec![
+                    record(
+                        "cairo-lang-compiler",
+                        "2.8.0",
+                        &[("cairo-lang-old", "=2.8.0")],
+                    ),
+                    record(
+                        "cairo-lang-compiler",
+                        "2.9.0",
+                        &[
+                            ("cairo-lang-utils", "=2.9.0"),
+                            ("cairo-lang-sierra", "~2.9.0"),
+                            ("serde", "1"),
+                        ],
+                    ),
+                ],
+                "cairo-lang-utils" => vec![record("cairo-lang-utils", "2.9.0", &[])],
+                "cairo-lang-sierra" => vec![
+                    record("cairo-lang-sierra", "2.9.0", &[]),
+                    record(
+                        "cairo-lang-sierra",
+                        "2.9.1",
+                        &[("cairo-lang-utils", "=2.9.0")],
+                    ),
+                ],
+                "cairo-lang-starknet" => vec![record(
+                    "cairo-lang-starknet",
+                    "2.9.0",
+                    &[
+                        ("cairo-lang-compiler", "=2.9.0"),
+                        ("cairo-lang-executable", "1.0"),
+                    ],
+                )],
+                "cairo-lang-executable" => vec![
+                    record("cairo-lang-executable", "1.0.3", &[]),
+                    // Yanked releases are skipped, including what they depend on.
+                    record(
+                        "cairo-lang-executable",
+                        "1.0.4",
+                        &[("cairo-lang-yanked", "1")],
+                    )
+                    .replace(r#""yanked":false"#, r#""yanked":true"#),
+                ],
+                "cairo-lang-old" => vec![record("cairo-lang-old", "2.8.0", &[])],
+                _ => bail!("unexpected request to {url}"),
+            };
+            Ok(records.join("\n"))
+        }
+    }
+
+    #[test]
+    fn test_pull_cairo_packages_from_index() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let index = Index::new(&IndexArgs::default(), &cache, &MockIndex);
+        let seeds = ["cairo-lang-old", "cairo-lang-starknet"].map(str::to_owned);
+
+        assert_eq!(
+            pull_cairo_packages_from_index(&index, &"2.9.0".parse().unwrap(), &seeds).unwrap(),
+            [
+                "cairo-lang-compiler",
+                "cairo-lang-executable",
+                "cairo-lang-sierra",
+                "cairo-lang-starknet",
+                "cairo-lang-utils",
+            ]
+        );
+        assert_eq!(
+            pull_cairo_packages_from_index(&index, &"2.8.0".parse().unwrap(), &[]).unwrap(),
+            ["cairo-lang-compiler", "cairo-lang-old"]
+        );
+        assert_eq!(
+            pull_cairo_packages_from_index(&index, &"2.10.0".parse().unwrap(), &seeds)
+                .unwrap_err()
+                .to_string(),
+            "cairo-lang-compiler 2.10.0 is not published on crates.io, or yanked"
+        );
+
+        let cargo_toml = "[workspace]\nmembers = [\"member\"]\n\n\
+            [workspace.dependencies]\ncairo-lang-utils = \"2.9.0\"\n\
+            starknet = { package = \"cairo-lang-starknet\", version = \"2.9.0\" }\nserde = \"1\"\n\n\
+            [dev-dependencies]\ncairo-lang-utils = \"2.9.0\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        // Crates only members depend on are used as well.
+        sh.write_file(
+            temp_dir.path().join("member/Cargo.toml"),
+            "[package]\nname = \"member\"\n\n[dependencies]\ncairo-lang-sierra = \"2.9.0\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            used_crates(temp_dir.path(), &cargo_toml, DepName::Cairo).unwrap(),
+            [
+                "cairo-lang-sierra",
+                "cairo-lang-starknet",
+                "cairo-lang-utils"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remember_crate_list() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let crates = |n: usize| {
+            (0..n)
+                .map(|i| format!("cairo-lang-{i}"))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(remember_crate_list(&cache, &crates(30)).is_empty());
+        assert!(remember_crate_list(&cache, &crates(31)).is_empty());
+        assert_eq!(
+            remember_crate_list(&cache, &crates(3)),
+            [
+                "the release script lists 3 crates, while the last one read listed 31, check \
+            whether its format changed"
+            ]
+        );
+
+        // A cache which cannot be written only loses the comparison.
+        let cache = Cache::new(temp_dir.path().join("cache"));
+        sh.write_file(temp_dir.path().join("cache"), "").unwrap();
+        let warnings = remember_crate_list(&cache, &crates(3));
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with("cannot remember the crate list for the next upgrade"),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn test_cairo_crate_versions() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cairo = temp_dir.path();
+        // Snapshot of the relevant parts of the cairo repository at a tag, where
+        // `cairo-lang-macro` follows its own release cadence.
+        for (path, contents) in [
+            (
+                "scripts/release_crates.sh",
+                "#!/bin/bash\nCRATES_TO_PUBLISH=(\n  cairo-lang-utils\n  cairo-lang-macro\n  \
+                cairo-lang-compiler\n  cairo-test\n)\n",
+            ),
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.package]\nversion = \"2.9.2\"\n",
+            ),
+            (
+                "crates/cairo-lang-utils/Cargo.toml",
+                "[package]\nname = \"cairo-lang-utils\"\nversion.workspace = true\n",
+            ),
+            (
+                "crates/cairo-lang-macro/Cargo.toml",
+                "[package]\nname = \"cairo-lang-macro\"\nversion = \"0.1.1\"\n",
+            ),
+            (
+                "crates/cairo-lang-compiler/Cargo.toml",
+                "[package]\nname = \"cairo-lang-compiler\"\nversion = { workspace = true }\n",
+            ),
+        ] {
+            sh.write_file(cairo.join(path), contents).unwrap();
+        }
+        let spec = Spec {
+            path: Some(cairo.to_path_buf()),
+            ..Default::default()
+        };
+        let cache = Cache::new(cairo.join("cache"));
+        // Local checkouts are read without accessing the network.
+        let http = Curl {
+            network: Network::Unconfirmed,
+        };
+
+        let crates = pull_cairo_packages_from_cairo_repository(&spec, &cache, &http).unwrap();
+        assert_eq!(
+            crates,
+            [
+                "cairo-lang-compiler",
+                "cairo-lang-macro",
+                "cairo-lang-utils"
+            ]
+        );
+        let crates = crates.iter().map(String::as_str).collect::<Vec<_>>();
+        let versions = cairo_crate_versions(&spec, &cache, &http, &crates).unwrap();
+        assert_eq!(
+            versions,
+            [
+                ("cairo-lang-compiler".to_owned(), Version::new(2, 9, 2)),
+                ("cairo-lang-macro".to_owned(), Version::new(0, 1, 1)),
+                ("cairo-lang-utils".to_owned(), Version::new(2, 9, 2)),
+            ]
+        );
+
+        let mut args = parse_args(["upgrade", "cairo", "2.9.2"]);
+        args.crate_versions = versions;
+        assert_eq!(
+            args.crate_version("cairo-lang-macro"),
+            Some(&Version::new(0, 1, 1))
+        );
+        assert_eq!(
+            args.crate_version("cairo-lang-compiler"),
+            Some(&Version::new(2, 9, 2))
+        );
+        assert_eq!(
+            args.crate_version("cairo-lang-new"),
+            Some(&Version::new(2, 9, 2))
+        );
+    }
+
+    #[test]
+    fn test_transaction_restores_files_on_purge_failure() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+
+        let original_toml = "[package]\nname = \"foo\"\n\n[patch.crates-io]\n";
+        let original_lock = "version = 4\n";
+        sh.write_file("Cargo.toml", original_toml).unwrap();
+        sh.write_file("Cargo.lock", original_lock).unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let result = transaction(&ctx, &["Cargo.toml", "Cargo.lock", "new.txt"], || {
+            let mut cargo_toml =
+                "[package]\nname = \"bar\"\n\n[patch.crates-io]\n".parse::<DocumentMut>()?;
+            sh.write_file("Cargo.toml", cargo_toml.to_string())?;
+            sh.write_file("new.txt", "")?;
+            // Simulate `cargo fetch` leaving a lockfile behind which the purge step cannot read.
+            sh.write_file("Cargo.lock", "[[[")?;
+            purge_unused_patches(&sh, Path::new("Cargo.lock"), &mut cargo_toml, &[])
+        });
+
+        assert!(result.is_err());
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original_toml);
+        assert_eq!(sh.read_file("Cargo.lock").unwrap(), original_lock);
+        assert!(!sh.path_exists("new.txt"));
+        assert_eq!(
+            ctx.captured().1,
+            ["error: restored Cargo.toml, Cargo.lock, new.txt to their original state"]
+        );
+    }
+
+    #[test]
+    fn test_reports_purged_patches() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-lint = \"2.11.0\"\n\n[patch.crates-io]\n\
+            foo = { path = \"../foo\" }\n",
+        )
+        .unwrap();
+        // As if Cargo did not use the patches written by the upgrade.
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[patch.unused]]\nname = \"cairo-lint\"\nversion = \"2.12.0\"\n\n\
+            [[patch.unused]]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairolint",
+            "--rev",
+            "1a2b3c4",
+            "--format",
+            "json",
+            "--no-sync-version",
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+
+        let (stdout, stderr) = ctx.captured();
+        let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!(
+            report["groups"][0]["purged_patches"],
+            serde_json::json!(["cairo-lint"])
+        );
+        assert!(stderr.contains(&"note: removed unused patches: cairo-lint".to_owned()));
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains("[patch.crates-io]\nfoo = { path = \"../foo\" }\n\n"),
+            "{cargo_toml}"
+        );
+    }
+
+    #[test]
+    fn test_provenance_survives_manifest_edits() {
+        let mut cargo_toml = r#"
+[workspace]
+members = []
+
+[workspace.dependencies]
+cairo-language-server = "2.8.0"
+
+[patch.crates-io]
+
+[workspace.metadata.cairo-toolchain.cairo]
+version = "2.9.2"
+source = "registry"
+repo = "https://github.com/starkware-libs/cairo"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = Args::parse_from(["upgrade", "cairols", "--rev", "abcdef"]);
+
+        edit_dependencies(&mut cargo_toml, &args);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        record_provenance(&mut cargo_toml, &args).unwrap();
+
+        let records = provenance::read(&cargo_toml);
+        assert_eq!(
+            records.iter().map(|r| r.group.as_str()).collect::<Vec<_>>(),
+            ["cairo", "cairols"]
+        );
+        assert_eq!(records[0].version, Some("2.9.2".parse().unwrap()));
+        assert_eq!(records[1].source, "git");
+        assert_eq!(records[1].rev.as_deref(), Some("abcdef"));
+        assert_eq!(records[1].version, None);
+        assert_eq!(
+            cargo_toml["workspace"]["dependencies"]["cairo-language-server"].as_str(),
+            Some("*")
+        );
+    }
+
+    #[test]
+    fn test_cargo_override() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--cargo",
+            "/opt/rust/bin/cargo",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        assert_eq!(
+            ctx.recorded(),
+            [
+                "/opt/rust/bin/cargo fetch --offline",
+                "/opt/rust/bin/cargo xtask sync-version"
+            ]
+        );
+
+        let args = Args::parse_from(["upgrade", "--rollback", "--cargo", "cargo-nightly"]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        assert_eq!(ctx.recorded(), ["cargo-nightly fetch --offline"]);
+    }
+
+    #[test]
+    fn test_rust_toolchain() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--rust-toolchain",
+            "nightly",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        assert_eq!(
+            ctx.recorded(),
+            [
+                "cargo +nightly fetch --offline",
+                "cargo +nightly xtask sync-version"
+            ]
+        );
+
+        let args = Args::parse_from([
+            "upgrade",
+            "--rollback",
+            "--rust-toolchain",
+            "nightly",
+            "--cargo",
+            "cargo-nightly",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        assert_eq!(ctx.recorded(), ["cargo-nightly +nightly fetch --offline"]);
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--rust-toolchain",
+            "nightly",
+            "--cargo",
+            "/opt/rust/bin/cargo",
+        ]);
+        assert_eq!(
+            Context::with_shell(sh.clone(), &args.global)
+                .err()
+                .unwrap()
+                .to_string(),
+            "--rust-toolchain cannot be used with --cargo /opt/rust/bin/cargo, `+nightly` is only \
+            understood by the rustup proxy, pass the path of the toolchain's Cargo alone"
+        );
+        let args = Args::parse_from(["upgrade", "--rollback", "--rust-toolchain", "+nightly"]);
+        assert!(Context::with_shell(sh.clone(), &args.global).is_err());
+    }
+
+    #[test]
+    fn test_no_sync_version() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let cargo_toml = "[dependencies]\n\n[patch.crates-io]\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        apply(
+            &ctx,
+            cargo_toml.clone(),
+            LineEnding::Lf,
+            std::slice::from_ref(&args),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            ctx.recorded(),
+            ["cargo fetch --offline", "cargo xtask sync-version"]
+        );
+
+        let ctx = Context::recording(sh);
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--no-sync-version"]);
+        apply(
+            &ctx,
+            cargo_toml,
+            LineEnding::Lf,
+            std::slice::from_ref(&args),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+    }
+
+    #[test]
+    fn test_synced_version_follows_configured_anchor() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"2.9.2\"\n",
+        )
+        .unwrap();
+        sh.write_file("Scarb.toml", "[package]\ncairo-version = \"=2.9.1\"\n")
+            .unwrap();
+        let cargo_toml = "[package]\nname = \"foo\"\nversion = \"2.9.1\"\n\n\
+            [package.metadata.cairo-toolchain-xtasks]\nanchor = \"scarb-toml\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+
+        let ctx = Context::recording(sh);
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        let (_, _, synced) = apply(
+            &ctx,
+            cargo_toml,
+            LineEnding::Lf,
+            std::slice::from_ref(&args),
+            &[],
+        )
+        .unwrap();
+        let synced = synced.unwrap();
+        assert_eq!(synced.anchor, sync_version::Anchor::ScarbToml);
+        assert_eq!(synced.compiler_version, Version::new(2, 9, 1));
+        assert!(!synced.adjusted());
+    }
+
+    #[test]
+    fn test_sync_version_forwards_global_args() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let root = temp_dir.path().join("rust");
+        sh.create_dir(&root).unwrap();
+        sh.write_file(root.join("Cargo.toml"), "[workspace]\n")
+            .unwrap();
+        sh.write_file(temp_dir.path().join("Cargo.lock"), "version = 4\n")
+            .unwrap();
+        sh.change_dir(temp_dir.path());
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "2.9.0",
+            "-C",
+            "rust",
+            "--lockfile-path",
+            "Cargo.lock",
+            "--confirm-network",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        apply(
+            &ctx,
+            DocumentMut::new(),
+            LineEnding::Lf,
+            std::slice::from_ref(&args),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            ctx.recorded(),
+            [
+                "cargo fetch".to_owned(),
+                format!(
+                    "cargo xtask sync-version --manifest-path {} --lockfile-path {} \
+                     --confirm-network",
+                    root.join("Cargo.toml").display(),
+                    temp_dir.path().join("Cargo.lock").display()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migrate_renamed_crates() {
+        let mut cargo_toml = r#"
+[dependencies]
+cairo-lint-core = { version = "2.11.0", optional = true }
+lint-alias = { package = "cairo-lint-core", version = "2.11.0" }
+
+[features]
+lint = ["dep:cairo-lint-core", "cairo-lint-core?/foo"]
+
+[patch.crates-io]
+cairo-lint-core = { git = "https://github.com/software-mansion/cairo-lint" }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = parse_args(["upgrade", "cairolint", "2.12.0"]);
+
+        let migrations = migrate_renamed_crates(&mut cargo_toml, &args);
+        assert_eq!(migrations.len(), 2);
+        edit_dependencies(&mut cargo_toml, &args);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"
+[dependencies]
+cairo-lint = { version = "2.12.0", optional = true }
+lint-alias = { package = "cairo-lint", version = "2.12.0" }
+
+[features]
+lint = ["dep:cairo-lint", "cairo-lint?/foo"]
+"#
+        );
+        assert_eq!(args.tool_anchor(), "cairo-lint");
+
+        // Downgrading across the boundary restores the old name.
+        let args = parse_args(["upgrade", "cairolint", "2.11.0"]);
+        assert_eq!(migrate_renamed_crates(&mut cargo_toml, &args).len(), 2);
+        assert!(cargo_toml["dependencies"].get("cairo-lint-core").is_some());
+        assert_eq!(
+            cargo_toml["features"]["lint"]
+                .as_array()
+                .unwrap()
+                .get(0)
+                .unwrap()
+                .as_str(),
+            Some("dep:cairo-lint-core")
+        );
+    }
+
+    #[test]
+    fn test_git_upgrade_is_idempotent() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        // As left by a previous git upgrade, with hand-edited formatting.
+        let patched = r#"[workspace.dependencies]
+cairo-language-server = "*"
+
+[workspace.metadata.cairo-toolchain.cairols]
+source = "git"
+repo = "https://github.com/software-mansion/cairols"
+rev = "1a2b3c4"
+
+[dependencies]
+cairo-language-server = {   version = "*",features = [ "testing" ]  }
+
+[dev-dependencies.cairo-language-server]
+version = "*"
+features = ["testing",  "debug"]
+
+[build-dependencies]
+cairo-language-server.version = "*"
+cairo-language-server.features = ["debug"]
+
+[patch.crates-io]
+cairo-language-server = { git = "https://github.com/software-mansion/cairols", rev = "1a2b3c4" }
+"#;
+        sh.write_file("Cargo.toml", patched).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let upgrade = || {
+            let args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--no-sync-version",
+            ];
+            run(&Context::recording(sh.clone()), Args::parse_from(args)).unwrap();
+            sh.read_file("Cargo.toml").unwrap()
+        };
+
+        assert_eq!(upgrade(), patched);
+        assert_eq!(upgrade(), patched);
+    }
+
+    #[test]
+    fn test_verify_version() {
+        struct LanguageServerIndex;
+
+        impl Http for LanguageServerIndex {
+            fn get(&self, url: &str) -> Result<String> {
+                ensure!(
+                    url.ends_with("/cairo-language-server"),
+                    "unexpected request to {url}"
+                );
+                Ok([
+                    ("2.8.0", false),
+                    ("2.8.1", true),
+                    ("2.8.2", false),
+                    ("2.9.0", false),
+                    ("2.9.1", false),
+                    ("2.10.0", false),
+                ]
+                .map(|(version, yanked)| {
+                    format!(
+                        r#"{{"name":"cairo-language-server","vers":"{version}","deps":[],"yanked":{yanked}}}"#
+                    )
+                })
+                .join("\n"))
+            }
+        }
+
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let index = Index::new(&IndexArgs::default(), &cache, &LanguageServerIndex);
+        let cargo_toml = DocumentMut::new();
+        let discover = |args: &[&str]| {
+            let group = parse_args([&["upgrade", "cairols"], args].concat());
+            super::discover(&group, &cargo_toml, None, &index, &MockRemote)
+        };
+        let err = |args: &[&str]| format!("{:#}", discover(args).err().unwrap());
+
+        assert!(discover(&["2.9.0"]).unwrap().warnings.is_empty());
+        assert_eq!(
+            err(&["2.9.9"]),
+            "cairo-language-server 2.9.9 is not published on crates.io, the closest published \
+            versions are 2.9.0, 2.9.1, 2.10.0"
+        );
+        assert_eq!(
+            err(&["2.8.1"]),
+            "cairo-language-server 2.8.1 has been yanked from crates.io, the closest published \
+            versions are 2.8.0, 2.8.2, 2.9.0"
+        );
+        assert_eq!(
+            discover(&["2.9.9", "--dry-run"]).unwrap().warnings,
+            [
+                "cairo-language-server 2.9.9 is not published on crates.io, the closest published \
+            versions are 2.9.0, 2.9.1, 2.10.0"
+            ]
+        );
+        assert!(discover(&["2.9.9", "--no-verify"])
+            .unwrap()
+            .warnings
+            .is_empty());
+
+        // Without the index, the version cannot be checked.
+        let cache = Cache::new(temp_dir.path().join("empty"));
+        let index = Index::new(&IndexArgs::default(), &cache, &MockIndex);
+        let group = parse_args(["upgrade", "cairols", "2.9.0"]);
+        let err = super::discover(&group, &cargo_toml, None, &index, &MockRemote).err();
+        assert_eq!(
+            format!("{:#}", err.unwrap()),
+            "cannot check that cairo-language-server 2.9.0 is published on crates.io, pass \
+            --no-verify to skip the check: failed to look up `cairo-language-server` in the \
+            crates.io index: unexpected request to https://index.crates.io/ca/ir/cairo-language-server"
+        );
+    }
+
+    #[test]
+    fn test_validate_revs() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let index = Index::new(&IndexArgs::default(), &cache, &MockIndex);
+        let cargo_toml = DocumentMut::new();
+        let discover = |args: &[&str]| {
+            let group = Args::parse_from(args);
+            discover(&group, &cargo_toml, None, &index, &MockRemote).map(|_| ())
+        };
+
+        discover(&[
+            "upgrade",
+            "cairols",
+            "--branch",
+            "release/2.9",
+            "--validate-revs",
+        ])
+        .unwrap();
+        assert_eq!(
+            discover(&[
+                "upgrade",
+                "cairols",
+                "--branch",
+                "release/2.8",
+                "--validate-revs"
+            ])
+            .unwrap_err()
+            .to_string(),
+            "https://github.com/software-mansion/cairols has no branch `release/2.8`, did you \
+            mean `release/2.9`?"
+        );
+        // Without the flag, a missing branch is left to Cargo.
+        discover(&["upgrade", "cairols", "--branch", "release/2.8"]).unwrap();
+
+        let discover_rev = |rev: &str| {
+            let group = Args::parse_from(["upgrade", "cairols", "--rev", rev, "--validate-revs"]);
+            super::discover(&group, &cargo_toml, None, &index, &MockRemote)
+        };
+        let discovered = discover_rev("0b3bfdc").unwrap();
+        assert_eq!(discovered.group.spec.commit.as_deref(), Some(BRANCH_HEAD));
+        assert!(discovered.warnings.is_empty());
+        assert_eq!(
+            discover_rev("1a2b3c4").err().unwrap().to_string(),
+            "rev 1a2b3c4 not found in https://github.com/software-mansion/cairols, no branch or \
+            tag points to it"
+        );
+        // Without the flag, commits are left to Cargo.
+        discover(&["upgrade", "cairols", "--rev", "1a2b3c4"]).unwrap();
+    }
+
+    #[test]
+    fn test_latest_on_branch() {
+        struct Offline;
+
+        impl Remote for Offline {
+            fn ls_remote(&self, _: &str, _: &[String]) -> Result<String> {
+                bail!("could not resolve host: github.com")
+            }
+        }
+
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let cache = Cache::new(temp_dir.path().to_path_buf());
+        let index = Index::new(&IndexArgs::default(), &cache, &MockIndex);
+        let cargo_toml = DocumentMut::new();
+        let args = Args::parse_from(["upgrade", "cairols", "--branch", "main", "--latest"]);
+
+        let discovered = discover(&args, &cargo_toml, None, &index, &MockRemote).unwrap();
+        assert_eq!(
+            discovered.notes,
+            [format!(
+                "using commit {BRANCH_HEAD} at the tip of branch `main` of cairols"
+            )]
+        );
+        assert!(discovered.warnings.is_empty());
+        let mut patched = cargo_toml.clone();
+        edit_patch(&mut patched, &discovered.group).unwrap();
+        assert_eq!(
+            patched["patch"]["crates-io"].to_string(),
+            format!(
+                "cairo-language-server = {{ git = \"https://github.com/software-mansion/cairols\", \
+                rev = \"{BRANCH_HEAD}\" }}\n"
+            )
+        );
+
+        let discovered = discover(&args, &cargo_toml, None, &index, &Offline).unwrap();
+        assert_eq!(
+            discovered.warnings,
+            [
+                "cannot look up the tip of branch `main` of cairols, keeping the floating branch \
+            instead: could not resolve host: github.com"
+            ]
+        );
+        assert_eq!(discovered.group.spec.branch.as_deref(), Some("main"));
+
+        // Only unreachable repositories keep the branch, missing branches fail.
+        let args = Args::parse_from(["upgrade", "cairols", "--branch", "mian", "--latest"]);
+        assert_eq!(
+            discover(&args, &cargo_toml, None, &index, &MockRemote)
+                .err()
+                .unwrap()
+                .to_string(),
+            "https://github.com/software-mansion/cairols has no branch `mian`"
+        );
+
+        assert!(Args::try_parse_from(["upgrade", "cairols", "--branch", "--latest"]).is_ok());
+        assert!(Args::try_parse_from([
+            "upgrade",
+            "cairols",
+            "--branch",
+            "main",
+            "--latest",
+            "--prerelease",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_stale_crates() {
+        let cargo_toml = r#"
+[dependencies]
+cairo-language-server = "2.8.0"
+
+[dev-dependencies]
+cairo-language-server-test-utils = "2.8.0"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+
+        let previous = ["cairo-language-server".to_owned()];
+        assert!(check_stale_crates(Path::new("Cargo.toml"), &cargo_toml, &args, &previous).is_ok());
+
+        let previous = [
+            "cairo-language-server".to_owned(),
+            "cairo-language-server-test-utils".to_owned(),
+        ];
+        let err =
+            check_stale_crates(Path::new("Cargo.toml"), &cargo_toml, &args, &previous).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cairo-language-server-test-utils (Cargo.toml [dev-dependencies])"));
+    }
+
+    #[test]
+    fn test_check_strict_group() {
+        let cargo_toml = r#"
+[workspace.dependencies]
+cairo-lang-compiler = "2.9.0"
+
+[dev-dependencies]
+utils = { package = "cairo-lang-utils", version = "2.9.0" }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let crates = [
+            "cairo-lang-compiler",
+            "cairo-lang-parser",
+            "cairo-lang-utils",
+            "cairo-lang-syntax",
+        ];
+
+        let args = parse_args(["upgrade", "cairo", "2.9.2", "--strict-group"]);
+        assert_eq!(
+            check_strict_group(&cargo_toml, &args, &crates)
+                .unwrap_err()
+                .to_string(),
+            "--strict-group: Cargo.toml does not depend on 2 crates of cairo:\n  \
+            cairo-lang-parser\n  cairo-lang-syntax"
+        );
+        assert!(check_strict_group(&cargo_toml, &args, &crates[..1]).is_ok());
+        assert_eq!(
+            check_strict_group(&cargo_toml, &args, &crates[..2])
+                .unwrap_err()
+                .to_string(),
+            "--strict-group: Cargo.toml does not depend on 1 crate of cairo:\n  cairo-lang-parser"
+        );
+
+        // Crates which are not selected are not required.
+        let args = Args::parse_from([
+            "upgrade",
+            "cairo",
+            "2.9.2",
+            "--strict-group",
+            "--exclude-regex",
+            "parser|syntax",
+        ]);
+        assert!(check_strict_group(&cargo_toml, &args, &crates).is_ok());
+    }
+
+    #[test]
+    fn test_lockfile_only() {
+        let cargo_lock = r#"version = 4
+
+[[package]]
+name = "cairo-lang-compiler"
+version = "2.9.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "cairo-lang-macro"
+version = "0.1.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "cairo-lang-utils"
+version = "2.9.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "anyhow"
+version = "1.0.95"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let cargo_toml = r#"
+[dependencies]
+cairo-lang-compiler = "2.9.1"
+cairo-lang-macro = "0.1"
+utils = { package = "cairo-lang-utils", version = "~2.9" }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let requirements = requirements(Path::new("."), &cargo_toml).unwrap();
+        let published = || {
+            Ok(["2.9.1", "2.9.2", "2.9.3", "2.10.0", "3.0.0-rc.0"]
+                .map(|v| (v.parse().unwrap(), v == "2.9.3"))
+                .to_vec())
+        };
+
+        let args = Args::parse_from(["upgrade", "cairo", "--lockfile-only"]);
+        let plan = LockPlan::new(&cargo_lock, &args, &requirements, |anchor| {
+            assert_eq!(anchor, "cairo-lang-compiler");
+            published()
+        })
+        .unwrap();
+        assert_eq!(plan.target, Version::new(2, 9, 2));
+        assert_eq!(
+            plan.moves
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "cairo-lang-compiler 2.9.1 -> 2.9.2",
+                "cairo-lang-macro 0.1.0 -> newest compatible",
+                "cairo-lang-utils 2.9.1 -> 2.9.2",
+            ]
+        );
+        assert_eq!(
+            plan.moves[0].cargo_args(),
+            ["-p", "cairo-lang-compiler@2.9.1", "--precise", "2.9.2"]
+        );
+        assert_eq!(plan.moves[1].cargo_args(), ["-p", "cairo-lang-macro@0.1.0"]);
+
+        let err = plan.verify(&cargo_lock).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "cairo crates did not all move to 2.9.2: cairo-lang-compiler 2.9.1, cairo-lang-utils 2.9.1"
+        );
+        let updated = cargo_lock.to_string().replace("2.9.1", "2.9.2");
+        plan.verify(&updated.parse().unwrap()).unwrap();
+
+        let args = parse_args(["upgrade", "cairo", "2.9.3", "--lockfile-only"]);
+        let plan = LockPlan::new(&cargo_lock, &args, &requirements, |_| unreachable!()).unwrap();
+        assert_eq!(plan.target, Version::new(2, 9, 3));
+    }
+
+    #[test]
+    fn test_build_and_target_dependencies() {
+        let original = r#"[build-dependencies]
+cairo-language-server = "2.8.0"
+
+[target.'cfg(unix)'.dependencies]
+cairo-language-server = { version = "2.8.0", optional = true }
+
+[target.x86_64-pc-windows-msvc.dev-dependencies]
+cairo-language-server = { git = "https://github.com/software-mansion/cairols" }
+"#;
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[build-dependencies]
+cairo-language-server = "2.9.0"
+
+[target.'cfg(unix)'.dependencies]
+cairo-language-server = { version = "2.9.0", optional = true }
+
+[target.x86_64-pc-windows-msvc.dev-dependencies]
+cairo-language-server = "2.9.0"
+"#
+        );
+    }
+
+    #[test]
+    fn test_no_sort() {
+        let original = r#"[dependencies]
+serde = "1"
+# The next release.
+ls-next = { package = "cairo-language-server", version = "2.8.0" }
+anyhow = "1"
+LS = { package = "cairo-language-server", version = "2.8.0" } # Keep in sync with Scarb.
+"#;
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--no-sort"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(cargo_toml.to_string(), original.replace("2.8.0", "2.9.0"));
+
+        // Otherwise, only crates of the group swap places.
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[dependencies]
+serde = "1"
+LS = { package = "cairo-language-server", version = "2.9.0" } # Keep in sync with Scarb.
+anyhow = "1"
+# The next release.
+ls-next = { package = "cairo-language-server", version = "2.9.0" }
+"#
+        );
+    }
+
+    #[test]
+    fn test_keeps_unrelated_dependencies_in_place() {
+        let original = r#"[dependencies]
+# Everything else, out of order.
+serde = "1"
+anyhow = { version = "1", features = [ "backtrace" ] }
+
+clap="4"
+cairo-language-server = "2.8.0" # Keep in sync with Scarb.
+
+# Tracing.
+tracing = "0.1"
+
+[dependencies.zip]
+version = "2"
+"#;
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(cargo_toml.to_string(), original.replace("2.8.0", "2.9.0"));
+    }
+
+    #[test]
+    fn test_sort_case_insensitive() {
+        let original = "[dependencies]\nserde = \"1\"\n\
+            ls = { package = \"cairo-language-server\", version = \"2.8.0\" }\n\
+            Inflector = \"0.11\"\nLS-next = { package = \"cairo-language-server\", version = \"2.8.0\" }\n";
+        let sorted = |flags: &[&str]| {
+            let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+            let args = Args::parse_from([&["upgrade", "cairols", "2.9.0"], flags].concat());
+            edit_dependencies(&mut cargo_toml, &args);
+            cargo_toml["dependencies"]
+                .as_table_like()
+                .unwrap()
+                .iter()
+                .map(|(key, _)| key.to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(&[]), ["serde", "LS-next", "Inflector", "ls"]);
+        assert_eq!(
+            sorted(&["--sort-case-insensitive"]),
+            ["serde", "ls", "Inflector", "LS-next"]
+        );
+    }
+
+    #[test]
+    fn test_exact_version() {
+        let mut cargo_toml = r#"
+[dependencies]
+cairo-language-server = "2.8.0"
+
+[dev-dependencies]
+cairo-language-server = { version = "2.8.0", features = ["testing"] }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--exact"]);
+
+        edit_dependencies(&mut cargo_toml, &args);
+
+        assert_eq!(
+            cargo_toml["dependencies"]["cairo-language-server"].as_str(),
+            Some("=2.9.0")
+        );
+        assert_eq!(
+            cargo_toml["dev-dependencies"]["cairo-language-server"]["version"].as_str(),
+            Some("=2.9.0")
+        );
+
+        let args = parse_args(["upgrade", "cairols", "2.9.1"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(
+            cargo_toml["dependencies"]["cairo-language-server"].as_str(),
+            Some("2.9.1")
+        );
+    }
+
+    #[test]
+    fn test_crlf_manifest_keeps_line_endings() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file("Cargo.lock", "version = 4\r\n").unwrap();
+
+        let original = "[package]\r\nname = \"foo\"\r\n\r\n[dependencies]\r\nanyhow = \"1\"\r\ncairo-language-server = \"2.8.0\"\r\n\r\n[patch.crates-io]\r\n";
+        let eol = LineEnding::detect(original);
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--no-sync-version"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        apply(&ctx, cargo_toml, eol, std::slice::from_ref(&args), &[]).unwrap();
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            original.replace("2.8.0", "2.9.0")
+        );
+    }
+
+    #[test]
+    fn test_patch_with_version() {
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+
+        let mut cargo_toml = original.clone();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--rev", "v2.9.0"]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"].to_string(),
+            "cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", rev = \
+            \"v2.9.0\" }\n"
+        );
+
+        let mut cargo_toml = original.clone();
+        let args = parse_args([
+            "upgrade",
+            "cairols",
+            "2.9.0",
+            "--rev",
+            "v2.9.0",
+            "--patch-with-version",
+        ]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"].to_string(),
+            "cairo-language-server = { version = \"2.9.0\", git = \
+            \"https://github.com/software-mansion/cairols\", rev = \"v2.9.0\" }\n"
+        );
+
+        // Path sources are matched by the package found there, so they get no version.
+        let mut cargo_toml = original.clone();
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--path",
+            "../cairols",
+            "--patch-with-version",
+        ]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"].to_string(),
+            "{ path = \"../cairols/crates/cairo-language-server\" }"
+        );
+
+        let mut cargo_toml = original;
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--branch",
+            "main",
+            "--patch-with-version",
+        ]);
+        assert_eq!(
+            edit_patch(&mut cargo_toml, &args).unwrap_err().to_string(),
+            "--patch-with-version needs the version of cairols to write into [patch.crates-io], \
+            pass it along with the git source, e.g. `cargo xtask upgrade cairols <VERSION> --rev \
+            <REV>`"
+        );
+    }
+
+    #[test]
+    fn test_on_conflict() {
+        let original = r#"[dependencies]
+cairo-language-server = "2.8.0"
+
+[patch.crates-io]
+cairo-language-server = { git = "https://github.com/someone/cairols", branch = "fix" }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let edit = |policy: &str| {
+            let mut cargo_toml = original.clone();
+            let args = Args::parse_from([
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--on-conflict",
+                policy,
+            ]);
+            edit_patch(&mut cargo_toml, &args)
+                .map(|warnings| (cargo_toml["patch"]["crates-io"].to_string(), warnings))
+        };
+
+        assert_eq!(
+            edit("ours").unwrap(),
+            (
+                "cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+                rev = \"1a2b3c4\" }\n"
+                    .to_owned(),
+                vec![]
+            )
+        );
+        assert_eq!(
+            edit("theirs").unwrap(),
+            (
+                original["patch"]["crates-io"].to_string(),
+                vec![
+                    "keeping patches of cairo-language-server in [patch.crates-io] written by hand"
+                        .to_owned()
+                ]
+            )
+        );
+        assert_eq!(
+            edit("error").unwrap_err().to_string(),
+            "[patch.crates-io] has patches of cairols crates written by hand, pass --on-conflict \
+            ours to replace them or --on-conflict theirs to keep them:\n  cairo-language-server = \
+            { git = \"https://github.com/someone/cairols\", branch = \"fix\" }"
+        );
+
+        // Entries written by a previous upgrade are replaced regardless of the policy.
+        let mut cargo_toml = r#"[dependencies]
+cairo-language-server = "2.8.0"
+
+[patch.crates-io]
+cairo-language-server = { git = "https://github.com/software-mansion/cairols.git", rev = "0b3bfdc" }
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--on-conflict",
+            "error",
+        ]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"]["rev"].as_str(),
+            Some("1a2b3c4")
+        );
+    }
+
+    #[test]
+    fn test_patch_table_lifecycle() {
+        let patched = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", rev = \
+            \"1a2b3c4\" }\n";
+        let git = Args::parse_from(["upgrade", "cairols", "--rev", "1a2b3c4"]);
+        let registry = parse_args(["upgrade", "cairols", "2.9.0"]);
+
+        let mut cargo_toml = "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        edit_patch(&mut cargo_toml, &git).unwrap();
+        assert_eq!(cargo_toml.to_string(), patched);
+
+        let mut cargo_toml = format!("{patched}foo = {{ path = \"../foo\" }}\n")
+            .parse::<DocumentMut>()
+            .unwrap();
+        edit_patch(&mut cargo_toml, &registry).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n\
+            foo = { path = \"../foo\" }\n"
+        );
+
+        let mut cargo_toml = patched.parse::<DocumentMut>().unwrap();
+        edit_patch(&mut cargo_toml, &registry).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+        let mut cargo_toml = patched.parse::<DocumentMut>().unwrap();
+        let keep = parse_args(["upgrade", "cairols", "2.9.0", "--keep-empty-patch-table"]);
+        edit_patch(&mut cargo_toml, &keep).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n"
+        );
+
+        // Other sources of `[patch]` are kept.
+        let mut cargo_toml = format!(
+            "{patched}\n[patch.\"https://github.com/someone/foo\"]\nfoo = {{ path = \"../foo\" }}\n"
+        )
+        .parse::<DocumentMut>()
+        .unwrap();
+        edit_patch(&mut cargo_toml, &registry).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n\
+            [patch.\"https://github.com/someone/foo\"]\nfoo = { path = \"../foo\" }\n"
+        );
+
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[patch.unused]]\nname = \"cairo-language-server\"\n\
+            version = \"2.9.0\"\n",
+        )
+        .unwrap();
+        let mut cargo_toml = patched.parse::<DocumentMut>().unwrap();
+        let purged = purge_unused_patches(&sh, Path::new("Cargo.lock"), &mut cargo_toml, &[git]);
+        assert_eq!(purged.unwrap(), ["cairo-language-server"]);
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+        let mut cargo_toml = patched.parse::<DocumentMut>().unwrap();
+        let keep = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--keep-empty-patch-table",
+        ]);
+        let purged = purge_unused_patches(&sh, Path::new("Cargo.lock"), &mut cargo_toml, &[keep]);
+        assert_eq!(purged.unwrap(), ["cairo-language-server"]);
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n"
+        );
+    }
+
+    #[test]
+    fn test_patch_forms() {
+        let patched = "[patch.crates-io]\ncairo-language-server = { git = \
+            \"https://github.com/software-mansion/cairols\", rev = \"1a2b3c4\" }\n";
+        let upgrade = |original: &str, args: &[&str]| {
+            let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+            edit_patch(&mut cargo_toml, &Args::parse_from(args)).unwrap();
+            cargo_toml.to_string()
+        };
+        let git = ["upgrade", "cairols", "--rev", "1a2b3c4"];
+        let registry = ["upgrade", "cairols", "2.9.0"];
+
+        let sub_table = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n\
+            [patch.crates-io.cairo-language-server]\n\
+            git = \"https://github.com/software-mansion/cairols\"\nrev = \"0b3bfdc\"\n\n\
+            [patch.crates-io.foo]\npath = \"../foo\"\n";
+        assert_eq!(
+            upgrade(sub_table, &git),
+            format!(
+                "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n{patched}\n\
+                [patch.crates-io.foo]\npath = \"../foo\"\n"
+            )
+        );
+        assert_eq!(
+            upgrade(sub_table, &registry),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n\
+            [patch.crates-io.foo]\npath = \"../foo\"\n"
+        );
+
+        let dotted = "patch.crates-io.cairo-language-server = { git = \
+            \"https://github.com/software-mansion/cairols\", rev = \"0b3bfdc\" }\n\n\
+            [dependencies]\ncairo-language-server = \"2.8.0\"\n";
+        assert_eq!(
+            upgrade(dotted, &git),
+            format!("{patched}\n[dependencies]\ncairo-language-server = \"2.8.0\"\n")
+        );
+        assert_eq!(
+            upgrade(dotted, &registry),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+
+        let dotted_in_patch = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch]\n\
+            crates-io.cairo-language-server.git = \"https://github.com/software-mansion/cairols\"\n\
+            crates-io.cairo-language-server.rev = \"0b3bfdc\"\n";
+        assert_eq!(
+            upgrade(dotted_in_patch, &git),
+            format!("[dependencies]\ncairo-language-server = \"2.8.0\"\n\n{patched}")
+        );
+        assert_eq!(
+            upgrade(dotted_in_patch, &registry),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+
+        // The canonical form is left as it is.
+        let canonical = format!("[dependencies]\ncairo-language-server = \"2.8.0\"\n\n{patched}");
+        assert_eq!(upgrade(&canonical, &git), canonical);
+
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[patch.unused]]\nname = \"cairo-language-server\"\n\
+            version = \"2.9.0\"\n",
+        )
+        .unwrap();
+        for original in [sub_table, dotted, dotted_in_patch] {
+            let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+            let groups = [Args::parse_from(git)];
+            let purged =
+                purge_unused_patches(&sh, Path::new("Cargo.lock"), &mut cargo_toml, &groups);
+            assert_eq!(purged.unwrap(), ["cairo-language-server"]);
+            assert!(!cargo_toml.to_string().contains("cairols"), "{cargo_toml}");
+        }
+    }
+
+    #[test]
+    fn test_path_from_env() {
+        let mut cargo_toml =
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n"
+                .parse::<DocumentMut>()
+                .unwrap();
+
+        let mut args = Args::parse_from(["upgrade", "cairols"]);
+        let mut warnings = Vec::new();
+        assert!(args
+            .spec_from_env(&GroupConfig::default(), |_| None, &mut warnings)
+            .is_err());
+        args.spec_from_env(
+            &GroupConfig::default(),
+            |key| (key == "CAIRO_XTASKS_PATH_CAIROLS").then(|| "../cairols".into()),
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(
+            warnings,
+            ["using path ../cairols from CAIRO_XTASKS_PATH_CAIROLS, avoid committing path sources \
+            to the repository"]
+        );
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"]["path"].as_str(),
+            Some("../cairols/crates/cairo-language-server")
+        );
+
+        // Command line arguments take precedence.
+        let mut args = Args::parse_from(["upgrade", "cairols", "--branch", "main"]);
+        args.spec_from_env(
+            &GroupConfig::default(),
+            |_| Some("../cairols".into()),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(args.spec.path, None);
+    }
+
+    #[test]
+    fn test_upgrade_other_directory() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let workspace = temp_dir.path().join("workspace");
+        sh.write_file(
+            workspace.join("Cargo.toml"),
+            "[workspace]\n\n[workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(workspace.join("Cargo.lock"), "version = 4\n")
+            .unwrap();
+        sh.write_file(
+            temp_dir
+                .path()
+                .join("cairols/crates/cairo-language-server/Cargo.toml"),
+            "",
+        )
+        .unwrap();
+        sh.change_dir(temp_dir.path());
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--path",
+            "cairols",
+            "-C",
+            "workspace",
+            "--no-sync-version",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+        let cargo_toml = sh
+            .read_file(workspace.join("Cargo.toml"))
+            .unwrap()
+            .parse::<DocumentMut>()
+            .unwrap();
+        assert_eq!(
+            cargo_toml["workspace"]["dependencies"]["cairo-language-server"].as_str(),
+            Some("*")
+        );
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"]["path"].as_str(),
+            temp_dir
+                .path()
+                .join("cairols/crates/cairo-language-server")
+                .to_str()
+        );
+    }
+
+    #[test]
+    fn test_crate_selects_group() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "--crate",
+            "cairo-language-server",
+            "--rev",
+            "1a2b3c4",
+            "--no-sync-version",
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+        let (_, stderr) = ctx.captured();
+        assert_eq!(stderr[0], "note: cairo-language-server belongs to cairols");
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains(
+                "cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+                rev = \"1a2b3c4\" }"
+            ),
+            "{cargo_toml}"
+        );
+
+        assert_eq!(
+            DepName::candidates_of_crate("cairo-lint-core").unwrap(),
+            [DepName::CairoLint]
+        );
+        assert_eq!(
+            DepName::candidates_of_crate("cairo-lang-compiler").unwrap(),
+            [DepName::Cairo]
+        );
+        assert_eq!(
+            DepName::candidates_of_crate("scarb-ui").unwrap(),
+            [DepName::Scarb]
+        );
+        assert_eq!(
+            DepName::candidates_of_crate("starknet")
+                .unwrap_err()
+                .to_string(),
+            "starknet does not belong to any toolchain dependency group, expected a \
+            `cairo-lang-*` crate, cairo-language-server, cairo-lint or a `scarb-*` crate"
+        );
+        assert!(Args::try_parse_from(["upgrade", "cairols", "--crate", "cairo-lint"]).is_err());
+    }
+
+    #[test]
+    fn test_from_github_release() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "release.json",
+            r#"{
+                "action": "published",
+                "release": { "tag_name": "v2.9.2", "draft": false },
+                "repository": { "html_url": "https://github.com/software-mansion/cairols" }
+            }"#,
+        )
+        .unwrap();
+        sh.write_file("untold.json", r#"{ "release": { "tag_name": "v2.9.2" } }"#)
+            .unwrap();
+        let apply = |args: &[&str]| {
+            let ctx = Context::recording(sh.clone());
+            let mut args = Args::parse_from(args);
+            let path = args.from_github_release.clone().unwrap();
+            apply_github_release(&ctx, &mut args, &path)
+                .map(|()| (args.dep, args.spec.rev.unwrap(), ctx.captured().1))
+                .map_err(|err| format!("{err:#}"))
+        };
+
+        assert_eq!(
+            apply(&["upgrade", "--from-github-release", "release.json"]).unwrap(),
+            (
+                vec![DepName::CairoLS],
+                "v2.9.2".to_owned(),
+                vec!["note: using tag v2.9.2 of cairols from the GitHub release".to_owned()]
+            )
+        );
+        assert_eq!(
+            apply(&[
+                "upgrade",
+                "cairols",
+                "--from-github-release",
+                "release.json"
+            ])
+            .unwrap()
+            .1,
+            "v2.9.2"
+        );
+        assert_eq!(
+            apply(&[
+                "upgrade",
+                "cairolint",
+                "--from-github-release",
+                "release.json"
+            ])
+            .unwrap_err(),
+            "release v2.9.2 is of cairols, not of cairolint"
+        );
+        assert_eq!(
+            apply(&[
+                "upgrade",
+                "cairolint",
+                "--from-github-release",
+                "untold.json"
+            ])
+            .unwrap()
+            .0,
+            [DepName::CairoLint]
+        );
+        assert_eq!(
+            apply(&["upgrade", "--from-github-release", "untold.json"]).unwrap_err(),
+            "the payload does not tell the repository of release v2.9.2, name the group to upgrade"
+        );
+
+        sh.write_file("release.json", r#"{ "release": { "draft": true } }"#)
+            .unwrap();
+        let err = apply(&["upgrade", "--from-github-release", "release.json"]).unwrap_err();
+        assert!(
+            err.starts_with("invalid GitHub release payload ")
+                && err.ends_with(
+                    "release.json: the payload has no `release.tag_name`, expected a GitHub \
+                    release event"
+                ),
+            "{err}"
+        );
+        assert!(Args::try_parse_from([
+            "upgrade",
+            "cairols",
+            "2.9.0",
+            "--from-github-release",
+            "release.json",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_apply_to_dependabot() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        // Dependabot bumped the workspace dependency only.
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\n\n[workspace.dependencies]\n\
+            cairo-language-server = \"2.9.0\"\n\n[dev-dependencies]\n\
+            cairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+            rev = \"1a2b3c4\" }\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "--apply-to-dependabot",
+                "cairo-language-server@2.9.0",
+                "--no-sync-version",
+            ],
+        );
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+        let (_, stderr) = ctx.captured();
+        assert_eq!(
+            stderr[..2],
+            [
+                "note: applying the update of cairo-language-server to 2.9.0 to its whole group",
+                "note: cairo-language-server belongs to cairols",
+            ]
+        );
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        for table in ["workspace.dependencies", "dev-dependencies"] {
+            assert!(
+                cargo_toml.contains(&format!("[{table}]\ncairo-language-server = \"2.9.0\"\n")),
+                "{cargo_toml}"
+            );
+        }
+        assert!(!cargo_toml.contains("[patch"), "{cargo_toml}");
+        for invalid in ["cairo-language-server", "cairo-language-server@2.9"] {
+            assert!(Args::try_parse_from(["upgrade", "--apply-to-dependabot", invalid]).is_err());
+        }
+        assert!(Args::try_parse_from([
+            "upgrade",
+            "--apply-to-dependabot",
+            "cairo-language-server@2.9.0",
+            "--rev",
+            "1a2b3c4",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_consolidate_patched_git_deps() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_toml = "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n\
+            [target.'cfg(unix)'.dependencies]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\" }\n\n\
+            [patch.crates-io]\n";
+        let member = "[package]\nname = \"foo\"\n\n[dependencies]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+            branch = \"main\", features = [\"testing\"] }\n";
+        let upgrade = |consolidate: bool| {
+            sh.write_file("Cargo.toml", cargo_toml).unwrap();
+            sh.write_file("crates/foo/Cargo.toml", member).unwrap();
+            sh.write_file("crates/bar/Cargo.toml", "[package]\nname = \"bar\"\n")
+                .unwrap();
+            sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+            // Dependency tables of the root and other members are not upgraded with `--package`.
+            let mut args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--package",
+                "bar",
+                "--no-sync-version",
+            ]
+            .to_vec();
+            args.extend(consolidate.then_some("--consolidate"));
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+            ctx.captured().1
+        };
+        let root = "Cargo.toml [target.'cfg(unix)'.dependencies] cairo-language-server depends on \
+            git https://github.com/software-mansion/cairols";
+        let member_dep = "crates/foo/Cargo.toml [dependencies] cairo-language-server depends on \
+            git https://github.com/software-mansion/cairols";
+
+        let stderr = upgrade(false);
+        for git_dep in [root, member_dep] {
+            assert!(
+                stderr.contains(&format!(
+                    "warn: {git_dep}, while [patch.crates-io] also patches it, pass --consolidate \
+                    to require `*` and rely on the patch"
+                )),
+                "{stderr:?}"
+            );
+        }
+        assert_eq!(sh.read_file("crates/foo/Cargo.toml").unwrap(), member);
+
+        let stderr = upgrade(true);
+        for git_dep in [root, member_dep] {
+            assert!(
+                stderr.contains(&format!(
+                    "note: {git_dep}, replaced it with `*` relying on [patch.crates-io]"
+                )),
+                "{stderr:?}"
+            );
+        }
+        assert!(sh
+            .read_file("Cargo.toml")
+            .unwrap()
+            .contains("[target.'cfg(unix)'.dependencies]\ncairo-language-server = \"*\"\n"));
+        assert_eq!(
+            sh.read_file("crates/foo/Cargo.toml").unwrap(),
+            "[package]\nname = \"foo\"\n\n[dependencies]\n\
+            cairo-language-server = { version = \"*\", features = [\"testing\"] }\n"
+        );
+
+        // Upgrading the whole workspace leaves the git dependencies to `--consolidate` as well.
+        let upgrade = |consolidate: bool| {
+            sh.write_file("Cargo.toml", cargo_toml).unwrap();
+            sh.write_file("crates/foo/Cargo.toml", member).unwrap();
+            let mut args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--no-sync-version",
+            ]
+            .to_vec();
+            args.extend(consolidate.then_some("--consolidate"));
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+            ctx.captured().1
+        };
+        let stderr = upgrade(false);
+        for git_dep in [root, member_dep] {
+            assert!(
+                stderr.contains(&format!(
+                    "warn: {git_dep}, while [patch.crates-io] also patches it, pass --consolidate \
+                    to require `*` and rely on the patch"
+                )),
+                "{stderr:?}"
+            );
+        }
+        assert!(sh.read_file("Cargo.toml").unwrap().contains(
+            "[target.'cfg(unix)'.dependencies]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\" }\n"
+        ));
+        assert_eq!(sh.read_file("crates/foo/Cargo.toml").unwrap(), member);
+
+        let stderr = upgrade(true);
+        for git_dep in [root, member_dep] {
+            assert!(
+                stderr.contains(&format!(
+                    "note: {git_dep}, replaced it with `*` relying on [patch.crates-io]"
+                )),
+                "{stderr:?}"
+            );
+        }
+        assert!(sh
+            .read_file("Cargo.toml")
+            .unwrap()
+            .contains("[target.'cfg(unix)'.dependencies]\ncairo-language-server = \"*\"\n"));
+        assert_eq!(
+            sh.read_file("crates/foo/Cargo.toml").unwrap(),
+            "[package]\nname = \"foo\"\n\n[dependencies]\n\
+            cairo-language-server = { version = \"*\", features = [\"testing\"] }\n"
+        );
+    }
+
+    #[test]
+    fn test_backup_ext() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let upgrade = |extra: &str| {
+            let args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--backup-ext",
+                "bak",
+                extra,
+            ];
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+        };
+
+        upgrade("--dry-run");
+        assert!(!sh.path_exists("Cargo.toml.bak"));
+
+        upgrade("--no-sync-version");
+        assert_eq!(sh.read_file("Cargo.toml.bak").unwrap(), original);
+        assert_ne!(sh.read_file("Cargo.toml").unwrap(), original);
+        // The timestamped backup is written along with it.
+        let backups = sh
+            .read_dir(".")
+            .unwrap()
+            .into_iter()
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("Cargo.toml.bak."))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), 1, "{backups:?}");
+        assert_eq!(sh.read_file(&backups[0]).unwrap(), original);
+
+        // Edited members are backed up too, untouched ones are not.
+        let root = "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        let member = "[dependencies]\ncairo-language-server = \"2.8.0\"\n";
+        sh.write_file("Cargo.toml", root).unwrap();
+        sh.write_file("crates/foo/Cargo.toml", member).unwrap();
+        sh.write_file("crates/bar/Cargo.toml", "[package]\nname = \"bar\"\n")
+            .unwrap();
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.0",
+                "--backup-ext",
+                "bak",
+                "--no-sync-version",
+            ],
+        );
+        run(&ctx, args).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml.bak").unwrap(), root);
+        assert_eq!(sh.read_file("crates/foo/Cargo.toml.bak").unwrap(), member);
+        assert!(!sh.path_exists("crates/bar/Cargo.toml.bak"));
+        let (_, stderr) = ctx.captured();
+        assert!(stderr.iter().any(
+            |line| line == "note: backed up crates/foo/Cargo.toml to crates/foo/Cargo.toml.bak"
+        ));
+    }
+
+    #[test]
+    fn test_stdin() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let manifest = "[package]\r\nname = \"foo\"\r\n\r\n[dependencies]\r\n\
+            cairo-language-server = \"2.8.0\"\r\n\r\n[patch.crates-io]\r\n";
+
+        let args = Args::parse_from(["upgrade", "cairols", "--rev", "1a2b3c4", "--stdin"]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record()
+            .with_stdin(manifest);
+        run(&ctx, args).unwrap();
+
+        assert!(ctx.recorded().is_empty());
+        assert!(!sh.path_exists("Cargo.toml"));
+        let (stdout, _) = ctx.captured();
+        assert_eq!(
+            stdout,
+            "[package]\r\nname = \"foo\"\r\n\r\n\
+            [package.metadata.cairo-toolchain.cairols]\r\nsource = \"git\"\r\n\
+            repo = \"https://github.com/software-mansion/cairols\"\r\nrev = \"1a2b3c4\"\r\n\r\n\
+            [dependencies]\r\ncairo-language-server = \"*\"\r\n\r\n[patch.crates-io]\r\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", \
+            rev = \"1a2b3c4\" }\r\n"
+        );
+
+        assert!(Args::try_parse_from(["upgrade", "--rollback", "--stdin"]).is_err());
+    }
+
+    #[test]
+    fn test_missing_local_crates() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let checkout = temp_dir.path().join("cairo");
+        for crate_name in ["cairo-lang-utils", "cairo-lang-parser"] {
+            sh.write_file(
+                checkout.join("crates").join(crate_name).join("Cargo.toml"),
+                "",
+            )
+            .unwrap();
+        }
+        let crates = [
+            "cairo-lang-utils",
+            "cairo-lang-filesystem",
+            "cairo-lang-parser",
+            "cairo-lang-lowering",
+        ];
+        let checkout = checkout.to_str().unwrap();
+        let args = Args::parse_from(["upgrade", "cairo", "--path", checkout]);
+        assert_eq!(
+            missing_local_crates(&args, &crates),
+            [
+                (
+                    "cairo-lang-filesystem".to_owned(),
+                    Path::new(checkout).join("crates/cairo-lang-filesystem")
+                ),
+                (
+                    "cairo-lang-lowering".to_owned(),
+                    Path::new(checkout).join("crates/cairo-lang-lowering")
+                ),
+            ]
+        );
+        let args = Args::parse_from([
+            "upgrade",
+            "cairo",
+            "--path",
+            checkout,
+            "--exclude",
+            "cairo-lang-lowering",
+        ]);
+        assert_eq!(missing_local_crates(&args, &crates).len(), 1);
+        let args = Args::parse_from(["upgrade", "cairo", "--rev", "1a2b3c4"]);
+        assert!(missing_local_crates(&args, &crates).is_empty());
+    }
+
+    #[test]
+    fn test_best_effort() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let checkout = temp_dir.path().join("cairols");
+        sh.create_dir(&checkout).unwrap();
+
+        let upgrade = |best_effort: bool| {
+            let mut args = [
+                "upgrade",
+                "cairols",
+                "--path",
+                "cairols",
+                "--no-sync-version",
+            ]
+            .to_vec();
+            args.extend(best_effort.then_some("--best-effort"));
+            let ctx = Context::recording(sh.clone());
+            let result = run(&ctx, Args::parse_from(args));
+            (result, ctx)
+        };
+        let missing = format!(
+            "1 crate cannot be patched to local checkouts:\n  cairols: cairo-language-server, no \
+            package at {}",
+            checkout.join("crates/cairo-language-server").display()
+        );
+
+        let (result, ctx) = upgrade(false);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!("{missing}\npass --best-effort to upgrade the other crates")
+        );
+        assert!(ctx.recorded().is_empty());
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+
+        let (result, ctx) = upgrade(true);
+        result.unwrap();
+        let (_, stderr) = ctx.captured();
+        assert_eq!(
+            stderr[0],
+            format!("warn: {missing}\nleaving them as they were")
+        );
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains("cairo-language-server = \"2.8.0\""),
+            "{cargo_toml}"
+        );
+        assert!(cargo_toml.contains("[patch.crates-io]\n\n"), "{cargo_toml}");
+    }
+
+    #[test]
+    fn test_emit_env_for_rev() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            r#"version = 4
+
+[[package]]
+name = "cairo-language-server"
+version = "2.9.0-dev.0"
+source = "git+https://github.com/software-mansion/cairols?rev=1a2b3c4#1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d"
+"#,
+        )
+        .unwrap();
+        sh.write_file("env", "EXISTING=1\n").unwrap();
+        let upgrade = |extra: Option<&str>| {
+            let mut args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--emit-env",
+                "env",
+                "--no-sync-version",
+            ]
+            .to_vec();
+            args.extend(extra);
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+            ctx.captured().1
+        };
+
+        // A dry run only prints the lines it would append.
+        let stderr = upgrade(Some("--dry-run"));
+        assert_eq!(sh.read_file("env").unwrap(), "EXISTING=1\n");
+        assert!(
+            stderr.contains(&format!(
+                "note: would append {}:\n\
+                CAIROLS_SOURCE=git\n\
+                CAIROLS_REV=1a2b3c4\n",
+                temp_dir.path().join("env").display()
+            )),
+            "{stderr:?}"
+        );
+
+        upgrade(None);
+        assert_eq!(
+            sh.read_file("env").unwrap(),
+            "EXISTING=1\n\
+            CAIROLS_SOURCE=git\n\
+            CAIROLS_VERSION=2.9.0-dev.0\n\
+            CAIROLS_REV=1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d\n"
+        );
+    }
+
+    #[test]
+    fn test_write_changelog_fragment() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let upgrade = |extra: Option<&str>| {
+            let mut args = [
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--changelog-fragment",
+                "changes",
+                "--no-sync-version",
+            ]
+            .to_vec();
+            args.extend(extra);
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+            ctx.captured().1
+        };
+        let fragment = |stderr: &[String], prefix: &str| {
+            stderr
+                .iter()
+                .find_map(|line| line.strip_prefix(prefix))
+                .unwrap_or_else(|| panic!("{stderr:?}"))
+                .to_owned()
+        };
+
+        let stderr = upgrade(Some("--dry-run"));
+        assert!(!sh.path_exists("changes"));
+        let would_write = fragment(&stderr, "note: would write ");
+        let (path, contents) = would_write.split_once(":\n").unwrap();
+        assert!(path.ends_with("-upgrade-cairols.md"), "{path}");
+        assert!(contents.starts_with("- Upgraded "), "{contents}");
+
+        let stderr = upgrade(None);
+        let written = fragment(&stderr, "note: wrote changelog fragment ");
+        assert_eq!(written, path);
+        assert!(sh.read_file(written).unwrap().starts_with("- Upgraded "));
+    }
+
+    #[test]
+    fn test_changelog_fragment() {
+        let groups = ["cairo", "cairols", "cairolint"]
+            .map(|dep| Args::parse_from(["upgrade", dep, "2.8.0"]));
+
+        let previous = [
+            Some("2.7.1".parse().unwrap()),
+            None,
+            Some("2.9.0".parse().unwrap()),
+        ];
+        let resolved = [
+            Resolved {
+                version: Some("2.8.0".parse().unwrap()),
+                commit: None,
+            },
+            Resolved {
+                version: Some("2.8.0".parse().unwrap()),
+                commit: Some("1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d".to_owned()),
+            },
+            Resolved {
+                version: Some("2.9.0".parse().unwrap()),
+                commit: None,
+            },
+        ];
+        let fragment = changelog_fragment(&groups, &previous, &resolved);
+        assert_eq!(
+            fragment,
+            "- Upgraded Cairo to 2.8.0 (was 2.7.1)\n\
+            - Upgraded CairoLS to 2.8.0 (1a2b3c4)\n\
+            - Upgraded Cairo Lint to 2.9.0\n"
+        );
+
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let path = fragment_path(
+            &temp_dir.path().join("changelog"),
+            "2024-10-01",
+            &groups[..1],
+        );
+        assert!(path.ends_with("changelog/2024-10-01-upgrade-cairo.md"));
+        let fragment = changelog_fragment(&groups[..1], &previous[..1], &resolved[..1]);
+        sh.write_file(&path, fragment).unwrap();
+        assert_eq!(
+            sh.read_file(&path).unwrap(),
+            "- Upgraded Cairo to 2.8.0 (was 2.7.1)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11_016), "2000-02-29");
+        assert_eq!(format_date(19_997), "2024-10-01");
+    }
+
+    #[test]
+    fn test_dry_run_prints_commands_of_real_run() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_toml = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        for extra in [None, Some("--no-sync-version")] {
+            sh.write_file("Cargo.toml", cargo_toml).unwrap();
+            let upgrade = |dry_run: bool| {
+                let mut args = vec!["upgrade", "cairols", "--rev", "1a2b3c4"];
+                args.extend(extra);
+                args.extend(dry_run.then_some("--dry-run"));
+                let ctx = Context::recording(sh.clone());
+                run(&ctx, Args::parse_from(args)).unwrap();
+                let (_, stderr) = ctx.captured();
+                let printed = stderr
+                    .iter()
+                    .filter_map(|line| line.strip_prefix("would run: ").map(str::to_owned))
+                    .collect::<Vec<_>>();
+                (ctx.recorded(), printed)
+            };
+
+            let (executed, printed) = upgrade(true);
+            assert!(executed.is_empty());
+            assert_eq!(sh.read_file("Cargo.toml").unwrap(), cargo_toml);
+            let (expected, _) = upgrade(false);
+            assert_eq!(printed, expected);
+            assert_eq!(printed[0], "cargo fetch --offline");
+            assert_eq!(printed.len(), if extra.is_some() { 1 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn test_compat_check() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_toml = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", cargo_toml).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--compat-check",
+            "--dry-run",
+        ]);
+        run(&ctx, args).unwrap();
+        let recorded = ctx.recorded();
+        assert_eq!(recorded.len(), 1);
+        assert!(
+            recorded[0].starts_with("cargo check --workspace --offline --manifest-path ")
+                && recorded[0].ends_with("target"),
+            "{recorded:?}"
+        );
+        let (_, stderr) = ctx.captured();
+        assert!(stderr.contains(&"note: the workspace compiles with the upgrade".to_owned()));
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), cargo_toml);
+    }
+
+    #[test]
+    fn test_dependencies_written_as_tables() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = r#"[package]
+name = "foo"
+
+[dependencies]
+cairo-language-server = { version = "2.8.0", features = ["serde"] }
+
+[dependencies.cairo-lint-core]
+version = "2.8.0"
+default-features = false
+features = ["testing", "serde"]
+
+[dev-dependencies.cairo-language-server]
+version = "2.8.0"
+features = ["testing"]
+
+[patch.crates-io]
+"#;
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols,cairolint",
+            "2.9.0",
+            "--no-sync-version",
+            "--no-verify",
+        ]);
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        let dependencies = original.find("\n[dependencies]").unwrap();
+        assert!(
+            cargo_toml.ends_with(&original[dependencies..].replace("2.8.0", "2.9.0")),
+            "{cargo_toml}"
+        );
+    }
+
+    #[test]
+    fn test_renamed_dependencies() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            r#"[workspace]
+
+[workspace.dependencies]
+cairo-lint-core = "2.8.0"
+lsp = { package = "cairo-language-server", version = "2.8.0" }
+
+[package]
+name = "foo"
+
+[dependencies]
+cairo-language-server = { package = "something-else", version = "0.1.0" }
+lint = { package = "cairo-lint-core", version = "2.8.0", default-features = false }
+
+[patch.crates-io]
+"#,
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols,cairolint",
+            "2.9.0",
+            "--no-sync-version",
+            "--no-verify",
+        ]);
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains(
+                "[workspace.dependencies]\ncairo-lint-core = \"2.9.0\"\n\
+                lsp = { package = \"cairo-language-server\", version = \"2.9.0\" }\n"
+            ),
+            "{cargo_toml}"
+        );
+        // The key alone does not make a dependency part of a group.
+        assert!(
+            cargo_toml.contains(
+                "[dependencies]\n\
+                cairo-language-server = { package = \"something-else\", version = \"0.1.0\" }\n\
+                lint = { package = \"cairo-lint-core\", version = \"2.9.0\", default-features = false }\n"
+            ),
+            "{cargo_toml}"
+        );
+    }
+
+    #[test]
+    fn test_path_upgrade_syncs_checkout_version() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let checkout = temp_dir.path().join("cairo");
+        for (path, contents) in [
+            (
+                "scripts/release_crates.sh",
+                "CRATES_TO_PUBLISH=(\n  cairo-lang-compiler\n)\n",
+            ),
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.package]\nversion = \"2.10.0\"\n",
+            ),
+            (
+                "crates/cairo-lang-compiler/Cargo.toml",
+                "[package]\nname = \"cairo-lang-compiler\"\nversion.workspace = true\n",
+            ),
+        ] {
+            sh.write_file(checkout.join(path), contents).unwrap();
+        }
+        let project = temp_dir.path().join("project");
+        sh.change_dir(&project);
+        sh.write_file(
+            "Cargo.toml",
+            "[package]\nname = \"foo\"\nversion = \"2.9.2\"\n\n\
+            [dependencies]\ncairo-lang-compiler = \"2.9.2\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        let lock = |version: &str| {
+            format!("version = 4\n\n[[package]]\nname = \"cairo-lang-compiler\"\nversion = \"{version}\"\n")
+        };
+        let upgrade = || {
+            let ctx = Context::recording(sh.clone());
+            let args = Args::parse_from(["upgrade", "cairo", "--path", checkout.to_str().unwrap()]);
+            let result = run(&ctx, args);
+            (result, ctx.recorded())
+        };
+
+        // Cargo keeps locking the previous version, so the compiler is updated explicitly.
+        sh.write_file("Cargo.lock", lock("2.9.2")).unwrap();
+        let (result, recorded) = upgrade();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "Cargo.lock locks cairo-lang-compiler 2.9.2, but the checkout in {} is at 2.10.0",
+                checkout.display()
+            )
+        );
+        assert_eq!(
+            recorded,
+            [
+                "cargo fetch --offline",
+                "cargo update -p cairo-lang-compiler --offline"
+            ]
+        );
+
+        sh.write_file("Cargo.lock", lock("2.10.0")).unwrap();
+        let (result, recorded) = upgrade();
+        result.unwrap();
+        assert_eq!(
+            recorded,
+            ["cargo fetch --offline", "cargo xtask sync-version"]
+        );
+
+        let ctx = Context::recording(sh.clone());
+        sync_version::run(&ctx, sync_version::Args::default()).unwrap();
+        let cargo_toml = sh
+            .read_file("Cargo.toml")
+            .unwrap()
+            .parse::<DocumentMut>()
+            .unwrap();
+        assert_eq!(cargo_toml["package"]["version"].as_str(), Some("2.10.0"));
+    }
+
+    #[test]
+    fn test_pin_rev() {
+        let commit = "0b3bfdcf2e4c1a7e8e6e0f7b9a3b2b9c1d2e3f40";
+        let patch = |pin: bool| {
+            let mut cargo_toml = "[patch.crates-io]\n".parse::<DocumentMut>().unwrap();
+            let mut args = ["upgrade", "cairols", "--rev", "v2.9.2"].to_vec();
+            args.extend(pin.then_some("--pin"));
+            let mut args = Args::parse_from(args);
+            args.spec.commit = Some(commit.to_owned());
+            edit_patch(&mut cargo_toml, &args).unwrap();
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"]["rev"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(patch(false), "v2.9.2");
+        assert_eq!(patch(true), commit);
+
+        let mut args = Args::parse_from(["upgrade", "cairols", "--rev", "v2.9.2"]);
+        args.spec.commit = Some(commit.to_owned());
+        let resolved = Resolved {
+            version: None,
+            commit: None,
+        };
+        assert!(env_vars(&args, &resolved).contains(&("CAIROLS_REV".to_owned(), commit.to_owned())));
+    }
+
+    #[test]
+    fn test_crate_filter() {
+        let script = std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/release_crates/v2.9.2.sh"
+        ))
+        .unwrap();
+        let crates = release_script::parse_array(&script, "CRATES_TO_PUBLISH").unwrap();
+        let selected = |args: &[&str]| {
+            let args = parse_args(
+                ["upgrade", "cairo", "2.9.2"]
+                    .into_iter()
+                    .chain(args.iter().copied()),
+            );
+            crates
+                .iter()
+                .filter(|c| args.filter.selects(c))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(selected(&[]).len(), crates.len());
+        assert_eq!(
+            selected(&["--only-regex", "sierra"]),
+            [
+                "cairo-lang-sierra",
+                "cairo-lang-sierra-type-size",
+                "cairo-lang-sierra-ap-change",
+                "cairo-lang-sierra-gas",
+                "cairo-lang-sierra-to-casm",
+                "cairo-lang-sierra-generator",
+            ]
+        );
+        assert_eq!(
+            selected(&[
+                "--only-regex",
+                "sierra",
+                "--only",
+                "cairo-lang-compiler",
+                "--exclude-regex",
+                "gas|ap-change",
+                "--exclude",
+                "cairo-lang-sierra-generator",
+            ]),
+            [
+                "cairo-lang-sierra",
+                "cairo-lang-sierra-type-size",
+                "cairo-lang-sierra-to-casm",
+                "cairo-lang-compiler",
+            ]
+        );
+
+        let mut cargo_toml = "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--exclude-regex", "^cairo-"]);
+        edit_dependencies(&mut cargo_toml, &args);
+        assert_eq!(
+            cargo_toml.to_string(),
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+    }
+
+    #[test]
+    fn test_dotted_key_dependencies() {
+        let mut cargo_toml = r#"dependencies.cairo-language-server.version = "2.8.0"
+
+[package]
+name = "foo"
+
+[dev-dependencies.cairo-language-server]
+version = "2.8.0"
+
+[workspace.dependencies]
+anyhow = "1"
+cairo-language-server.version = "2.8.0"
+cairo-language-server.features = ["testing"]
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_dependencies(&mut cargo_toml, &args);
+
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"dependencies.cairo-language-server.version = "2.9.0"
+
+[package]
+name = "foo"
+
+[dev-dependencies.cairo-language-server]
+version = "2.9.0"
+
+[workspace.dependencies]
+anyhow = "1"
+cairo-language-server.version = "2.9.0"
+cairo-language-server.features = ["testing"]
+"#
+        );
+    }
+
+    #[test]
+    fn test_group_versions() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            r#"[dependencies]
+cairo-language-server = "2.8.0"
+cairo-lint-core = "2.8.0"
+
+[patch.crates-io]
+"#,
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--group-version",
+            "cairols=2.9.0",
+            "--group-version",
+            "cairolint=2.8.1",
+            "--no-verify",
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        let cargo_toml = cargo_toml.parse::<DocumentMut>().unwrap();
+        assert_eq!(
+            cargo_toml["dependencies"]["cairo-language-server"].as_str(),
+            Some("2.9.0")
+        );
+        assert_eq!(
+            cargo_toml["dependencies"]["cairo-lint-core"].as_str(),
+            Some("2.8.1")
+        );
+        assert_eq!(
+            provenance::read(&cargo_toml)
+                .into_iter()
+                .map(|p| (p.group, p.version.unwrap().to_string()))
+                .collect::<Vec<_>>(),
+            [
+                ("cairols".to_owned(), "2.9.0".to_owned()),
+                ("cairolint".to_owned(), "2.8.1".to_owned())
+            ]
+        );
+        // Cargo resolves all groups at once.
+        assert_eq!(
+            ctx.recorded(),
+            ["cargo fetch --offline", "cargo xtask sync-version"]
+        );
+    }
+
+    #[test]
+    fn test_several_groups() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\ncairo-lint-core = \"2.8.0\"\n\n\
+            [patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols,cairolint,cairols",
+            "2.9.0",
+            "--no-verify",
+        ]);
+        let groups = args
+            .groups(&DocumentMut::new(), |_| None, &mut Vec::new())
+            .unwrap();
+        assert_eq!(
+            groups.iter().map(Args::dep).collect::<Vec<_>>(),
+            [DepName::CairoLS, DepName::CairoLint]
+        );
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.starts_with(
+                "[dependencies]\ncairo-language-server = \"2.9.0\"\ncairo-lint-core = \"2.9.0\"\n"
+            ),
+            "{cargo_toml}"
+        );
+        assert_eq!(
+            ctx.recorded(),
+            ["cargo fetch --offline", "cargo xtask sync-version"]
+        );
+
+        let args = Args::parse_from(["upgrade", "cairols", "cairolint", "--rev", "v2.9.0"]);
+        assert_eq!(
+            args.groups(&DocumentMut::new(), |_| None, &mut Vec::new())
+                .err()
+                .unwrap()
+                .to_string(),
+            "expected a version, got the group cairolint, separate the names of several groups \
+            with commas, e.g. `cairols,cairolint`"
+        );
+        let args = Args::parse_from(["upgrade", "cairols,cairolint", "--path", "../tools"]);
+        assert_eq!(
+            args.groups(&DocumentMut::new(), |_| None, &mut Vec::new())
+                .err()
+                .unwrap()
+                .to_string(),
+            "--path points to the checkout of a single group, upgrade cairols, cairolint one at a \
+            time"
+        );
+    }
+
+    #[test]
+    fn test_group_versions_require_main_group_source() {
+        let args = Args::parse_from(["upgrade", "cairols", "--group-version", "cairolint=2.8.1"]);
+        let err = args
+            .groups(&DocumentMut::new(), |_| None, &mut Vec::new())
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("--group-version cairols=<VERSION>"));
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--group-version",
+            "cairols=2.9.0",
+        ]);
+        assert!(args
+            .groups(&DocumentMut::new(), |_| None, &mut Vec::new())
+            .is_err());
+
+        assert!(
+            Args::try_parse_from(["upgrade", "cairols", "--group-version", "starknet=2.9.0"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_edit_patch_leaves_foreign_entries_untouched() {
+        let original = r#"[patch.crates-io]
+starknet-types-core = { git = "https://github.com/example/types-rs",   rev = "abc" }
+cairo-language-server = { git = "https://github.com/example/cairols", branch = "old", package = "cairo-language-server" } # pinned
+local-fork = { path = "../local-fork" }
+"#;
+
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = Args::parse_from(["upgrade", "cairols", "--rev", "1a2b3c4"]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[patch.crates-io]
+starknet-types-core = { git = "https://github.com/example/types-rs",   rev = "abc" }
+cairo-language-server = { git = "https://github.com/software-mansion/cairols", rev = "1a2b3c4", package = "cairo-language-server" } # pinned
+local-fork = { path = "../local-fork" }
+"#
+        );
+
+        let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml.to_string(),
+            r#"[patch.crates-io]
+starknet-types-core = { git = "https://github.com/example/types-rs",   rev = "abc" }
+local-fork = { path = "../local-fork" }
+"#
+        );
+    }
+
+    #[test]
+    fn test_edit_patch_rejects_malformed_table() {
+        let original = r#"[[patch]]
+crates-io = { cairo-language-server = { path = "../cairols" } }
+"#;
+        for spec in [["--rev", "1a2b3c4"], ["--path", "../cairols"]] {
+            let mut cargo_toml = original.parse::<DocumentMut>().unwrap();
+            let args = Args::parse_from(["upgrade", "cairols"].into_iter().chain(spec));
+            assert_eq!(
+                edit_patch(&mut cargo_toml, &args).unwrap_err().to_string(),
+                "malformed Cargo.toml: `patch` is an array of tables (`[[patch]]`), expected a \
+                table of sources with a `[patch.crates-io]` sub-table"
+            );
+            assert_eq!(cargo_toml.to_string(), original);
+        }
+
+        let mut cargo_toml = "[patch]\ncrates-io = []\n".parse::<DocumentMut>().unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        assert_eq!(
+            edit_patch(&mut cargo_toml, &args).unwrap_err().to_string(),
+            "malformed Cargo.toml: `patch.crates-io` is a array, expected a `[patch.crates-io]` \
+            table mapping crate names to their sources"
+        );
+    }
+
+    #[test]
+    fn test_network_requires_confirmation() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[workspace]\n\n[workspace.dependencies]\n\
+            cairo-lang-compiler = \"2.9.0\"\ncairo-language-server = \"2.9.0\"\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        let error = |args: &[&str]| {
+            let args = Args::parse_from(args);
+            let ctx = Context::with_shell(sh.clone(), &args.global)
+                .unwrap()
+                .record();
+            let err = run(&ctx, args).unwrap_err();
+            assert!(ctx.recorded().is_empty());
+            format!("{err:#}")
+        };
+
+        let err = error(&["upgrade", "cairols", "--rev", "v2.9.2", "--no-sync-version"]);
+        assert_eq!(
+            err,
+            "failed to resolve cairols: refusing to list references of \
+            https://github.com/software-mansion/cairols: accessing the network requires \
+            --confirm-network, pass it or use a local checkout with --path"
+        );
+        let err = error(&["upgrade", "cairo", "--branch", "main", "--no-sync-version"]);
+        assert!(
+            err.contains(
+                "refusing to fetch https://raw.githubusercontent.com/starkware-libs/cairo/\
+                refs/heads/main/scripts/release_crates.sh: accessing the network requires \
+                --confirm-network"
+            ),
+            "{err}"
+        );
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+
+        // Upgrades which need no network of their own keep Cargo offline as well.
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.2",
+                "--no-sync-version",
+                "--no-backup",
+            ],
+        );
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+
+        let ctx = Context::with_shell(
+            sh.clone(),
+            &GlobalArgs {
+                confirm_network: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(ctx.network().unwrap(), Network::Allowed);
+
+        // Workspaces may opt out of the confirmation.
+        sh.write_file(
+            "Cargo.toml",
+            format!("{original}\n[workspace.metadata.cairo-toolchain-xtasks]\nconfirm_network = false\n"),
+        )
+        .unwrap();
+        let ctx = Context::recording(sh.clone());
+        assert_eq!(ctx.network().unwrap(), Network::Allowed);
+    }
+
+    #[test]
+    fn test_allowed_git_hosts() {
+        let manifest = |hosts: &str| {
+            format!(
+                "[workspace.metadata.cairo-toolchain-xtasks]\nallowed_git_hosts = {hosts}\n\n\
+                [patch.crates-io]\n"
+            )
+            .parse::<DocumentMut>()
+            .unwrap()
+        };
+        let args = Args::parse_from(["upgrade", "cairols", "--rev", "1a2b3c4"]);
+
+        let mut cargo_toml = manifest(r#"["gitlab.example.com"]"#);
+        let err = edit_patch(&mut cargo_toml, &args).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "refusing to patch with git source https://github.com/software-mansion/cairols, host \
+            github.com is not in allowed_git_hosts (gitlab.example.com)"
+        );
+        assert!(get_table_like(&cargo_toml, "patch.crates-io")
+            .unwrap()
+            .is_empty());
+
+        let mut cargo_toml = manifest(r#"["gitlab.example.com", "GitHub.com"]"#);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["cairo-language-server"]["rev"].as_str(),
+            Some("1a2b3c4")
+        );
+
+        let mut cargo_toml = manifest(r#"["gitlab.example.com"]"#);
+        let args = parse_args(["upgrade", "cairols", "2.9.0"]);
+        edit_patch(&mut cargo_toml, &args).unwrap();
+    }
+
+    #[test]
+    fn test_upgrades_member_requirements() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        let inheriting =
+            "[dependencies]\ncairo-language-server = { workspace = true, features = [\"x\"] }\n";
+        sh.write_file("crates/inheriting/Cargo.toml", inheriting)
+            .unwrap();
+        let overriding = "[dependencies]\nanyhow = \"1.0.95\"\n\n\
+            [build-dependencies]\ncairo-language-server = { version = \"2.8.0\", optional = true }\n";
+        sh.write_file("crates/overriding/Cargo.toml", overriding)
+            .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.0",
+                "--no-sync-version",
+                "--dry-run",
+            ],
+        );
+        run(&ctx, args).unwrap();
+        let (_, stderr) = ctx.captured();
+        let member_diff = stderr
+            .iter()
+            .position(|line| line == "--- a/crates/overriding/Cargo.toml")
+            .unwrap_or_else(|| panic!("{stderr:?}"));
+        assert_eq!(
+            stderr[member_diff..member_diff + 8],
+            [
+                "--- a/crates/overriding/Cargo.toml",
+                "+++ b/crates/overriding/Cargo.toml",
+                "@@ -2,4 +2,4 @@",
+                " anyhow = \"1.0.95\"",
+                " ",
+                " [build-dependencies]",
+                "-cairo-language-server = { version = \"2.8.0\", optional = true }",
+                "+cairo-language-server = { version = \"2.9.0\", optional = true }",
+            ]
+        );
+        assert!(
+            !stderr.iter().any(|line| line.contains("crates/inheriting")),
+            "{stderr:?}"
+        );
+        assert_eq!(
+            sh.read_file("crates/overriding/Cargo.toml").unwrap(),
+            overriding
+        );
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairols", "2.9.0", "--no-sync-version"],
+        );
+        run(&ctx, args).unwrap();
+        assert_eq!(
+            sh.read_file("crates/overriding/Cargo.toml").unwrap(),
+            overriding.replace("2.8.0", "2.9.0")
+        );
+        assert_eq!(
+            sh.read_file("crates/inheriting/Cargo.toml").unwrap(),
+            inheriting
+        );
+    }
+
+    #[test]
+    fn test_json_report_keeps_diagnostics_out_of_stdout() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "crates/overriding/Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-language-server\"\nversion = \"2.8.0\"\n\
+            source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--format",
+            "json",
+            "--no-sync-version",
+            "--no-backup",
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+
+        let (stdout, stderr) = ctx.captured();
+        let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "dry_run": false,
+                "groups": [{
+                    "group": "cairols",
+                    "source": "git",
+                    "previous_version": "2.8.0",
+                    "version": "2.8.0",
+                    "commit": null,
+                    "purged_patches": [],
+                }],
+                "version_sync": null,
+            })
+        );
+        assert_eq!(stderr, ["summary:", "  cairols: 2.8.0 -> 2.8.0"]);
+        assert_eq!(
+            sh.read_file("crates/overriding/Cargo.toml").unwrap(),
+            "[dependencies]\ncairo-language-server = \"*\"\n"
+        );
+    }
+
+    #[test]
+    fn test_preserve_path() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\n\
+            cairo-language-server = { path = \"vendor/cairols\", features = [\"x\"] }\n\
+            \n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairols", "2.9.0", "--no-sync-version"],
+        );
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml
+                .contains("cairo-language-server = { version = \"2.9.0\", features = [\"x\"] }"),
+            "{cargo_toml}"
+        );
+
+        // Sourcing the group from git would add a patch otherwise.
+        for source in [&["2.9.0"][..], &["--rev", "1a2b3c4"]] {
+            sh.write_file("Cargo.toml", original).unwrap();
+            let ctx = Context::recording(sh.clone());
+            let args = offline_args(
+                temp_dir.path(),
+                &[
+                    &["upgrade", "cairols"],
+                    source,
+                    &["--preserve-path", "--no-sync-version"],
+                ]
+                .concat(),
+            );
+            run(&ctx, args).unwrap();
+            let (_, stderr) = ctx.captured();
+            assert!(stderr
+                .iter()
+                .any(|line| line
+                    == "note: cairols: keeping path dependencies on cairo-language-server"));
+            let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+            assert!(cargo_toml.starts_with(original), "{cargo_toml}");
+            assert!(!cargo_toml.contains("git = "), "{cargo_toml}");
+        }
+
+        // Path dependencies declared by members are kept as well.
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        let member =
+            "[dependencies]\ncairo-language-server = { path = \"../../vendor/cairols\" }\n";
+        sh.write_file("crates/foo/Cargo.toml", member).unwrap();
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.0",
+                "--preserve-path",
+                "--no-sync-version",
+            ],
+        );
+        run(&ctx, args).unwrap();
+        let (_, stderr) = ctx.captured();
+        assert!(
+            stderr.iter().any(|line| line
+                == "note: crates/foo/Cargo.toml: cairols: keeping path dependencies on \
+                    cairo-language-server"),
+            "{stderr:?}"
+        );
+        assert_eq!(sh.read_file("crates/foo/Cargo.toml").unwrap(), member);
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains("cairo-language-server = \"2.9.0\""),
+            "{cargo_toml}"
+        );
+    }
+
+    #[test]
+    fn test_migrates_member_requirements() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-lint-core = \"2.11.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "crates/foo/Cargo.toml",
+            "[dependencies]\ncairo-lint-core = { version = \"2.11.0\", optional = true }\n\n\
+            [features]\nlint = [\"dep:cairo-lint-core\"]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairolint", "2.12.0", "--no-sync-version"],
+        );
+        run(&ctx, args).unwrap();
+        let (_, stderr) = ctx.captured();
+        assert!(
+            stderr.iter().any(|line| line
+                == "note: crates/foo/Cargo.toml: cairo-lint-core is called cairo-lint at the \
+                    requested version, updated [dependencies]"),
+            "{stderr:?}"
+        );
+        assert_eq!(
+            sh.read_file("crates/foo/Cargo.toml").unwrap(),
+            "[dependencies]\ncairo-lint = { version = \"2.12.0\", optional = true }\n\n\
+            [features]\nlint = [\"dep:cairo-lint\"]\n"
+        );
+        assert!(sh
+            .read_file("Cargo.toml")
+            .unwrap()
+            .contains("cairo-lint = \"2.12.0\""));
+    }
+
+    #[test]
+    fn test_package() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        for (dir, name) in [("crates/a", "foo"), ("crates/b", "bar")] {
+            sh.write_file(
+                format!("{dir}/Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{name}\"\n\n\
+                    [dependencies]\ncairo-language-server = \"2.8.0\"\n"
+                ),
+            )
+            .unwrap();
+        }
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--package",
+            "bar",
+            "--no-sync-version",
+        ]);
+        run(&ctx, args).unwrap();
+
+        assert_eq!(
+            sh.read_file("crates/a/Cargo.toml").unwrap(),
+            "[package]\nname = \"foo\"\n\n[dependencies]\ncairo-language-server = \"2.8.0\"\n"
+        );
+        assert_eq!(
+            sh.read_file("crates/b/Cargo.toml").unwrap(),
+            "[package]\nname = \"bar\"\n\n[dependencies]\ncairo-language-server = \"*\"\n"
+        );
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains(
+                "[patch.crates-io]\ncairo-language-server = { git = \
+                \"https://github.com/software-mansion/cairols\", rev = \"1a2b3c4\" }\n"
+            ),
+            "{cargo_toml}"
+        );
+        assert!(!cargo_toml.contains("[dependencies]"), "{cargo_toml}");
+
+        let ctx = Context::recording(sh.clone());
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--package", "baz"]);
+        assert_eq!(
+            run(&ctx, args).unwrap_err().to_string(),
+            "the workspace has no member named `baz`, its members are: bar, foo"
+        );
+    }
+
+    #[test]
+    fn test_dedupe_patches() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n\
+            [patch.crates-io]\n\
+            cairo-language-server = { git = \"https://github.com/software-mansion/cairols\", rev = \"0a1b2c3\" }\n\n\
+            [patch.\"https://github.com/software-mansion/cairols\"]\n\
+            cairo-language-server = { path = \"../stale/cairols\" }\n\
+            foo = { path = \"../foo\" }\n\n\
+            [patch.my-registry]\ncairo-language-server = { git = \"https://example.com/cairols\" }\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let upgrade = |dedupe: bool| {
+            let ctx = Context::recording(sh.clone());
+            let mut args = vec![
+                "upgrade",
+                "cairols",
+                "--rev",
+                "1a2b3c4",
+                "--no-sync-version",
+            ];
+            args.extend(dedupe.then_some("--dedupe-patches"));
+            run(&ctx, Args::parse_from(args)).unwrap();
+            let (_, stderr) = ctx.captured();
+            stderr
+                .into_iter()
+                .filter(|line| line.starts_with("warn: "))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(upgrade(false).is_empty());
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains("cairo-language-server = { path = \"../stale/cairols\" }\n"),
+            "{cargo_toml}"
+        );
+        assert!(cargo_toml.contains("[patch.my-registry]\n"), "{cargo_toml}");
+
+        sh.write_file("Cargo.toml", original).unwrap();
+        assert_eq!(
+            upgrade(true),
+            [
+                "warn: removed patches of cairo-language-server from \
+                [patch.\"https://github.com/software-mansion/cairols\"], only [patch.crates-io] \
+                patches cairols",
+                "warn: removed patches of cairo-language-server from [patch.my-registry], only \
+                [patch.crates-io] patches cairols",
+            ]
+        );
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.contains(
+                "[patch.crates-io]\ncairo-language-server = { git = \
+                \"https://github.com/software-mansion/cairols\", rev = \"1a2b3c4\" }\n\n\
+                [patch.\"https://github.com/software-mansion/cairols\"]\n\
+                foo = { path = \"../foo\" }\n"
+            ),
+            "{cargo_toml}"
+        );
+        assert!(!cargo_toml.contains("my-registry"), "{cargo_toml}");
+        assert_eq!(cargo_toml.matches("cairo-language-server = {").count(), 1);
+    }
+
+    #[test]
+    fn test_read_repo_file_from_default_branch() {
+        /// Responds with the fetched URL.
+        struct Echo;
+
+        impl Http for Echo {
+            fn get(&self, url: &str) -> Result<String> {
+                Ok(url.to_owned())
+            }
+        }
+
+        let cache = Cache::new(PathBuf::from("/nonexistent"));
+        let spec = Spec {
+            default_branch: Some("develop".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            read_repo_file(&spec, &cache, &Echo, DepName::Scarb, "Cargo.toml", |rev| {
+                format!("scarb-manifests/{rev}/Cargo.toml")
+            })
+            .unwrap(),
+            "https://raw.githubusercontent.com/software-mansion/scarb/refs/heads/develop/Cargo.toml"
+        );
+    }
+
+    #[test]
+    fn test_upgrade_scarb() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        // Snapshot of the relevant manifests of the Scarb repository at a tag.
+        let cache = Cache::new(temp_dir.path().join("cache"));
+        for (path, contents) in [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\n  \"scarb\",\n  \"scarb-metadata\",\n  \
+                \"utils/scarb-ui\",\n  \"plugins/cairo-lang-macro\",\n  \"xtask\",\n]\n\n\
+                [workspace.package]\nversion = \"2.8.0\"\n",
+            ),
+            (
+                "scarb/Cargo.toml",
+                "[package]\nname = \"scarb\"\nversion.workspace = true\n",
+            ),
+            (
+                "scarb-metadata/Cargo.toml",
+                "[package]\nname = \"scarb-metadata\"\nversion = \"1.12.0\"\n",
+            ),
+            (
+                "utils/scarb-ui/Cargo.toml",
+                "[package]\nname = \"scarb-ui\"\nversion = \"0.1.5\"\n",
+            ),
+        ] {
+            cache
+                .put(
+                    &format!("scarb-manifests/refs/tags/v2.8.0/{path}"),
+                    contents,
+                )
+                .unwrap();
+        }
+        let spec = Spec {
+            version: Some(Version::new(2, 8, 0)),
+            ..Default::default()
+        };
+        let http = Curl {
+            network: Network::Unconfirmed,
+        };
+        assert_eq!(
+            pull_scarb_members(&spec, &cache, &http).unwrap(),
+            [
+                ("scarb".to_owned(), "scarb".to_owned()),
+                ("scarb-metadata".to_owned(), "scarb-metadata".to_owned()),
+                ("scarb-ui".to_owned(), "utils/scarb-ui".to_owned()),
+            ]
+        );
+
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-lang-macro = \"0.1\"\nscarb-metadata = \"1.11.0\"\n\n\
+            [dev-dependencies]\nscarb-ui = { version = \"0.1.3\", features = [\"x\"] }\n\n\
+            [patch.crates-io]\n\
+            scarb-ui = { git = \"https://github.com/software-mansion/scarb\", rev = \"1a2b3c4\" }\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let args = Args::parse_from([
+            "upgrade".as_ref(),
+            "scarb".as_ref(),
+            "2.8.0".as_ref(),
+            "--no-sync-version".as_ref(),
+            "--cache-dir".as_ref(),
+            cache_dir.as_os_str(),
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(
+            cargo_toml.starts_with(
+                "[dependencies]\ncairo-lang-macro = \"0.1\"\nscarb-metadata = \"1.12.0\"\n\n\
+                [dev-dependencies]\nscarb-ui = { version = \"0.1.5\", features = [\"x\"] }\n"
+            ),
+            "{cargo_toml}"
+        );
+        assert!(!cargo_toml.contains("[patch"), "{cargo_toml}");
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+
+        let (_, stderr) = ctx.captured();
+        assert!(
+            !stderr.iter().any(|line| line.starts_with("warn:")),
+            "{stderr:?}"
+        );
+
+        // Local checkouts are patched at the directories of the members.
+        let mut cargo_toml = sh.read_file("Cargo.toml").unwrap().parse().unwrap();
+        let mut args = Args::parse_from(["upgrade", "scarb", "--path", "scarb"]);
+        args.spec.path = Some(sh.current_dir().join("scarb"));
+        edit_patch(&mut cargo_toml, &args).unwrap();
+        assert_eq!(
+            cargo_toml["patch"]["crates-io"]["scarb-ui"]["path"].as_str(),
+            Some(
+                sh.current_dir()
+                    .join("scarb/utils/scarb-ui")
+                    .to_str()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_file() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-language-server\"\nversion = \"2.8.0\"\n\
+            source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+        )
+        .unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairols",
+            "--rev",
+            "1a2b3c4",
+            "--report-file",
+            "reports/upgrade.json",
+            "--no-sync-version",
+            "--no-backup",
+        ]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+
+        let (stdout, stderr) = ctx.captured();
+        assert_eq!(stdout, "");
+        let report_path = temp_dir.path().join("reports/upgrade.json");
+        assert_eq!(
+            stderr,
+            [
+                "summary:".to_owned(),
+                "  cairols: 2.8.0 -> 2.8.0".to_owned(),
+                format!("note: wrote report {}", report_path.display()),
+            ]
+        );
+        let report: serde_json::Value =
+            serde_json::from_str(&sh.read_file(&report_path).unwrap()).unwrap();
+        assert_eq!(report["groups"][0]["group"], "cairols");
+        assert_eq!(report["groups"][0]["source"], "git");
+    }
+
+    #[test]
+    fn test_resolved_crates() {
+        let cargo_lock = [
+            ("cairo-lang-utils", "2.9.2"),
+            ("cairo-lang-compiler", "2.9.2"),
+            ("cairo-lang-macro", "0.1.1"),
+            ("cairo-lang-macro", "0.1.0"),
+            ("anyhow", "1.0.95"),
+        ]
+        .iter()
+        .map(|(name, version)| {
+            format!(
+                "[[package]]\nname = \"{name}\"\nversion = \"{version}\"\n\
+                source = \"registry+https://github.com/rust-lang/crates.io-index\"\n\n"
+            )
+        })
+        .collect::<String>()
+        .parse::<DocumentMut>()
+        .unwrap();
+        let crates = [
+            "cairo-lang-utils",
+            "cairo-lang-macro",
+            "cairo-lang-compiler",
+            "cairo-lang-starknet",
+        ];
+
+        let resolved = resolved_crates(&cargo_lock, crates.into_iter()).unwrap();
+        let pairs = resolved
+            .iter()
+            .map(|(name, version)| (name.as_str(), version.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [
+                ("cairo-lang-compiler", "2.9.2".to_owned()),
+                ("cairo-lang-macro", "0.1.1".to_owned()),
+                ("cairo-lang-macro", "0.1.0".to_owned()),
+                ("cairo-lang-utils", "2.9.2".to_owned()),
+            ]
+        );
+        assert_eq!(
+            resolved_crates_table(resolved.iter()),
+            "crate                version\n\
+            cairo-lang-compiler  2.9.2\n\
+            cairo-lang-macro     0.1.1\n\
+            cairo-lang-macro     0.1.0\n\
+            cairo-lang-utils     2.9.2\n"
+        );
+    }
+
+    #[test]
+    fn test_print_resolved_crates_with_versions() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-lint = \"2.11.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-lint\"\nversion = \"2.12.0\"\n",
+        )
+        .unwrap();
+        let upgrade = |extra: &[&str]| {
+            let args = [
+                "upgrade",
+                "cairolint",
+                "--rev",
+                "1a2b3c4",
+                "--no-sync-version",
+            ];
+            let args = args
+                .iter()
+                .chain(extra)
+                .chain(&["--print-resolved-crates-with-versions"]);
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args)).unwrap();
+            ctx.captured().0
+        };
+
+        assert_eq!(upgrade(&[]), "crate       version\ncairo-lint  2.12.0\n");
+        let report: serde_json::Value =
+            serde_json::from_str(&upgrade(&["--format", "json"])).unwrap();
+        assert_eq!(
+            report["groups"][0]["crates"],
+            serde_json::json!([{ "name": "cairo-lint", "version": "2.12.0" }])
+        );
+    }
+
+    #[test]
+    fn test_report_timings() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-lint = \"2.11.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let args = Args::parse_from([
+            "upgrade",
+            "cairolint",
+            "--rev",
+            "1a2b3c4",
+            "--no-sync-version",
+            "--format",
+            "json",
+            "--timings",
+        ]);
+        let ctx = Context::with_shell(sh.clone(), &args.global)
+            .unwrap()
+            .record();
+        run(&ctx, args).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&ctx.captured().0).unwrap();
+        let timings = report["timings"].as_array().unwrap();
+        let phases = timings
+            .iter()
+            .filter(|phase| phase["nested"] == false)
+            .map(|phase| phase["phase"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(phases.contains(&"resolve sources"), "{timings:?}");
+        assert!(phases.contains(&"edit manifest"), "{timings:?}");
+        assert!(timings.iter().all(|phase| phase["duration_ms"].is_u64()));
+    }
+
+    #[test]
+    fn test_print_state_hash() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-lint = \"2.11.0\"\n\n[patch.crates-io]\n",
+        )
+        .unwrap();
+        let lock = |packages: &[(&str, &str)]| {
+            let mut cargo_lock = "version = 4\n".to_owned();
+            for (name, version) in packages {
+                cargo_lock.push_str(&format!(
+                    "\n[[package]]\nname = \"{name}\"\nversion = \"{version}\"\n\
+                    source = \"git+https://github.com/software-mansion/cairo-lint?rev=1a2b3c4#1a2b3c4\"\n"
+                ));
+            }
+            cargo_lock
+        };
+        let upgrade = |cargo_lock: String, extra: &[&str]| {
+            sh.write_file("Cargo.lock", cargo_lock).unwrap();
+            let args = [
+                "upgrade",
+                "cairolint",
+                "--rev",
+                "1a2b3c4",
+                "--no-sync-version",
+                "--print-state-hash",
+            ];
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, Args::parse_from(args.iter().chain(extra))).unwrap();
+            ctx.captured().0
+        };
+
+        let hash = upgrade(
+            lock(&[("cairo-lint", "2.12.0"), ("cairo-lint-core", "2.12.0")]),
+            &[],
+        );
+        assert_eq!(hash.trim_end().len(), 64, "{hash}");
+        // Neither the order of Cargo.lock nor crates of other groups change the hash.
+        assert_eq!(
+            upgrade(
+                lock(&[
+                    ("anyhow", "1.0.0"),
+                    ("cairo-lint-core", "2.12.0"),
+                    ("cairo-lint", "2.12.0"),
+                ]),
+                &[]
+            ),
+            hash
+        );
+        assert_ne!(
+            upgrade(
+                lock(&[("cairo-lint", "2.12.1"), ("cairo-lint-core", "2.12.0")]),
+                &[]
+            ),
+            hash
+        );
+
+        let report: serde_json::Value = serde_json::from_str(&upgrade(
+            lock(&[("cairo-lint", "2.12.0"), ("cairo-lint-core", "2.12.0")]),
+            &["--format", "json"],
+        ))
+        .unwrap();
+        assert_eq!(report["state_hash"], hash.trim_end());
+    }
+
+    #[test]
+    fn test_summary_of_synced_version() {
+        let groups = [parse_args(["upgrade", "cairo", "2.9.0"])];
+        let previous = [Some(Version::new(2, 8, 4))];
+        let resolved = [Resolved {
+            version: Some(Version::new(2, 9, 0)),
+            commit: None,
+        }];
+        let synced = sync_version::Outcome {
+            table_path: "workspace.package",
+            anchor: sync_version::Anchor::Lockfile,
+            compiler_version: Version::new(2, 9, 0),
+            previous_version: Some(Version::parse("2.8.4+1").unwrap()),
+            version: Version::parse("2.9.0+1").unwrap(),
+        };
+
+        assert_eq!(
+            summary(&groups, &previous, &resolved, Some(&synced)),
+            [
+                "summary:",
+                "  cairo: 2.8.4 -> 2.9.0",
+                "  [workspace.package] version: 2.8.4+1 -> 2.9.0+1 (adjusted from \
+                 cairo-lang-compiler 2.9.0)",
+            ]
+        );
+        assert_eq!(
+            report(
+                &groups,
+                &previous,
+                &resolved,
+                None,
+                &[],
+                Some(&synced),
+                false
+            )["version_sync"],
+            serde_json::json!({
+                "table": "workspace.package",
+                "previous_version": "2.8.4+1",
+                "version": "2.9.0+1",
+                "compiler_version": "2.9.0",
+                "adjusted": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_patch_from_lock() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"*\"\nanyhow = \"1\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            r#"version = 4
+
+[[package]]
+name = "anyhow"
+version = "1.0.95"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "cairo-language-server"
+version = "2.9.0-dev.0"
+source = "git+https://github.com/software-mansion/cairols?branch=release%2F2.9#1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d"
+"#,
+        )
+        .unwrap();
+
+        let args = Args::parse_from(["upgrade", "cairols", "--patch-from-lock"]);
+        let ctx = Context::recording(sh.clone());
+        run(&ctx, args).unwrap();
+
+        assert_eq!(
+            sh.read_file("Cargo.toml").unwrap(),
+            r#"[dependencies]
+cairo-language-server = "*"
+anyhow = "1"
+
+[patch.crates-io]
+cairo-language-server = { git = "https://github.com/software-mansion/cairols", branch = "release/2.9" }
+"#
+        );
+        assert_eq!(ctx.recorded(), ["cargo fetch --locked"]);
+
+        let args = Args::parse_from(["upgrade", "cairolint", "--patch-from-lock"]);
+        assert!(run(&ctx, args).is_err());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[dependencies]\ncairo-language-server = \"2.8.0\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairols", "2.9.0", "--no-sync-version"],
+        );
+        run(&ctx, args).unwrap();
+        let upgraded = sh.read_file("Cargo.toml").unwrap();
+        assert_ne!(upgraded, original);
+
+        sh.write_file("Cargo.toml", format!("{upgraded}# manual edit\n"))
+            .unwrap();
+        let err = run(&ctx, Args::parse_from(["upgrade", "--rollback"]))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("files were modified after upgrade run"),
+            "{err}"
+        );
+        assert!(err.contains("Cargo.toml"), "{err}");
+
+        run(&ctx, Args::parse_from(["upgrade", "--rollback", "--force"])).unwrap();
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+        assert_eq!(
+            ctx.recorded(),
+            ["cargo fetch --offline", "cargo fetch --offline"]
+        );
+
+        let err = run(&ctx, Args::parse_from(["upgrade", "--rollback"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no upgrade runs recorded"), "{err}");
+        assert!(Args::try_parse_from(["upgrade", "cairols", "--rollback"]).is_err());
+
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.0",
+                "--no-sync-version",
+                "--no-backup",
+            ],
+        );
+        run(&ctx, args).unwrap();
+        assert_ne!(sh.read_file("Cargo.toml").unwrap(), original);
+        assert!(Snapshot::find(&sh, None).is_err());
+    }
+
+    #[test]
+    fn test_refuses_own_manifest() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[package]\nname = \"cairo-toolchain-xtasks\"\nversion = \"1.0.0\"\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairols", "2.9.0", "--no-sync-version"],
+        );
+        let err = run(&ctx, args).unwrap_err().to_string();
+        assert!(err.contains("refusing to upgrade the manifest of cairo-toolchain-xtasks"));
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+        assert!(ctx.recorded().is_empty());
+
+        // The manifest does not depend on the group, which is only reported with --dry-run.
+        let args = offline_args(
+            temp_dir.path(),
+            &["upgrade", "cairols", "2.9.0", "--dry-run"],
+        );
+        let err = run(&ctx, args).unwrap_err();
+        assert!(err.is::<NothingToDo>());
+
+        let args = offline_args(
+            temp_dir.path(),
+            &[
+                "upgrade",
+                "cairols",
+                "2.9.0",
+                "--no-sync-version",
+                "--force",
+                "--force-patch",
+            ],
+        );
+        run(&ctx, args).unwrap();
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+    }
+
+    #[test]
+    fn test_repair_patch_sections() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[workspace.dependencies]\ncairo-lint = \"2.12.0\"\n\n\
+            [patch.crates-io]\nfoo = { path = \"../foo\" }\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let upgrade = [
+            "upgrade",
+            "cairolint",
+            "--rev",
+            "1a2b3c4",
+            "--no-sync-version",
+        ];
+        assert_eq!(
+            run(&ctx, Args::parse_from(upgrade))
+                .unwrap_err()
+                .to_string(),
+            "malformed patch sections in Cargo.toml, pass --fix to repair them:\n  \
+            lines 4, 7: `[patch.crates-io]` is declared 2 times"
+        );
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+
+        run(&ctx, Args::parse_from(upgrade.into_iter().chain(["--fix"]))).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(cargo_toml.ends_with(
+            "[patch.crates-io]\nfoo = { path = \"../foo\" }\ncairo-lint = { git = \
+            \"https://github.com/software-mansion/cairo-lint\", rev = \"1a2b3c4\" }\n"
+        ));
+        assert_eq!(cargo_toml.matches("[patch.crates-io]").count(), 1);
+
+        sh.write_file(
+            "Cargo.toml",
+            "[patch.crates-io]\nfoo = { path = \"../foo\", git = \"https://example.com/foo\" }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            run(&ctx, Args::parse_from(upgrade.into_iter().chain(["--fix"])))
+                .unwrap_err()
+                .to_string(),
+            "malformed patch sections in Cargo.toml, repair them by hand:\n  \
+            line 2: `patch.crates-io.foo` has both a `path` and a `git` source"
+        );
+    }
+
+    #[test]
+    fn test_preserves_cargo_features() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let cargo_features =
+            "cargo-features = [\"edition2024\",   # Needed by the nightly toolchain.\n  \
+            'profile-rustflags' ]\n\n";
+        let write = |manifest: &str| {
+            sh.write_file("Cargo.toml", format!("{cargo_features}{manifest}"))
+                .unwrap();
+        };
+        sh.write_file("Cargo.lock", "version = 4\n").unwrap();
+        let ctx = Context::recording(sh.clone());
+        let upgrade = |rev: &str, extra: &[&str]| {
+            let args = ["upgrade", "cairolint", "--rev", rev, "--no-sync-version"];
+            run(&ctx, Args::parse_from(args.iter().chain(extra))).unwrap();
+            let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+            assert!(cargo_toml.starts_with(cargo_features), "{cargo_toml}");
+            cargo_toml
+        };
+
+        // Adding the patch table, then updating it in place.
+        write("[workspace.dependencies]\ncairo-lint = \"2.12.0\"\n");
+        let cargo_toml = upgrade("1a2b3c4", &[]);
+        assert!(cargo_toml.contains("[patch.crates-io]\ncairo-lint = "));
+        upgrade("5d6e7f8", &[]);
+
+        // Repairing patch sections, which edits the manifest as text.
+        write(
+            "[workspace.dependencies]\ncairo-lint = \"2.12.0\"\n\n\
+            [patch.crates-io]\nfoo = { path = \"../foo\" }\n\n[patch.crates-io]\n\n[patch]\n",
+        );
+        upgrade("1a2b3c4", &["--fix"]);
+    }
+
+    #[test]
+    fn test_unused_group_has_nothing_to_do() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        let original = "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\nanyhow = \"1\"\n\n[patch.crates-io]\n";
+        sh.write_file("Cargo.toml", original).unwrap();
+        sh.write_file(
+            "crates/foo/Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.9.0\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "Cargo.lock",
+            "version = 4\n\n[[package]]\nname = \"cairo-lint\"\nversion = \"2.12.0\"\n\
+            source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+        )
+        .unwrap();
+
+        let ctx = Context::recording(sh.clone());
+        let args = Args::parse_from(["upgrade", "cairolint", "--rev", "1a2b3c4"]);
+        let err = run(&ctx, args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "nothing to do: no dependency table of Cargo.toml, crates/foo/Cargo.toml contains a \
+            crate of cairolint, pass --force-patch to patch it as a transitive dependency anyway"
+        );
+        assert!(err.is::<NothingToDo>());
+        assert_eq!(sh.read_file("Cargo.toml").unwrap(), original);
+        assert!(ctx.recorded().is_empty());
+        assert_eq!(
+            exit_code(Err(err)).unwrap(),
+            ExitCode::from(NothingToDo::EXIT_CODE)
+        );
+        assert_eq!(exit_code(Ok(())).unwrap(), ExitCode::SUCCESS);
+        assert!(exit_code(Err(anyhow::anyhow!("failed"))).is_err());
+
+        // Cairo Lint is only a transitive dependency, which may be patched on purpose.
+        let args = Args::parse_from([
+            "upgrade",
+            "cairolint",
+            "--rev",
+            "1a2b3c4",
+            "--force-patch",
+            "--no-sync-version",
+        ]);
+        run(&ctx, args).unwrap();
+        let cargo_toml = sh.read_file("Cargo.toml").unwrap();
+        assert!(cargo_toml.contains(
+            "cairo-lint = { git = \"https://github.com/software-mansion/cairo-lint\", \
+            rev = \"1a2b3c4\" }"
+        ));
+        assert_eq!(ctx.recorded(), ["cargo fetch --offline"]);
+    }
+
+    #[test]
+    fn test_discovery_errors_are_aggregated() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[dependencies]\ncairo-language-server = \"2.9.0\"\ncairo-lint = \"2.12.0\"\n",
+        )
+        .unwrap();
+        sh.create_dir("index").unwrap();
+
+        for jobs in ["1", "2"] {
+            let args = Args::parse_from([
+                "upgrade",
+                "--all",
+                "--channel",
+                "stable",
+                "--offline",
+                "--index-cache-dir",
+                "index",
+                "--jobs",
+                jobs,
+            ]);
+            let ctx = Context::recording(sh.clone());
+            let err = run(&ctx, args).unwrap_err().to_string();
+            assert_eq!(
+                err,
+                "failed to resolve 2 groups:\n\
+                cairols: `cairo-language-server` is not in the crates.io index cache, run without \
+                --offline\n\
+                cairolint: `cairo-lint` is not in the crates.io index cache, run without --offline"
+            );
+        }
+    }
+
+    #[test]
+    fn test_channels() {
+        let cargo_toml = r#"
+[workspace.dependencies]
+cairo-language-server = "2.9.0"
+cairo-lint = "2.12.0"
+
+[workspace.metadata.cairo-toolchain-xtasks.cairols]
+branch = "release/2.9"
+
+[workspace.metadata.cairo-toolchain-xtasks.cairolint]
+channel = "stable"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let resolve = |args: &[&str]| {
+            let args = Args::parse_from(args);
+            let mut groups = args.groups(&cargo_toml, |_| None, &mut Vec::new()).unwrap();
+            for group in &mut groups {
+                let config = config::group(&cargo_toml, group.dep().name()).unwrap();
+                group
+                    .resolve_channel(&config, &mut Vec::new(), |anchor, prerelease| {
+                        assert_eq!(anchor, "cairo-lint");
+                        assert!(!prerelease);
+                        Ok(Version::new(2, 12, 1))
+                    })
+                    .unwrap();
+            }
+            groups
+                .into_iter()
+                .map(|group| {
+                    let name = group.dep().name();
+                    let spec = group.spec;
+                    let source = match (spec.branch, spec.version) {
+                        (Some(branch), _) => branch,
+                        (_, Some(version)) => version.to_string(),
+                        _ => unreachable!(),
+                    };
+                    format!("{name} {source}")
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            resolve(&["upgrade", "--all", "--channel", "dev"]),
+            ["cairols release/2.9", "cairolint main"]
+        );
+        assert_eq!(
+            resolve(&["upgrade", "cairols", "--branch"]),
+            ["cairols release/2.9"]
+        );
+        assert_eq!(
+            resolve(&[
+                "upgrade",
+                "cairols",
+                "--branch",
+                "feature",
+                "--channel",
+                "dev"
+            ]),
+            ["cairols feature"]
+        );
+        assert_eq!(resolve(&["upgrade", "cairolint"]), ["cairolint 2.12.1"]);
+        assert!(Args::parse_from(["upgrade", "cairols"])
+            .groups(&cargo_toml, |_| None, &mut Vec::new())
+            .is_err());
+
+        let versions = ["2.11.0", "2.12.0", "2.12.1", "2.13.0-rc.0"]
+            .map(|v| (v.parse().unwrap(), v == "2.12.1"));
+        assert_eq!(
+            latest_release(&versions, false),
+            Some(Version::new(2, 12, 0))
+        );
+        assert_eq!(
+            latest_release(&versions, true),
+            Some("2.13.0-rc.0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_latest() {
+        let resolve = |args: &[&str]| {
+            let mut args = Args::parse_from(args);
+            let expected_anchor = args.tool_anchor();
+            args.resolve_channel(
+                &GroupConfig::default(),
+                &mut Vec::new(),
+                |anchor, prerelease| {
+                    assert_eq!(anchor, expected_anchor);
+                    Ok(if prerelease {
+                        "2.10.0-rc.1".parse().unwrap()
+                    } else {
+                        Version::new(2, 9, 4)
+                    })
+                },
+            )
+            .map(|()| args.spec.version.unwrap().to_string())
+        };
+
+        assert_eq!(resolve(&["upgrade", "cairo", "--latest"]).unwrap(), "2.9.4");
+        assert_eq!(
+            resolve(&["upgrade", "cairols", "--latest", "--prerelease"]).unwrap(),
+            "2.10.0-rc.1"
+        );
+        assert_eq!(
+            resolve(&["upgrade", "scarb", "--latest"])
+                .unwrap_err()
+                .to_string(),
+            "scarb is not published on crates.io, so --latest cannot look it up, pass the version \
+            of a release instead"
+        );
+        assert!(Args::try_parse_from(["upgrade", "cairo", "--prerelease"]).is_err());
+        assert!(Args::try_parse_from(["upgrade", "cairo", "2.9.0", "--latest"]).is_err());
+        assert!(
+            Args::try_parse_from(["upgrade", "cairo", "--latest", "--channel", "dev"]).is_err()
+        );
+
+        let args = Args::parse_from(["upgrade", "cairols", "--latest"]);
+        let groups = args
+            .groups(
+                &DocumentMut::new(),
+                |_| Some("../cairols".into()),
+                &mut Vec::new(),
+            )
+            .unwrap();
+        assert_eq!(groups[0].spec.path, None);
+    }
+
+    #[test]
+    fn test_list_tables() {
+        let cargo_toml = r#"
+[package]
+name = "foo"
+
+[dependencies]
+anyhow = "1"
+cairo-language-server = "2.8.0"
+
+[dev-dependencies]
+indoc = "2"
+
+[target.'cfg(unix)'.build-dependencies]
+cairo-language-server = "2.8.0"
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+        let args = parse_args(["upgrade", "cairols", "2.9.0", "--list-tables"]);
+
+        assert_eq!(
+            list_tables(Path::new("Cargo.toml"), &cargo_toml, &args),
+            [
+                "Cargo.toml [dependencies] owned crates: cairo-language-server",
+                "Cargo.toml [dev-dependencies] no owned crates",
+                "Cargo.toml [workspace.dependencies] not found",
+                "Cargo.toml [target.'cfg(unix)'.build-dependencies] owned crates: \
+                cairo-language-server",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_member_tables() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.write_file(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\n\n\
+            [workspace.dependencies]\ncairo-language-server = \"2.8.0\"\n",
+        )
+        .unwrap();
+        sh.write_file(
+            "crates/foo/Cargo.toml",
+            "[package]\nname = \"foo\"\n\n[dev-dependencies]\n\
+            cairo-language-server.workspace = true\n",
+        )
+        .unwrap();
+        let list = |extra: &[&str]| {
+            let mut args = ["upgrade", "cairols", "2.9.0", "--list-tables"].to_vec();
+            args.extend(extra);
+            let ctx = Context::recording(sh.clone());
+            run(&ctx, offline_args(temp_dir.path(), &args)).unwrap();
+            let (stdout, _) = ctx.captured();
+            stdout
+        };
+
+        assert_eq!(
+            list(&[]),
+            "Cargo.toml [dependencies] not found\n\
+            Cargo.toml [dev-dependencies] not found\n\
+            Cargo.toml [workspace.dependencies] owned crates: cairo-language-server\n\
+            crates/foo/Cargo.toml [dependencies] not found\n\
+            crates/foo/Cargo.toml [dev-dependencies] owned crates: cairo-language-server\n\
+            crates/foo/Cargo.toml [workspace.dependencies] not found\n"
+        );
+        assert_eq!(
+            list(&["--package", "foo"]),
+            "crates/foo/Cargo.toml [dependencies] not found\n\
+            crates/foo/Cargo.toml [dev-dependencies] owned crates: cairo-language-server\n\
+            crates/foo/Cargo.toml [workspace.dependencies] not found\n"
+        );
     }
 }
@@ -0,0 +1,656 @@
+//! Verify that features requested from toolchain crates still exist and build.
+
+use crate::cache::CacheArgs;
+use crate::context::{Context, GlobalArgs};
+use crate::http::Curl;
+use crate::index::{Index, IndexArgs};
+use crate::manifest;
+use crate::workspace;
+use anyhow::{bail, ensure, Result};
+use clap::Parser;
+use semver::Version;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs};
+use toml_edit::{DocumentMut, Item, TableLike};
+use xshell::cmd;
+
+/// Verify that features requested from toolchain crates still exist and build.
+///
+/// Every feature listed in a dependency entry on a toolchain (`cairo-*`) crate, in the root
+/// manifest and all workspace members, is looked up in the crate resolved in `Cargo.lock`.
+/// Run this after `upgrade`, as it needs an up-to-date lockfile.
+#[derive(Parser)]
+pub struct Args {
+    /// Run `cargo check` for this workspace member, once per feature set. Can be repeated.
+    #[arg(long = "check", value_name = "PACKAGE")]
+    check: Vec<String>,
+
+    /// Feature set to check members with. Can be repeated, defaults to `default`.
+    ///
+    /// One of `default`, `none` (no default features), `all` or a comma-separated list of
+    /// features, which are enabled on top of no default features.
+    #[arg(long = "feature-set", value_name = "SET", value_parser = FeatureSet::parse)]
+    feature_sets: Vec<FeatureSet>,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+
+    #[command(flatten)]
+    index: IndexArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+/// Set of features to build a package with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum FeatureSet {
+    Default,
+    None,
+    All,
+    List(String),
+}
+
+impl FeatureSet {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "default" => Self::Default,
+            "none" => Self::None,
+            "all" => Self::All,
+            "" => bail!("empty feature set, use `none` to disable default features"),
+            features => Self::List(features.to_owned()),
+        })
+    }
+
+    fn cargo_args(&self) -> Vec<String> {
+        match self {
+            Self::Default => Vec::new(),
+            Self::None => vec!["--no-default-features".to_owned()],
+            Self::All => vec!["--all-features".to_owned()],
+            Self::List(features) => vec![
+                "--no-default-features".to_owned(),
+                "--features".to_owned(),
+                features.clone(),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default features"),
+            Self::None => write!(f, "no default features"),
+            Self::All => write!(f, "all features"),
+            Self::List(features) => write!(f, "features {features}"),
+        }
+    }
+}
+
+/// A feature requested in a dependency entry, which the resolved crate does not provide.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct MissingFeature {
+    manifest: String,
+    table: String,
+    crate_name: String,
+    version: Version,
+    feature: String,
+    /// First version, not newer than the resolved one, which no longer had the feature.
+    removed_in: Option<Version>,
+}
+
+impl fmt::Display for MissingFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}: feature `{}` does not exist in {} {}",
+            self.manifest, self.table, self.crate_name, self.feature, self.crate_name, self.version
+        )?;
+        if let Some(removed_in) = &self.removed_in {
+            write!(f, " (removed in {removed_in})")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn main(args: Args) -> Result<()> {
+    let ctx = Context::new(&args.global)?;
+    let cache = args.cache.cache();
+    let curl = Curl {
+        network: ctx.network()?,
+    };
+    let index = Index::new(&args.index, &cache, &curl);
+    run(&ctx, &args, &index)
+}
+
+fn run(ctx: &Context, args: &Args, index: &Index<'_>) -> Result<()> {
+    let (missing, warnings) = ctx.phase("resolve features", || {
+        find_missing_features(&ctx.sh.current_dir(), &ctx.lockfile(), index, &cargo_home())
+    })?;
+    for warning in warnings.into_iter().chain(index.take_warnings()) {
+        ctx.warn(warning);
+    }
+    for missing in &missing {
+        ctx.error(missing);
+    }
+
+    let failed = ctx.phase("cargo check", || Ok(check_matrix(ctx, args)))?;
+    for failed in &failed {
+        ctx.error(format_args!("cargo check failed for {failed}"));
+    }
+
+    ensure!(
+        missing.is_empty() && failed.is_empty(),
+        "found {} nonexistent feature references and {} failing feature combinations",
+        missing.len(),
+        failed.len()
+    );
+    eprintln!("all referenced toolchain features exist");
+    Ok(())
+}
+
+/// Runs `cargo check` for every configured member and feature set, returning failed combinations.
+fn check_matrix(ctx: &Context, args: &Args) -> Vec<String> {
+    let sh = &ctx.sh;
+    let cargo = ctx.cargo();
+    let toolchain = ctx.rust_toolchain();
+    let feature_sets = if args.feature_sets.is_empty() {
+        vec![FeatureSet::Default]
+    } else {
+        args.feature_sets.clone()
+    };
+
+    let mut failed = Vec::new();
+    for member in &args.check {
+        for feature_set in &feature_sets {
+            let label = format!("{member} with {feature_set}");
+            eprintln!("checking {label}");
+            let cargo_args = feature_set.cargo_args();
+            if let Err(err) = ctx.run(cmd!(
+                sh,
+                "{cargo} {toolchain...} check -p {member} {cargo_args...}"
+            )) {
+                eprintln!("{err:#}");
+                failed.push(label);
+            }
+        }
+    }
+    failed
+}
+
+/// Finds features requested from toolchain crates, which the crates resolved in `Cargo.lock` lack.
+///
+/// Also returns warnings about crates whose features could not be looked up, which are skipped.
+fn find_missing_features(
+    root: &Path,
+    lockfile: &Path,
+    index: &Index<'_>,
+    cargo_home: &Path,
+) -> Result<(Vec<MissingFeature>, Vec<String>)> {
+    let root_manifest = manifest::read(&root.join("Cargo.toml"))?;
+    ensure!(
+        lockfile.is_file(),
+        "{} not found, run `cargo fetch` first",
+        lockfile.display()
+    );
+    let cargo_lock = manifest::read(lockfile)?;
+
+    let mut manifests = vec![(PathBuf::new(), root_manifest.clone())];
+    for member in workspace::members(root, &root_manifest)? {
+        let manifest = manifest::read(&root.join(&member).join("Cargo.toml"))?;
+        manifests.push((member, manifest));
+    }
+
+    let mut resolver = Resolver {
+        root,
+        root_manifest: &root_manifest,
+        cargo_lock: &cargo_lock,
+        cargo_home,
+        index,
+        available: HashMap::new(),
+        warnings: Vec::new(),
+    };
+
+    let mut missing = Vec::new();
+    for (dir, manifest) in &manifests {
+        for (table_path, table) in workspace::dependency_tables(manifest) {
+            for (key, dep) in table.iter() {
+                let crate_name = manifest::crate_name(key, dep).to_owned();
+                let Some(dep) = dep.as_table_like() else {
+                    continue;
+                };
+                let features = string_array(dep.get("features"));
+                if !crate_name.starts_with("cairo-") || features.is_empty() {
+                    continue;
+                }
+
+                let Some(resolved) = resolver.resolve(&crate_name, &root.join(dir), dep) else {
+                    resolver.warnings.push(format!(
+                        "{crate_name} is not resolved in Cargo.lock, skipping"
+                    ));
+                    continue;
+                };
+                for feature in features {
+                    if resolved.features.contains(&feature) {
+                        continue;
+                    }
+                    missing.push(MissingFeature {
+                        manifest: dir.join("Cargo.toml").display().to_string(),
+                        table: table_path.clone(),
+                        removed_in: resolver.removed_in(&crate_name, &resolved.version, &feature),
+                        crate_name: crate_name.clone(),
+                        version: resolved.version.clone(),
+                        feature,
+                    });
+                }
+            }
+        }
+    }
+    Ok((missing, resolver.warnings))
+}
+
+/// A crate resolved in `Cargo.lock` along with the features it provides.
+#[derive(Clone)]
+struct ResolvedCrate {
+    version: Version,
+    features: BTreeSet<String>,
+}
+
+/// Looks up features of crates resolved in `Cargo.lock`, caching results per crate.
+struct Resolver<'a> {
+    root: &'a Path,
+    root_manifest: &'a DocumentMut,
+    cargo_lock: &'a DocumentMut,
+    cargo_home: &'a Path,
+    index: &'a Index<'a>,
+    available: HashMap<String, Option<ResolvedCrate>>,
+    /// Crates skipped because their features could not be read.
+    warnings: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(
+        &mut self,
+        crate_name: &str,
+        manifest_dir: &Path,
+        dep: &dyn TableLike,
+    ) -> Option<ResolvedCrate> {
+        if let Some(resolved) = self.available.get(crate_name) {
+            return resolved.clone();
+        }
+        let resolved = self.lookup(crate_name, manifest_dir, dep);
+        if let Some(Err(err)) = &resolved {
+            self.warnings
+                .push(format!("cannot read features of {crate_name}: {err:#}"));
+        }
+        let resolved = resolved.and_then(Result::ok);
+        self.available
+            .insert(crate_name.to_owned(), resolved.clone());
+        resolved
+    }
+
+    fn lookup(
+        &self,
+        crate_name: &str,
+        manifest_dir: &Path,
+        dep: &dyn TableLike,
+    ) -> Option<Result<ResolvedCrate>> {
+        let (version, source) = self
+            .cargo_lock
+            .get("package")?
+            .as_array_of_tables()?
+            .iter()
+            .filter(|pkg| pkg.get("name").and_then(Item::as_str) == Some(crate_name))
+            .filter_map(|pkg| {
+                let version = pkg.get("version")?.as_str()?.parse::<Version>().ok()?;
+                let source = pkg.get("source").and_then(Item::as_str).map(str::to_owned);
+                Some((version, source))
+            })
+            .max_by(|(a, _), (b, _)| a.cmp(b))?;
+
+        let features = match source.as_deref() {
+            None => self
+                .path_source(crate_name, manifest_dir, dep)
+                .map(|dir| manifest_features(&dir.join("Cargo.toml"))),
+            Some(source) if source.starts_with("git+") => {
+                let sha = source.rsplit_once('#').map(|(_, sha)| sha)?;
+                git_checkout_manifest(self.cargo_home, crate_name, sha)
+                    .map(|manifest| manifest_features(&manifest))
+            }
+            Some(_) => Some(self.registry_features(crate_name, &version)),
+        };
+        let features = match features {
+            Some(features) => features,
+            None => Err(anyhow::anyhow!(
+                "source of {crate_name} {version} not found locally, run `cargo fetch` first"
+            )),
+        };
+        Some(features.map(|features| ResolvedCrate { version, features }))
+    }
+
+    /// Finds the directory of a path dependency, declared directly or via the root manifest.
+    fn path_source(
+        &self,
+        crate_name: &str,
+        manifest_dir: &Path,
+        dep: &dyn TableLike,
+    ) -> Option<PathBuf> {
+        if let Some(path) = dep.get("path").and_then(Item::as_str) {
+            return Some(manifest_dir.join(path));
+        }
+        let declared = |table: Option<&Item>| {
+            table?
+                .get(crate_name)?
+                .get("path")?
+                .as_str()
+                .map(|path| self.root.join(path))
+        };
+        let root = self.root_manifest;
+        declared(root.get("patch").and_then(|patch| patch.get("crates-io"))).or_else(|| {
+            declared(
+                root.get("workspace")
+                    .and_then(|workspace| workspace.get("dependencies")),
+            )
+        })
+    }
+
+    /// Reads features of a registry crate from the extracted registry cache or the index.
+    fn registry_features(&self, crate_name: &str, version: &Version) -> Result<BTreeSet<String>> {
+        let extracted = self.cargo_home.join("registry").join("src");
+        if let Ok(registries) = fs::read_dir(&extracted) {
+            for registry in registries.filter_map(|entry| entry.ok()) {
+                let manifest = registry
+                    .path()
+                    .join(format!("{crate_name}-{version}"))
+                    .join("Cargo.toml");
+                if manifest.is_file() {
+                    return manifest_features(&manifest);
+                }
+            }
+        }
+
+        let records = self.index.records(crate_name)?;
+        match records
+            .iter()
+            .find(|record| record["vers"].as_str() == Some(&version.to_string()))
+        {
+            Some(record) => Ok(record_features(record)),
+            None => bail!("{crate_name} {version} is not published on crates.io"),
+        }
+    }
+
+    /// Finds the first published version which no longer had a feature, using the crates.io index.
+    fn removed_in(&self, crate_name: &str, version: &Version, feature: &str) -> Option<Version> {
+        let mut records = self
+            .index
+            .records(crate_name)
+            .ok()?
+            .into_iter()
+            .filter_map(|record| {
+                let vers = record["vers"].as_str()?.parse::<Version>().ok()?;
+                (vers <= *version).then_some((vers, record))
+            })
+            .collect::<Vec<_>>();
+        records.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let last_with = records
+            .iter()
+            .rposition(|(_, record)| record_features(record).contains(feature))?;
+        records.get(last_with + 1).map(|(vers, _)| vers.clone())
+    }
+}
+
+/// Collects features declared in a manifest, including implicit features of optional dependencies.
+fn manifest_features(path: &Path) -> Result<BTreeSet<String>> {
+    let manifest = manifest::read(path)?;
+    let mut features = BTreeSet::new();
+    let mut references = Vec::new();
+    if let Some(declared) = manifest.get("features").and_then(Item::as_table_like) {
+        for (feature, enables) in declared.iter() {
+            features.insert(feature.to_owned());
+            references.extend(string_array(Some(enables)));
+        }
+    }
+
+    for (_, table) in workspace::dependency_tables(&manifest) {
+        for (name, dep) in table.iter() {
+            let optional = dep
+                .as_table_like()
+                .and_then(|dep| dep.get("optional"))
+                .and_then(Item::as_bool)
+                .unwrap_or(false);
+            if optional && !references.contains(&format!("dep:{name}")) {
+                features.insert(name.to_owned());
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// Collects features of a sparse index record, including implicit features of optional dependencies.
+fn record_features(record: &serde_json::Value) -> BTreeSet<String> {
+    let mut features = BTreeSet::new();
+    let mut references = Vec::new();
+    for key in ["features", "features2"] {
+        if let Some(declared) = record[key].as_object() {
+            for (feature, enables) in declared {
+                features.insert(feature.clone());
+                references.extend(
+                    enables
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|enables| enables.as_str().map(str::to_owned)),
+                );
+            }
+        }
+    }
+
+    for dep in record["deps"].as_array().into_iter().flatten() {
+        let Some(name) = dep["name"].as_str() else {
+            continue;
+        };
+        if dep["optional"].as_bool() == Some(true) && !references.contains(&format!("dep:{name}")) {
+            features.insert(name.to_owned());
+        }
+    }
+    features
+}
+
+/// Finds the manifest of a crate in Cargo's checkout of a git dependency at commit `sha`.
+fn git_checkout_manifest(cargo_home: &Path, crate_name: &str, sha: &str) -> Option<PathBuf> {
+    let checkouts = fs::read_dir(cargo_home.join("git").join("checkouts")).ok()?;
+    for repo in checkouts.filter_map(|entry| entry.ok()) {
+        let Ok(revs) = fs::read_dir(repo.path()) else {
+            continue;
+        };
+        for rev in revs.filter_map(|entry| entry.ok()) {
+            let rev_name = rev.file_name().to_string_lossy().into_owned();
+            if rev_name.len() >= 7 && sha.starts_with(&rev_name) {
+                if let Some(manifest) = find_package_manifest(&rev.path(), crate_name, 3) {
+                    return Some(manifest);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn find_package_manifest(dir: &Path, crate_name: &str, depth: usize) -> Option<PathBuf> {
+    let manifest = dir.join("Cargo.toml");
+    let package_name = manifest::read(&manifest)
+        .ok()
+        .and_then(|doc| doc.get("package")?.get("name")?.as_str().map(str::to_owned));
+    if package_name.as_deref() == Some(crate_name) {
+        return Some(manifest);
+    }
+    if depth == 0 {
+        return None;
+    }
+
+    let mut subdirs = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter(|entry| !matches!(entry.file_name().to_str(), Some("target" | ".git")))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    subdirs.sort();
+    subdirs
+        .iter()
+        .find_map(|subdir| find_package_manifest(subdir, crate_name, depth - 1))
+}
+
+fn string_array(item: Option<&Item>) -> Vec<String> {
+    item.and_then(Item::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str().map(str::to_owned))
+        .collect()
+}
+
+fn cargo_home() -> PathBuf {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")))
+        .unwrap_or_else(|| PathBuf::from(".cargo"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+    use crate::http::Http;
+    use xshell::Shell;
+
+    struct FixtureHttp;
+
+    impl Http for FixtureHttp {
+        fn get(&self, url: &str) -> Result<String> {
+            assert_eq!(url, "https://index.crates.io/ca/ir/cairo-lang-sierra");
+            Ok(concat!(
+                r#"{"name":"cairo-lang-sierra","vers":"2.7.0","deps":[],"features":{"serde":[],"gone":[]}}"#,
+                "\n",
+                r#"{"name":"cairo-lang-sierra","vers":"2.8.0","deps":[],"features":{"serde":[]}}"#,
+                "\n",
+                r#"{"name":"cairo-lang-sierra","vers":"2.9.0","deps":[{"name":"schemars","optional":true}],"features":{"serde":[]}}"#,
+                "\n",
+            )
+            .to_owned())
+        }
+    }
+
+    #[test]
+    fn test_missing_features() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let root = temp_dir.path();
+        sh.write_file(
+            root.join("Cargo.toml"),
+            r#"[workspace]
+members = ["crates/*"]
+
+[workspace.dependencies]
+cairo-lang-sierra = { version = "2.9.0", features = ["serde", "schemars"] }
+cairo-lang-filesystem = "2.9.0"
+cairo-lang-utils = { version = "2.9.0", features = ["serde"] }
+
+[patch.crates-io]
+cairo-lang-filesystem = { path = "vendor/filesystem" }
+"#,
+        )
+        .unwrap();
+        sh.write_file(
+            root.join("crates/foo/Cargo.toml"),
+            r#"[package]
+name = "foo"
+
+[dependencies]
+anyhow = { version = "1", features = ["backtrace"] }
+cairo-lang-sierra = { workspace = true, features = ["gone"] }
+cairo-lang-filesystem = { workspace = true, features = ["local", "serde"] }
+"#,
+        )
+        .unwrap();
+        sh.write_file(
+            root.join("vendor/filesystem/Cargo.toml"),
+            r#"[package]
+name = "cairo-lang-filesystem"
+
+[features]
+local = ["dep:salsa"]
+
+[dependencies]
+salsa = { version = "0.1", optional = true }
+serde = { version = "1", optional = true }
+"#,
+        )
+        .unwrap();
+        sh.write_file(
+            root.join("Cargo.lock"),
+            r#"version = 4
+
+[[package]]
+name = "cairo-lang-filesystem"
+version = "2.9.0"
+
+[[package]]
+name = "cairo-lang-sierra"
+version = "2.9.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#,
+        )
+        .unwrap();
+
+        let index = Index::new(
+            &IndexArgs::default(),
+            &Cache::new(root.join("cache")),
+            &FixtureHttp,
+        );
+        let (missing, warnings) = find_missing_features(
+            root,
+            &root.join("Cargo.lock"),
+            &index,
+            &root.join("cargo-home"),
+        )
+        .unwrap();
+        assert_eq!(
+            missing.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["crates/foo/Cargo.toml [dependencies] cairo-lang-sierra: \
+            feature `gone` does not exist in cairo-lang-sierra 2.9.0 (removed in 2.8.0)"]
+        );
+        assert_eq!(
+            warnings,
+            ["cairo-lang-utils is not resolved in Cargo.lock, skipping"]
+        );
+    }
+
+    #[test]
+    fn test_check_matrix() {
+        let sh = Shell::new().unwrap();
+        let ctx = Context::recording(sh);
+        let args = Args::parse_from([
+            "verify-features",
+            "--check",
+            "foo",
+            "--check",
+            "bar",
+            "--feature-set",
+            "none",
+            "--feature-set",
+            "serde,testing",
+        ]);
+
+        assert!(check_matrix(&ctx, &args).is_empty());
+        assert_eq!(
+            ctx.recorded(),
+            [
+                "cargo check -p foo --no-default-features",
+                "cargo check -p foo --no-default-features --features serde,testing",
+                "cargo check -p bar --no-default-features",
+                "cargo check -p bar --no-default-features --features serde,testing",
+            ]
+        );
+    }
+}
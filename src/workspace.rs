@@ -0,0 +1,384 @@
+//! Discover workspace members and dependency tables of manifests.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// Lists paths of workspace members, relative to the workspace root, excluding the root package.
+///
+/// Supports `*` and `?` wildcards in `workspace.members` and honors `workspace.exclude`.
+pub(crate) fn members(root: &Path, cargo_toml: &DocumentMut) -> Result<Vec<PathBuf>> {
+    let workspace = cargo_toml.get("workspace");
+    let patterns = |key: &str| {
+        workspace
+            .and_then(|workspace| workspace.get(key))
+            .and_then(Item::as_array)
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_owned))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let exclude = patterns("exclude")
+        .into_iter()
+        .map(|path| normalize(Path::new(&path)))
+        .collect::<Vec<_>>();
+
+    let mut members = Vec::new();
+    for pattern in patterns("members") {
+        for member in expand(root, Path::new(&pattern))? {
+            let member = normalize(&member);
+            if member.as_os_str().is_empty()
+                || exclude.contains(&member)
+                || members.contains(&member)
+                || !root.join(&member).join("Cargo.toml").is_file()
+            {
+                continue;
+            }
+            members.push(member);
+        }
+    }
+    Ok(members)
+}
+
+/// Finds the workspace member whose `package.name` is `name`, returning its path like [`members`].
+pub(crate) fn find_member(root: &Path, cargo_toml: &DocumentMut, name: &str) -> Result<PathBuf> {
+    let mut names = Vec::new();
+    for member in members(root, cargo_toml)? {
+        let manifest = crate::manifest::read(&root.join(&member).join("Cargo.toml"))?;
+        match manifest
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(Item::as_str)
+        {
+            Some(package) if package == name => return Ok(member),
+            Some(package) => names.push(package.to_owned()),
+            None => {}
+        }
+    }
+    if names.is_empty() {
+        bail!("the workspace has no member named `{name}`, nor any other members");
+    }
+    names.sort();
+    bail!(
+        "the workspace has no member named `{name}`, its members are: {}",
+        names.join(", ")
+    )
+}
+
+/// Finds the root of the workspace containing directory `start`, the way Cargo does.
+///
+/// This is the closest ancestor with a `[workspace]` manifest listing the package `start` is in,
+/// or that package itself if it is not a workspace member. Returns `start` if no manifest exists
+/// in any of its ancestors.
+pub(crate) fn find_root(start: &Path) -> PathBuf {
+    let Some(package) = start
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+    else {
+        return start.to_owned();
+    };
+
+    package
+        .ancestors()
+        .find(|dir| {
+            let Ok(cargo_toml) = crate::manifest::read(&dir.join("Cargo.toml")) else {
+                return false;
+            };
+            if cargo_toml.get("workspace").is_none() {
+                return false;
+            }
+            let Ok(relative) = package.strip_prefix(dir) else {
+                return false;
+            };
+            relative.as_os_str().is_empty()
+                || members(dir, &cargo_toml)
+                    .map(|members| members.iter().any(|member| member == relative))
+                    .unwrap_or(false)
+        })
+        .unwrap_or(package)
+        .to_owned()
+}
+
+/// Kinds of dependency tables, which may also be nested in `[target.<TARGET>]`.
+const KINDS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Lists all dependency tables of a manifest with their `.`-separated paths, e.g.
+/// `dependencies` or `target.'cfg(unix)'.dev-dependencies`.
+pub(crate) fn dependency_tables(cargo_toml: &DocumentMut) -> Vec<(String, &dyn TableLike)> {
+    fn push<'a>(
+        tables: &mut Vec<(String, &'a dyn TableLike)>,
+        path: String,
+        item: Option<&'a Item>,
+    ) {
+        if let Some(table) = item.and_then(Item::as_table_like) {
+            tables.push((path, table));
+        }
+    }
+
+    let mut tables = Vec::new();
+    for kind in KINDS {
+        push(&mut tables, kind.to_owned(), cargo_toml.get(kind));
+    }
+    push(
+        &mut tables,
+        "workspace.dependencies".to_owned(),
+        cargo_toml
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies")),
+    );
+    if let Some(targets) = cargo_toml.get("target").and_then(Item::as_table_like) {
+        for (target, table) in targets.iter() {
+            for kind in KINDS {
+                push(
+                    &mut tables,
+                    format!("target.{}.{kind}", quote_key(target)),
+                    table.get(kind),
+                );
+            }
+        }
+    }
+    tables
+}
+
+/// Lists all dependency tables of a manifest like [`dependency_tables`], for editing.
+///
+/// Tables are listed in the order of the manifest.
+pub(crate) fn dependency_tables_mut(
+    cargo_toml: &mut DocumentMut,
+) -> Vec<(String, &mut dyn TableLike)> {
+    dependency_items_mut(cargo_toml)
+        .into_iter()
+        .filter_map(|(path, item)| Some((path, item.as_table_like_mut()?)))
+        .collect()
+}
+
+/// Lists the items of all dependency tables like [`dependency_tables_mut`], for edits which
+/// depend on the form of the table, like sorting.
+pub(crate) fn dependency_items_mut(cargo_toml: &mut DocumentMut) -> Vec<(String, &mut Item)> {
+    let mut items = Vec::new();
+    for (key, item) in cargo_toml.iter_mut() {
+        match key.get() {
+            kind if KINDS.contains(&kind) && item.is_table_like() => {
+                items.push((kind.to_owned(), item));
+            }
+            "workspace" => {
+                if let Some(item) = item
+                    .get_mut("dependencies")
+                    .filter(|item| item.is_table_like())
+                {
+                    items.push(("workspace.dependencies".to_owned(), item));
+                }
+            }
+            "target" => {
+                let Some(targets) = item.as_table_like_mut() else {
+                    continue;
+                };
+                for (target, table) in targets.iter_mut() {
+                    let Some(table) = table.as_table_like_mut() else {
+                        continue;
+                    };
+                    let target = quote_key(target.get());
+                    for (kind, item) in table.iter_mut() {
+                        if item.is_table_like() && KINDS.contains(&kind.get()) {
+                            items.push((format!("target.{target}.{}", kind.get()), item));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+/// Quotes a key for use in a `.`-separated table path, if needed.
+pub(crate) fn quote_key(key: &str) -> String {
+    if key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        key.to_owned()
+    } else {
+        format!("'{key}'")
+    }
+}
+
+/// Expands wildcards in a relative path pattern against the filesystem.
+fn expand(root: &Path, pattern: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if !component.contains(['*', '?']) {
+            for path in &mut paths {
+                path.push(&*component);
+            }
+            continue;
+        }
+
+        let mut expanded = Vec::new();
+        for path in paths {
+            let dir = root.join(&path);
+            if !dir.is_dir() {
+                continue;
+            }
+            let mut entries = std::fs::read_dir(&dir)
+                .with_context(|| format!("failed to read {}", dir.display()))?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| wildcard_match(&component, name))
+                .collect::<Vec<_>>();
+            entries.sort();
+            expanded.extend(entries.into_iter().map(|name| path.join(name)));
+        }
+        paths = expanded;
+    }
+    Ok(paths)
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, std::path::Component::CurDir))
+        .collect()
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    fn go(p: &[char], n: &[char]) -> bool {
+        match (p.first(), n.first()) {
+            (None, None) => true,
+            (Some('*'), _) => go(&p[1..], n) || (!n.is_empty() && go(p, &n[1..])),
+            (Some('?'), Some(_)) => go(&p[1..], &n[1..]),
+            (Some(a), Some(b)) if a == b => go(&p[1..], &n[1..]),
+            _ => false,
+        }
+    }
+    go(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xshell::Shell;
+
+    #[test]
+    fn test_find_member() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let root = temp_dir.path();
+        for (member, name) in [("crates/a", "foo"), ("crates/b", "bar")] {
+            sh.write_file(
+                root.join(member).join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\n"),
+            )
+            .unwrap();
+        }
+        let cargo_toml = "[workspace]\nmembers = [\"crates/*\"]\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+
+        assert_eq!(
+            find_member(root, &cargo_toml, "bar").unwrap(),
+            Path::new("crates/b")
+        );
+        assert_eq!(
+            find_member(root, &cargo_toml, "baz")
+                .unwrap_err()
+                .to_string(),
+            "the workspace has no member named `baz`, its members are: bar, foo"
+        );
+        assert_eq!(
+            find_member(root, &DocumentMut::new(), "foo")
+                .unwrap_err()
+                .to_string(),
+            "the workspace has no member named `foo`, nor any other members"
+        );
+    }
+
+    #[test]
+    fn test_members() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let root = temp_dir.path();
+        for member in ["crates/a", "crates/b", "crates/skipped", "tools/x", "xtask"] {
+            sh.write_file(root.join(member).join("Cargo.toml"), "[package]\n")
+                .unwrap();
+        }
+        sh.create_dir(root.join("crates/not-a-crate")).unwrap();
+
+        let cargo_toml = r#"
+[workspace]
+members = [".", "crates/*", "./xtask", "tools/?"]
+exclude = ["crates/skipped"]
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        assert_eq!(
+            members(root, &cargo_toml).unwrap(),
+            ["crates/a", "crates/b", "xtask", "tools/x"]
+                .map(PathBuf::from)
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_find_root() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        let repo = temp_dir.path();
+        sh.write_file(
+            repo.join("rust/Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        sh.write_file(repo.join("rust/crates/a/Cargo.toml"), "[package]\n")
+            .unwrap();
+        sh.write_file(repo.join("rust/fixtures/b/Cargo.toml"), "[package]\n")
+            .unwrap();
+        sh.create_dir(repo.join("rust/crates/a/src")).unwrap();
+        sh.create_dir(repo.join("docs")).unwrap();
+
+        assert_eq!(find_root(&repo.join("rust")), repo.join("rust"));
+        assert_eq!(
+            find_root(&repo.join("rust/crates/a/src")),
+            repo.join("rust")
+        );
+        assert_eq!(
+            find_root(&repo.join("rust/fixtures/b")),
+            repo.join("rust/fixtures/b")
+        );
+        assert_eq!(find_root(&repo.join("docs")), repo.join("docs"));
+    }
+
+    #[test]
+    fn test_dependency_tables() {
+        let cargo_toml = r#"
+[dependencies]
+[build-dependencies]
+[workspace.dependencies]
+[target.'cfg(unix)'.dependencies]
+[target.wasm32-unknown-unknown.dev-dependencies]
+"#
+        .parse::<DocumentMut>()
+        .unwrap();
+
+        assert_eq!(
+            dependency_tables(&cargo_toml)
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>(),
+            [
+                "dependencies",
+                "build-dependencies",
+                "workspace.dependencies",
+                "target.'cfg(unix)'.dependencies",
+                "target.wasm32-unknown-unknown.dev-dependencies",
+            ]
+        );
+    }
+}
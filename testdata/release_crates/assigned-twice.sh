@@ -0,0 +1,8 @@
+#!/bin/bash
+# Synthetic: the array is reassigned instead of appended to.
+CRATES_TO_PUBLISH=(
+    cairo-lang-utils
+)
+
+# Later on:
+CRATES_TO_PUBLISH=(cairo-lang-compiler)
@@ -0,0 +1,6 @@
+#!/bin/bash
+# Synthetic: an entry computed by the shell.
+CRATES_TO_PUBLISH=(
+    cairo-lang-utils
+    $(ls crates | grep cairo-lang-plugin)
+)
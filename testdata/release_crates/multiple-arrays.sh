@@ -0,0 +1,18 @@
+#!/bin/bash
+# Synthetic: several arrays, inline comments, quoted entries and a single-line array.
+
+CRATES_TO_SKIP=(cairo-lang-test-utils)
+
+readonly CRATES_TO_PUBLISH=(
+    cairo-lang-utils # Leaf crate, publish first.
+    'cairo-lang-macro'
+
+    "cairo-lang-compiler"
+    # cairo-lang-removed
+)
+CRATES_TO_PUBLISH+=( cairo-lang-starknet
+    cairo-lang-test-plugin )
+
+for CRATE in "${CRATES_TO_PUBLISH[@]}"; do
+    cargo publish --package "$CRATE"
+done
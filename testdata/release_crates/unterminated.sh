@@ -0,0 +1,5 @@
+#!/bin/bash
+# Synthetic: the closing parenthesis is missing.
+CRATES_TO_PUBLISH=(
+    cairo-lang-utils
+    cairo-lang-compiler
@@ -0,0 +1,62 @@
+#!/bin/bash
+# Synthetic: modelled on the layout of `scripts/release_crates.sh` around v2.9.2, not a copy of it.
+
+# An optional argument, '-s | --skip-first <#num>', can be passed to skip the first <#num> crates.
+SKIP_FIRST=0
+while [[ "$#" -gt 0 ]]; do
+    case $1 in
+        -s|--skip-first) SKIP_FIRST="$2"; shift ;;
+        *) echo "Unknown parameter passed: $1"; exit 1 ;;
+    esac
+    shift
+done
+
+# The list of crates to be published, in dependency order.
+CRATES_TO_PUBLISH=(
+    cairo-lang-utils
+    cairo-lang-debug
+    cairo-lang-proc-macros
+    cairo-lang-filesystem
+    cairo-lang-syntax-codegen
+    cairo-lang-syntax
+    cairo-lang-parser
+    cairo-lang-diagnostics
+    cairo-lang-defs
+    cairo-lang-plugins
+
+    # Semantic model and lowering.
+    cairo-lang-semantic
+    cairo-lang-lowering
+    cairo-lang-sierra
+    cairo-lang-sierra-type-size
+    cairo-lang-sierra-ap-change
+    cairo-lang-sierra-gas
+    cairo-lang-eq-solver
+    cairo-lang-casm
+    cairo-lang-sierra-to-casm
+    cairo-lang-sierra-generator
+
+    cairo-lang-compiler
+    cairo-lang-formatter
+    cairo-lang-starknet-classes
+    cairo-lang-starknet
+    cairo-lang-runnable-utils
+    cairo-lang-runner
+    cairo-lang-test-utils
+    cairo-lang-project
+    cairo-lang-doc
+    cairo-lang-test-plugin
+    cairo-lang-test-runner
+    cairo-lang-executable
+    cairo-lang-macro
+)
+
+# Assert that the number of crates to skip is not larger than the number of crates to publish.
+if [ "$SKIP_FIRST" -gt ${#CRATES_TO_PUBLISH[@]} ]; then
+    echo "The number of crates to skip is larger than the number of crates to publish."
+    exit 1
+fi
+
+for CRATE in "${CRATES_TO_PUBLISH[@]:$SKIP_FIRST}"; do
+    cargo publish --package $CRATE
+done
@@ -0,0 +1,306 @@
+//! End-to-end runs of the xtasks against disposable fixture workspaces, with real Cargo.
+//!
+//! Each test generates a workspace in a temporary directory, set up with the `init` xtask, next
+//! to checkouts of stand-in Cairo crates. The workspace starts out patched to one checkout, as
+//! after a previous `upgrade --path`, since Cargo could not resolve it otherwise. Cargo runs
+//! offline, and files the xtasks fetch over HTTP, like the release script of the version locked
+//! before, are served from a mirror directory with `CAIRO_XTASKS_HTTP_MIRROR`, so nothing is
+//! fetched.
+//!
+//! Building the xtask of each fixture is slow, so these tests are ignored by default, run them
+//! with `cargo test -- --ignored`.
+
+use cairo_toolchain_xtasks::init;
+use clap::Parser;
+use std::process::Output;
+use xshell::{cmd, Shell, TempDir};
+
+/// Version of the Cairo checkout fixtures start out patched to.
+const INITIAL_VERSION: &str = "2.76.0";
+
+/// Target directory shared by all fixtures, so that the xtask dependencies are only built once.
+const TARGET_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/end-to-end");
+
+/// A fixture workspace in `project`, along with Cairo checkouts in `cairo-<VERSION>` and the HTTP
+/// mirror in `mirror`.
+struct Fixture {
+    /// Shell operating in the fixture workspace.
+    sh: Shell,
+    _temp_dir: TempDir,
+}
+
+impl Fixture {
+    /// Creates a workspace with two members depending on Cairo crates, patched to a checkout at
+    /// [`INITIAL_VERSION`].
+    fn new() -> Self {
+        let sh = Shell::new().unwrap();
+        let temp_dir = sh.create_temp_dir().unwrap();
+        sh.change_dir(temp_dir.path());
+        sh.change_dir("project");
+        sh.write_file(
+            "Cargo.toml",
+            format!(
+                "[workspace]\nmembers = [\"crates/*\"]\nresolver = \"2\"\n\n\
+                [workspace.package]\nversion = \"{INITIAL_VERSION}\"\n\n\
+                [workspace.dependencies]\ncairo-lang-compiler = \"{INITIAL_VERSION}\"\n\n\
+                [patch.crates-io]\ncairo-toolchain-xtasks = {{ path = {:?} }}\n\
+                cairo-lang-compiler = {{ path = \"../cairo-{INITIAL_VERSION}/crates/cairo-lang-compiler\" }}\n\
+                cairo-lang-utils = {{ path = \"../cairo-{INITIAL_VERSION}/crates/cairo-lang-utils\" }}\n",
+                env!("CARGO_MANIFEST_DIR")
+            ),
+        )
+        .unwrap();
+        write_package(
+            &sh,
+            "crates/foo",
+            "version.workspace = true",
+            "cairo-lang-compiler.workspace = true\n",
+        );
+        write_package(
+            &sh,
+            "crates/bar",
+            "version.workspace = true",
+            &format!("cairo-lang-utils = \"{INITIAL_VERSION}\"\nfoo = {{ path = \"../foo\" }}\n"),
+        );
+
+        // The xtask depends on this crate by the patch written above.
+        let directory = sh.current_dir();
+        init::main(init::Args::parse_from([
+            "init".as_ref(),
+            "-C".as_ref(),
+            directory.as_os_str(),
+        ]))
+        .unwrap();
+
+        let fixture = Self {
+            sh,
+            _temp_dir: temp_dir,
+        };
+        let checkout = fixture.checkout(INITIAL_VERSION);
+        // The crates of the locked version are read from the release script of its tag.
+        fixture
+            .sh
+            .copy_file(
+                format!("{checkout}/scripts/release_crates.sh"),
+                fixture
+                    .sh
+                    .create_dir(format!(
+                        "../mirror/raw.githubusercontent.com/starkware-libs/cairo/refs/tags/\
+                    v{INITIAL_VERSION}/scripts"
+                    ))
+                    .unwrap(),
+            )
+            .unwrap();
+        fixture
+    }
+
+    /// Creates a Cairo checkout with stand-in crates at `version`, returning its path relative to
+    /// the workspace.
+    fn checkout(&self, version: &str) -> String {
+        let sh = &self.sh;
+        let dir = format!("../cairo-{version}");
+        sh.write_file(
+            format!("{dir}/Cargo.toml"),
+            format!(
+                "[workspace]\nmembers = [\"crates/*\"]\n\n\
+                [workspace.package]\nversion = \"{version}\"\n"
+            ),
+        )
+        .unwrap();
+        sh.write_file(
+            format!("{dir}/scripts/release_crates.sh"),
+            "CRATES_TO_PUBLISH=(\n  cairo-lang-utils\n  cairo-lang-compiler\n)\n",
+        )
+        .unwrap();
+        write_package(
+            sh,
+            &format!("{dir}/crates/cairo-lang-utils"),
+            "version.workspace = true",
+            "",
+        );
+        write_package(
+            sh,
+            &format!("{dir}/crates/cairo-lang-compiler"),
+            "version.workspace = true",
+            &format!(
+                "cairo-lang-utils = {{ path = \"../cairo-lang-utils\", version = \"{version}\" }}\n"
+            ),
+        );
+        dir
+    }
+
+    /// Runs `cargo xtask` in the workspace the way projects do.
+    fn xtask(&self, args: &[&str]) -> Output {
+        let sh = &self.sh;
+        cmd!(sh, "cargo xtask {args...}")
+            .env("CARGO_NET_OFFLINE", "true")
+            .env(
+                "CAIRO_XTASKS_HTTP_MIRROR",
+                sh.current_dir().join("../mirror"),
+            )
+            .env("CARGO_TARGET_DIR", TARGET_DIR)
+            .ignore_status()
+            .output()
+            .unwrap()
+    }
+
+    fn read(&self, path: &str) -> String {
+        self.sh.read_file(path).unwrap()
+    }
+}
+
+fn write_package(sh: &Shell, dir: &str, version: &str, dependencies: &str) {
+    let name = dir.rsplit('/').next().unwrap();
+    sh.write_file(
+        format!("{dir}/Cargo.toml"),
+        format!(
+            "[package]\nname = \"{name}\"\n{version}\nedition = \"2021\"\n\n\
+            [dependencies]\n{dependencies}"
+        ),
+    )
+    .unwrap();
+    sh.write_file(format!("{dir}/src/lib.rs"), "").unwrap();
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Finds the locked versions of `crate_name`, with their sources.
+fn locked(cargo_lock: &str, crate_name: &str) -> Vec<(String, Option<String>)> {
+    let cargo_lock = cargo_lock.parse::<toml_edit::DocumentMut>().unwrap();
+    cargo_lock["package"]
+        .as_array_of_tables()
+        .unwrap()
+        .iter()
+        .filter(|pkg| pkg["name"].as_str() == Some(crate_name))
+        .map(|pkg| {
+            (
+                pkg["version"].as_str().unwrap().to_owned(),
+                pkg.get("source")
+                    .and_then(|s| s.as_str())
+                    .map(str::to_owned),
+            )
+        })
+        .collect()
+}
+
+#[test]
+#[ignore = "builds the xtask of a fixture workspace, run with --ignored"]
+fn test_upgrade_to_local_checkout() {
+    let fixture = Fixture::new();
+    let checkout = fixture.checkout("2.77.0");
+
+    let output = fixture.xtask(&["upgrade", "cairo", "--path", &checkout, "--offline"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // Crates of the previous version were listed from the mirror to check for removed ones.
+    assert!(
+        !stderr(&output).contains("cannot check for crates removed upstream"),
+        "{}",
+        stderr(&output)
+    );
+
+    let cargo_toml = fixture.read("Cargo.toml");
+    for crate_name in ["cairo-lang-compiler", "cairo-lang-utils"] {
+        let path = fixture
+            .sh
+            .current_dir()
+            .join(&checkout)
+            .join("crates")
+            .join(crate_name);
+        assert!(
+            cargo_toml.contains(&format!("{crate_name} = {{ path = {:?} }}", path.display())),
+            "{crate_name} is not patched to the checkout:\n{cargo_toml}"
+        );
+    }
+    // Synced by the chained `sync-version` run.
+    assert!(
+        cargo_toml.contains("[workspace.package]\nversion = \"2.77.0\"\n"),
+        "{cargo_toml}"
+    );
+
+    let cargo_lock = fixture.read("Cargo.lock");
+    for crate_name in ["cairo-lang-compiler", "cairo-lang-utils"] {
+        assert_eq!(
+            locked(&cargo_lock, crate_name),
+            [("2.77.0".to_owned(), None)]
+        );
+    }
+
+    let output = fixture.xtask(&["sync-version", "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+#[ignore = "builds the xtask of a fixture workspace, run with --ignored"]
+fn test_best_effort_with_incomplete_checkout() {
+    let fixture = Fixture::new();
+    let checkout = fixture.checkout("2.77.0");
+    fixture
+        .sh
+        .remove_path(format!("{checkout}/crates/cairo-lang-compiler"))
+        .unwrap();
+    let original = fixture.read("Cargo.toml");
+
+    let args = ["upgrade", "cairo", "--path", &checkout, "--offline"];
+    let output = fixture.xtask(&args);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "1 crate cannot be patched to local checkouts:\n  cairo: cairo-lang-compiler, no \
+            package at"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(fixture.read("Cargo.toml"), original);
+
+    let output = fixture.xtask(&[&args[..], &["--best-effort", "--no-sync-version"]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    let cargo_toml = fixture.read("Cargo.toml");
+    let patched = |crate_name: &str, version: &str| {
+        cargo_toml.lines().any(|line| {
+            line.starts_with(&format!("{crate_name} = "))
+                && line.contains(&format!("cairo-{version}/crates/{crate_name}"))
+        })
+    };
+    assert!(patched("cairo-lang-utils", "2.77.0"), "{cargo_toml}");
+    assert!(
+        patched("cairo-lang-compiler", INITIAL_VERSION),
+        "{cargo_toml}"
+    );
+}
+
+#[test]
+#[ignore = "builds the xtask of a fixture workspace, run with --ignored"]
+fn test_exit_codes() {
+    let fixture = Fixture::new();
+    let original = fixture.read("Cargo.toml");
+
+    // No manifest depends on Cairo Lint.
+    let output = fixture.xtask(&["upgrade", "cairolint", "--rev", "1a2b3c4", "--offline"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("nothing to do"));
+    assert_eq!(fixture.read("Cargo.toml"), original);
+
+    let output = fixture.xtask(&["upgrade", "cairo", "--path", "../missing", "--offline"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("local checkout"));
+    assert_eq!(fixture.read("Cargo.toml"), original);
+
+    let output = fixture.xtask(&["sync-version", "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    fixture
+        .sh
+        .write_file(
+            "Cargo.toml",
+            original.replace(
+                "package]\nversion = \"2.76.0\"",
+                "package]\nversion = \"2.76.1\"",
+            ),
+        )
+        .unwrap();
+    let output = fixture.xtask(&["sync-version", "--check"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("version consistency checks failed"));
+}